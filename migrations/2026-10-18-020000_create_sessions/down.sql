@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "idx_session_user_id";
+
+DROP INDEX IF EXISTS "idx_session_refresh_token";
+
+DROP TABLE IF EXISTS "Sessions";
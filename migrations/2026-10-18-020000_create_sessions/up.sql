@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- A session is started by each login and lives as long as its refresh token.
+-- Access tokens carry the session id, so revoking a session kills them as well.
+CREATE TABLE "Sessions" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    refresh_token VARCHAR(64) NOT NULL, -- SHA-256 of the refresh token
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT false,
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX "idx_session_refresh_token" ON "Sessions"(refresh_token);
+
+CREATE INDEX "idx_session_user_id" ON "Sessions"(user_id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "Users" DROP COLUMN IF EXISTS role;
+
+DROP TYPE IF EXISTS "UserRole";
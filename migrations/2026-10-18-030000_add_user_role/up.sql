@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TYPE "UserRole" AS ENUM ('USER', 'MODERATOR', 'ADMIN');
+
+-- The first admin has to be promoted by hand:
+-- UPDATE "Users" SET role = 'ADMIN' WHERE username = '...';
+ALTER TABLE "Users" ADD COLUMN role "UserRole" NOT NULL DEFAULT 'USER';
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "idx_password_reset_token_hash";
+
+DROP TABLE IF EXISTS "PasswordResetTokens";
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE "PasswordResetTokens" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    token_hash VARCHAR(64) NOT NULL, -- SHA-256 of the reset token
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP, -- tokens are single-use
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX "idx_password_reset_token_hash" ON "PasswordResetTokens"(token_hash);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "idx_email_verification_token_hash";
+
+DROP TABLE IF EXISTS "EmailVerificationTokens";
+
+ALTER TABLE "Users" DROP COLUMN IF EXISTS email_verified;
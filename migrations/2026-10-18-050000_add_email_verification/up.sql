@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- IAAA accounts are verified by IAAA, only password accounts need to verify
+-- their email.
+ALTER TABLE "Users" ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE "EmailVerificationTokens" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    email VARCHAR(100) NOT NULL, -- the address the token was sent to
+    token_hash VARCHAR(64) NOT NULL, -- SHA-256 of the verification token
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP, -- tokens are single-use
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX "idx_email_verification_token_hash" ON "EmailVerificationTokens"(token_hash);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "idx_login_attempt_ip_address";
+DROP INDEX IF EXISTS "idx_login_attempt_username";
+
+DROP TABLE IF EXISTS "LoginAttempts";
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE "LoginAttempts" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    username VARCHAR NOT NULL, -- as sent by the client, the user may not exist
+    ip_address VARCHAR,
+    success BOOLEAN NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX "idx_login_attempt_username" ON "LoginAttempts"(username, created_at);
+CREATE INDEX "idx_login_attempt_ip_address" ON "LoginAttempts"(ip_address, created_at);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "idx_iaaa_profile_dept_id";
+
+DROP TABLE IF EXISTS "IaaaProfiles";
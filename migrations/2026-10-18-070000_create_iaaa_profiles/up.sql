@@ -0,0 +1,16 @@
+-- Your SQL goes here
+-- Attributes of IAAA accounts, refreshed on each IAAA login
+CREATE TABLE "IaaaProfiles" (
+    user_id INT NOT NULL PRIMARY KEY,
+    dept_id VARCHAR NOT NULL,
+    dept VARCHAR NOT NULL,
+    campus VARCHAR NOT NULL,
+    identity_type VARCHAR NOT NULL,
+    detail_type VARCHAR NOT NULL,
+    identity_status VARCHAR NOT NULL,
+    graduated BOOLEAN NOT NULL, -- identity_status is not 在校
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE INDEX "idx_iaaa_profile_dept_id" ON "IaaaProfiles"(dept_id);
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- Ways to login to an account, at most one per provider
+CREATE TABLE "Identities" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    provider "LoginProvider" NOT NULL,
+    subject VARCHAR(255) NOT NULL, -- identity id for IAAA, username for PASSWORD
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE,
+    UNIQUE (provider, subject),
+    UNIQUE (user_id, provider)
+);
+
+-- Existing accounts login the way they were created, the oldest of
+-- duplicate usernames keeps it
+INSERT INTO "Identities" (user_id, provider, subject, created_at)
+SELECT DISTINCT ON (login_provider, username) id, login_provider, username, created_at
+FROM "Users"
+ORDER BY login_provider, username, id;
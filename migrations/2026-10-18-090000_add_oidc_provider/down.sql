@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+-- Postgres cannot drop an enum value, rebuild the type without it
+DELETE FROM "Identities" WHERE provider = 'OIDC';
+DELETE FROM "Users" WHERE login_provider = 'OIDC';
+
+ALTER TYPE "LoginProvider" RENAME TO "LoginProvider_old";
+CREATE TYPE "LoginProvider" AS ENUM ('IAAA', 'PASSWORD');
+ALTER TABLE "Users"
+    ALTER COLUMN login_provider TYPE "LoginProvider" USING login_provider::text::"LoginProvider";
+ALTER TABLE "Identities"
+    ALTER COLUMN provider TYPE "LoginProvider" USING provider::text::"LoginProvider";
+DROP TYPE "LoginProvider_old";
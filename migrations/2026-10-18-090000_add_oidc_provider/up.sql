@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Login through a generic OpenID Connect issuer
+ALTER TYPE "LoginProvider" ADD VALUE 'OIDC';
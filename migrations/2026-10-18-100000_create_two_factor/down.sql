@@ -0,0 +1,10 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX IF EXISTS "idx_login_challenge_token_hash";
+
+DROP TABLE IF EXISTS "LoginChallenges";
+
+DROP INDEX IF EXISTS "idx_recovery_codes_user_id";
+
+DROP TABLE IF EXISTS "RecoveryCodes";
+
+DROP TABLE IF EXISTS "TotpCredentials";
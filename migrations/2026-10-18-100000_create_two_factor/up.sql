@@ -0,0 +1,35 @@
+-- Your SQL goes here
+-- TOTP second factor of password logins, at most one per account
+CREATE TABLE "TotpCredentials" (
+    user_id INT NOT NULL PRIMARY KEY,
+    secret BYTEA NOT NULL, -- sealed with the secret key
+    confirmed_at TIMESTAMP, -- enrollment pending until a code is confirmed
+    last_used_step BIGINT, -- codes are single-use
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE TABLE "RecoveryCodes" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    code_hash VARCHAR(64) NOT NULL, -- SHA-256 of the recovery code
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    used_at TIMESTAMP, -- codes are single-use
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE INDEX "idx_recovery_codes_user_id" ON "RecoveryCodes"(user_id);
+
+-- Password logins waiting for the second factor
+CREATE TABLE "LoginChallenges" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    token_hash VARCHAR(64) NOT NULL, -- SHA-256 of the challenge token
+    failed_attempts INT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at TIMESTAMP NOT NULL,
+    used_at TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX "idx_login_challenge_token_hash" ON "LoginChallenges"(token_hash);
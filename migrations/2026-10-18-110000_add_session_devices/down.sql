@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "Sessions"
+    DROP COLUMN device_name,
+    DROP COLUMN ip_address,
+    DROP COLUMN user_agent,
+    DROP COLUMN last_seen_at;
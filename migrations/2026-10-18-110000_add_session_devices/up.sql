@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- What the user needs to tell its sessions apart, as given on login
+ALTER TABLE "Sessions"
+    ADD COLUMN device_name VARCHAR(255),
+    ADD COLUMN ip_address VARCHAR(64),
+    ADD COLUMN user_agent VARCHAR(255),
+    ADD COLUMN last_seen_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP; -- updated on each refresh
+
+UPDATE "Sessions" SET last_seen_at = created_at;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX "idx_user_delete_after";
+ALTER TABLE "Users" DROP COLUMN delete_after;
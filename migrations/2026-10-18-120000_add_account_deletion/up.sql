@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Set when the user asks to delete the account, it is purged once the grace
+-- period is over unless the user cancels.
+ALTER TABLE "Users" ADD COLUMN delete_after TIMESTAMP;
+
+CREATE INDEX "idx_user_delete_after" ON "Users"(delete_after) WHERE delete_after IS NOT NULL;
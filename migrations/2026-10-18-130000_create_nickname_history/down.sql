@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE "NicknameHistory";
+DROP INDEX "idx_user_nickname";
+ALTER TABLE "Users" DROP COLUMN nickname_changed_at;
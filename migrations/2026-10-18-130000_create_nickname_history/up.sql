@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- Unset until the user picks a nickname with ChangeNickname, the default one
+-- is its name on IAAA or the OIDC issuer, or empty.
+ALTER TABLE "Users" ADD COLUMN nickname_changed_at TIMESTAMP;
+
+-- Picked nicknames are unique regardless of case, default ones may clash
+CREATE UNIQUE INDEX "idx_user_nickname" ON "Users"(lower(nickname)) WHERE nickname_changed_at IS NOT NULL;
+
+-- The nicknames a user went by before
+CREATE TABLE "NicknameHistory" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    nickname VARCHAR NOT NULL,
+    changed_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP, -- when it was replaced
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
+
+CREATE INDEX "idx_nickname_history_user_id" ON "NicknameHistory"(user_id);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE "UserProfiles";
+DROP TYPE "Visibility";
+DROP TYPE "ContactMethod";
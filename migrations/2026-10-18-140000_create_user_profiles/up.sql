@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TYPE "ContactMethod" AS ENUM ('EMAIL', 'PHONE', 'WECHAT', 'QQ', 'OTHER');
+-- Who sees a part of a profile, besides its owner
+CREATE TYPE "Visibility" AS ENUM ('ONLY_ME', 'SIGNED_IN', 'EVERYONE');
+
+-- Users without a row have an empty profile and the default settings, keep
+-- the defaults in sync with `UserProfile::empty`.
+CREATE TABLE "UserProfiles" (
+    user_id INT NOT NULL PRIMARY KEY,
+    bio TEXT NOT NULL DEFAULT '',
+    pronouns VARCHAR(32),
+    contact_method "ContactMethod",
+    contact VARCHAR(255),
+    links TEXT[] NOT NULL DEFAULT '{}',
+    favorites_visibility "Visibility" NOT NULL DEFAULT 'ONLY_ME',
+    likes_visibility "Visibility" NOT NULL DEFAULT 'ONLY_ME',
+    participations_visibility "Visibility" NOT NULL DEFAULT 'SIGNED_IN',
+    contact_visibility "Visibility" NOT NULL DEFAULT 'SIGNED_IN',
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE
+);
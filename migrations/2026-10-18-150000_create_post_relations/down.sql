@@ -0,0 +1,40 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "Posts" ADD COLUMN comments_id INT[] NOT NULL DEFAULT '{}';
+UPDATE "Posts" p SET comments_id = ARRAY(
+    SELECT c.id FROM "Comments" c WHERE c.post_id = p.id ORDER BY c.id
+);
+ALTER TABLE "Posts" ALTER COLUMN comments_id DROP DEFAULT;
+
+CREATE OR REPLACE FUNCTION check_comments_ids() RETURNS TRIGGER AS $$
+BEGIN
+    -- 检查 comments_id 数组中的每个值是否存在于 Comments 表中
+    IF NEW.comments_id IS NOT NULL AND NEW.comments_id <> '{}' THEN
+        FOR i IN 1..array_length(NEW.comments_id, 1) LOOP
+            IF NOT EXISTS (SELECT 1 FROM "Comments" WHERE id = NEW.comments_id[i]) THEN
+                RAISE EXCEPTION 'Invalid comment id: %', NEW.comments_id[i];
+            END IF;
+        END LOOP;
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_check_comments_ids
+BEFORE INSERT OR UPDATE ON "Posts"
+FOR EACH ROW
+EXECUTE FUNCTION check_comments_ids();
+
+ALTER TABLE "Users" ADD COLUMN favorite_posts INT[] NOT NULL DEFAULT '{}';
+ALTER TABLE "Users" ADD COLUMN liked_posts INT[] NOT NULL DEFAULT '{}';
+ALTER TABLE "Users" ADD COLUMN take_part_posts INT[] NOT NULL DEFAULT '{}';
+UPDATE "Users" u SET
+    favorite_posts = ARRAY(SELECT post_id FROM "PostFavorites" WHERE user_id = u.id ORDER BY id),
+    liked_posts = ARRAY(SELECT post_id FROM "PostLikes" WHERE user_id = u.id ORDER BY id),
+    take_part_posts = ARRAY(SELECT post_id FROM "PostParticipants" WHERE user_id = u.id ORDER BY id);
+ALTER TABLE "Users" ALTER COLUMN favorite_posts DROP DEFAULT;
+ALTER TABLE "Users" ALTER COLUMN liked_posts DROP DEFAULT;
+ALTER TABLE "Users" ALTER COLUMN take_part_posts DROP DEFAULT;
+
+DROP TABLE "PostParticipants";
+DROP TABLE "PostFavorites";
+DROP TABLE "PostLikes";
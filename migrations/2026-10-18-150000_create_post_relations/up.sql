@@ -0,0 +1,88 @@
+-- Your SQL goes here
+-- Who liked, favorited and took part in which post, in place of the arrays
+-- on "Users". Rows are numbered in the order they were added.
+CREATE TABLE "PostLikes" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    post_id INT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, post_id),
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE,
+    FOREIGN KEY (post_id) REFERENCES "Posts"(id) ON DELETE CASCADE
+);
+
+CREATE TABLE "PostFavorites" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    post_id INT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, post_id),
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE,
+    FOREIGN KEY (post_id) REFERENCES "Posts"(id) ON DELETE CASCADE
+);
+
+CREATE TABLE "PostParticipants" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    post_id INT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, post_id),
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE,
+    FOREIGN KEY (post_id) REFERENCES "Posts"(id) ON DELETE CASCADE
+);
+
+-- (user_id, post_id) is covered by the unique constraints
+CREATE INDEX "idx_post_like_post_id" ON "PostLikes"(post_id);
+CREATE INDEX "idx_post_favorite_post_id" ON "PostFavorites"(post_id);
+CREATE INDEX "idx_post_participant_post_id" ON "PostParticipants"(post_id);
+
+-- Places the organizers reserved for themselves, which are counted in
+-- people_already without anyone in take_part_posts
+CREATE TEMPORARY TABLE reserved_places AS
+SELECT p.id AS post_id, GREATEST(COALESCE(p.people_already, 0) - COUNT(t.post_id), 0) AS places
+FROM "Posts" p
+LEFT JOIN (SELECT unnest(take_part_posts) AS post_id FROM "Users") t ON t.post_id = p.id
+WHERE p.post_type = 'AMUSEMENTPOST'
+GROUP BY p.id;
+
+-- Keep the order of the arrays, skip duplicates and deleted posts
+INSERT INTO "PostLikes" (user_id, post_id)
+SELECT u.id, p.post_id
+FROM "Users" u, unnest(u.liked_posts) WITH ORDINALITY AS p(post_id, ord)
+WHERE EXISTS (SELECT 1 FROM "Posts" WHERE id = p.post_id)
+ORDER BY u.id, p.ord
+ON CONFLICT DO NOTHING;
+
+INSERT INTO "PostFavorites" (user_id, post_id)
+SELECT u.id, p.post_id
+FROM "Users" u, unnest(u.favorite_posts) WITH ORDINALITY AS p(post_id, ord)
+WHERE EXISTS (SELECT 1 FROM "Posts" WHERE id = p.post_id)
+ORDER BY u.id, p.ord
+ON CONFLICT DO NOTHING;
+
+INSERT INTO "PostParticipants" (user_id, post_id)
+SELECT u.id, p.post_id
+FROM "Users" u, unnest(u.take_part_posts) WITH ORDINALITY AS p(post_id, ord)
+WHERE EXISTS (SELECT 1 FROM "Posts" WHERE id = p.post_id)
+ORDER BY u.id, p.ord
+ON CONFLICT DO NOTHING;
+
+-- The counters must agree with the rows, which lack the duplicates and
+-- stale entries of the arrays
+UPDATE "Posts" p SET
+    likes = (SELECT COUNT(*) FROM "PostLikes" WHERE post_id = p.id),
+    favorates = (SELECT COUNT(*) FROM "PostFavorites" WHERE post_id = p.id);
+UPDATE "Posts" p SET
+    people_already = r.places + (SELECT COUNT(*) FROM "PostParticipants" WHERE post_id = p.id)
+FROM reserved_places r
+WHERE r.post_id = p.id;
+DROP TABLE reserved_places;
+
+ALTER TABLE "Users" DROP COLUMN favorite_posts;
+ALTER TABLE "Users" DROP COLUMN liked_posts;
+ALTER TABLE "Users" DROP COLUMN take_part_posts;
+
+-- The comments of a post are the ones with its post_id
+DROP TRIGGER trg_check_comments_ids ON "Posts";
+DROP FUNCTION check_comments_ids();
+ALTER TABLE "Posts" DROP COLUMN comments_id;
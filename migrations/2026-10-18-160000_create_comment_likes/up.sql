@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Who liked which comment, Comments.likes counts them
+CREATE TABLE "CommentLikes" (
+    id SERIAL NOT NULL PRIMARY KEY,
+    user_id INT NOT NULL,
+    comment_id INT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, comment_id),
+    FOREIGN KEY (user_id) REFERENCES "Users"(id) ON DELETE CASCADE,
+    FOREIGN KEY (comment_id) REFERENCES "Comments"(id) ON DELETE CASCADE
+);
+
+-- (user_id, comment_id) is covered by the unique constraint
+CREATE INDEX "idx_comment_like_comment_id" ON "CommentLikes"(comment_id);
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- `ip_address` is where the login came from, the address the client tells is
+-- kept apart as it is not checked
+ALTER TABLE "Sessions"
+    ADD COLUMN reported_ip_address VARCHAR(64);
@@ -0,0 +1,151 @@
+//! HoloPKU administration module.
+use log::{error, trace};
+use tonic::{Request, Response, Status};
+
+use crate::codegen::admin::admin_server::Admin;
+use crate::codegen::admin::{ListUsersByRoleRequest, ListUsersByRoleResponse};
+use crate::codegen::admin::{ResetTotpRequest, ResetTotpResponse};
+use crate::codegen::admin::{SetUserRoleRequest, SetUserRoleResponse};
+use crate::codegen::auth::UserRole;
+use crate::db::models;
+use crate::db::{delete_totp, query_iaaa_profile, query_image_by_id, query_users_by_role};
+use crate::db::{query_user_post_ids, update_user_role, DBClient};
+use crate::middleware::current_user;
+use crate::permission::ensure_role;
+
+#[derive(Debug)]
+pub struct AdminService {
+    pub client: DBClient,
+}
+
+#[tonic::async_trait]
+#[allow(clippy::result_large_err)]
+impl Admin for AdminService {
+    async fn set_user_role(
+        &self,
+        request: Request<SetUserRoleRequest>,
+    ) -> Result<Response<SetUserRoleResponse>, Status> {
+        let user = current_user(&request)?;
+        ensure_role(&user, UserRole::Admin)?;
+        let req = request.into_inner();
+        trace!("SetUserRole got request: {req:#?}");
+
+        // an admin demoting itself could leave the server without any admin
+        if req.user_id == user.user_id {
+            return Err(Status::failed_precondition("Cannot change your own role"));
+        }
+        let new_role =
+            UserRole::try_from(req.role).map_err(|_| Status::invalid_argument("Invalid role"))?;
+
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let dbuser = update_user_role(
+                    conn,
+                    req.user_id,
+                    models::UserRole::from_proto_type(new_role),
+                )
+                .map_err(|e| {
+                    error!("Fail to set role of user {}: {e}", req.user_id);
+                    Status::not_found("No such user")
+                })?;
+                trace!(
+                    "User {} set role of user {} to {new_role:?}",
+                    user.user_id,
+                    dbuser.id
+                );
+
+                let icon = query_image_by_id(dbuser.icon).map_err(|e| {
+                    error!("Fail to query image by id {} :{e}", dbuser.icon);
+                    Status::internal("Fail to set user role")
+                })?;
+                let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(|e| {
+                    error!("Fail to query IAAA profile of user {}: {e}", dbuser.id);
+                    Status::internal("Fail to set user role")
+                })?;
+                let posts = query_user_post_ids(conn, dbuser.id).map_err(|e| {
+                    error!("Fail to query posts of user {}: {e}", dbuser.id);
+                    Status::internal("Fail to set user role")
+                })?;
+
+                Ok(Response::new(SetUserRoleResponse {
+                    success: true,
+                    user: Some(dbuser.into_proto_user(icon, iaaa_profile, posts)),
+                }))
+            })
+            .await
+    }
+
+    async fn list_users_by_role(
+        &self,
+        request: Request<ListUsersByRoleRequest>,
+    ) -> Result<Response<ListUsersByRoleResponse>, Status> {
+        let user = current_user(&request)?;
+        ensure_role(&user, UserRole::Admin)?;
+        let req = request.into_inner();
+        trace!("ListUsersByRole got request: {req:#?}");
+
+        let role =
+            UserRole::try_from(req.role).map_err(|_| Status::invalid_argument("Invalid role"))?;
+
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let dbusers = query_users_by_role(conn, models::UserRole::from_proto_type(role))
+                    .map_err(|e| {
+                        error!("Fail to query users by role {role:?}: {e}");
+                        Status::internal("Fail to list users")
+                    })?;
+
+                let mut users = Vec::with_capacity(dbusers.len());
+                for dbuser in dbusers {
+                    let icon = query_image_by_id(dbuser.icon).map_err(|e| {
+                        error!("Fail to query image by id {} :{e}", dbuser.icon);
+                        Status::internal("Fail to list users")
+                    })?;
+                    let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(|e| {
+                        error!("Fail to query IAAA profile of user {}: {e}", dbuser.id);
+                        Status::internal("Fail to list users")
+                    })?;
+                    let posts = query_user_post_ids(conn, dbuser.id).map_err(|e| {
+                        error!("Fail to query posts of user {}: {e}", dbuser.id);
+                        Status::internal("Fail to list users")
+                    })?;
+                    users.push(dbuser.into_proto_user(icon, iaaa_profile, posts));
+                }
+
+                Ok(Response::new(ListUsersByRoleResponse {
+                    success: true,
+                    users,
+                }))
+            })
+            .await
+    }
+
+    async fn reset_totp(
+        &self,
+        request: Request<ResetTotpRequest>,
+    ) -> Result<Response<ResetTotpResponse>, Status> {
+        let user = current_user(&request)?;
+        ensure_role(&user, UserRole::Admin)?;
+        let req = request.into_inner();
+        trace!("ResetTotp got request: {req:#?}");
+
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let deleted = delete_totp(conn, req.user_id).map_err(|e| {
+                    error!("Fail to delete TOTP of user {}: {e}", req.user_id);
+                    Status::internal("Fail to reset two-factor authentication")
+                })?;
+                if !deleted {
+                    return Err(Status::not_found("User without two-factor authentication"));
+                }
+                trace!(
+                    "User {} reset two-factor authentication of user {}",
+                    user.user_id,
+                    req.user_id
+                );
+
+                Ok(Response::new(ResetTotpResponse { success: true }))
+            })
+            .await
+    }
+}
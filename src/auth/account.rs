@@ -0,0 +1,396 @@
+//! Leaving the platform: exporting one's data, and deleting the account.
+//!
+//! Deletion waits for a grace period, during which the user can still login
+//! and cancel it. Once it is over, the account is purged:
+//! - its posts are deleted, with their comments and images,
+//! - its comments on the posts of others are deleted,
+//! - its likes, favorites and participations are withdrawn, and the counters
+//!   of the posts follow,
+//! - other users no longer hold the deleted posts among their likes,
+//!   favorites and participations,
+//! - its sessions, identities, tokens and login attempts go with it.
+use chrono::{Duration, NaiveDateTime, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, info, trace, warn};
+use serde_json::json;
+use std::error::Error as StdError;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::Status;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::clock::Clock;
+use crate::codegen::auth::ExportMyDataResponse;
+use crate::db::models;
+use crate::db::{cancel_user_deletion, delete_image, get_user_by_id, purge_user};
+use crate::db::{query_iaaa_profile, query_identities, query_image_by_id, query_user_comments};
+use crate::db::{query_nickname_history, query_user_post_ids, query_user_posts};
+use crate::db::{query_user_liked_comment_ids, query_user_profile};
+use crate::db::{query_user_sessions, query_users_to_delete, schedule_user_deletion, DBClient};
+use crate::middleware::AuthenticatedUser;
+
+/// How long a deleted account can still be recovered.
+pub const DELETION_GRACE_PERIOD: Duration = Duration::days(14);
+/// How often to look for accounts to purge.
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+/// Size of the chunks the export archive is streamed in.
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks of the export archive waiting for the client.
+const EXPORT_CHANNEL_SIZE: usize = 4;
+
+pub type ExportStream = Pin<Box<dyn Stream<Item = Result<ExportMyDataResponse, Status>> + Send>>;
+
+fn timestamp(time: NaiveDateTime) -> i64 {
+    time.and_utc().timestamp()
+}
+
+/// Path of an image in the archive.
+fn image_path(image_id: i32) -> String {
+    format!("images/{}", image_id as u32)
+}
+
+fn export_post(post: &models::Post) -> serde_json::Value {
+    json!({
+        "id": post.id,
+        "post_type": format!("{:?}", post.post_type),
+        "title": post.title,
+        "content": post.content,
+        "contact": post.contact,
+        "likes": post.likes,
+        "favorites": post.favorates,
+        "created_at": timestamp(post.created_at),
+        "updated_at": post.updated_at.map(timestamp),
+        "images": post.images.0.iter().flatten().map(|id| image_path(*id)).collect::<Vec<_>>(),
+        "food_place": post.food_place.as_ref().map(|place| format!("{place:?}")),
+        "score": post.score,
+        "people_all": post.people_all,
+        "people_already": post.people_already,
+        "game_type": post.game_type.as_ref().map(|game| format!("{game:?}")),
+        "start_time": post.start_time.map(timestamp),
+        "amuse_place": post.amuse_place,
+        "price": post.price,
+        "goods_type": post.goods_type.as_ref().map(|goods| format!("{goods:?}")),
+        "sold": post.sold,
+    })
+}
+
+/// Gather the data of a user for `data.json`, with the images to archive.
+fn collect_data(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<(serde_json::Value, Vec<i32>), Box<dyn StdError>> {
+    let dbuser = get_user_by_id(conn, the_user_id)?;
+    let iaaa_profile = query_iaaa_profile(conn, the_user_id)?;
+    let identities = query_identities(conn, the_user_id)?;
+    let nickname_history = query_nickname_history(conn, the_user_id)?;
+    let profile = query_user_profile(conn, the_user_id)?;
+    let sessions = query_user_sessions(conn, the_user_id)?;
+    let posts = query_user_posts(conn, the_user_id)?;
+    let comments = query_user_comments(conn, the_user_id)?;
+    let post_ids = query_user_post_ids(conn, the_user_id)?;
+    let liked_comment_ids = query_user_liked_comment_ids(conn, the_user_id)?;
+
+    let mut image_ids = vec![dbuser.icon];
+    image_ids.extend(posts.iter().flat_map(|post| post.images.0.iter().flatten()));
+
+    let data = json!({
+        "user": {
+            "id": dbuser.id,
+            "username": dbuser.username,
+            "email": dbuser.email,
+            "email_verified": dbuser.is_email_verified(),
+            "nickname": dbuser.nickname,
+            "role": format!("{:?}", dbuser.role),
+            "icon": image_path(dbuser.icon),
+            "created_at": timestamp(dbuser.created_at),
+            "updated_at": dbuser.updated_at.map(timestamp),
+            "delete_after": dbuser.delete_after.map(timestamp),
+        },
+        "iaaa_profile": iaaa_profile.map(|profile| json!({
+            "dept_id": profile.dept_id,
+            "dept": profile.dept,
+            "campus": profile.campus,
+            "identity_type": profile.identity_type,
+            "detail_type": profile.detail_type,
+            "identity_status": profile.identity_status,
+            "graduated": profile.graduated,
+            "updated_at": timestamp(profile.updated_at),
+        })),
+        "profile": profile.map(|profile| json!({
+            "bio": profile.bio,
+            "pronouns": profile.pronouns,
+            "contact_method": profile.contact_method.map(|method| format!("{method:?}")),
+            "contact": profile.contact,
+            "links": profile.links.into_iter().flatten().collect::<Vec<_>>(),
+            "privacy": {
+                "favorites": format!("{:?}", profile.favorites_visibility),
+                "likes": format!("{:?}", profile.likes_visibility),
+                "participations": format!("{:?}", profile.participations_visibility),
+                "contact": format!("{:?}", profile.contact_visibility),
+            },
+            "updated_at": timestamp(profile.updated_at),
+        })),
+        "nickname_history": nickname_history.iter().map(|past| json!({
+            "nickname": past.nickname,
+            "changed_at": timestamp(past.changed_at),
+        })).collect::<Vec<_>>(),
+        "identities": identities.iter().map(|identity| json!({
+            "provider": format!("{:?}", identity.provider),
+            "subject": identity.subject,
+            "created_at": timestamp(identity.created_at),
+        })).collect::<Vec<_>>(),
+        "sessions": sessions.iter().map(|session| json!({
+            "device_name": session.device_name,
+            "ip_address": session.ip_address,
+            "reported_ip_address": session.reported_ip_address,
+            "user_agent": session.user_agent,
+            "created_at": timestamp(session.created_at),
+            "last_seen_at": timestamp(session.last_seen_at),
+        })).collect::<Vec<_>>(),
+        "posts": posts.iter().map(export_post).collect::<Vec<_>>(),
+        "comments": comments.iter().map(|comment| json!({
+            "id": comment.id,
+            "post_id": comment.post_id,
+            "content": comment.content,
+            "likes": comment.likes,
+            "created_at": timestamp(comment.created_at),
+            "updated_at": comment.updated_at.map(timestamp),
+        })).collect::<Vec<_>>(),
+        "liked_posts": post_ids.liked,
+        "favorite_posts": post_ids.favorite,
+        "take_part_posts": post_ids.take_part,
+        "liked_comments": liked_comment_ids,
+    });
+    Ok((data, image_ids))
+}
+
+/// Write the ZIP archive, `data.json` and the images under `images/`.
+/// Returns the size of the archive.
+fn write_archive(
+    writer: ExportWriter,
+    data: &serde_json::Value,
+    image_ids: Vec<i32>,
+    the_user_id: i32,
+) -> Result<u64, Box<dyn StdError>> {
+    let mut archive = ZipWriter::new(writer);
+    // each file is sent once written, the writer only keeps the current one
+    archive.set_flush_on_finish_file(true);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    archive.start_file("data.json", deflated)?;
+    archive.write_all(&serde_json::to_vec_pretty(data)?)?;
+    // images are compressed already
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    for image_id in image_ids {
+        let image = match query_image_by_id(image_id) {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("Image {image_id} of user {the_user_id} is missing: {e}");
+                continue;
+            }
+        };
+        archive.start_file(image_path(image_id), stored)?;
+        archive.write_all(&image)?;
+    }
+    let mut writer = archive.finish()?;
+    writer.flush()?;
+    Ok(writer.sent)
+}
+
+/// Sends the archive to the export stream as it is written.
+///
+/// The archive seeks back to the header of a file once the file is written,
+/// so the bytes of the current file are kept until the archive flushes them
+/// when the file is done.
+struct ExportWriter {
+    sender: mpsc::Sender<Result<ExportMyDataResponse, Status>>,
+    /// Bytes already sent, before `buffer`.
+    sent: u64,
+    buffer: Vec<u8>,
+    position: u64,
+}
+
+impl ExportWriter {
+    fn new(sender: mpsc::Sender<Result<ExportMyDataResponse, Status>>) -> Self {
+        Self {
+            sender,
+            sent: 0,
+            buffer: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl Write for ExportWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let start = (self.position - self.sent) as usize;
+        let end = start + buf.len();
+        if end > self.buffer.len() {
+            self.buffer.resize(end, 0);
+        }
+        self.buffer[start..end].copy_from_slice(buf);
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for chunk in self.buffer.chunks(EXPORT_CHUNK_SIZE) {
+            let response = ExportMyDataResponse {
+                chunk: chunk.to_vec(),
+            };
+            // the client went away
+            self.sender
+                .blocking_send(Ok(response))
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        self.sent += self.buffer.len() as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+// only for copying files within the archive, which the export does not do,
+// but flushing after each file asks for it
+impl Read for ExportWriter {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+impl Seek for ExportWriter {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let end = self.sent + self.buffer.len() as u64;
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => end.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) if (self.sent..=end).contains(&position) => {
+                self.position = position;
+                Ok(position)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "cannot seek out of the file being written",
+            )),
+        }
+    }
+}
+
+/// Read the data of the user, then stream the archive from a blocking
+/// thread as it is written, one file at a time.
+#[allow(clippy::result_large_err)]
+pub(super) async fn export_my_data(
+    client: &DBClient,
+    user: AuthenticatedUser,
+) -> Result<ExportStream, Status> {
+    let the_user_id = user.user_id;
+    let (data, image_ids) = client
+        .run(move |conn| {
+            collect_data(conn, the_user_id).map_err(|e| {
+                error!("Fail to export data of user {the_user_id}: {e}");
+                Status::internal("Fail to export data")
+            })
+        })
+        .await?;
+
+    let (sender, receiver) = mpsc::channel(EXPORT_CHANNEL_SIZE);
+    tokio::task::spawn_blocking(move || {
+        let writer = ExportWriter::new(sender.clone());
+        match write_archive(writer, &data, image_ids, the_user_id) {
+            Ok(size) => trace!("Exported {size} bytes of data of user {the_user_id}"),
+            Err(e) => {
+                error!("Fail to export data of user {the_user_id}: {e}");
+                let _ = sender.blocking_send(Err(Status::internal("Fail to export data")));
+            }
+        }
+    });
+    Ok(Box::pin(ReceiverStream::new(receiver)))
+}
+
+/// Returns when the account is to be deleted.
+#[allow(clippy::result_large_err)]
+pub(super) fn delete_account(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+) -> Result<NaiveDateTime, Status> {
+    let delete_after = Utc::now().naive_utc() + DELETION_GRACE_PERIOD;
+    let delete_after = schedule_user_deletion(conn, user.user_id, delete_after).map_err(|e| {
+        error!("Fail to schedule deletion of user {}: {e}", user.user_id);
+        Status::internal("Fail to delete account")
+    })?;
+    info!(
+        "User {} is to be deleted after {delete_after}",
+        user.user_id
+    );
+    Ok(delete_after)
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn cancel_account_deletion(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+) -> Result<(), Status> {
+    let cancelled = cancel_user_deletion(conn, user.user_id).map_err(|e| {
+        error!("Fail to cancel deletion of user {}: {e}", user.user_id);
+        Status::internal("Fail to cancel account deletion")
+    })?;
+    if !cancelled {
+        return Err(Status::failed_precondition("Account is not to be deleted"));
+    }
+    info!(
+        "User {} cancelled the deletion of the account",
+        user.user_id
+    );
+    Ok(())
+}
+
+/// Purge the accounts to delete by `now`, returns how many were.
+pub fn purge_deleted_accounts(
+    client: &DBClient,
+    now: NaiveDateTime,
+) -> Result<usize, Box<dyn StdError>> {
+    let conn = &mut client.get_conn()?;
+    let mut purged = 0;
+    for user_id in query_users_to_delete(conn, now)? {
+        let images = match purge_user(conn, user_id, now) {
+            Ok(Some(images)) => images,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("Fail to delete user {user_id}: {e}");
+                continue;
+            }
+        };
+        info!("User {user_id} deleted");
+        purged += 1;
+        for image_id in images {
+            if let Err(e) = delete_image(image_id) {
+                warn!("Fail to delete image {image_id} of user {user_id}: {e}");
+            }
+        }
+    }
+    Ok(purged)
+}
+
+/// Purge the accounts whose grace period is over, from time to time.
+pub async fn run_account_purger(client: DBClient, clock: Arc<dyn Clock>) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let (client, now) = (client.clone(), clock.now());
+        let purge = tokio::task::spawn_blocking(move || {
+            if let Err(e) = purge_deleted_accounts(&client, now) {
+                error!("Fail to purge deleted accounts: {e}");
+            }
+        });
+        if let Err(e) = purge.await {
+            error!("Fail to run account purger: {e}");
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Password hashing.
+//!
+//! Hashes are stored as PHC strings (`$argon2id$v=19$m=...$salt$hash`), which
+//! name their algorithm and parameters, so they can be checked after the
+//! defaults change. New passwords are hashed with Argon2id. Bcrypt hashes of
+//! older accounts are still accepted, and replaced on the next login.
+//!
+//! Hashing is slow on purpose, [`hash_password`] and [`verify_password`] run
+//! it on the blocking thread pool of Tokio.
+use std::error::Error as StdError;
+use std::sync::Arc;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use log::{error, warn};
+use tonic::Status;
+
+/// Hash of a password nobody has, with the default Argon2id parameters.
+/// Checked instead when the user does not exist, so that the response takes
+/// as long either way.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$KJbdBy8T97KW0IRgoHXC0g$ACmOf2fmOUZTHTQPlJR3EpJAqiHhWpG5x+/0pIRXGk4";
+
+/// Outcome of checking a password against a stored hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordMatch {
+    Mismatch,
+    Match,
+    /// The password matches, but the hash uses an older algorithm or
+    /// parameters and should be replaced.
+    Outdated,
+}
+
+impl PasswordMatch {
+    pub fn is_match(self) -> bool {
+        self != Self::Mismatch
+    }
+}
+
+pub trait PasswordHasher: Send + Sync + 'static {
+    /// Hash a new password into a PHC string.
+    fn hash(&self, password: &str) -> Result<String, Box<dyn StdError + Send + Sync>>;
+    /// Check `password` against a stored hash.
+    fn verify(&self, password: &str, hash: &str) -> PasswordMatch;
+}
+
+/// Hashes with Argon2id, verifies Argon2 and bcrypt hashes.
+#[derive(Clone)]
+pub struct Argon2Hasher {
+    argon2: Argon2<'static>,
+}
+
+impl Default for Argon2Hasher {
+    /// The parameters recommended by OWASP, also the defaults of `argon2`.
+    fn default() -> Self {
+        Self::new(Params::default())
+    }
+}
+
+impl Argon2Hasher {
+    pub fn new(params: Params) -> Self {
+        Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        }
+    }
+
+    fn verify_argon2(&self, password: &str, hash: &str) -> PasswordMatch {
+        let parsed = match PasswordHash::new(hash) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                warn!("Malformed password hash: {e}");
+                return PasswordMatch::Mismatch;
+            }
+        };
+        if self
+            .argon2
+            .verify_password(password.as_bytes(), &parsed)
+            .is_err()
+        {
+            return PasswordMatch::Mismatch;
+        }
+        let wanted = self.argon2.params();
+        let current = parsed.algorithm == Algorithm::Argon2id.ident()
+            && parsed.version == Some(Version::V0x13.into())
+            && Params::try_from(&parsed).is_ok_and(|params| {
+                params.m_cost() == wanted.m_cost()
+                    && params.t_cost() == wanted.t_cost()
+                    && params.p_cost() == wanted.p_cost()
+            });
+        if current {
+            PasswordMatch::Match
+        } else {
+            PasswordMatch::Outdated
+        }
+    }
+}
+
+impl PasswordHasher for Argon2Hasher {
+    fn hash(&self, password: &str) -> Result<String, Box<dyn StdError + Send + Sync>> {
+        let salt = SaltString::generate(&mut rand::rngs::OsRng);
+        let hash = self.argon2.hash_password(password.as_bytes(), &salt)?;
+        Ok(hash.to_string())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> PasswordMatch {
+        if hash.starts_with("$argon2") {
+            self.verify_argon2(password, hash)
+        } else if hash.starts_with("$2") {
+            // bcrypt, from before Argon2
+            match bcrypt::verify(password, hash) {
+                Ok(true) => PasswordMatch::Outdated,
+                Ok(false) => PasswordMatch::Mismatch,
+                Err(e) => {
+                    warn!("Malformed bcrypt hash: {e}");
+                    PasswordMatch::Mismatch
+                }
+            }
+        } else {
+            warn!("Password hash of unknown algorithm");
+            PasswordMatch::Mismatch
+        }
+    }
+}
+
+/// Hash a new password off the async runtime.
+pub async fn hash_password(
+    hasher: &Arc<dyn PasswordHasher>,
+    password: String,
+) -> Result<String, Status> {
+    let hasher = hasher.clone();
+    tokio::task::spawn_blocking(move || hasher.hash(&password))
+        .await
+        .map_err(|e| {
+            error!("Password hashing task failed: {e}");
+            Status::internal("Fail to hash password")
+        })?
+        .map_err(|e| {
+            error!("Fail to hash password: {e}");
+            Status::internal("Fail to hash password")
+        })
+}
+
+/// Check a password off the async runtime. Without a stored `hash`, a dummy
+/// one is checked, and the password never matches.
+pub async fn verify_password(
+    hasher: &Arc<dyn PasswordHasher>,
+    password: String,
+    hash: Option<String>,
+) -> Result<PasswordMatch, Status> {
+    let hasher = hasher.clone();
+    tokio::task::spawn_blocking(move || match hash {
+        Some(hash) => hasher.verify(&password, &hash),
+        None => {
+            hasher.verify(&password, DUMMY_PASSWORD_HASH);
+            PasswordMatch::Mismatch
+        }
+    })
+    .await
+    .map_err(|e| {
+        error!("Password verifying task failed: {e}");
+        Status::internal("Fail to verify password")
+    })
+}
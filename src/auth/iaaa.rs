@@ -1,17 +1,31 @@
+//! Login through IAAA, the unified identity authentication of PKU.
+//!
+//! The client logs in at IAAA and sends us the token it got, which we check
+//! with the validation endpoint of IAAA through an [`IdentityValidator`].
+pub mod mock;
+
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::PgConnection;
+use log::{error, trace, warn};
 use serde::{Deserialize, Serialize};
 use std::error::Error as StdError;
+use std::time::Duration;
 use tonic::Status;
 
-use crate::db::query_image_by_id;
-use crate::middleware::issue_token;
+use super::session::{start_session, DeviceInfo};
+use super::totp::{start_challenge, two_factor_enabled};
+use crate::db::models::IaaaProfile;
+use crate::db::{query_image_by_id, query_user_post_ids, upsert_iaaa_profile, DBClient};
 use crate::{codegen::auth::LoginResponse, db::get_iaaa_user_from_db};
 
 // IAAA logic
-const VALIDATE_ENDPOINT: &'static str = "https://iaaa.pku.edu.cn/iaaa/svc/token/validate.do";
+pub const VALIDATE_ENDPOINT: &str = "https://iaaa.pku.edu.cn/iaaa/svc/token/validate.do";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_RETRIES: u32 = 2;
+/// `identityStatus` of enrolled members of the university.
+const ENROLLED_STATUS: &str = "在校";
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Hash)]
 pub struct IAAAUserInfo {
     // example 'Tom'
     #[serde(rename = "name")]
@@ -54,116 +68,237 @@ pub struct IAAAUserInfo {
 pub struct IAAAValidateResponse {
     #[serde(rename = "success")]
     success: bool,
-    #[serde(rename = "errCode")]
+    #[serde(rename = "errCode", default)]
     err_code: String,
-    #[serde(rename = "errMsg")]
+    #[serde(rename = "errMsg", default)]
     err_msg: String,
-    #[serde(rename = "userInfo")]
+    // absent when validation fails
+    #[serde(rename = "userInfo", default)]
     pub user_info: IAAAUserInfo,
 }
 
+impl IAAAUserInfo {
+    /// The attributes to store for the user `user_id`.
+    pub fn to_profile(&self, user_id: i32) -> IaaaProfile {
+        IaaaProfile {
+            user_id,
+            dept_id: self.dept_id.clone(),
+            dept: self.dept.clone(),
+            campus: self.campus.clone(),
+            identity_type: self.identity_type.clone(),
+            detail_type: self.detail_type.clone(),
+            identity_status: self.identity_status.clone(),
+            graduated: self.identity_status != ENROLLED_STATUS,
+            updated_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+}
+
 impl IAAAValidateResponse {
     pub fn is_success(&self) -> bool {
         self.success
     }
 }
 
-pub(super) async fn login_iaaa(
-    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-    ip_address: &String,
-    iaaa_id: &String,
-    iaaa_key: &String,
-    token: &String,
-) -> Result<LoginResponse, Status> {
-    let resp = if std::env::var("TEST")
-        .and_then(|x| Ok(x.to_ascii_lowercase()))
-        .is_ok_and(|x| x.eq("true"))
-    {
-        example_iaaa_validate_response()
-    } else {
-        validate(ip_address, iaaa_id, iaaa_key, token)
-            .await
-            .map_err(|_| Status::unauthenticated("Fail to validate"))?
-    };
+/// Checks IAAA tokens.
+#[tonic::async_trait]
+pub trait IdentityValidator: Send + Sync + 'static {
+    /// Validate the `token` a user got from IAAA, logging in from
+    /// `remote_addr`.
+    async fn validate(
+        &self,
+        remote_addr: &str,
+        token: &str,
+    ) -> Result<IAAAValidateResponse, Box<dyn StdError + Send + Sync>>;
+}
+
+/// Validates tokens with the IAAA validation endpoint.
+#[derive(Debug, Clone)]
+pub struct IaaaValidator {
+    http: reqwest::Client,
+    endpoint: String,
+    app_id: String,
+    app_key: String,
+    retries: u32,
+}
+
+impl IaaaValidator {
+    pub fn new(endpoint: impl Into<String>, app_id: String, app_key: String) -> Self {
+        Self {
+            http: http_client(DEFAULT_TIMEOUT),
+            endpoint: endpoint.into(),
+            app_id,
+            app_key,
+            retries: DEFAULT_RETRIES,
+        }
+    }
+
+    /// Configure from `IAAA_ID`, `IAAA_KEY`, and optionally `IAAA_ENDPOINT`,
+    /// `IAAA_TIMEOUT` (in seconds) and `IAAA_RETRIES`.
+    pub fn from_env() -> Self {
+        let app_id = std::env::var("IAAA_ID").expect("Must set IAAA_ID");
+        let app_key = std::env::var("IAAA_KEY").expect("Must set IAAA_KEY");
+        let endpoint =
+            std::env::var("IAAA_ENDPOINT").unwrap_or_else(|_| VALIDATE_ENDPOINT.to_string());
+        let mut validator = Self::new(endpoint, app_id, app_key);
+        if let Ok(timeout) = std::env::var("IAAA_TIMEOUT") {
+            let timeout = timeout
+                .parse()
+                .expect("IAAA_TIMEOUT must be set to a positive integer");
+            validator = validator.with_timeout(Duration::from_secs(timeout));
+        }
+        if let Ok(retries) = std::env::var("IAAA_RETRIES") {
+            let retries = retries
+                .parse()
+                .expect("IAAA_RETRIES must be set to a non-negative integer");
+            validator = validator.with_retries(retries);
+        }
+        validator
+    }
+
+    /// Timeout of each request to IAAA.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http = http_client(timeout);
+        self
+    }
+
+    /// Times to retry after a timeout, a connection failure or a server
+    /// error of IAAA.
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    async fn request(
+        &self,
+        remote_addr: &str,
+        token: &str,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let payload = format!(
+            "appId={}&remoteAddr={remote_addr}&token={token}",
+            self.app_id
+        );
+        let sign = md5_hash(&(payload + &self.app_key));
+        self.http
+            .get(&self.endpoint)
+            .query(&[
+                ("appId", self.app_id.as_str()),
+                ("remoteAddr", remote_addr),
+                ("token", token),
+                ("msgAbs", sign.as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()
+    }
+}
+
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("Fail to build HTTP client")
+}
+
+#[tonic::async_trait]
+impl IdentityValidator for IaaaValidator {
+    async fn validate(
+        &self,
+        remote_addr: &str,
+        token: &str,
+    ) -> Result<IAAAValidateResponse, Box<dyn StdError + Send + Sync>> {
+        let mut attempt = 0;
+        let response = loop {
+            match self.request(remote_addr, token).await {
+                Ok(response) => break response,
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    attempt += 1;
+                    warn!("IAAA request failed, retry {attempt}: {e}");
+                    tokio::time::sleep(Duration::from_millis(100 << attempt)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        Ok(response.json::<IAAAValidateResponse>().await?)
+    }
+}
+
+fn is_transient(e: &reqwest::Error) -> bool {
+    e.is_timeout() || e.is_connect() || e.status().is_some_and(|s| s.is_server_error())
+}
+
+/// Check the `token` a user got from IAAA, returning who it was issued to.
+pub(super) async fn validate_token(
+    validator: &dyn IdentityValidator,
+    ip_address: &str,
+    token: &str,
+) -> Result<IAAAUserInfo, Status> {
+    let resp = validator.validate(ip_address, token).await.map_err(|e| {
+        error!("Fail to validate IAAA token: {e}");
+        Status::unavailable("Fail to validate")
+    })?;
 
     if !resp.is_success() {
+        trace!("IAAA rejected token: {} {}", resp.err_code, resp.err_msg);
         return Err(Status::unauthenticated("Fail to authorize"));
     }
+    Ok(resp.user_info)
+}
 
-    let dbuser = get_iaaa_user_from_db(conn, resp)
-        .map_err(|_| Status::unauthenticated("Fail to find user or auto-register for IAAA user"))?;
+#[allow(clippy::result_large_err)]
+pub(super) async fn login_iaaa(
+    client: &DBClient,
+    validator: &dyn IdentityValidator,
+    ip_address: &str,
+    token: &str,
+    device: DeviceInfo,
+) -> Result<LoginResponse, Status> {
+    let user_info = validate_token(validator, ip_address, token).await?;
+    client
+        .run(move |conn| issue_login(conn, user_info, device))
+        .await
+}
 
-    let created_at: i64 = dbuser.created_at.and_utc().timestamp();
-    let updated_at: Option<i64> = dbuser
-        .updated_at
-        .and_then(|x| Some(x.and_utc().timestamp()));
+/// Answer a login of the user IAAA vouched for, registering it if new.
+#[allow(clippy::result_large_err)]
+fn issue_login(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user_info: IAAAUserInfo,
+    device: DeviceInfo,
+) -> Result<LoginResponse, Status> {
+    let dbuser = get_iaaa_user_from_db(conn, &user_info)
+        .map_err(|_| Status::unauthenticated("Fail to find user or auto-register for IAAA user"))?;
+    let profile = upsert_iaaa_profile(conn, &user_info.to_profile(dbuser.id)).map_err(|e| {
+        error!("Fail to update IAAA profile of user {}: {e}", dbuser.id);
+        Status::internal("Fail to update IAAA profile")
+    })?;
+    if profile.graduated {
+        trace!("User {} is no longer enrolled", dbuser.id);
+    }
+    if two_factor_enabled(conn, dbuser.id)? {
+        return start_challenge(conn, &dbuser);
+    }
 
-    let token = issue_token(
-        &dbuser.id.to_string(),
-        dbuser.email.as_ref().unwrap_or(&"".to_string()),
-    )
-    .map_err(|_| Status::unauthenticated("Fail to assign token"))?;
+    let tokens = start_session(conn, &dbuser, device)?;
 
     let icon =
         query_image_by_id(dbuser.icon).map_err(|_| Status::internal("Fail to get user icon"))?;
+    let posts = query_user_post_ids(conn, dbuser.id).map_err(|e| {
+        error!("Fail to query posts of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
+    })?;
 
-    use crate::codegen::auth::User;
     let response = LoginResponse {
         success: true,
-        user: Some(User {
-            id: dbuser.id,
-            username: dbuser.username,
-            email: dbuser.email,
-            login_provider: dbuser.login_provider as i32,
-            nickname: dbuser.nickname,
-            created_at,
-            updated_at,
-            icon,
-            favorite_posts: dbuser.favorite_posts.to_vec_i32(),
-            liked_posts: dbuser.liked_posts.to_vec_i32(),
-            take_part_posts: dbuser.take_part_posts.to_vec_i32(),
-        }),
-        token,
+        user: Some(dbuser.into_proto_user(icon, Some(profile), posts)),
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        two_factor_challenge: None,
     };
     Ok(response)
 }
 
-pub async fn validate(
-    remote_addr: &String,
-    app_id: &String,
-    app_key: &String,
-    token: &String,
-) -> Result<IAAAValidateResponse, Box<dyn StdError>> {
-    let payload = format!("appId={app_id}&remoteAddr={remote_addr}&token={token}");
-    let sign = md5_hash(&(payload.clone() + &app_key));
-    let url = format!("{VALIDATE_ENDPOINT}?{payload}&msgAbs={sign}");
-    let data = reqwest::get(url)
-        .await?
-        .json::<IAAAValidateResponse>()
-        .await?;
-    return Ok(data);
-}
-
-fn md5_hash(msg: &String) -> String {
+fn md5_hash(msg: &str) -> String {
     let digest = md5::compute(msg);
     format!("{:x}", digest)
 }
-
-fn example_iaaa_validate_response() -> IAAAValidateResponse {
-    IAAAValidateResponse {
-        success: true,
-        err_code: "".into(),
-        err_msg: "".into(),
-        user_info: IAAAUserInfo {
-            name: "Tom".into(),
-            status: "Present".into(),
-            identity_id: "2200088888".into(),
-            dept_id: "example".into(),
-            dept: "example".into(),
-            identity_type: "example".into(),
-            detail_type: "example".into(),
-            identity_status: "example".into(),
-            campus: "pku".into(),
-        },
-    }
-}
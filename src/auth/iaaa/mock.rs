@@ -0,0 +1,165 @@
+//! A stand-in for the IAAA validation endpoint, for development and tests.
+//!
+//! The answer depends on the token:
+//! - `fail`: a validation failure;
+//! - `malformed`: a body that is not JSON;
+//! - `error`: an HTTP 500;
+//! - `slow`: a success, after [`MockIaaa::with_delay`];
+//! - `graduated`: a success, for [`GRADUATED_IDENTITY_ID`] who has left;
+//! - a string of digits: a success, for the user with that identity id;
+//! - anything else: a success, for [`MOCK_IDENTITY_ID`].
+//!
+//! Requests with a wrong `appId` or `msgAbs` get a validation failure.
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{error, trace};
+use tokio::net::TcpListener;
+
+use super::{md5_hash, IAAAUserInfo, IAAAValidateResponse};
+
+pub const MOCK_IDENTITY_ID: &str = "2200088888";
+pub const GRADUATED_IDENTITY_ID: &str = "1800011111";
+pub const MOCK_DEPT_ID: &str = "00048";
+
+#[derive(Debug)]
+pub struct MockIaaa {
+    app_id: String,
+    app_key: String,
+    delay: Duration,
+    requests: AtomicUsize,
+}
+
+impl MockIaaa {
+    /// A mock accepting requests signed as `app_id` with `app_key`.
+    pub fn new(app_id: impl Into<String>, app_key: impl Into<String>) -> Self {
+        Self {
+            app_id: app_id.into(),
+            app_key: app_key.into(),
+            delay: Duration::from_secs(10),
+            requests: AtomicUsize::new(0),
+        }
+    }
+
+    /// How long to answer the token `slow`.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Number of requests received so far.
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+
+    /// Serve requests from `listener` until an accept fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let mock = self.clone();
+            tokio::spawn(async move {
+                let service = service_fn(|request| mock.clone().handle(request));
+                if let Err(e) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    error!("Mock IAAA connection failed: {e}");
+                }
+            });
+        }
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        request: Request<Incoming>,
+    ) -> Result<Response<String>, Infallible> {
+        self.requests.fetch_add(1, Ordering::SeqCst);
+        let query: HashMap<String, String> =
+            url::form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect();
+        let param = |name: &str| query.get(name).map(String::as_str).unwrap_or("");
+        let token = param("token");
+        trace!("Mock IAAA got token {token}");
+
+        let payload = format!(
+            "appId={}&remoteAddr={}&token={token}",
+            param("appId"),
+            param("remoteAddr")
+        );
+        if param("appId") != self.app_id || param("msgAbs") != md5_hash(&(payload + &self.app_key))
+        {
+            return Ok(json(failure("E02", "Invalid signature")));
+        }
+
+        let response = match token {
+            "fail" => json(failure("E01", "Invalid token")),
+            "malformed" => Response::new("{\"success\": tr".to_string()),
+            "error" => {
+                let mut response = Response::new("Internal Server Error".to_string());
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                response
+            }
+            "graduated" => {
+                let mut response = success(GRADUATED_IDENTITY_ID);
+                response.user_info.identity_status = "毕业".into();
+                json(response)
+            }
+            "slow" => {
+                tokio::time::sleep(self.delay).await;
+                json(success(MOCK_IDENTITY_ID))
+            }
+            token if !token.is_empty() && token.chars().all(|c| c.is_ascii_digit()) => {
+                json(success(token))
+            }
+            _ => json(success(MOCK_IDENTITY_ID)),
+        };
+        Ok(response)
+    }
+}
+
+fn json(response: IAAAValidateResponse) -> Response<String> {
+    let body = serde_json::to_string(&response).expect("serializable response");
+    let mut response = Response::new(body);
+    response.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn failure(err_code: &str, err_msg: &str) -> IAAAValidateResponse {
+    IAAAValidateResponse {
+        success: false,
+        err_code: err_code.into(),
+        err_msg: err_msg.into(),
+        user_info: IAAAUserInfo::default(),
+    }
+}
+
+fn success(identity_id: &str) -> IAAAValidateResponse {
+    IAAAValidateResponse {
+        success: true,
+        err_code: "".into(),
+        err_msg: "".into(),
+        user_info: IAAAUserInfo {
+            name: "Tom".into(),
+            status: "Present".into(),
+            identity_id: identity_id.into(),
+            dept_id: MOCK_DEPT_ID.into(),
+            dept: "信息科学技术学院".into(),
+            identity_type: "学生".into(),
+            detail_type: "本专科学生".into(),
+            identity_status: "在校".into(),
+            campus: "燕园".into(),
+        },
+    }
+}
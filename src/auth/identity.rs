@@ -0,0 +1,144 @@
+//! The ways to login to an account.
+//!
+//! An account has at most one identity of each provider, and logins resolve
+//! through them, so the same account is reached whichever way its user logs
+//! in. The password identity logs in with the username of the account.
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, trace};
+use std::error::Error as StdError;
+use std::sync::Arc;
+use tonic::Status;
+
+use super::hasher::PasswordHasher;
+use super::iaaa::{validate_token, IdentityValidator};
+use super::oidc::{redeem, OidcClient};
+use super::password::hash_new_password;
+use crate::codegen::auth::{Identity, LinkIdentityRequest, LoginProvider, UnlinkIdentityRequest};
+use crate::db::models;
+use crate::db::DBClient;
+use crate::db::{insert_identity, link_iaaa_identity, link_password_identity, query_identities};
+use crate::middleware::AuthenticatedUser;
+
+#[allow(clippy::result_large_err)]
+pub(super) fn list_identities(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+) -> Result<Vec<Identity>, Status> {
+    let identities = query_identities(conn, user.user_id).map_err(|e| {
+        error!("Fail to query identities of user {}: {e}", user.user_id);
+        Status::internal("Fail to get identities")
+    })?;
+    Ok(identities
+        .into_iter()
+        .map(models::Identity::into_proto_identity)
+        .collect())
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) async fn link_identity(
+    client: &DBClient,
+    validator: &dyn IdentityValidator,
+    oidc: Option<&OidcClient>,
+    hasher: &Arc<dyn PasswordHasher>,
+    user: AuthenticatedUser,
+    req: LinkIdentityRequest,
+) -> Result<Vec<Identity>, Status> {
+    let provider = LoginProvider::try_from(req.provider)
+        .map_err(|_| Status::invalid_argument("invalid login provider"))?;
+    let linked = client.run(move |conn| list_identities(conn, &user)).await?;
+    if linked
+        .iter()
+        .any(|identity| identity.provider == req.provider)
+    {
+        return Err(Status::failed_precondition("Identity already linked"));
+    }
+
+    let internal = move |e: Box<dyn StdError>| {
+        error!("Fail to link identity of user {}: {e}", user.user_id);
+        Status::internal("Fail to link identity")
+    };
+    let identity = match provider {
+        LoginProvider::Iaaa => {
+            if req.iaaa_token.is_empty() {
+                return Err(Status::invalid_argument("IAAA token cannot be empty!"));
+            }
+            let Some(ip_address) = req.ip_address else {
+                return Err(Status::invalid_argument("ip_address cannot be empty!"));
+            };
+            let user_info = validate_token(validator, &ip_address, &req.iaaa_token).await?;
+            let profile = user_info.to_profile(user.user_id);
+            client
+                .run(move |conn| {
+                    link_iaaa_identity(conn, &user_info.identity_id, &profile).map_err(internal)
+                })
+                .await?
+        }
+        LoginProvider::Password => {
+            let hashed_password = hash_new_password(hasher, req.password).await?;
+            client
+                .run(move |conn| {
+                    link_password_identity(conn, user.user_id, hashed_password).map_err(internal)
+                })
+                .await?
+        }
+        LoginProvider::Oidc => {
+            let Some(oidc) = oidc else {
+                return Err(Status::unimplemented("OIDC login is not configured"));
+            };
+            let claims = redeem(oidc, req.oidc).await?;
+            let new_identity = models::NewIdentity {
+                user_id: user.user_id,
+                provider: models::LoginProvider::OIDC,
+                subject: claims.sub,
+            };
+            client
+                .run(move |conn| insert_identity(conn, &new_identity).map_err(internal))
+                .await?
+        }
+    };
+    // the other constraint was checked above, barring a race
+    if identity.is_none() {
+        return Err(Status::already_exists(
+            "Identity belongs to another account",
+        ));
+    }
+    trace!("User {} linked a {provider:?} identity", user.user_id);
+
+    client.run(move |conn| list_identities(conn, &user)).await
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn unlink_identity(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+    req: UnlinkIdentityRequest,
+) -> Result<Vec<Identity>, Status> {
+    let provider = LoginProvider::try_from(req.provider)
+        .map_err(|_| Status::invalid_argument("invalid login provider"))?;
+    let linked = list_identities(conn, user)?;
+    if !linked
+        .iter()
+        .any(|identity| identity.provider == req.provider)
+    {
+        return Err(Status::not_found("Identity not linked"));
+    }
+
+    let unlinked = crate::db::unlink_identity(
+        conn,
+        user.user_id,
+        models::LoginProvider::from_proto_type(provider),
+    )
+    .map_err(|e| {
+        error!("Fail to unlink identity of user {}: {e}", user.user_id);
+        Status::internal("Fail to unlink identity")
+    })?;
+    if !unlinked {
+        return Err(Status::failed_precondition(
+            "Cannot unlink the last identity",
+        ));
+    }
+    trace!("User {} unlinked a {provider:?} identity", user.user_id);
+
+    list_identities(conn, user)
+}
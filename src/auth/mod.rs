@@ -1,38 +1,136 @@
 //! HokoPKU authentication module.
+pub mod account;
+pub mod hasher;
 pub mod iaaa;
+mod identity;
+pub mod nickname;
+pub mod oidc;
 pub mod password;
-
-use iaaa::login_iaaa;
+pub mod profile;
+mod session;
+pub mod throttle;
+pub mod totp;
+mod verification;
+
+use account::{cancel_account_deletion, delete_account, export_my_data, ExportStream};
+use hasher::PasswordHasher;
+use iaaa::{login_iaaa, IdentityValidator};
+use identity::{link_identity, list_identities, unlink_identity};
 use log::{error, trace};
-use password::{login_password, register_password};
+use nickname::{change_nickname, list_nickname_history};
+use oidc::{login_oidc, OidcClient, SCOPES};
+use password::{change_password, login_password, register_password};
+use password::{request_password_reset, reset_password};
+use profile::{get_user, update_privacy_settings, update_profile};
+use session::{list_sessions, revoke_session, DeviceInfo};
+use session::{refresh_session, session_of_refresh_token};
+use std::sync::Arc;
+use throttle::LoginThrottle;
 use tonic::{Request, Response, Status};
+use totp::{begin_totp_enrollment, confirm_totp, disable_totp, login_second_factor};
+use verification::{resend_verification, verify_email};
 
 use crate::codegen::auth::auth_server::Auth;
-use crate::codegen::auth::{ChangeIconRequest, ChangeIconResponse, User};
+use crate::codegen::auth::ExportMyDataRequest;
+use crate::codegen::auth::{BeginTotpEnrollmentRequest, BeginTotpEnrollmentResponse};
+use crate::codegen::auth::{CancelAccountDeletionRequest, CancelAccountDeletionResponse};
+use crate::codegen::auth::{ChangeIconRequest, ChangeIconResponse};
+use crate::codegen::auth::{ChangeNicknameRequest, ChangeNicknameResponse};
+use crate::codegen::auth::{ChangePasswordRequest, ChangePasswordResponse};
 use crate::codegen::auth::{ChangeUsernameRequest, ChangeUsernameResponse};
+use crate::codegen::auth::{ConfirmTotpRequest, ConfirmTotpResponse};
+use crate::codegen::auth::{DeleteAccountRequest, DeleteAccountResponse};
+use crate::codegen::auth::{DisableTotpRequest, DisableTotpResponse};
+use crate::codegen::auth::{GetOidcConfigRequest, GetOidcConfigResponse};
 use crate::codegen::auth::{GetUserRequest, GetUserResponse};
+use crate::codegen::auth::{LinkIdentityRequest, LinkIdentityResponse};
+use crate::codegen::auth::{ListIdentitiesRequest, ListIdentitiesResponse};
+use crate::codegen::auth::{ListNicknameHistoryRequest, ListNicknameHistoryResponse};
+use crate::codegen::auth::{ListSessionsRequest, ListSessionsResponse};
 use crate::codegen::auth::{LoginRequest, LoginResponse};
+use crate::codegen::auth::{LogoutRequest, LogoutResponse};
+use crate::codegen::auth::{RefreshTokenRequest, RefreshTokenResponse};
 use crate::codegen::auth::{RegisterRequest, RegisterResponse};
-use crate::db::{
-    add_image, get_password_user_from_db, get_user_by_id, query_image_by_id, update_user_icon_id,
-    update_username, DBClient,
-};
+use crate::codegen::auth::{RequestPasswordResetRequest, RequestPasswordResetResponse};
+use crate::codegen::auth::{ResendVerificationRequest, ResendVerificationResponse};
+use crate::codegen::auth::{ResetPasswordRequest, ResetPasswordResponse};
+use crate::codegen::auth::{RevokeSessionRequest, RevokeSessionResponse};
+use crate::codegen::auth::{UnlinkIdentityRequest, UnlinkIdentityResponse};
+use crate::codegen::auth::{UpdatePrivacySettingsRequest, UpdatePrivacySettingsResponse};
+use crate::codegen::auth::{UpdateProfileRequest, UpdateProfileResponse};
+use crate::codegen::auth::{VerifyEmailRequest, VerifyEmailResponse};
+use crate::db::DBClient;
+use crate::db::{add_image, query_iaaa_profile, query_user_post_ids, update_user_icon_id};
+use crate::mail::Mailer;
+use crate::middleware::revocation::RevocationStore;
+use crate::middleware::{current_user, ensure_acting_user};
 
-#[derive(Debug)]
 pub struct AuthService {
     pub client: DBClient,
-    pub iaaa_id: String,
-    pub iaaa_key: String,
+    pub iaaa: Arc<dyn IdentityValidator>,
+    pub revocations: Arc<dyn RevocationStore>,
+    pub mailer: Arc<dyn Mailer>,
+    pub hasher: Arc<dyn PasswordHasher>,
+    pub throttle: LoginThrottle,
+    /// `None` disables OIDC login.
+    pub oidc: Option<Arc<OidcClient>>,
+}
+
+impl AuthService {
+    #[allow(clippy::result_large_err)]
+    fn oidc(&self) -> Result<&OidcClient, Status> {
+        self.oidc
+            .as_deref()
+            .ok_or_else(|| Status::unimplemented("OIDC login is not configured"))
+    }
 }
 
 #[tonic::async_trait]
+#[allow(clippy::result_large_err)]
 impl Auth for AuthService {
+    type ExportMyDataStream = ExportStream;
+
     async fn login(
         &self,
         request: Request<LoginRequest>,
     ) -> Result<Response<LoginResponse>, Status> {
-        let req = request.into_inner();
-        trace!("Login got request: {req:#?}");
+        let remote_ip = request.remote_addr().map(|addr| addr.ip().to_string());
+        let user_agent = request
+            .metadata()
+            .get("user-agent")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let mut req = request.into_inner();
+        // not the whole request, which has the credentials
+        trace!(
+            "Login got request: {:?} {}",
+            req.auth_provider(),
+            req.username
+        );
+        // the client may claim any address, the one it connected from is shown
+        let device = DeviceInfo::new(
+            req.device_name.clone(),
+            remote_ip.clone(),
+            req.ip_address.clone(),
+            user_agent,
+        );
+
+        if let Some(second_factor) = req.second_factor.take() {
+            let throttle = self.throttle.clone();
+            let response = self
+                .client
+                .run(move |conn| {
+                    login_second_factor(
+                        conn,
+                        &throttle,
+                        remote_ip.as_deref(),
+                        second_factor,
+                        device,
+                    )
+                })
+                .await?;
+            return Ok(Response::new(response));
+        }
 
         use crate::codegen::auth::LoginProvider;
         if req.auth_provider == LoginProvider::Iaaa as i32 {
@@ -44,17 +142,23 @@ impl Auth for AuthService {
             }
         };
 
-        // get connection to database
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to authorize")
-        })?;
+        let client = &self.client;
         let response = if req.auth_provider == LoginProvider::Iaaa as i32 {
             let token = &req.iaaa_token;
             let ip_address = req.ip_address.as_ref().unwrap(); // unwrap safe
-            login_iaaa(conn, ip_address, &self.iaaa_id, &self.iaaa_key, &token).await
+            login_iaaa(client, self.iaaa.as_ref(), ip_address, token, device).await
+        } else if req.auth_provider == LoginProvider::Oidc as i32 {
+            login_oidc(client, self.oidc()?, req.oidc, device).await
         } else if req.auth_provider == LoginProvider::Password as i32 {
-            login_password(conn, req).await
+            login_password(
+                client,
+                &self.hasher,
+                self.throttle.clone(),
+                remote_ip,
+                req,
+                device,
+            )
+            .await
         } else {
             error!("Unknown login provider: {}", req.auth_provider);
             Err(Status::invalid_argument("invalid login provider"))
@@ -69,16 +173,12 @@ impl Auth for AuthService {
     ) -> Result<Response<RegisterResponse>, Status> {
         use crate::codegen::auth::LoginProvider;
         let req = request.into_inner();
-        trace!("Register got request: {req:#?}");
+        trace!("Register got request: {}", req.username);
 
         let resp = if req.auth_provider == LoginProvider::Iaaa as i32 {
             Err(Status::unavailable("IAAA should not call Register"))
         } else if req.auth_provider == LoginProvider::Password as i32 {
-            let conn = &mut self.client.get_conn().map_err(|e| {
-                error!("Fail to get connection to database: {e}");
-                Status::internal("Fail to authorize")
-            })?;
-            register_password(conn, req).await
+            register_password(&self.client, &self.hasher, self.mailer.as_ref(), req).await
         } else {
             Err(Status::invalid_argument("invalid login provider"))
         }?;
@@ -90,57 +190,70 @@ impl Auth for AuthService {
         &self,
         request: Request<GetUserRequest>,
     ) -> Result<Response<GetUserResponse>, Status> {
+        let viewer = current_user(&request).ok();
         let req = request.into_inner();
         trace!("GetUser got request: {req:#?}");
 
-        let the_user_id = req.user_id;
+        let user = self
+            .client
+            .run(move |conn| get_user(conn, viewer.as_ref(), req.user_id))
+            .await?;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to get user")
-        })?;
+        Ok(Response::new(GetUserResponse {
+            success: true,
+            user: Some(user),
+        }))
+    }
 
-        let dbuser = get_user_by_id(conn, the_user_id).map_err(|e| {
-            error!("Fail to get user from database: {e}");
-            Status::internal("Fail to get user")
-        })?;
+    async fn update_profile(
+        &self,
+        request: Request<UpdateProfileRequest>,
+    ) -> Result<Response<UpdateProfileResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!("UpdateProfile got request from user {}", user.user_id);
 
-        let created_at: i64 = dbuser.created_at.and_utc().timestamp();
-        let updated_at: Option<i64> = dbuser
-            .updated_at
-            .and_then(|x| Some(x.and_utc().timestamp()));
+        let user = self
+            .client
+            .run(move |conn| update_profile(conn, &user, req))
+            .await?;
 
-        let icon = query_image_by_id(dbuser.icon).map_err(|e| {
-            error!("Fail to query image by id {} :{e}", dbuser.icon);
-            Status::internal("Fail to change username")
-        })?;
+        Ok(Response::new(UpdateProfileResponse {
+            success: true,
+            user: Some(user),
+        }))
+    }
 
-        let response = GetUserResponse {
-            success: true,
-            user: Some(crate::codegen::auth::User {
-                id: dbuser.id,
-                username: dbuser.username,
-                email: dbuser.email,
-                login_provider: dbuser.login_provider as i32,
-                nickname: dbuser.nickname,
-                created_at,
-                updated_at,
-                icon,
-                favorite_posts: dbuser.favorite_posts.to_vec_i32(),
-                liked_posts: dbuser.liked_posts.to_vec_i32(),
-                take_part_posts: dbuser.take_part_posts.to_vec_i32(),
-            }),
-        };
+    async fn update_privacy_settings(
+        &self,
+        request: Request<UpdatePrivacySettingsRequest>,
+    ) -> Result<Response<UpdatePrivacySettingsResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!(
+            "UpdatePrivacySettings got request from user {}",
+            user.user_id
+        );
 
-        Ok(Response::new(response))
+        let privacy = self
+            .client
+            .run(move |conn| update_privacy_settings(conn, &user, req))
+            .await?;
+
+        Ok(Response::new(UpdatePrivacySettingsResponse {
+            success: true,
+            privacy: Some(privacy),
+        }))
     }
 
     async fn change_icon(
         &self,
         request: tonic::Request<ChangeIconRequest>,
     ) -> Result<tonic::Response<ChangeIconResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
-        trace!("Register got request: {req:#?}");
+        trace!("ChangeIcon got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
         let icon_bytes = req.new_icon;
 
         let image_id = add_image(&icon_bytes).map_err(|e| {
@@ -148,35 +261,26 @@ impl Auth for AuthService {
             Status::internal("Fail to change icon")
         })?;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to authorize")
-        })?;
-
-        let Ok(dbuser) = update_user_icon_id(conn, req.user_id, image_id) else {
-            error!("Fail to change icon");
-            return Err(Status::internal("Fail to change icon"));
-        };
-
-        let created_at: i64 = dbuser.created_at.and_utc().timestamp();
-        let updated_at: Option<i64> = dbuser
-            .updated_at
-            .and_then(|x| Some(x.and_utc().timestamp()));
-
-        use crate::codegen::auth::User;
-        let user = Some(User {
-            id: dbuser.id,
-            username: dbuser.username,
-            email: dbuser.email,
-            login_provider: dbuser.login_provider as i32,
-            nickname: dbuser.nickname,
-            created_at,
-            updated_at,
-            icon: icon_bytes,
-            favorite_posts: dbuser.favorite_posts.to_vec_i32(),
-            liked_posts: dbuser.liked_posts.to_vec_i32(),
-            take_part_posts: dbuser.take_part_posts.to_vec_i32(),
-        });
+        let (dbuser, iaaa_profile, posts) = self
+            .client
+            .run(move |conn| {
+                let Ok(dbuser) = update_user_icon_id(conn, user.user_id, image_id) else {
+                    error!("Fail to change icon");
+                    return Err(Status::internal("Fail to change icon"));
+                };
+                let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(|e| {
+                    error!("Fail to query IAAA profile of user {}: {e}", dbuser.id);
+                    Status::internal("Fail to change icon")
+                })?;
+                let posts = query_user_post_ids(conn, dbuser.id).map_err(|e| {
+                    error!("Fail to query posts of user {}: {e}", dbuser.id);
+                    Status::internal("Fail to change icon")
+                })?;
+                Ok((dbuser, iaaa_profile, posts))
+            })
+            .await?;
+
+        let user = Some(dbuser.into_proto_user(icon_bytes, iaaa_profile, posts));
 
         Ok(Response::new(ChangeIconResponse {
             success: true,
@@ -189,52 +293,376 @@ impl Auth for AuthService {
         request: tonic::Request<ChangeUsernameRequest>,
     ) -> Result<tonic::Response<ChangeUsernameResponse>, tonic::Status> {
         let req = request.into_inner();
-        trace!("Register got request: {req:#?}");
-        let new_name = req.new_name;
+        trace!("ChangeUsername got request: {req:#?}");
+        // users login with their username, IAAA ones with their student number
+        Err(Status::unimplemented(
+            "Usernames cannot be changed, use ChangeNickname",
+        ))
+    }
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to authorize")
-        })?;
+    async fn change_nickname(
+        &self,
+        request: Request<ChangeNicknameRequest>,
+    ) -> Result<Response<ChangeNicknameResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!("ChangeNickname got request from user {}", user.user_id);
 
-        let name_duplicate = get_password_user_from_db(conn, &new_name).is_ok();
-        if name_duplicate {
-            return Err(Status::internal("Fail to change username: Username exist"));
-        }
+        let user = self
+            .client
+            .run(move |conn| change_nickname(conn, &user, req))
+            .await?;
+
+        Ok(Response::new(ChangeNicknameResponse {
+            success: true,
+            user: Some(user),
+        }))
+    }
+
+    async fn list_nickname_history(
+        &self,
+        request: Request<ListNicknameHistoryRequest>,
+    ) -> Result<Response<ListNicknameHistoryResponse>, Status> {
+        let user = current_user(&request)?;
+        trace!("ListNicknameHistory got request from user {}", user.user_id);
+
+        let nicknames = self
+            .client
+            .run(move |conn| list_nickname_history(conn, &user))
+            .await?;
 
-        let Ok(dbuser) = update_username(conn, req.user_id, new_name) else {
-            error!("Fail to change username");
-            return Err(Status::internal("Fail to change username"));
+        Ok(Response::new(ListNicknameHistoryResponse {
+            success: true,
+            nicknames,
+        }))
+    }
+
+    async fn refresh_token(
+        &self,
+        request: Request<RefreshTokenRequest>,
+    ) -> Result<Response<RefreshTokenResponse>, Status> {
+        let req = request.into_inner();
+        trace!("RefreshToken got request");
+
+        let revocations = self.revocations.clone();
+        let tokens = self
+            .client
+            .run(move |conn| refresh_session(conn, revocations.as_ref(), &req.refresh_token))
+            .await?;
+
+        Ok(Response::new(RefreshTokenResponse {
+            success: true,
+            token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+        }))
+    }
+
+    async fn logout(
+        &self,
+        request: Request<LogoutRequest>,
+    ) -> Result<Response<LogoutResponse>, Status> {
+        let caller = current_user(&request);
+        let req = request.into_inner();
+        trace!("Logout got request");
+
+        let session_id = match req.refresh_token {
+            Some(refresh_token) => {
+                self.client
+                    .run(move |conn| session_of_refresh_token(conn, &refresh_token))
+                    .await?
+            }
+            None => caller?.session_id,
         };
 
-        let created_at: i64 = dbuser.created_at.and_utc().timestamp();
-        let updated_at: Option<i64> = dbuser
-            .updated_at
-            .and_then(|x| Some(x.and_utc().timestamp()));
+        let revocations = self.revocations.clone();
+        self.client
+            .run(move |conn| {
+                revocations.revoke_with(conn, session_id).map_err(|e| {
+                    error!("Fail to revoke session {session_id}: {e}");
+                    Status::internal("Fail to logout")
+                })
+            })
+            .await?;
+        trace!("Session {session_id} revoked");
+
+        Ok(Response::new(LogoutResponse { success: true }))
+    }
 
-        let icon = query_image_by_id(dbuser.icon).map_err(|e| {
-            error!("Fail to query image by id {} :{e}", dbuser.icon);
-            Status::internal("Fail to change username")
-        })?;
+    async fn change_password(
+        &self,
+        request: Request<ChangePasswordRequest>,
+    ) -> Result<Response<ChangePasswordResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!("ChangePassword got request from user {}", user.user_id);
+
+        change_password(&self.client, &self.hasher, &self.revocations, user, req).await?;
+
+        Ok(Response::new(ChangePasswordResponse { success: true }))
+    }
+
+    async fn request_password_reset(
+        &self,
+        request: Request<RequestPasswordResetRequest>,
+    ) -> Result<Response<RequestPasswordResetResponse>, Status> {
+        let req = request.into_inner();
+        trace!("RequestPasswordReset got request: {req:#?}");
+
+        request_password_reset(&self.client, self.mailer.as_ref(), req).await?;
+
+        Ok(Response::new(RequestPasswordResetResponse {
+            success: true,
+        }))
+    }
+
+    async fn reset_password(
+        &self,
+        request: Request<ResetPasswordRequest>,
+    ) -> Result<Response<ResetPasswordResponse>, Status> {
+        let req = request.into_inner();
+        trace!("ResetPassword got request");
+
+        reset_password(&self.client, &self.hasher, &self.revocations, req).await?;
+
+        Ok(Response::new(ResetPasswordResponse { success: true }))
+    }
+
+    async fn verify_email(
+        &self,
+        request: Request<VerifyEmailRequest>,
+    ) -> Result<Response<VerifyEmailResponse>, Status> {
+        let req = request.into_inner();
+        trace!("VerifyEmail got request");
+
+        self.client.run(move |conn| verify_email(conn, req)).await?;
 
-        use crate::codegen::auth::User;
-        let user = Some(User {
-            id: dbuser.id,
-            username: dbuser.username,
-            email: dbuser.email,
-            login_provider: dbuser.login_provider as i32,
-            nickname: dbuser.nickname,
-            created_at,
-            updated_at,
-            icon,
-            favorite_posts: dbuser.favorite_posts.to_vec_i32(),
-            liked_posts: dbuser.liked_posts.to_vec_i32(),
-            take_part_posts: dbuser.take_part_posts.to_vec_i32(),
-        });
-
-        Ok(Response::new(ChangeUsernameResponse {
+        Ok(Response::new(VerifyEmailResponse { success: true }))
+    }
+
+    async fn resend_verification(
+        &self,
+        request: Request<ResendVerificationRequest>,
+    ) -> Result<Response<ResendVerificationResponse>, Status> {
+        let user = current_user(&request)?;
+        trace!("ResendVerification got request from user {}", user.user_id);
+
+        resend_verification(&self.client, self.mailer.as_ref(), user).await?;
+
+        Ok(Response::new(ResendVerificationResponse { success: true }))
+    }
+
+    async fn list_identities(
+        &self,
+        request: Request<ListIdentitiesRequest>,
+    ) -> Result<Response<ListIdentitiesResponse>, Status> {
+        let user = current_user(&request)?;
+        trace!("ListIdentities got request from user {}", user.user_id);
+
+        let identities = self
+            .client
+            .run(move |conn| list_identities(conn, &user))
+            .await?;
+
+        Ok(Response::new(ListIdentitiesResponse {
             success: true,
+            identities,
+        }))
+    }
+
+    async fn link_identity(
+        &self,
+        request: Request<LinkIdentityRequest>,
+    ) -> Result<Response<LinkIdentityResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!("LinkIdentity got request from user {}", user.user_id);
+
+        let identities = link_identity(
+            &self.client,
+            self.iaaa.as_ref(),
+            self.oidc.as_deref(),
+            &self.hasher,
             user,
+            req,
+        )
+        .await?;
+
+        Ok(Response::new(LinkIdentityResponse {
+            success: true,
+            identities,
+        }))
+    }
+
+    async fn unlink_identity(
+        &self,
+        request: Request<UnlinkIdentityRequest>,
+    ) -> Result<Response<UnlinkIdentityResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!("UnlinkIdentity got request from user {}", user.user_id);
+
+        let identities = self
+            .client
+            .run(move |conn| unlink_identity(conn, &user, req))
+            .await?;
+
+        Ok(Response::new(UnlinkIdentityResponse {
+            success: true,
+            identities,
+        }))
+    }
+
+    async fn get_oidc_config(
+        &self,
+        _request: Request<GetOidcConfigRequest>,
+    ) -> Result<Response<GetOidcConfigResponse>, Status> {
+        trace!("GetOidcConfig got request");
+        let oidc = self.oidc()?;
+        let discovery = oidc.discovery().await.map_err(|e| {
+            error!("Fail to discover OIDC issuer: {e}");
+            Status::unavailable("Fail to reach OIDC issuer")
+        })?;
+
+        Ok(Response::new(GetOidcConfigResponse {
+            success: true,
+            issuer: discovery.issuer.clone(),
+            authorization_endpoint: discovery.authorization_endpoint.clone(),
+            client_id: oidc.config().client_id.clone(),
+            scopes: SCOPES.iter().map(|scope| scope.to_string()).collect(),
+        }))
+    }
+
+    async fn begin_totp_enrollment(
+        &self,
+        request: Request<BeginTotpEnrollmentRequest>,
+    ) -> Result<Response<BeginTotpEnrollmentResponse>, Status> {
+        let user = current_user(&request)?;
+        trace!("BeginTotpEnrollment got request from user {}", user.user_id);
+
+        let response = self
+            .client
+            .run(move |conn| begin_totp_enrollment(conn, &user))
+            .await?;
+
+        Ok(Response::new(response))
+    }
+
+    async fn confirm_totp(
+        &self,
+        request: Request<ConfirmTotpRequest>,
+    ) -> Result<Response<ConfirmTotpResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!("ConfirmTotp got request from user {}", user.user_id);
+
+        let recovery_codes = self
+            .client
+            .run(move |conn| confirm_totp(conn, &user, req))
+            .await?;
+
+        Ok(Response::new(ConfirmTotpResponse {
+            success: true,
+            recovery_codes,
+        }))
+    }
+
+    async fn disable_totp(
+        &self,
+        request: Request<DisableTotpRequest>,
+    ) -> Result<Response<DisableTotpResponse>, Status> {
+        let user = current_user(&request)?;
+        let remote_ip = request.remote_addr().map(|addr| addr.ip().to_string());
+        let req = request.into_inner();
+        trace!("DisableTotp got request from user {}", user.user_id);
+
+        let throttle = self.throttle.clone();
+        self.client
+            .run(move |conn| disable_totp(conn, &throttle, remote_ip.as_deref(), &user, req))
+            .await?;
+
+        Ok(Response::new(DisableTotpResponse { success: true }))
+    }
+
+    async fn list_sessions(
+        &self,
+        request: Request<ListSessionsRequest>,
+    ) -> Result<Response<ListSessionsResponse>, Status> {
+        let user = current_user(&request)?;
+        trace!("ListSessions got request from user {}", user.user_id);
+
+        let sessions = self
+            .client
+            .run(move |conn| list_sessions(conn, &user))
+            .await?;
+
+        Ok(Response::new(ListSessionsResponse {
+            success: true,
+            sessions,
+        }))
+    }
+
+    async fn revoke_session(
+        &self,
+        request: Request<RevokeSessionRequest>,
+    ) -> Result<Response<RevokeSessionResponse>, Status> {
+        let user = current_user(&request)?;
+        let req = request.into_inner();
+        trace!("RevokeSession got request from user {}", user.user_id);
+
+        let revocations = self.revocations.clone();
+        self.client
+            .run(move |conn| revoke_session(conn, revocations.as_ref(), &user, req))
+            .await?;
+
+        Ok(Response::new(RevokeSessionResponse { success: true }))
+    }
+
+    async fn export_my_data(
+        &self,
+        request: Request<ExportMyDataRequest>,
+    ) -> Result<Response<Self::ExportMyDataStream>, Status> {
+        let user = current_user(&request)?;
+        trace!("ExportMyData got request from user {}", user.user_id);
+
+        let stream = export_my_data(&self.client, user).await?;
+
+        Ok(Response::new(stream))
+    }
+
+    async fn delete_account(
+        &self,
+        request: Request<DeleteAccountRequest>,
+    ) -> Result<Response<DeleteAccountResponse>, Status> {
+        let user = current_user(&request)?;
+        trace!("DeleteAccount got request from user {}", user.user_id);
+
+        let delete_after = self
+            .client
+            .run(move |conn| delete_account(conn, &user))
+            .await?;
+
+        Ok(Response::new(DeleteAccountResponse {
+            success: true,
+            delete_after: delete_after.and_utc().timestamp(),
+        }))
+    }
+
+    async fn cancel_account_deletion(
+        &self,
+        request: Request<CancelAccountDeletionRequest>,
+    ) -> Result<Response<CancelAccountDeletionResponse>, Status> {
+        let user = current_user(&request)?;
+        trace!(
+            "CancelAccountDeletion got request from user {}",
+            user.user_id
+        );
+
+        self.client
+            .run(move |conn| cancel_account_deletion(conn, &user))
+            .await?;
+
+        Ok(Response::new(CancelAccountDeletionResponse {
+            success: true,
         }))
     }
 }
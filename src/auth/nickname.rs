@@ -0,0 +1,103 @@
+//! Nicknames, the names users are shown by. Unlike usernames, users may
+//! change them, though not too often, and the old ones are kept.
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, info};
+use tonic::Status;
+
+use crate::codegen::auth::{ChangeNicknameRequest, PastNickname, User};
+use crate::db::query_user_post_ids;
+use crate::db::{change_nickname as update_nickname, NicknameChange};
+use crate::db::{get_user_by_id, query_iaaa_profile, query_image_by_id, query_nickname_history};
+use crate::middleware::AuthenticatedUser;
+
+pub const MIN_NICKNAME_LEN: usize = 2;
+pub const MAX_NICKNAME_LEN: usize = 20;
+/// How long a user has to wait between two changes.
+pub const NICKNAME_COOLDOWN: Duration = Duration::days(30);
+
+/// Check a nickname, returns it without surrounding whitespace. Letters of
+/// any script and digits are allowed, as well as `_`, `-`, `.` and single
+/// spaces in between.
+#[allow(clippy::result_large_err)]
+pub fn validate_nickname(nickname: &str) -> Result<&str, Status> {
+    let nickname = nickname.trim();
+    let len = nickname.chars().count();
+    if !(MIN_NICKNAME_LEN..=MAX_NICKNAME_LEN).contains(&len) {
+        return Err(Status::invalid_argument(format!(
+            "Nickname must be {MIN_NICKNAME_LEN} to {MAX_NICKNAME_LEN} characters long"
+        )));
+    }
+    let allowed = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ' ');
+    if !nickname.chars().all(allowed) || nickname.contains("  ") {
+        return Err(Status::invalid_argument(
+            "Nickname may only contain letters, digits, '_', '-', '.' and single spaces",
+        ));
+    }
+    Ok(nickname)
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn change_nickname(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+    req: ChangeNicknameRequest,
+) -> Result<User, Status> {
+    let nickname = validate_nickname(&req.nickname)?;
+    let internal = |e| {
+        error!("Fail to change nickname of user {}: {e}", user.user_id);
+        Status::internal("Fail to change nickname")
+    };
+    let dbuser = get_user_by_id(conn, user.user_id).map_err(internal)?;
+    if dbuser.nickname == nickname {
+        return Err(Status::invalid_argument("Nickname unchanged"));
+    }
+
+    let now = Utc::now().naive_utc();
+    let change =
+        update_nickname(conn, user.user_id, nickname, NICKNAME_COOLDOWN, now).map_err(internal)?;
+    let dbuser = match change {
+        NicknameChange::Changed(dbuser) => *dbuser,
+        NicknameChange::Taken => return Err(Status::already_exists("Nickname is taken")),
+        NicknameChange::TooSoon(next_change) => {
+            return Err(Status::failed_precondition(format!(
+                "Nickname was changed recently, it can be changed again after {}",
+                next_change.and_utc().to_rfc3339()
+            )));
+        }
+    };
+    info!(
+        "User {} changed nickname to {:?}",
+        user.user_id, dbuser.nickname
+    );
+
+    let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(internal)?;
+    let icon = query_image_by_id(dbuser.icon).map_err(|e| {
+        error!("Fail to query image by id {} :{e}", dbuser.icon);
+        Status::internal("Fail to change nickname")
+    })?;
+    let posts = query_user_post_ids(conn, dbuser.id).map_err(internal)?;
+    Ok(dbuser.into_proto_user(icon, iaaa_profile, posts))
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn list_nickname_history(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+) -> Result<Vec<PastNickname>, Status> {
+    let history = query_nickname_history(conn, user.user_id).map_err(|e| {
+        error!(
+            "Fail to query nickname history of user {}: {e}",
+            user.user_id
+        );
+        Status::internal("Fail to get nickname history")
+    })?;
+    Ok(history
+        .into_iter()
+        .map(|past| PastNickname {
+            nickname: past.nickname,
+            changed_at: past.changed_at.and_utc().timestamp(),
+        })
+        .collect())
+}
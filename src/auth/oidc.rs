@@ -0,0 +1,366 @@
+//! Login through a generic OpenID Connect issuer, such as the SSO of another
+//! university.
+//!
+//! The client makes the authorization request itself, with the code flow and
+//! PKCE, and sends us the code along with its verifier. We redeem the code at
+//! the token endpoint of the issuer, found in its discovery document, and
+//! check the ID token we get with the keys the issuer publishes.
+pub mod mock;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use log::{error, trace};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+use tokio::sync::{OnceCell, RwLock};
+use tonic::Status;
+
+use super::session::{start_session, DeviceInfo};
+use super::totp::{start_challenge, two_factor_enabled};
+use crate::codegen::auth::{LoginResponse, OidcAuthorization};
+use crate::db::{get_oidc_user_from_db, query_iaaa_profile, query_image_by_id};
+use crate::db::{query_user_post_ids, DBClient};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Scopes to request, `email` and `profile` fill in new accounts.
+pub const SCOPES: [&str; 3] = ["openid", "email", "profile"];
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// Issuer identifier, the discovery document is found under it.
+    pub issuer: String,
+    pub client_id: String,
+    /// Absent for public clients.
+    pub client_secret: Option<String>,
+}
+
+impl OidcConfig {
+    /// Configure from `OIDC_ISSUER`, `OIDC_CLIENT_ID` and optionally
+    /// `OIDC_CLIENT_SECRET`. Without `OIDC_ISSUER`, OIDC login is disabled.
+    pub fn from_env() -> Option<Self> {
+        let issuer = std::env::var("OIDC_ISSUER").ok()?;
+        let client_id =
+            std::env::var("OIDC_CLIENT_ID").expect("Must set OIDC_CLIENT_ID with OIDC_ISSUER");
+        let client_secret = std::env::var("OIDC_CLIENT_SECRET").ok();
+        Some(Self {
+            issuer,
+            client_id,
+            client_secret,
+        })
+    }
+}
+
+/// The part of the discovery document we use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Discovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// Claims of an ID token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub exp: i64,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub name: Option<String>,
+    pub preferred_username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenError {
+    error: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    /// The issuer cannot be reached, or answers nonsense.
+    #[error("OIDC issuer unavailable: {0}")]
+    Unavailable(String),
+    /// The issuer refuses the code, or the ID token does not check.
+    #[error("OIDC authorization rejected: {0}")]
+    Rejected(String),
+}
+
+fn unavailable(e: impl ToString) -> OidcError {
+    OidcError::Unavailable(e.to_string())
+}
+
+fn rejected(e: impl ToString) -> OidcError {
+    OidcError::Rejected(e.to_string())
+}
+
+/// The S256 code challenge of a PKCE code verifier.
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Client of an OIDC issuer. The discovery document is fetched on first use,
+/// the keys whenever an ID token is signed with a key we do not know yet.
+#[derive(Debug)]
+pub struct OidcClient {
+    http: reqwest::Client,
+    config: OidcConfig,
+    discovery: OnceCell<Discovery>,
+    jwks: RwLock<JwkSet>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            http: http_client(DEFAULT_TIMEOUT),
+            config,
+            discovery: OnceCell::new(),
+            jwks: RwLock::new(JwkSet { keys: vec![] }),
+        }
+    }
+
+    /// Timeout of each request to the issuer.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.http = http_client(timeout);
+        self
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    pub async fn discovery(&self) -> Result<&Discovery, OidcError> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                let discovery: Discovery = self.get_json(&url).await?;
+                if discovery.issuer != self.config.issuer {
+                    return Err(unavailable(format!(
+                        "discovery document is for issuer {}",
+                        discovery.issuer
+                    )));
+                }
+                Ok(discovery)
+            })
+            .await
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, OidcError> {
+        let response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(unavailable)?;
+        response.json().await.map_err(unavailable)
+    }
+
+    /// Redeem an authorization code, returning the claims of the ID token.
+    pub async fn exchange(
+        &self,
+        code: &str,
+        code_verifier: &str,
+        redirect_uri: &str,
+        nonce: Option<&str>,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let discovery = self.discovery().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let response = self
+            .http
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .map_err(unavailable)?;
+
+        let status = response.status();
+        if status.is_client_error() {
+            let error = match response.json::<TokenError>().await {
+                Ok(e) => e.error,
+                Err(_) => status.to_string(),
+            };
+            // our credentials are wrong, not the code of the user
+            if error == "invalid_client" {
+                return Err(unavailable(format!("token request: {error}")));
+            }
+            return Err(rejected(format!("token request: {error}")));
+        }
+        let response: TokenResponse = response
+            .error_for_status()
+            .map_err(unavailable)?
+            .json()
+            .await
+            .map_err(unavailable)?;
+        self.verify_id_token(&response.id_token, nonce).await
+    }
+
+    /// Check the signature and the claims of an ID token issued to us. With
+    /// `nonce`, the token must carry it.
+    pub async fn verify_id_token(
+        &self,
+        id_token: &str,
+        nonce: Option<&str>,
+    ) -> Result<IdTokenClaims, OidcError> {
+        let discovery = self.discovery().await?;
+        let header = jsonwebtoken::decode_header(id_token).map_err(rejected)?;
+        // symmetric algorithms would sign with the client secret, we do not
+        // take that
+        use Algorithm::*;
+        if !matches!(
+            header.alg,
+            RS256 | RS384 | RS512 | PS256 | PS384 | PS512 | ES256 | ES384 | EdDSA
+        ) {
+            return Err(rejected(format!("algorithm {:?}", header.alg)));
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&discovery.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(rejected)?
+            .claims;
+        if nonce.is_some() && claims.nonce.as_deref() != nonce {
+            return Err(rejected("nonce mismatch"));
+        }
+        Ok(claims)
+    }
+
+    /// The key `kid` of the issuer. The issuer rotates its keys, so an
+    /// unknown one makes us fetch them again.
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, OidcError> {
+        if let Some(key) = find_key(&*self.jwks.read().await, kid)? {
+            return Ok(key);
+        }
+        let discovery = self.discovery().await?;
+        let jwks: JwkSet = self.get_json(&discovery.jwks_uri).await?;
+        let key = find_key(&jwks, kid)?;
+        *self.jwks.write().await = jwks;
+        key.ok_or_else(|| rejected(format!("unknown key {kid:?}")))
+    }
+}
+
+fn find_key(jwks: &JwkSet, kid: Option<&str>) -> Result<Option<DecodingKey>, OidcError> {
+    let jwk = match kid {
+        Some(kid) => jwks.find(kid),
+        // without a key id, only a lone key will do
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    };
+    jwk.map(DecodingKey::from_jwk).transpose().map_err(rejected)
+}
+
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        // the token endpoint must answer itself
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("Fail to build HTTP client")
+}
+
+/// Redeem what the client got from the issuer.
+pub(super) async fn redeem(
+    oidc: &OidcClient,
+    authorization: Option<OidcAuthorization>,
+) -> Result<IdTokenClaims, Status> {
+    let Some(authorization) = authorization else {
+        return Err(Status::invalid_argument(
+            "OIDC authorization cannot be empty!",
+        ));
+    };
+    oidc.exchange(
+        &authorization.code,
+        &authorization.code_verifier,
+        &authorization.redirect_uri,
+        authorization.nonce.as_deref(),
+    )
+    .await
+    .map_err(|e| match e {
+        OidcError::Unavailable(_) => {
+            error!("Fail to redeem OIDC code: {e}");
+            Status::unavailable("Fail to validate")
+        }
+        OidcError::Rejected(_) => {
+            trace!("{e}");
+            Status::unauthenticated("Fail to authorize")
+        }
+    })
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) async fn login_oidc(
+    client: &DBClient,
+    oidc: &OidcClient,
+    authorization: Option<OidcAuthorization>,
+    device: DeviceInfo,
+) -> Result<LoginResponse, Status> {
+    let claims = redeem(oidc, authorization).await?;
+    client
+        .run(move |conn| issue_login(conn, claims, device))
+        .await
+}
+
+/// Answer a login of the user the issuer vouched for, registering it if new.
+#[allow(clippy::result_large_err)]
+fn issue_login(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    claims: IdTokenClaims,
+    device: DeviceInfo,
+) -> Result<LoginResponse, Status> {
+    let dbuser = get_oidc_user_from_db(conn, &claims).map_err(|e| {
+        error!("Fail to find or register OIDC user {}: {e}", claims.sub);
+        Status::internal("Fail to find user or auto-register for OIDC user")
+    })?;
+    if two_factor_enabled(conn, dbuser.id)? {
+        return start_challenge(conn, &dbuser);
+    }
+    let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(|e| {
+        error!("Fail to query IAAA profile of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
+    })?;
+
+    let tokens = start_session(conn, &dbuser, device)?;
+
+    let icon =
+        query_image_by_id(dbuser.icon).map_err(|_| Status::internal("Fail to get user icon"))?;
+    let posts = query_user_post_ids(conn, dbuser.id).map_err(|e| {
+        error!("Fail to query posts of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
+    })?;
+
+    Ok(LoginResponse {
+        success: true,
+        user: Some(dbuser.into_proto_user(icon, iaaa_profile, posts)),
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        two_factor_challenge: None,
+    })
+}
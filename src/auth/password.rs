@@ -1,93 +1,155 @@
+use chrono::{Duration, Utc};
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::PgConnection;
 use log::{error, trace};
+use std::sync::Arc;
 use tonic::Status;
 
+use super::hasher::{hash_password, verify_password, PasswordHasher, PasswordMatch};
+use super::session::{revoke_user_sessions, start_session, DeviceInfo};
+use super::throttle::LoginThrottle;
+use super::totp::{start_challenge, two_factor_enabled};
+use super::verification::send_verification;
+use crate::codegen::auth::ResetPasswordRequest;
+use crate::codegen::auth::{ChangePasswordRequest, RequestPasswordResetRequest};
 use crate::codegen::auth::{LoginRequest, LoginResponse, RegisterRequest, RegisterResponse};
-use crate::db::models::{LoginProvider, PasswordNewUser};
+use crate::crypto::{hash_token, random_token};
+use crate::db::models::{NewPasswordResetToken, PasswordNewUser};
 use crate::db::{get_password_user_from_db, insert_password_user_into_db, query_image_by_id};
-use crate::middleware::issue_token;
+use crate::db::{
+    get_user_by_id, insert_password_reset_token, reset_password_with_token, update_user_password,
+};
+use crate::db::{query_iaaa_profile, query_user_by_username, query_user_post_ids, DBClient};
+use crate::mail::{Mail, Mailer};
+use crate::middleware::revocation::RevocationStore;
+use crate::middleware::AuthenticatedUser;
 
+/// Lifetime of a password reset token (in seconds).
+const PASSWORD_RESET_EXPIRE_TIME: i64 = 30 * 60;
+const MIN_PASSWORD_LEN: usize = 8;
+
+#[allow(clippy::result_large_err)]
 pub(super) async fn login_password(
-    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    client: &DBClient,
+    hasher: &Arc<dyn PasswordHasher>,
+    throttle: LoginThrottle,
+    ip_address: Option<String>,
     req: LoginRequest,
+    device: DeviceInfo,
+) -> Result<LoginResponse, Status> {
+    let username = req.username.clone();
+
+    let (the_throttle, the_username, the_ip_address) =
+        (throttle.clone(), username.clone(), ip_address.clone());
+    let dbuser = client
+        .run(move |conn| {
+            the_throttle.check_with(conn, &the_username, the_ip_address.as_deref())?;
+            Ok::<_, Status>(
+                get_password_user_from_db(conn, &the_username)
+                    .map_err(|e| trace!("User {the_username} not found: {e}"))
+                    .ok(),
+            )
+        })
+        .await?;
+    let hash = dbuser.as_ref().and_then(|user| user.password.clone());
+    let password_match = verify_password(hasher, req.password.clone(), hash).await?;
+    let verified = password_match.is_match();
+    // the same error whether the user exists or not
+    let Some(dbuser) = dbuser.filter(|_| verified) else {
+        trace!("Failed login of {username}");
+        client
+            .run(move |conn| throttle.record_with(conn, &username, ip_address.as_deref(), false))
+            .await?;
+        return Err(Status::unauthenticated("Invalid username or password"));
+    };
+
+    if password_match == PasswordMatch::Outdated {
+        rehash_password(client, hasher, dbuser.id, req.password).await;
+    }
+    let user_id = dbuser.id;
+    if client
+        .run(move |conn| two_factor_enabled(conn, user_id))
+        .await?
+    {
+        // the attempt is recorded once the second factor is checked
+        return client.run(move |conn| start_challenge(conn, &dbuser)).await;
+    }
+    trace!("Password verified, issue token");
+    client
+        .run(move |conn| {
+            throttle.record_with(conn, &username, ip_address.as_deref(), true)?;
+            issue_login(conn, dbuser, device)
+        })
+        .await
+}
+
+/// Answer a login with the right password and no second factor.
+#[allow(clippy::result_large_err)]
+fn issue_login(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    dbuser: crate::db::models::User,
+    device: DeviceInfo,
 ) -> Result<LoginResponse, Status> {
-    let username = &req.username;
-    let dbuser = get_password_user_from_db(conn, username).map_err(|e| {
-        error!("User {username} not found: {e}");
-        Status::not_found("No such user")
+    let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(|e| {
+        error!("Fail to query IAAA profile of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
     })?;
+    let tokens = start_session(conn, &dbuser, device)?;
 
-    let hash = dbuser.password.as_ref().ok_or_else(|| {
-        error!("User without password");
-        Status::internal("user without password")
+    let icon =
+        query_image_by_id(dbuser.icon).map_err(|_| Status::internal("Fail to get user icon"))?;
+    let posts = query_user_post_ids(conn, dbuser.id).map_err(|e| {
+        error!("Fail to query posts of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
     })?;
-    if let Ok(true) = bcrypt::verify(&req.password, &hash) {
-        trace!("Password verified, issue token");
-        use crate::codegen::auth::User;
-        let created_at: i64 = dbuser.created_at.and_utc().timestamp();
-        let updated_at: Option<i64> = dbuser
-            .updated_at
-            .and_then(|x| Some(x.and_utc().timestamp()));
-        let token = issue_token(
-            &dbuser.id.to_string(),
-            dbuser.email.as_ref().unwrap_or(&"".to_string()),
-        )
-        .map_err(|e| {
-            error!("Fail to issue token: {e}");
-            Status::unauthenticated("Fail to assign token")
-        })?;
-
-        trace!("Issued token: {token:?}");
-
-        let icon = query_image_by_id(dbuser.icon)
-            .map_err(|_| Status::internal("Fail to get user icon"))?;
-
-        let response = LoginResponse {
-            success: true,
-            user: Some(User {
-                id: dbuser.id,
-                username: dbuser.username,
-                email: dbuser.email,
-                login_provider: dbuser.login_provider as i32,
-                nickname: dbuser.nickname,
-                created_at,
-                updated_at,
-                icon,
-                favorite_posts: dbuser.favorite_posts.to_vec_i32(),
-                liked_posts: dbuser.liked_posts.to_vec_i32(),
-                take_part_posts: dbuser.take_part_posts.to_vec_i32(),
-            }),
-            token,
-        };
-        Ok(response)
-    } else {
-        error!("Wrong password, user: {username}");
-        Err(Status::unauthenticated("wrong password"))
-    }
+
+    let response = LoginResponse {
+        success: true,
+        user: Some(dbuser.into_proto_user(icon, iaaa_profile, posts)),
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        two_factor_challenge: None,
+    };
+    Ok(response)
 }
 
+#[allow(clippy::result_large_err)]
 pub(super) async fn register_password(
-    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    client: &DBClient,
+    hasher: &Arc<dyn PasswordHasher>,
+    mailer: &dyn Mailer,
     req: RegisterRequest,
 ) -> Result<RegisterResponse, Status> {
-    let password = &req.password;
-    let hashed_password = bcrypt::hash(&password, 10).map_err(|e| {
-        error!("Fail to hash password: {e}");
-        Status::internal("Fail to register new user")
-    })?;
+    let hashed_password = hash_new_password(hasher, req.password.clone()).await?;
 
-    if get_password_user_from_db(conn, &req.username).is_ok() {
-        error!("User {} exist", req.username);
-        return Err(Status::unavailable("User exist"));
-    }
+    let dbuser = client
+        .run(move |conn| {
+            let existing = query_user_by_username(conn, &req.username).map_err(|e| {
+                error!("Fail to query user {}: {e}", req.username);
+                Status::internal("Fail to register new user")
+            })?;
+            if existing.is_some() || get_password_user_from_db(conn, &req.username).is_ok() {
+                error!("User {} exist", req.username);
+                return Err(Status::unavailable("User exist"));
+            }
 
-    let new_user = PasswordNewUser::new(req.username, Some(req.email), Some(hashed_password));
+            let new_user =
+                PasswordNewUser::new(req.username, Some(req.email), Some(hashed_password));
 
-    let _ = insert_password_user_into_db(conn, &new_user).map_err(|e| {
-        error!("Fail to register new user {new_user:#?}: {e}");
-        Status::internal("Fail to register new user")
-    })?;
+            insert_password_user_into_db(conn, &new_user).map_err(|e| {
+                error!("Fail to register new user {new_user:#?}: {e}");
+                Status::internal("Fail to register new user")
+            })
+        })
+        .await?;
+
+    // the account exists already, the user can ask for another mail
+    if let Err(e) = send_verification(client, mailer, &dbuser).await {
+        error!(
+            "Fail to send verification mail to new user {}: {e}",
+            dbuser.id
+        );
+    }
 
     let response = RegisterResponse {
         success: true,
@@ -96,3 +158,181 @@ pub(super) async fn register_password(
 
     Ok(response)
 }
+
+/// Replace an outdated hash of a password that has just been verified.
+/// Failing to is not fatal, the old hash still works.
+#[allow(clippy::result_large_err)]
+async fn rehash_password(
+    client: &DBClient,
+    hasher: &Arc<dyn PasswordHasher>,
+    user_id: i32,
+    password: String,
+) {
+    let result = match hash_password(hasher, password).await {
+        Ok(hashed_password) => {
+            client
+                .run(move |conn| {
+                    update_user_password(conn, user_id, hashed_password)
+                        .map_err(|e| Status::internal(e.to_string()))
+                })
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => trace!("Password hash of user {user_id} upgraded"),
+        Err(e) => error!(
+            "Fail to upgrade password hash of user {user_id}: {}",
+            e.message()
+        ),
+    }
+}
+
+pub(super) async fn hash_new_password(
+    hasher: &Arc<dyn PasswordHasher>,
+    password: String,
+) -> Result<String, Status> {
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(Status::invalid_argument(format!(
+            "Password must have at least {MIN_PASSWORD_LEN} characters"
+        )));
+    }
+    hash_password(hasher, password).await
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) async fn change_password(
+    client: &DBClient,
+    hasher: &Arc<dyn PasswordHasher>,
+    revocations: &Arc<dyn RevocationStore>,
+    user: AuthenticatedUser,
+    req: ChangePasswordRequest,
+) -> Result<(), Status> {
+    let dbuser = client
+        .run(move |conn| {
+            get_user_by_id(conn, user.user_id).map_err(|e| {
+                error!("Fail to get user {}: {e}", user.user_id);
+                Status::internal("Fail to change password")
+            })
+        })
+        .await?;
+    let Some(hash) = dbuser.password else {
+        return Err(Status::failed_precondition("User without password"));
+    };
+    let password_match = verify_password(hasher, req.old_password, Some(hash)).await?;
+    if !password_match.is_match() {
+        trace!("Wrong old password, user: {}", user.user_id);
+        return Err(Status::unauthenticated("wrong password"));
+    }
+
+    let hashed_password = hash_new_password(hasher, req.new_password).await?;
+    let revocations = revocations.clone();
+    client
+        .run(move |conn| {
+            update_user_password(conn, user.user_id, hashed_password).map_err(|e| {
+                error!("Fail to update password of user {}: {e}", user.user_id);
+                Status::internal("Fail to change password")
+            })?;
+
+            revoke_user_sessions(
+                conn,
+                revocations.as_ref(),
+                user.user_id,
+                Some(user.session_id),
+            )
+        })
+        .await
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) async fn request_password_reset(
+    client: &DBClient,
+    mailer: &dyn Mailer,
+    req: RequestPasswordResetRequest,
+) -> Result<(), Status> {
+    let reset = client
+        .run(move |conn| {
+            // the response is the same whether the user exists or not
+            let Ok(dbuser) = get_password_user_from_db(conn, &req.username) else {
+                trace!("Password reset requested for unknown user {}", req.username);
+                return Ok(None);
+            };
+            let email = match dbuser.email.clone() {
+                Some(email) if !email.is_empty() => email,
+                _ => {
+                    trace!(
+                        "Password reset requested for user {} without email",
+                        dbuser.id
+                    );
+                    return Ok(None);
+                }
+            };
+
+            let reset_token = random_token();
+            let expires_at = Utc::now() + Duration::seconds(PASSWORD_RESET_EXPIRE_TIME);
+            let new_token = NewPasswordResetToken {
+                user_id: dbuser.id,
+                token_hash: hash_token(&reset_token),
+                expires_at: expires_at.naive_utc(),
+            };
+            insert_password_reset_token(conn, &new_token).map_err(|e| {
+                error!(
+                    "Fail to store password reset token of user {}: {e}",
+                    dbuser.id
+                );
+                Status::internal("Fail to request password reset")
+            })?;
+            Ok::<_, Status>(Some((dbuser, email, reset_token)))
+        })
+        .await?;
+    let Some((dbuser, email, reset_token)) = reset else {
+        return Ok(());
+    };
+
+    let mail = Mail {
+        to: email,
+        subject: "Reset your HoloPKU password".into(),
+        body: format!(
+            "Hi {},\r\n\r\n\
+             Use this token to reset your password, it expires in {} minutes:\r\n\r\n\
+             {reset_token}\r\n\r\n\
+             If you did not ask for it, ignore this mail.",
+            dbuser.username,
+            PASSWORD_RESET_EXPIRE_TIME / 60
+        ),
+    };
+    // an error would tell that the user exists, the failure is only logged
+    match mailer.send(mail).await {
+        Ok(()) => trace!("Password reset token sent to user {}", dbuser.id),
+        Err(e) => error!(
+            "Fail to send password reset mail to user {}: {e}",
+            dbuser.id
+        ),
+    }
+    Ok(())
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) async fn reset_password(
+    client: &DBClient,
+    hasher: &Arc<dyn PasswordHasher>,
+    revocations: &Arc<dyn RevocationStore>,
+    req: ResetPasswordRequest,
+) -> Result<(), Status> {
+    let hashed_password = hash_new_password(hasher, req.new_password).await?;
+    let revocations = revocations.clone();
+    client
+        .run(move |conn| {
+            let token_hash = hash_token(&req.reset_token);
+            let user_id = reset_password_with_token(conn, &token_hash, hashed_password)
+                .map_err(|e| {
+                    error!("Fail to reset password: {e}");
+                    Status::internal("Fail to reset password")
+                })?
+                .ok_or_else(|| Status::unauthenticated("Invalid reset token"))?;
+            trace!("Password of user {user_id} reset");
+
+            revoke_user_sessions(conn, revocations.as_ref(), user_id, None)
+        })
+        .await
+}
@@ -0,0 +1,241 @@
+//! Profiles, and who gets to see what of a user.
+//!
+//! The owner of an account sees all of it. Others see a public view: no
+//! email, IAAA profile or privacy settings, and the favorites, likes,
+//! participations and contact info only as the privacy settings allow.
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, trace};
+use tonic::Status;
+
+use crate::codegen::auth::{
+    ContactMethod, PrivacySettings, Profile, UpdatePrivacySettingsRequest, UpdateProfileRequest,
+    User, Visibility,
+};
+use crate::db::models::{self, UserProfile};
+use crate::db::{get_user_by_id, query_iaaa_profile, query_image_by_id, query_user_profile};
+use crate::db::{query_user_post_ids, upsert_privacy_settings, upsert_user_profile};
+use crate::middleware::AuthenticatedUser;
+
+pub const MAX_BIO_LEN: usize = 500;
+pub const MAX_PRONOUNS_LEN: usize = 32;
+pub const MAX_CONTACT_LEN: usize = 255;
+pub const MAX_LINKS: usize = 5;
+pub const MAX_LINK_LEN: usize = 255;
+
+/// Check a profile, returns it with surrounding whitespace trimmed and empty
+/// optional fields dropped.
+#[allow(clippy::result_large_err)]
+pub fn validate_profile(profile: Profile) -> Result<Profile, Status> {
+    let too_long = |field: &str, max_len: usize| {
+        Status::invalid_argument(format!("{field} must be at most {max_len} characters"))
+    };
+    let trimmed = |value: Option<String>| {
+        value
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let bio = profile.bio.trim().to_string();
+    if bio.chars().count() > MAX_BIO_LEN {
+        return Err(too_long("Bio", MAX_BIO_LEN));
+    }
+    let pronouns = trimmed(profile.pronouns);
+    if pronouns
+        .as_ref()
+        .is_some_and(|p| p.chars().count() > MAX_PRONOUNS_LEN)
+    {
+        return Err(too_long("Pronouns", MAX_PRONOUNS_LEN));
+    }
+
+    let contact = trimmed(profile.contact);
+    if contact
+        .as_ref()
+        .is_some_and(|c| c.chars().count() > MAX_CONTACT_LEN)
+    {
+        return Err(too_long("Contact", MAX_CONTACT_LEN));
+    }
+    let contact_method = match profile.contact_method {
+        Some(method) => Some(
+            ContactMethod::try_from(method)
+                .map_err(|_| Status::invalid_argument("Invalid contact method"))?,
+        ),
+        None => None,
+    };
+    if contact.is_some() != contact_method.is_some() {
+        return Err(Status::invalid_argument(
+            "Contact and contact method go together",
+        ));
+    }
+
+    let links: Vec<String> = profile
+        .links
+        .iter()
+        .map(|link| link.trim().to_string())
+        .filter(|link| !link.is_empty())
+        .collect();
+    if links.len() > MAX_LINKS {
+        return Err(Status::invalid_argument(format!(
+            "At most {MAX_LINKS} links"
+        )));
+    }
+    for link in &links {
+        if link.chars().count() > MAX_LINK_LEN {
+            return Err(too_long("Link", MAX_LINK_LEN));
+        }
+        let is_web = url::Url::parse(link)
+            .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host());
+        if !is_web {
+            return Err(Status::invalid_argument(format!(
+                "Link is not an http(s) URL: {link}"
+            )));
+        }
+    }
+
+    Ok(Profile {
+        bio,
+        pronouns,
+        contact_method: contact_method.map(Into::into),
+        contact,
+        links,
+    })
+}
+
+/// Whether a part of a profile with `visibility` is shown to someone else,
+/// signed in or not.
+pub fn is_visible(visibility: models::Visibility, signed_in: bool) -> bool {
+    match visibility {
+        models::Visibility::EVERYONE => true,
+        models::Visibility::SIGNED_IN => signed_in,
+        models::Visibility::ONLY_ME => false,
+    }
+}
+
+/// `dbuser` as `viewer` gets to see it.
+#[allow(clippy::result_large_err)]
+fn user_view(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    dbuser: models::User,
+    viewer: Option<&AuthenticatedUser>,
+) -> Result<User, Status> {
+    let internal = |e| {
+        error!("Fail to get profile of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
+    };
+    let profile = query_user_profile(conn, dbuser.id)
+        .map_err(internal)?
+        .unwrap_or_else(|| UserProfile::empty(dbuser.id));
+    let icon = query_image_by_id(dbuser.icon).map_err(|e| {
+        error!("Fail to query image by id {} :{e}", dbuser.icon);
+        Status::internal("Fail to get user")
+    })?;
+    let posts = query_user_post_ids(conn, dbuser.id).map_err(internal)?;
+
+    if viewer.is_some_and(|viewer| viewer.user_id == dbuser.id) {
+        let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(internal)?;
+        let mut user = dbuser.into_proto_user(icon, iaaa_profile, posts);
+        user.profile = Some(profile.to_proto_profile(true));
+        user.privacy = Some(profile.to_proto_privacy());
+        return Ok(user);
+    }
+
+    let visible = |visibility| is_visible(visibility, viewer.is_some());
+    let shown = |post_ids: Vec<i32>, visibility| {
+        if visible(visibility) {
+            post_ids
+        } else {
+            vec![]
+        }
+    };
+    let email_verified = dbuser.is_email_verified();
+    Ok(User {
+        id: dbuser.id,
+        username: dbuser.username,
+        email: None,
+        login_provider: dbuser.login_provider as i32,
+        nickname: dbuser.nickname,
+        created_at: dbuser.created_at.and_utc().timestamp(),
+        updated_at: dbuser.updated_at.map(|x| x.and_utc().timestamp()),
+        icon,
+        favorite_posts: shown(posts.favorite, profile.favorites_visibility),
+        liked_posts: shown(posts.liked, profile.likes_visibility),
+        take_part_posts: shown(posts.take_part, profile.participations_visibility),
+        role: dbuser.role.to_proto_type().into(),
+        email_verified,
+        iaaa_profile: None,
+        delete_after: None,
+        profile: Some(profile.to_proto_profile(visible(profile.contact_visibility))),
+        privacy: None,
+    })
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn get_user(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    viewer: Option<&AuthenticatedUser>,
+    the_user_id: i32,
+) -> Result<User, Status> {
+    let dbuser = get_user_by_id(conn, the_user_id).map_err(|e| {
+        trace!("Fail to get user {the_user_id}: {e}");
+        Status::not_found("No such user")
+    })?;
+    user_view(conn, dbuser, viewer)
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn update_profile(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+    req: UpdateProfileRequest,
+) -> Result<User, Status> {
+    let profile = validate_profile(req.profile.unwrap_or_default())?;
+    let contact_method = profile
+        .contact_method
+        .map(|_| models::ContactMethod::from_proto_type(profile.contact_method()));
+    let update = models::ProfileUpdate {
+        user_id: user.user_id,
+        bio: profile.bio,
+        pronouns: profile.pronouns,
+        contact_method,
+        contact: profile.contact,
+        links: profile.links.into_iter().map(Some).collect(),
+    };
+    upsert_user_profile(conn, &update).map_err(|e| {
+        error!("Fail to update profile of user {}: {e}", user.user_id);
+        Status::internal("Fail to update profile")
+    })?;
+    trace!("User {} updated profile", user.user_id);
+
+    get_user(conn, Some(user), user.user_id)
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn update_privacy_settings(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+    req: UpdatePrivacySettingsRequest,
+) -> Result<PrivacySettings, Status> {
+    let privacy = req.privacy.unwrap_or_default();
+    let visibility = |value: i32| {
+        Visibility::try_from(value)
+            .map(models::Visibility::from_proto_type)
+            .map_err(|_| Status::invalid_argument("Invalid visibility"))
+    };
+    let update = models::PrivacyUpdate {
+        user_id: user.user_id,
+        favorites_visibility: visibility(privacy.favorites)?,
+        likes_visibility: visibility(privacy.likes)?,
+        participations_visibility: visibility(privacy.participations)?,
+        contact_visibility: visibility(privacy.contact)?,
+    };
+    let profile = upsert_privacy_settings(conn, &update).map_err(|e| {
+        error!(
+            "Fail to update privacy settings of user {}: {e}",
+            user.user_id
+        );
+        Status::internal("Fail to update privacy settings")
+    })?;
+    trace!("User {} updated privacy settings", user.user_id);
+
+    Ok(profile.to_proto_privacy())
+}
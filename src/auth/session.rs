@@ -0,0 +1,241 @@
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, trace};
+use tonic::Status;
+
+use crate::codegen::auth::{RevokeSessionRequest, Session};
+use crate::crypto::{hash_token, random_token};
+use crate::db::models::{NewSession, User};
+use crate::db::{
+    get_user_by_id, insert_session, query_active_session_ids, query_session_by_refresh_token,
+    query_user_sessions, rotate_session_refresh_token,
+};
+use crate::middleware::revocation::RevocationStore;
+use crate::middleware::{issue_token, AuthenticatedUser};
+use crate::REFRESH_TOKEN_EXPIRE_TIME;
+
+const MAX_DEVICE_NAME_LEN: usize = 255;
+const MAX_IP_ADDRESS_LEN: usize = 64;
+const MAX_USER_AGENT_LEN: usize = 255;
+
+/// Tokens handed to the client on login and refresh.
+pub(super) struct SessionTokens {
+    pub access_token: Vec<u8>,
+    pub refresh_token: String,
+}
+
+/// What the client tells about itself on login, to tell its session apart.
+#[derive(Debug, Clone)]
+pub(super) struct DeviceInfo {
+    pub device_name: Option<String>,
+    /// Where the login came from.
+    pub ip_address: Option<String>,
+    /// `ip_address` of the login request, not checked.
+    pub reported_ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+impl DeviceInfo {
+    /// Longer values than the columns take are cut.
+    pub fn new(
+        device_name: Option<String>,
+        ip_address: Option<String>,
+        reported_ip_address: Option<String>,
+        user_agent: Option<String>,
+    ) -> Self {
+        let cut = |value: Option<String>, max_len| {
+            value
+                .map(|value| value.trim().chars().take(max_len).collect::<String>())
+                .filter(|value| !value.is_empty())
+        };
+        Self {
+            device_name: cut(device_name, MAX_DEVICE_NAME_LEN),
+            ip_address: cut(ip_address, MAX_IP_ADDRESS_LEN),
+            reported_ip_address: cut(reported_ip_address, MAX_IP_ADDRESS_LEN),
+            user_agent: cut(user_agent, MAX_USER_AGENT_LEN),
+        }
+    }
+}
+
+fn refresh_token_expires_at() -> chrono::NaiveDateTime {
+    (Utc::now() + Duration::seconds(*REFRESH_TOKEN_EXPIRE_TIME)).naive_utc()
+}
+
+#[allow(clippy::result_large_err)]
+fn access_token_for(dbuser: &User, session_id: i32) -> Result<Vec<u8>, Status> {
+    issue_token(dbuser.id, session_id, dbuser.role.to_proto_type()).map_err(|e| {
+        error!("Fail to issue token: {e}");
+        Status::unauthenticated("Fail to assign token")
+    })
+}
+
+/// Start a new session for `dbuser`, who has just logged in from `device`.
+#[allow(clippy::result_large_err)]
+pub(super) fn start_session(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    dbuser: &User,
+    device: DeviceInfo,
+) -> Result<SessionTokens, Status> {
+    let refresh_token = random_token();
+    let new_session = NewSession {
+        user_id: dbuser.id,
+        refresh_token: hash_token(&refresh_token),
+        expires_at: refresh_token_expires_at(),
+        device_name: device.device_name,
+        ip_address: device.ip_address,
+        user_agent: device.user_agent,
+        reported_ip_address: device.reported_ip_address,
+    };
+    let session = insert_session(conn, &new_session).map_err(|e| {
+        error!("Fail to create session for user {}: {e}", dbuser.id);
+        Status::internal("Fail to assign token")
+    })?;
+    trace!("Session {} started for user {}", session.id, dbuser.id);
+
+    let access_token = access_token_for(dbuser, session.id)?;
+    Ok(SessionTokens {
+        access_token,
+        refresh_token,
+    })
+}
+
+/// Exchange a refresh token for a new access token. The refresh token is
+/// rotated, so each one can be used only once.
+#[allow(clippy::result_large_err)]
+pub(super) fn refresh_session(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    revocations: &dyn RevocationStore,
+    refresh_token: &str,
+) -> Result<SessionTokens, Status> {
+    let invalid = || Status::unauthenticated("Invalid refresh token");
+    let old_hash = hash_token(refresh_token);
+    let session = query_session_by_refresh_token(conn, &old_hash)
+        .map_err(|e| {
+            error!("Fail to query session: {e}");
+            Status::internal("Fail to refresh token")
+        })?
+        .ok_or_else(invalid)?;
+
+    if session.expires_at <= Utc::now().naive_utc() {
+        trace!("Session {} expired", session.id);
+        return Err(invalid());
+    }
+    let revoked = revocations.is_revoked_with(conn, session.id).map_err(|e| {
+        error!("Fail to check revocation of session {}: {e}", session.id);
+        Status::internal("Fail to refresh token")
+    })?;
+    if session.revoked || revoked {
+        trace!("Session {} revoked", session.id);
+        return Err(invalid());
+    }
+
+    let new_refresh_token = random_token();
+    let session = rotate_session_refresh_token(
+        conn,
+        session.id,
+        &old_hash,
+        &hash_token(&new_refresh_token),
+        refresh_token_expires_at(),
+    )
+    .map_err(|e| {
+        error!(
+            "Fail to rotate refresh token of session {}: {e}",
+            session.id
+        );
+        Status::internal("Fail to refresh token")
+    })?
+    // lost a race against a concurrent refresh or logout
+    .ok_or_else(invalid)?;
+
+    let dbuser = get_user_by_id(conn, session.user_id).map_err(|e| {
+        error!("Fail to get user {}: {e}", session.user_id);
+        Status::internal("Fail to refresh token")
+    })?;
+    let access_token = access_token_for(&dbuser, session.id)?;
+    Ok(SessionTokens {
+        access_token,
+        refresh_token: new_refresh_token,
+    })
+}
+
+/// Find the session a refresh token belongs to, expired or not.
+#[allow(clippy::result_large_err)]
+pub(super) fn session_of_refresh_token(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    refresh_token: &str,
+) -> Result<i32, Status> {
+    query_session_by_refresh_token(conn, &hash_token(refresh_token))
+        .map_err(|e| {
+            error!("Fail to query session: {e}");
+            Status::internal("Fail to logout")
+        })?
+        .map(|session| session.id)
+        .ok_or_else(|| Status::unauthenticated("Invalid refresh token"))
+}
+
+/// Revoke every session of a user, except `keep`.
+#[allow(clippy::result_large_err)]
+pub(super) fn revoke_user_sessions(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    revocations: &dyn RevocationStore,
+    user_id: i32,
+    keep: Option<i32>,
+) -> Result<(), Status> {
+    let session_ids = query_active_session_ids(conn, user_id).map_err(|e| {
+        error!("Fail to query sessions of user {user_id}: {e}");
+        Status::internal("Fail to revoke sessions")
+    })?;
+    for session_id in session_ids.into_iter().filter(|id| Some(*id) != keep) {
+        revocations.revoke_with(conn, session_id).map_err(|e| {
+            error!("Fail to revoke session {session_id}: {e}");
+            Status::internal("Fail to revoke sessions")
+        })?;
+        trace!("Session {session_id} of user {user_id} revoked");
+    }
+    Ok(())
+}
+
+/// The live sessions of the caller.
+#[allow(clippy::result_large_err)]
+pub(super) fn list_sessions(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+) -> Result<Vec<Session>, Status> {
+    let sessions = query_user_sessions(conn, user.user_id).map_err(|e| {
+        error!("Fail to query sessions of user {}: {e}", user.user_id);
+        Status::internal("Fail to get sessions")
+    })?;
+    Ok(sessions
+        .into_iter()
+        .map(|session| {
+            let current = session.id == user.session_id;
+            session.into_proto_session(current)
+        })
+        .collect())
+}
+
+/// End a live session of the caller, maybe the current one.
+#[allow(clippy::result_large_err)]
+pub(super) fn revoke_session(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    revocations: &dyn RevocationStore,
+    user: &AuthenticatedUser,
+    req: RevokeSessionRequest,
+) -> Result<(), Status> {
+    let sessions = query_user_sessions(conn, user.user_id).map_err(|e| {
+        error!("Fail to query sessions of user {}: {e}", user.user_id);
+        Status::internal("Fail to revoke session")
+    })?;
+    // sessions of other users are not found either
+    let Some(session) = sessions.into_iter().find(|s| s.id == req.session_id) else {
+        return Err(Status::not_found("No such session"));
+    };
+
+    revocations.revoke_with(conn, session.id).map_err(|e| {
+        error!("Fail to revoke session {}: {e}", session.id);
+        Status::internal("Fail to revoke session")
+    })?;
+    trace!("Session {} of user {} revoked", session.id, user.user_id);
+    Ok(())
+}
@@ -0,0 +1,404 @@
+//! Throttling of password logins against brute force.
+//!
+//! Every login attempt is recorded. Failed attempts are counted per username,
+//! since the last successful login, and per IP address, whatever the
+//! username. Past a few free attempts, the next attempt has to wait for a
+//! delay that doubles with each failure, until a cap.
+use std::error::Error as StdError;
+use std::sync::{Arc, Mutex};
+
+use chrono::{Duration, NaiveDateTime};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, warn};
+use tonic::Status;
+
+use crate::clock::Clock;
+use crate::db::models::NewLoginAttempt;
+use crate::db::{
+    insert_login_attempt, query_ip_login_failures, query_username_login_failures, DBClient,
+};
+
+/// Failed login attempts in a window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Failures {
+    pub count: i64,
+    /// Time of the last failure.
+    pub last: Option<NaiveDateTime>,
+}
+
+impl From<(i64, Option<NaiveDateTime>)> for Failures {
+    fn from((count, last): (i64, Option<NaiveDateTime>)) -> Self {
+        Self { count, last }
+    }
+}
+
+/// Audit record of login attempts.
+pub trait LoginAttemptStore: Send + Sync + 'static {
+    fn record(&self, attempt: NewLoginAttempt) -> Result<(), Box<dyn StdError>>;
+    /// Failures on `username` after `since` and after its last success.
+    fn username_failures(
+        &self,
+        username: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>>;
+    /// Failures from `ip_address` after `since`.
+    fn ip_failures(
+        &self,
+        ip_address: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>>;
+
+    /// [`LoginAttemptStore::record`] for callers that hold a connection
+    /// already, which a store in the database uses instead of taking another
+    /// one from the pool.
+    fn record_with(
+        &self,
+        _conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        attempt: NewLoginAttempt,
+    ) -> Result<(), Box<dyn StdError>> {
+        self.record(attempt)
+    }
+
+    /// [`LoginAttemptStore::username_failures`] on a connection the caller
+    /// holds.
+    fn username_failures_with(
+        &self,
+        _conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        username: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        self.username_failures(username, since)
+    }
+
+    /// [`LoginAttemptStore::ip_failures`] on a connection the caller holds.
+    fn ip_failures_with(
+        &self,
+        _conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        ip_address: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        self.ip_failures(ip_address, since)
+    }
+}
+
+/// Login attempt store backed by the `LoginAttempts` table.
+#[derive(Debug, Clone)]
+pub struct DBLoginAttemptStore {
+    client: DBClient,
+}
+
+impl DBLoginAttemptStore {
+    pub fn new(client: DBClient) -> Self {
+        Self { client }
+    }
+}
+
+impl LoginAttemptStore for DBLoginAttemptStore {
+    fn record(&self, attempt: NewLoginAttempt) -> Result<(), Box<dyn StdError>> {
+        self.client
+            .run_blocking(|conn| self.record_with(conn, attempt))
+    }
+
+    fn username_failures(
+        &self,
+        username: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        self.client
+            .run_blocking(|conn| self.username_failures_with(conn, username, since))
+    }
+
+    fn ip_failures(
+        &self,
+        ip_address: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        self.client
+            .run_blocking(|conn| self.ip_failures_with(conn, ip_address, since))
+    }
+
+    fn record_with(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        attempt: NewLoginAttempt,
+    ) -> Result<(), Box<dyn StdError>> {
+        insert_login_attempt(conn, &attempt)
+    }
+
+    fn username_failures_with(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        username: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        query_username_login_failures(conn, username, since).map(Failures::from)
+    }
+
+    fn ip_failures_with(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        ip_address: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        query_ip_login_failures(conn, ip_address, since).map(Failures::from)
+    }
+}
+
+/// In-memory login attempt store, for tests.
+#[derive(Debug, Default)]
+pub struct MemoryLoginAttemptStore {
+    attempts: Mutex<Vec<NewLoginAttempt>>,
+}
+
+impl MemoryLoginAttemptStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn failures(
+        &self,
+        matches: impl Fn(&NewLoginAttempt) -> bool,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        let attempts = self.attempts.lock().map_err(|e| e.to_string())?;
+        let failures = attempts
+            .iter()
+            .filter(|attempt| matches(attempt) && !attempt.success && attempt.created_at > since)
+            .map(|attempt| attempt.created_at);
+        Ok(failures.fold(Failures::default(), |acc, at| Failures {
+            count: acc.count + 1,
+            last: acc.last.max(Some(at)),
+        }))
+    }
+}
+
+impl LoginAttemptStore for MemoryLoginAttemptStore {
+    fn record(&self, attempt: NewLoginAttempt) -> Result<(), Box<dyn StdError>> {
+        let mut attempts = self.attempts.lock().map_err(|e| e.to_string())?;
+        attempts.push(attempt);
+        Ok(())
+    }
+
+    fn username_failures(
+        &self,
+        username: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        let last_success = {
+            let attempts = self.attempts.lock().map_err(|e| e.to_string())?;
+            attempts
+                .iter()
+                .filter(|attempt| attempt.username == username && attempt.success)
+                .map(|attempt| attempt.created_at)
+                .max()
+        };
+        let since = last_success.map_or(since, |last| last.max(since));
+        self.failures(|attempt| attempt.username == username, since)
+    }
+
+    fn ip_failures(
+        &self,
+        ip_address: &str,
+        since: NaiveDateTime,
+    ) -> Result<Failures, Box<dyn StdError>> {
+        self.failures(
+            |attempt| attempt.ip_address.as_deref() == Some(ip_address),
+            since,
+        )
+    }
+}
+
+/// How long to wait after repeated failures.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    /// Failures allowed without waiting.
+    pub free_attempts: i64,
+    /// Wait after the first failure past the free ones, doubled afterwards.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Only failures this recent are counted.
+    pub window: Duration,
+}
+
+impl Backoff {
+    /// Failures on a single account.
+    pub const PER_USERNAME: Self = Self {
+        free_attempts: 5,
+        base_delay: Duration::seconds(30),
+        max_delay: Duration::hours(1),
+        window: Duration::hours(1),
+    };
+    /// Failures from a single address. Campus networks put many users
+    /// behind the same address, so it allows many more.
+    pub const PER_IP: Self = Self {
+        free_attempts: 50,
+        base_delay: Duration::seconds(30),
+        max_delay: Duration::hours(1),
+        window: Duration::hours(1),
+    };
+
+    /// [`Backoff::PER_IP`], allowing `LOGIN_IP_FREE_ATTEMPTS` failures if set,
+    /// for servers that see every login from one address, such as in tests.
+    pub fn per_ip_from_env() -> Self {
+        let free_attempts = std::env::var("LOGIN_IP_FREE_ATTEMPTS")
+            .map(|attempts| {
+                attempts
+                    .parse::<i64>()
+                    .ok()
+                    .filter(|attempts| *attempts >= 0)
+                    .expect("LOGIN_IP_FREE_ATTEMPTS must be set to a non-negative integer")
+            })
+            .unwrap_or(Self::PER_IP.free_attempts);
+        Self {
+            free_attempts,
+            ..Self::PER_IP
+        }
+    }
+
+    /// When the next attempt is allowed, `None` if it is right away.
+    pub fn locked_until(&self, failures: &Failures) -> Option<NaiveDateTime> {
+        let last = failures.last?;
+        let excess = failures.count - self.free_attempts;
+        if excess < 0 {
+            return None;
+        }
+        // 2^20 times any sensible base delay is beyond the cap
+        let delay = self.base_delay * (1 << excess.min(20));
+        Some(last + delay.min(self.max_delay))
+    }
+}
+
+/// Decides whether a password login may be attempted, and records its
+/// outcome.
+#[derive(Clone)]
+pub struct LoginThrottle {
+    store: Arc<dyn LoginAttemptStore>,
+    clock: Arc<dyn Clock>,
+    per_username: Backoff,
+    per_ip: Backoff,
+}
+
+impl LoginThrottle {
+    pub fn new(store: Arc<dyn LoginAttemptStore>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            store,
+            clock,
+            per_username: Backoff::PER_USERNAME,
+            per_ip: Backoff::PER_IP,
+        }
+    }
+
+    pub fn with_backoff(mut self, per_username: Backoff, per_ip: Backoff) -> Self {
+        self.per_username = per_username;
+        self.per_ip = per_ip;
+        self
+    }
+
+    /// Check that `username` may attempt to login from `ip_address`.
+    /// Whether the user exists does not matter, so the answer does not tell.
+    #[allow(clippy::result_large_err)]
+    pub fn check(&self, username: &str, ip_address: Option<&str>) -> Result<(), Status> {
+        self.check_on(None, username, ip_address)
+    }
+
+    /// [`LoginThrottle::check`] on a connection the caller holds.
+    #[allow(clippy::result_large_err)]
+    pub fn check_with(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        username: &str,
+        ip_address: Option<&str>,
+    ) -> Result<(), Status> {
+        self.check_on(Some(conn), username, ip_address)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn check_on(
+        &self,
+        mut conn: Option<&mut PooledConnection<ConnectionManager<PgConnection>>>,
+        username: &str,
+        ip_address: Option<&str>,
+    ) -> Result<(), Status> {
+        let internal = |e| {
+            error!("Fail to query login attempts: {e}");
+            Status::internal("Fail to authorize")
+        };
+        let now = self.clock.now();
+        let since = now - self.per_username.window;
+        let failures = match conn.as_deref_mut() {
+            Some(conn) => self.store.username_failures_with(conn, username, since),
+            None => self.store.username_failures(username, since),
+        }
+        .map_err(internal)?;
+        let mut locked_until = self.per_username.locked_until(&failures);
+        if let Some(ip_address) = ip_address {
+            let since = now - self.per_ip.window;
+            let failures = match conn {
+                Some(conn) => self.store.ip_failures_with(conn, ip_address, since),
+                None => self.store.ip_failures(ip_address, since),
+            }
+            .map_err(internal)?;
+            locked_until = locked_until.max(self.per_ip.locked_until(&failures));
+        }
+
+        match locked_until {
+            Some(until) if until > now => {
+                warn!("Login of {username} from {ip_address:?} throttled until {until}");
+                // round up, retrying right at the given time must succeed
+                let wait = ((until - now).num_milliseconds() + 999) / 1000;
+                Err(Status::resource_exhausted(format!(
+                    "Too many failed attempts, retry in {wait} seconds"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record the outcome of a login attempt.
+    #[allow(clippy::result_large_err)]
+    pub fn record(
+        &self,
+        username: &str,
+        ip_address: Option<&str>,
+        success: bool,
+    ) -> Result<(), Status> {
+        self.record_on(None, username, ip_address, success)
+    }
+
+    /// [`LoginThrottle::record`] on a connection the caller holds.
+    #[allow(clippy::result_large_err)]
+    pub fn record_with(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        username: &str,
+        ip_address: Option<&str>,
+        success: bool,
+    ) -> Result<(), Status> {
+        self.record_on(Some(conn), username, ip_address, success)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn record_on(
+        &self,
+        conn: Option<&mut PooledConnection<ConnectionManager<PgConnection>>>,
+        username: &str,
+        ip_address: Option<&str>,
+        success: bool,
+    ) -> Result<(), Status> {
+        let attempt = NewLoginAttempt {
+            username: username.to_string(),
+            ip_address: ip_address.map(str::to_string),
+            success,
+            created_at: self.clock.now(),
+        };
+        match conn {
+            Some(conn) => self.store.record_with(conn, attempt),
+            None => self.store.record(attempt),
+        }
+        .map_err(|e| {
+            error!("Fail to record login attempt of {username}: {e}");
+            Status::internal("Fail to authorize")
+        })
+    }
+}
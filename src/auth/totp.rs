@@ -0,0 +1,398 @@
+//! Two-factor authentication of logins with TOTP (RFC 6238).
+//!
+//! A user enrolls by adding the secret to an authenticator app and
+//! confirming a code, and gets single-use recovery codes for when the app is
+//! lost. From then on, the right password, or a login through a linked IAAA
+//! or OIDC identity, only gets a challenge, which `Login` exchanges for the
+//! tokens along with a code.
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, trace};
+use ring::hmac;
+use tonic::Status;
+
+use super::session::{start_session, DeviceInfo};
+use super::throttle::LoginThrottle;
+use crate::codegen::auth::{BeginTotpEnrollmentResponse, ConfirmTotpRequest, DisableTotpRequest};
+use crate::codegen::auth::{LoginResponse, SecondFactor};
+use crate::crypto::{decrypt_secret, encrypt_secret, hash_token, random_token};
+use crate::db::models::{NewLoginChallenge, NewTotpCredential, TotpCredential, User};
+use crate::db::{delete_totp, fail_login_challenge, get_user_by_id, insert_login_challenge};
+use crate::db::{query_iaaa_profile, query_image_by_id, query_login_challenge};
+use crate::db::{query_totp_credential, upsert_pending_totp, use_login_challenge};
+use crate::db::{query_user_post_ids, use_recovery_code, use_totp_step};
+use crate::middleware::AuthenticatedUser;
+
+pub const DIGITS: u32 = 6;
+/// Length of a time step (in seconds).
+pub const PERIOD: i64 = 30;
+/// Time steps of clock drift accepted either way.
+const SKEW: i64 = 1;
+const SECRET_LEN: usize = 20;
+const ISSUER: &str = "HoloPKU";
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Lifetime of a login challenge (in seconds).
+const CHALLENGE_EXPIRE_TIME: i64 = 5 * 60;
+/// Wrong codes a challenge takes before the password must be entered again.
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_secret() -> Vec<u8> {
+    rand::random::<[u8; SECRET_LEN]>().to_vec()
+}
+
+/// Base32 (RFC 4648) without padding, as authenticator apps take secrets.
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Decode base32, ignoring case and padding.
+pub fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in encoded.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&a| a == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u32;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// The HOTP (RFC 4226) code of `secret` at `counter`.
+pub fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &counter.to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let mut truncated = [0u8; 4];
+    truncated.copy_from_slice(&digest[offset..offset + 4]);
+    (u32::from_be_bytes(truncated) & 0x7fff_ffff) % 10u32.pow(DIGITS)
+}
+
+/// The time step of a unix timestamp.
+pub fn time_step(timestamp: i64) -> i64 {
+    timestamp.div_euclid(PERIOD)
+}
+
+/// The TOTP code of `secret` at time step `step`, as the user types it.
+pub fn totp_code(secret: &[u8], step: i64) -> String {
+    format!(
+        "{:0width$}",
+        hotp(secret, step as u64),
+        width = DIGITS as usize
+    )
+}
+
+/// The time step around `step` whose code is `code`, if any.
+pub fn verify_code(secret: &[u8], code: &str, step: i64) -> Option<i64> {
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    (step - SKEW..=step + SKEW).find(|&s| s >= 0 && totp_code(secret, s) == code)
+}
+
+/// The URI authenticator apps take, see
+/// <https://github.com/google/google-authenticator/wiki/Key-Uri-Format>.
+pub fn otpauth_uri(account: &str, secret: &[u8]) -> String {
+    let mut uri = url::Url::parse("otpauth://totp").expect("valid URI");
+    uri.set_path(&format!("{ISSUER}:{account}"));
+    uri.query_pairs_mut()
+        .append_pair("secret", &base32_encode(secret))
+        .append_pair("issuer", ISSUER)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &DIGITS.to_string())
+        .append_pair("period", &PERIOD.to_string());
+    uri.into()
+}
+
+/// Generate recovery codes, like `3f9a-c2d1-07be`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code = hex::encode(rand::random::<[u8; 6]>());
+            format!("{}-{}-{}", &code[..4], &code[4..8], &code[8..])
+        })
+        .collect()
+}
+
+/// Hash a recovery code as typed by the user, dashes and case aside.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
+
+fn current_step() -> i64 {
+    time_step(Utc::now().timestamp())
+}
+
+#[allow(clippy::result_large_err)]
+fn open_secret(credential: &TotpCredential) -> Result<Vec<u8>, Status> {
+    decrypt_secret(&credential.secret).map_err(|e| {
+        error!(
+            "Fail to open TOTP secret of user {}: {e}",
+            credential.user_id
+        );
+        Status::internal("Fail to check code")
+    })
+}
+
+/// Check a TOTP code or a recovery code of a confirmed enrollment, using it
+/// up.
+#[allow(clippy::result_large_err)]
+fn check_second_factor(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    credential: &TotpCredential,
+    code: &str,
+) -> Result<bool, Status> {
+    let internal = |e| {
+        error!(
+            "Fail to check second factor of user {}: {e}",
+            credential.user_id
+        );
+        Status::internal("Fail to check code")
+    };
+    let secret = open_secret(credential)?;
+    if let Some(step) = verify_code(&secret, code.trim(), current_step()) {
+        // a code used already does not count, whoever sent it
+        return use_totp_step(conn, credential.user_id, step).map_err(internal);
+    }
+    use_recovery_code(conn, credential.user_id, &hash_recovery_code(code)).map_err(internal)
+}
+
+/// The confirmed enrollment of a user, if any.
+#[allow(clippy::result_large_err)]
+fn confirmed_credential(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: i32,
+) -> Result<Option<TotpCredential>, Status> {
+    let credential = query_totp_credential(conn, user_id).map_err(|e| {
+        error!("Fail to query TOTP of user {user_id}: {e}");
+        Status::internal("Fail to check two-factor authentication")
+    })?;
+    Ok(credential.filter(TotpCredential::is_confirmed))
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn begin_totp_enrollment(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+) -> Result<BeginTotpEnrollmentResponse, Status> {
+    let internal = |e| {
+        error!(
+            "Fail to begin TOTP enrollment of user {}: {e}",
+            user.user_id
+        );
+        Status::internal("Fail to begin enrollment")
+    };
+    let dbuser = get_user_by_id(conn, user.user_id).map_err(internal)?;
+    if dbuser.password.is_none() {
+        return Err(Status::failed_precondition(
+            "Two-factor authentication is for password logins",
+        ));
+    }
+
+    let secret = generate_secret();
+    let new_credential = NewTotpCredential {
+        user_id: dbuser.id,
+        secret: encrypt_secret(&secret),
+    };
+    if !upsert_pending_totp(conn, &new_credential).map_err(internal)? {
+        return Err(Status::failed_precondition(
+            "Two-factor authentication already enabled",
+        ));
+    }
+    trace!("User {} began TOTP enrollment", user.user_id);
+
+    Ok(BeginTotpEnrollmentResponse {
+        success: true,
+        otpauth_uri: otpauth_uri(&dbuser.username, &secret),
+        secret: base32_encode(&secret),
+    })
+}
+
+/// Enable two-factor authentication, returns the recovery codes.
+#[allow(clippy::result_large_err)]
+pub(super) fn confirm_totp(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user: &AuthenticatedUser,
+    req: ConfirmTotpRequest,
+) -> Result<Vec<String>, Status> {
+    let internal = |e| {
+        error!("Fail to confirm TOTP of user {}: {e}", user.user_id);
+        Status::internal("Fail to confirm enrollment")
+    };
+    let credential = query_totp_credential(conn, user.user_id).map_err(internal)?;
+    let Some(credential) = credential.filter(|c| !c.is_confirmed()) else {
+        return Err(Status::failed_precondition("No pending enrollment"));
+    };
+    let secret = open_secret(&credential)?;
+    let Some(step) = verify_code(&secret, req.code.trim(), current_step()) else {
+        trace!("Wrong TOTP code to confirm, user: {}", user.user_id);
+        return Err(Status::unauthenticated("Invalid code"));
+    };
+
+    let recovery_codes = generate_recovery_codes();
+    let code_hashes = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+    if !crate::db::confirm_totp(conn, user.user_id, step, code_hashes).map_err(internal)? {
+        return Err(Status::failed_precondition("No pending enrollment"));
+    }
+    trace!("User {} enabled two-factor authentication", user.user_id);
+
+    Ok(recovery_codes)
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn disable_totp(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    throttle: &LoginThrottle,
+    ip_address: Option<&str>,
+    user: &AuthenticatedUser,
+    req: DisableTotpRequest,
+) -> Result<(), Status> {
+    let internal = |e| {
+        error!("Fail to disable TOTP of user {}: {e}", user.user_id);
+        Status::internal("Fail to disable two-factor authentication")
+    };
+    let Some(credential) = confirmed_credential(conn, user.user_id)? else {
+        return Err(Status::failed_precondition(
+            "Two-factor authentication not enabled",
+        ));
+    };
+    // codes are guessed no faster than at login
+    let dbuser = get_user_by_id(conn, user.user_id).map_err(internal)?;
+    throttle.check_with(conn, &dbuser.username, ip_address)?;
+    let verified = check_second_factor(conn, &credential, &req.code)?;
+    throttle.record_with(conn, &dbuser.username, ip_address, verified)?;
+    if !verified {
+        trace!("Wrong code to disable TOTP, user: {}", user.user_id);
+        return Err(Status::unauthenticated("Invalid code"));
+    }
+
+    delete_totp(conn, user.user_id).map_err(internal)?;
+    trace!("User {} disabled two-factor authentication", user.user_id);
+    Ok(())
+}
+
+/// Whether logins of the user need a second factor, whatever the first.
+#[allow(clippy::result_large_err)]
+pub(super) fn two_factor_enabled(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: i32,
+) -> Result<bool, Status> {
+    Ok(confirmed_credential(conn, user_id)?.is_some())
+}
+
+/// Answer a login that passed the first factor, which waits for the
+/// second.
+#[allow(clippy::result_large_err)]
+pub(super) fn start_challenge(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    dbuser: &User,
+) -> Result<LoginResponse, Status> {
+    let challenge = random_token();
+    let new_challenge = NewLoginChallenge {
+        user_id: dbuser.id,
+        token_hash: hash_token(&challenge),
+        expires_at: (Utc::now() + Duration::seconds(CHALLENGE_EXPIRE_TIME)).naive_utc(),
+    };
+    insert_login_challenge(conn, &new_challenge).map_err(|e| {
+        error!("Fail to store login challenge of user {}: {e}", dbuser.id);
+        Status::internal("Fail to authorize")
+    })?;
+    trace!("Login of user {} waits for the second factor", dbuser.id);
+
+    Ok(LoginResponse {
+        success: false,
+        user: None,
+        token: vec![],
+        refresh_token: String::new(),
+        two_factor_challenge: Some(challenge),
+    })
+}
+
+/// Complete a login with the second factor.
+#[allow(clippy::result_large_err)]
+pub(super) fn login_second_factor(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    throttle: &LoginThrottle,
+    ip_address: Option<&str>,
+    second_factor: SecondFactor,
+    device: DeviceInfo,
+) -> Result<LoginResponse, Status> {
+    let invalid = || Status::unauthenticated("Invalid or expired challenge");
+    let internal = |e| {
+        error!("Fail to check login challenge: {e}");
+        Status::internal("Fail to authorize")
+    };
+    let challenge = query_login_challenge(conn, &hash_token(&second_factor.challenge))
+        .map_err(internal)?
+        .filter(|challenge| challenge.failed_attempts < MAX_CHALLENGE_ATTEMPTS)
+        .ok_or_else(invalid)?;
+    let dbuser = get_user_by_id(conn, challenge.user_id).map_err(internal)?;
+    // wrong codes count as failed logins of the username, like wrong
+    // passwords, so guessing is as slow
+    let username = &dbuser.username;
+    throttle.check_with(conn, username, ip_address)?;
+    let Some(credential) = confirmed_credential(conn, dbuser.id)? else {
+        return Err(invalid());
+    };
+    let verified = check_second_factor(conn, &credential, &second_factor.code)?;
+    throttle.record_with(conn, username, ip_address, verified)?;
+    if !verified {
+        fail_login_challenge(conn, challenge.id).map_err(internal)?;
+        trace!("Wrong second factor of user {}", dbuser.id);
+        return Err(Status::unauthenticated("Invalid code"));
+    }
+    if !use_login_challenge(conn, challenge.id).map_err(internal)? {
+        return Err(invalid());
+    }
+
+    let iaaa_profile = query_iaaa_profile(conn, dbuser.id).map_err(|e| {
+        error!("Fail to query IAAA profile of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
+    })?;
+    let tokens = start_session(conn, &dbuser, device)?;
+
+    let icon =
+        query_image_by_id(dbuser.icon).map_err(|_| Status::internal("Fail to get user icon"))?;
+    let posts = query_user_post_ids(conn, dbuser.id).map_err(|e| {
+        error!("Fail to query posts of user {}: {e}", dbuser.id);
+        Status::internal("Fail to get user")
+    })?;
+
+    Ok(LoginResponse {
+        success: true,
+        user: Some(dbuser.into_proto_user(icon, iaaa_profile, posts)),
+        token: tokens.access_token,
+        refresh_token: tokens.refresh_token,
+        two_factor_challenge: None,
+    })
+}
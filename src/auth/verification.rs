@@ -0,0 +1,101 @@
+use chrono::{Duration, Utc};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+use log::{error, trace};
+use tonic::Status;
+
+use crate::codegen::auth::VerifyEmailRequest;
+use crate::crypto::{hash_token, random_token};
+use crate::db::models::{NewEmailVerificationToken, User};
+use crate::db::DBClient;
+use crate::db::{get_user_by_id, insert_email_verification_token, verify_email_with_token};
+use crate::mail::{Mail, Mailer};
+use crate::middleware::AuthenticatedUser;
+
+/// Lifetime of an email verification token (in seconds).
+const EMAIL_VERIFICATION_EXPIRE_TIME: i64 = 24 * 60 * 60;
+
+/// Mail a verification token to the email of `dbuser`.
+#[allow(clippy::result_large_err)]
+pub(super) async fn send_verification(
+    client: &DBClient,
+    mailer: &dyn Mailer,
+    dbuser: &User,
+) -> Result<(), Status> {
+    let email = match dbuser.email.as_deref() {
+        Some(email) if !email.is_empty() => email,
+        _ => return Err(Status::failed_precondition("User without email")),
+    };
+
+    let verification_token = random_token();
+    let expires_at = Utc::now() + Duration::seconds(EMAIL_VERIFICATION_EXPIRE_TIME);
+    let new_token = NewEmailVerificationToken {
+        user_id: dbuser.id,
+        email: email.to_string(),
+        token_hash: hash_token(&verification_token),
+        expires_at: expires_at.naive_utc(),
+    };
+    let user_id = dbuser.id;
+    client
+        .run(move |conn| {
+            insert_email_verification_token(conn, &new_token).map_err(|e| {
+                error!("Fail to store email verification token of user {user_id}: {e}");
+                Status::internal("Fail to send verification mail")
+            })
+        })
+        .await?;
+
+    let mail = Mail {
+        to: email.to_string(),
+        subject: "Verify your HoloPKU email".into(),
+        body: format!(
+            "Hi {},\r\n\r\n\
+             Use this token to verify your email, it expires in {} hours:\r\n\r\n\
+             {verification_token}\r\n\r\n\
+             If you did not register, ignore this mail.",
+            dbuser.username,
+            EMAIL_VERIFICATION_EXPIRE_TIME / 3600
+        ),
+    };
+    mailer.send(mail).await.map_err(|e| {
+        error!("Fail to send verification mail to user {}: {e}", dbuser.id);
+        Status::internal("Fail to send verification mail")
+    })?;
+    trace!("Verification token sent to user {}", dbuser.id);
+    Ok(())
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) async fn resend_verification(
+    client: &DBClient,
+    mailer: &dyn Mailer,
+    user: AuthenticatedUser,
+) -> Result<(), Status> {
+    let dbuser = client
+        .run(move |conn| {
+            get_user_by_id(conn, user.user_id).map_err(|e| {
+                error!("Fail to get user {}: {e}", user.user_id);
+                Status::internal("Fail to send verification mail")
+            })
+        })
+        .await?;
+    if dbuser.is_email_verified() {
+        return Err(Status::failed_precondition("Email already verified"));
+    }
+    send_verification(client, mailer, &dbuser).await
+}
+
+#[allow(clippy::result_large_err)]
+pub(super) fn verify_email(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    req: VerifyEmailRequest,
+) -> Result<(), Status> {
+    let user_id = verify_email_with_token(conn, &hash_token(&req.verification_token))
+        .map_err(|e| {
+            error!("Fail to verify email: {e}");
+            Status::internal("Fail to verify email")
+        })?
+        .ok_or_else(|| Status::unauthenticated("Invalid verification token"))?;
+    trace!("Email of user {user_id} verified");
+    Ok(())
+}
@@ -1,16 +1,12 @@
 use holopku::codegen::auth::auth_client::AuthClient;
-use holopku::codegen::auth::{GetUserRequest, LoginProvider, LoginRequest, RegisterRequest};
+use holopku::codegen::auth::{LoginProvider, LoginRequest, RegisterRequest};
 use holopku::codegen::food_post::FoodPost;
 use holopku::codegen::forum::forum_client::ForumClient;
 use holopku::codegen::post::Post;
 // use holopku::codegen::forum::CreatePostRequest;
 use holopku::AUTHORIZATION_KEY;
-use hyper::client::conn::http1;
-use hyper::header::HeaderValue;
-use hyper_util::rt::TokioExecutor;
-use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::metadata::MetadataValue;
 use tonic::{IntoRequest, Request};
-use tonic_web::GrpcWebClientLayer;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -35,6 +31,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             username: "2200088888".into(),
             password: "mypassword".into(),
             ip_address: Some("my-ip-address".into()),
+            oidc: None,
+            second_factor: None,
+            device_name: None,
         }))
         .await;
     println!("RESPONSE = {:?}", response);
@@ -47,6 +46,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             username: "laughoutloud".into(),
             password: "mypassword".into(),
             ip_address: None,
+            oidc: None,
+            second_factor: None,
+            device_name: None,
         }))
         .await;
     println!("RESPONSE = {:?}", response);
@@ -70,6 +72,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             username: "laughoutloud".into(),
             password: "mypassword".into(),
             ip_address: None,
+            oidc: None,
+            second_factor: None,
+            device_name: None,
         }))
         .await;
     println!("RESPONSE = {:?}", response);
@@ -100,7 +105,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     post: Some(Post {
                         id: 1,
                         title: "first post--test".into(),
-                        user_id: user_id,
+                        user_id,
                         content: "this is the first post to test".into(),
                         likes: 0,
                         favorates: 0,
@@ -204,7 +209,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .delete_post({
             let mut delete_post = holopku::codegen::forum::DeletePostRequest {
                 post_id: the_new_post_id,
-                user_id: user_id,
+                user_id,
             }
             .into_request();
             let metadata = delete_post.metadata_mut();
@@ -0,0 +1,44 @@
+//! Source of the current time, so that time dependent logic can be tested
+//! without waiting.
+use std::sync::{Mutex, PoisonError};
+
+use chrono::{Duration, NaiveDateTime, Utc};
+
+pub trait Clock: Send + Sync + 'static {
+    /// Current time in UTC, like the timestamps stored in the database.
+    fn now(&self) -> NaiveDateTime;
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> NaiveDateTime {
+        Utc::now().naive_utc()
+    }
+}
+
+/// A clock that only moves when told to, for tests.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<NaiveDateTime>,
+}
+
+impl ManualClock {
+    pub fn new(now: NaiveDateTime) -> Self {
+        Self {
+            now: Mutex::new(now),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap_or_else(PoisonError::into_inner);
+        *now += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> NaiveDateTime {
+        *self.now.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
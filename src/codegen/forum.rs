@@ -90,7 +90,7 @@ pub mod list_personal_posts_response {
         AResponse(super::ListAmusementPostsResponse),
     }
 }
-#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ListFoodPostsRequest {
     /// food post
     #[prost(enumeration = "super::food_post::Place", optional, tag = "1")]
@@ -101,8 +101,11 @@ pub struct ListFoodPostsRequest {
     pub random: bool,
     #[prost(int32, tag = "4")]
     pub number: i32,
+    /// only posts by IAAA users of this department
+    #[prost(string, optional, tag = "5")]
+    pub dept_id: ::core::option::Option<::prost::alloc::string::String>,
 }
-#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ListSellPostsRequest {
     /// sell post
     #[prost(enumeration = "super::sell_post::GoodsType", optional, tag = "1")]
@@ -111,8 +114,11 @@ pub struct ListSellPostsRequest {
     pub price_upbond: i32,
     #[prost(int32, tag = "3")]
     pub number: i32,
+    /// only posts by IAAA users of this department
+    #[prost(string, optional, tag = "4")]
+    pub dept_id: ::core::option::Option<::prost::alloc::string::String>,
 }
-#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ListAmusementPostsRequest {
     /// amusement post
     #[prost(enumeration = "super::amusement_post::GameType", optional, tag = "1")]
@@ -127,6 +133,9 @@ pub struct ListAmusementPostsRequest {
     pub time_about: ::core::option::Option<i64>,
     #[prost(int32, tag = "6")]
     pub number: i32,
+    /// only posts by IAAA users of this department
+    #[prost(string, optional, tag = "7")]
+    pub dept_id: ::core::option::Option<::prost::alloc::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ListFoodPostsResponse {
@@ -182,6 +191,15 @@ pub struct LikePostRequest {
 pub struct LikePostResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller likes the post now
+    #[prost(bool, tag = "2")]
+    pub liked: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub likes: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct UnlikePostRequest {
@@ -194,6 +212,15 @@ pub struct UnlikePostRequest {
 pub struct UnlikePostResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller likes the post now
+    #[prost(bool, tag = "2")]
+    pub liked: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub likes: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct FavorateRequest {
@@ -206,6 +233,15 @@ pub struct FavorateRequest {
 pub struct FavorateResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller favorites the post now
+    #[prost(bool, tag = "2")]
+    pub favorated: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub favorates: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct UnfavorateRequest {
@@ -218,6 +254,15 @@ pub struct UnfavorateRequest {
 pub struct UnfavorateResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller favorites the post now
+    #[prost(bool, tag = "2")]
+    pub favorated: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub favorates: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct LikeCommentRequest {
@@ -230,6 +275,15 @@ pub struct LikeCommentRequest {
 pub struct LikeCommentResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller likes the comment now
+    #[prost(bool, tag = "2")]
+    pub liked: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub likes: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct UnlikeCommentRequest {
@@ -242,6 +296,15 @@ pub struct UnlikeCommentRequest {
 pub struct UnlikeCommentResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller likes the comment now
+    #[prost(bool, tag = "2")]
+    pub liked: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub likes: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct TakePartAmusePostRequest {
@@ -254,6 +317,15 @@ pub struct TakePartAmusePostRequest {
 pub struct TakePartAmusePostResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller takes part now
+    #[prost(bool, tag = "2")]
+    pub taking_part: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub people_already: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct NoTakePartAmusePostRequest {
@@ -266,6 +338,15 @@ pub struct NoTakePartAmusePostRequest {
 pub struct NoTakePartAmusePostResponse {
     #[prost(bool, tag = "1")]
     pub success: bool,
+    /// whether the caller takes part now
+    #[prost(bool, tag = "2")]
+    pub taking_part: bool,
+    /// the count after the request
+    #[prost(int32, tag = "3")]
+    pub people_already: i32,
+    /// false if it was so already
+    #[prost(bool, tag = "4")]
+    pub changed: bool,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct SetSoldRequest {
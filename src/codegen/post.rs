@@ -42,6 +42,9 @@ pub struct Comment {
     pub created_at: i64,
     #[prost(int64, optional, tag = "7")]
     pub updated_at: ::core::option::Option<i64>,
+    /// whether the caller likes it
+    #[prost(bool, tag = "8")]
+    pub liked: bool,
 }
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
 #[repr(i32)]
@@ -1,22 +1,131 @@
-use aes::cipher::block_padding::{Pkcs7, UnpadError};
-use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+//! Encryption of the tokens handed to clients.
+//!
+//! Tokens are sealed with AES-256-GCM under a key derived from `AES256KEY`
+//! with HKDF-SHA256. A sealed token is laid out as
+//! `version (1 byte) || nonce (12 bytes) || ciphertext || tag (16 bytes)`,
+//! the nonce is random for every token.
+//!
+//! Secrets stored in the database, such as TOTP secrets, are sealed the same
+//! way under another key derived from `AES256KEY`.
+//!
+//! Tokens issued before the switch are unversioned AES-256-CBC ciphertexts
+//! under the static `AES256IV`, they can still be opened until they expire.
+//! That fallback is unauthenticated and goes away in 0.2.0.
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
 use aes::Aes256;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use hkdf::Hkdf;
 use log::trace;
+use sha2::{Digest, Sha256};
 
-use crate::{AES256IV, AES256KEY};
+use crate::{AES256IV, AES256KEY, SECRET_KEY, TOKEN_KEY};
 
-type Aes256CbcEnc = cbc::Encryptor<Aes256>;
 type Aes256CbcDec = cbc::Decryptor<Aes256>;
 
-pub fn encrypt_aes256(plain_text: &[u8]) -> Vec<u8> {
-    trace!("Plain text: {plain_text:?}");
-    let s = Aes256CbcEnc::new(AES256KEY.as_slice().into(), AES256IV.as_slice().into())
-        .encrypt_padded_vec_mut::<Pkcs7>(plain_text);
-    trace!("Encrypted text: {s:?}");
-    s
+/// Version prefix of tokens sealed with AES-256-GCM.
+const VERSION_AES256GCM: u8 = 1;
+const NONCE_LEN: usize = 12;
+const HKDF_INFO: &[u8] = b"holopku token encryption v1";
+const SECRET_HKDF_INFO: &[u8] = b"holopku secret encryption v1";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("malformed ciphertext")]
+    Malformed,
+    #[error("ciphertext failed authentication")]
+    Unauthenticated,
+}
+
+/// Derive the AES-256-GCM key from the configured secret.
+pub fn derive_key(secret: &[u8]) -> [u8; 32] {
+    hkdf_expand(secret, HKDF_INFO)
+}
+
+/// Derive the AES-256-GCM key of stored secrets from the configured secret.
+pub fn derive_secret_key(secret: &[u8]) -> [u8; 32] {
+    hkdf_expand(secret, SECRET_HKDF_INFO)
+}
+
+fn hkdf_expand(secret: &[u8], info: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, secret)
+        .expand(info, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+pub fn encrypt_token(plain_text: &[u8]) -> Vec<u8> {
+    seal(&TOKEN_KEY, plain_text)
+}
+
+pub fn decrypt_token(encrypt_text: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    match encrypt_text.first() {
+        Some(&VERSION_AES256GCM) => open(&TOKEN_KEY, encrypt_text).or_else(|e| {
+            // an unversioned CBC ciphertext may start with the version byte
+            trace!("Fail to open token as AES-256-GCM ({e}), try legacy AES-256-CBC");
+            open_legacy(&AES256KEY, &AES256IV, encrypt_text).map_err(|_| e)
+        }),
+        // TODO: remove the CBC fallback in 0.2.0, tokens of 0.1.0 have expired by then.
+        Some(_) => open_legacy(&AES256KEY, &AES256IV, encrypt_text),
+        None => Err(CryptoError::Malformed),
+    }
+}
+
+pub fn encrypt_secret(plain_text: &[u8]) -> Vec<u8> {
+    seal(&SECRET_KEY, plain_text)
+}
+
+pub fn decrypt_secret(encrypt_text: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    open(&SECRET_KEY, encrypt_text)
+}
+
+pub(crate) fn seal(key: &[u8; 32], plain_text: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let cipher_text = cipher
+        .encrypt(&nonce, plain_text)
+        .expect("AES-256-GCM encryption of a token cannot fail");
+
+    let mut sealed = Vec::with_capacity(1 + NONCE_LEN + cipher_text.len());
+    sealed.push(VERSION_AES256GCM);
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&cipher_text);
+    sealed
+}
+
+pub(crate) fn open(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>, CryptoError> {
+    let Some((&VERSION_AES256GCM, rest)) = sealed.split_first() else {
+        return Err(CryptoError::Malformed);
+    };
+    if rest.len() < NONCE_LEN {
+        return Err(CryptoError::Malformed);
+    }
+    let (nonce, cipher_text) = rest.split_at(NONCE_LEN);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    cipher
+        .decrypt(Nonce::from_slice(nonce), cipher_text)
+        .map_err(|_| CryptoError::Unauthenticated)
+}
+
+pub(crate) fn open_legacy(
+    key: &[u8; 32],
+    iv: &[u8; 16],
+    encrypt_text: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    Aes256CbcDec::new(key.into(), iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(encrypt_text)
+        .map_err(|_| CryptoError::Malformed)
+}
+
+/// Generate a random opaque token, hex encoded.
+pub fn random_token() -> String {
+    let bytes: [u8; 32] = rand::random();
+    hex::encode(bytes)
 }
 
-pub fn decrypt_aes256(encrypt_text: &[u8]) -> Result<Vec<u8>, UnpadError> {
-    Aes256CbcDec::new(AES256KEY.as_slice().into(), AES256IV.as_slice().into())
-        .decrypt_padded_vec_mut::<Pkcs7>(&encrypt_text)
+/// Hash a token before it is stored, so a leaked table cannot be replayed.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
@@ -4,38 +4,47 @@ use crate::codegen::food_post::FoodPost;
 use crate::codegen::post::Comment;
 use crate::codegen::sell_post::SellPost;
 use crate::db::models::NewAmusementPost;
-use chrono::{Duration, NaiveDateTime};
+use chrono::{DateTime, Duration, NaiveDateTime};
 use diesel::prelude::*;
-use diesel::query_dsl::methods::ModifyLockDsl;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use diesel::PgConnection;
-use models::{
-    IaaaNewUser, NewFoodPost, NewSellPost, NullableIntArray, PasswordNewUser, PostType, User,
-};
+use log::error;
+use models::{IaaaNewUser, NewFoodPost, NewSellPost, NullableIntArray, OidcNewUser};
+use models::{PasswordNewUser, PostType};
 use rand::Rng;
-use schema::Posts::{comments_id, people_all};
-use schema::Users::{email, nickname};
-use serde::de::IntoDeserializer;
+use tokio::runtime::{Handle, RuntimeFlavor};
+use tonic::Status;
 
+use std::collections::HashSet;
 use std::error::Error as StdError;
 use std::io::{Read, Write};
-use std::option;
 
-use crate::auth::iaaa::IAAAValidateResponse;
+use crate::auth::iaaa::IAAAUserInfo;
+use crate::auth::oidc::IdTokenClaims;
 
 pub(crate) mod models;
+pub mod pool;
 pub(crate) mod schema;
 
+use pool::{PoolConfig, StatementTimeout};
+
 #[derive(Debug, thiserror::Error)]
 pub enum DBError {
     #[error("Fail to connect database: {0}")]
     Connection(String),
     #[error("Fail to fetch connection: {0}")]
     FetchConn(String),
+    #[error("Fail to run database task: {0}")]
+    Task(String),
 }
 
 pub type DBResult<T> = std::result::Result<T, DBError>;
 
+define_sql_function! {
+    /// Case-insensitive comparisons of nicknames.
+    fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text;
+}
+
 /// Database client. Since `PgPool` is clone-safe, `DBClient` is clone-safe as well.
 #[derive(Debug, Clone)]
 pub struct DBClient {
@@ -43,16 +52,21 @@ pub struct DBClient {
 }
 
 impl DBClient {
-    pub fn connect(database_url: &String) -> DBResult<Self> {
+    pub fn connect(database_url: &String, config: PoolConfig) -> DBResult<Self> {
         let manager = ConnectionManager::<PgConnection>::new(database_url);
         let pool = Pool::builder()
             .test_on_check_out(true)
+            .max_size(config.max_size)
+            .connection_timeout(config.connection_timeout)
+            .connection_customizer(Box::new(StatementTimeout(config.statement_timeout)))
             .build(manager)
             .map_err(|e| DBError::Connection(e.to_string()))?;
 
         Ok(Self { pool })
     }
 
+    /// Get a connection, blocking until one is free.
+    /// In async code, use [`DBClient::run`] instead.
     pub fn get_conn(&self) -> DBResult<PooledConnection<ConnectionManager<PgConnection>>> {
         let conn = self
             .pool
@@ -60,7 +74,57 @@ impl DBClient {
             .map_err(|e| DBError::FetchConn(e.to_string()))?;
         Ok(conn)
     }
+
+    /// Run `f` with a connection on the blocking threads of the runtime,
+    /// so that waiting for the pool or for queries does not stall the
+    /// other requests. Failing to get a connection is an error of `f`.
+    pub async fn run<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut PooledConnection<ConnectionManager<PgConnection>>) -> Result<T, E>
+            + Send
+            + 'static,
+        T: Send + 'static,
+        E: From<DBError> + Send + 'static,
+    {
+        let client = self.clone();
+        tokio::task::spawn_blocking(move || f(&mut client.get_conn()?))
+            .await
+            .map_err(|e| DBError::Task(e.to_string()))?
+    }
+
+    /// Like [`DBClient::run`], for synchronous code called on the runtime,
+    /// such as interceptors: on a multi-thread runtime, the other tasks of
+    /// the thread move elsewhere while `f` blocks. A current-thread runtime
+    /// has nowhere to move them, so `f` blocks it.
+    ///
+    /// Code holding a connection already should pass it on instead: under
+    /// load, every connection would be held while waiting for another one.
+    pub fn run_blocking<T, E, F>(&self, f: F) -> Result<T, E>
+    where
+        F: FnOnce(&mut PooledConnection<ConnectionManager<PgConnection>>) -> Result<T, E>,
+        E: From<DBError>,
+    {
+        match Handle::try_current().map(|handle| handle.runtime_flavor()) {
+            Ok(RuntimeFlavor::MultiThread) => {
+                tokio::task::block_in_place(|| f(&mut self.get_conn()?))
+            }
+            // `block_in_place` panics elsewhere
+            _ => f(&mut self.get_conn()?),
+        }
+    }
+}
+
+impl From<DBError> for Status {
+    fn from(e: DBError) -> Self {
+        error!("Fail to access database: {e}");
+        match e {
+            // the pool is exhausted, or the database is down
+            DBError::FetchConn(_) => Status::unavailable("Database is busy, try again later"),
+            DBError::Connection(_) | DBError::Task(_) => Status::internal("Database error"),
+        }
+    }
 }
+
 impl models::IaaaNewUser {
     pub fn new(username: String, the_nickname: Option<String>) -> IaaaNewUser {
         IaaaNewUser {
@@ -69,9 +133,6 @@ impl models::IaaaNewUser {
             login_provider: models::LoginProvider::IAAA,
             nickname: the_nickname,
             icon: 0,
-            favorite_posts: NullableIntArray(vec![]),
-            liked_posts: NullableIntArray(vec![]),
-            take_part_posts: NullableIntArray(vec![]),
         }
     }
 }
@@ -88,20 +149,132 @@ impl models::PasswordNewUser {
             nickname: "".into(),
             password: hashed_password,
             icon: 0,
-            favorite_posts: NullableIntArray(vec![]),
-            liked_posts: NullableIntArray(vec![]),
-            take_part_posts: NullableIntArray(vec![]),
         }
     }
 }
-impl models::Comment {
-    pub fn to_proto_comment(&self) -> Comment {
-        let update_time = if let Some(update_naive_time) = self.updated_at {
-            Some(update_naive_time.and_utc().timestamp())
+impl models::OidcNewUser {
+    pub fn new(
+        username: String,
+        the_email: Option<String>,
+        the_nickname: Option<String>,
+        the_email_verified: bool,
+    ) -> OidcNewUser {
+        OidcNewUser {
+            email_verified: the_email_verified && the_email.is_some(),
+            username,
+            email: the_email,
+            login_provider: models::LoginProvider::OIDC,
+            nickname: the_nickname.unwrap_or_default(),
+            icon: 0,
+        }
+    }
+}
+impl models::User {
+    pub fn into_proto_user(
+        self,
+        icon: Vec<u8>,
+        iaaa_profile: Option<models::IaaaProfile>,
+        posts: UserPostIds,
+    ) -> crate::codegen::auth::User {
+        let email_verified = self.is_email_verified();
+        crate::codegen::auth::User {
+            id: self.id,
+            username: self.username,
+            email: self.email,
+            login_provider: self.login_provider as i32,
+            nickname: self.nickname,
+            created_at: self.created_at.and_utc().timestamp(),
+            updated_at: self.updated_at.map(|x| x.and_utc().timestamp()),
+            icon,
+            favorite_posts: posts.favorite,
+            liked_posts: posts.liked,
+            take_part_posts: posts.take_part,
+            role: self.role.to_proto_type().into(),
+            email_verified,
+            iaaa_profile: iaaa_profile.map(models::IaaaProfile::into_proto_profile),
+            delete_after: self.delete_after.map(|x| x.and_utc().timestamp()),
+            profile: None,
+            privacy: None,
+        }
+    }
+
+    /// IAAA accounts are verified by IAAA.
+    pub fn is_email_verified(&self) -> bool {
+        self.login_provider == models::LoginProvider::IAAA || self.email_verified
+    }
+}
+impl models::Identity {
+    pub fn into_proto_identity(self) -> crate::codegen::auth::Identity {
+        crate::codegen::auth::Identity {
+            provider: self.provider.to_proto_type().into(),
+            subject: self.subject,
+            created_at: self.created_at.and_utc().timestamp(),
+        }
+    }
+}
+impl models::Session {
+    /// `current` tells the session of the caller.
+    pub fn into_proto_session(self, current: bool) -> crate::codegen::auth::Session {
+        crate::codegen::auth::Session {
+            id: self.id,
+            device_name: self.device_name,
+            ip_address: self.ip_address,
+            user_agent: self.user_agent,
+            created_at: self.created_at.and_utc().timestamp(),
+            last_seen_at: self.last_seen_at.and_utc().timestamp(),
+            expires_at: self.expires_at.and_utc().timestamp(),
+            current,
+            reported_ip_address: self.reported_ip_address,
+        }
+    }
+}
+impl models::IaaaProfile {
+    pub fn into_proto_profile(self) -> crate::codegen::auth::IaaaProfile {
+        crate::codegen::auth::IaaaProfile {
+            dept_id: self.dept_id,
+            dept: self.dept,
+            campus: self.campus,
+            identity_type: self.identity_type,
+            detail_type: self.detail_type,
+            identity_status: self.identity_status,
+            graduated: self.graduated,
+        }
+    }
+}
+impl models::UserProfile {
+    /// Without `with_contact`, the contact info is left out.
+    pub fn to_proto_profile(&self, with_contact: bool) -> crate::codegen::auth::Profile {
+        let (contact_method, contact) = if with_contact {
+            (self.contact_method, self.contact.clone())
         } else {
-            None
+            (None, None)
         };
-        let comment = Comment {
+        crate::codegen::auth::Profile {
+            bio: self.bio.clone(),
+            pronouns: self.pronouns.clone(),
+            contact_method: contact_method.map(|method| method.to_proto_type().into()),
+            contact,
+            links: self.links.iter().flatten().cloned().collect(),
+        }
+    }
+
+    pub fn to_proto_privacy(&self) -> crate::codegen::auth::PrivacySettings {
+        crate::codegen::auth::PrivacySettings {
+            favorites: self.favorites_visibility.to_proto_type().into(),
+            likes: self.likes_visibility.to_proto_type().into(),
+            participations: self.participations_visibility.to_proto_type().into(),
+            contact: self.contact_visibility.to_proto_type().into(),
+        }
+    }
+}
+impl models::Comment {
+    /// `liked` tells whether the caller likes the comment.
+    pub fn to_proto_comment(&self, liked: bool) -> Comment {
+        let update_time = self
+            .updated_at
+            .map(|update_naive_time| update_naive_time.and_utc().timestamp());
+
+        Comment {
             id: self.id,
             user_id: self.user_id,
             post_id: self.post_id,
@@ -109,14 +282,15 @@ impl models::Comment {
             likes: self.likes,
             created_at: self.created_at.and_utc().timestamp(),
             updated_at: update_time,
-        };
-        comment
+            liked,
+        }
     }
 }
 
 impl models::Post {
     pub fn from_proto_sell_post(
         post: Option<SellPost>,
+        the_user_id: i32,
     ) -> Result<models::NewSellPost, Box<dyn StdError>> {
         if let Some(sell_post) = post {
             // get sell post field
@@ -135,11 +309,10 @@ impl models::Post {
                 // make post to insert
                 let new_sell_post = NewSellPost {
                     title: base_post.title,
-                    user_id: base_post.user_id,
+                    user_id: the_user_id,
                     content: base_post.content,
                     post_type: crate::db::models::PostType::SELLPOST,
                     images: NullableIntArray(image_ids),
-                    comments_id: NullableIntArray(vec![]),
                     contact: the_contact,
 
                     price: Some(the_price),
@@ -155,34 +328,26 @@ impl models::Post {
         }
     }
 
+    /// `viewer` is the user the post is shown to.
     pub fn to_proto_sell_post(
         &self,
         conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        viewer: i32,
     ) -> Result<SellPost, Box<dyn StdError>> {
         if self.post_type != models::PostType::SELLPOST {
             return Err(Box::new(std::fmt::Error));
         }
-        let update_time = if let Some(update_naive_time) = self.updated_at {
-            Some(update_naive_time.and_utc().timestamp())
-        } else {
-            None
-        };
+        let update_time = self
+            .updated_at
+            .map(|update_naive_time| update_naive_time.and_utc().timestamp());
         // get comments
-        let mut comments = vec![];
-        for comment_id in &self.comments_id.0 {
-            if let Some(comment_id) = comment_id {
-                let comment = query_comment_by_id(conn, *comment_id)?;
-                comments.push(comment.to_proto_comment());
-            }
-        }
+        let comments = query_post_comments_as(conn, self.id, viewer)?;
 
         // get images
         let mut images = vec![];
-        for image_id in &self.images.0 {
-            if let Some(image_id) = image_id {
-                let image = query_image_by_id(*image_id)?;
-                images.push(image);
-            }
+        for image_id in self.images.0.iter().flatten() {
+            let image = query_image_by_id(*image_id)?;
+            images.push(image);
         }
 
         let base_post = crate::codegen::post::Post {
@@ -194,8 +359,8 @@ impl models::Post {
             favorates: self.favorates,
             created_at: self.created_at.and_utc().timestamp(),
             updated_at: update_time,
-            comments: comments,
-            images: images,
+            comments,
+            images,
             post_type: self.post_type.to_proto_type().into(),
         };
 
@@ -222,6 +387,7 @@ impl models::Post {
 
     pub fn from_proto_food_post(
         post: Option<FoodPost>,
+        the_user_id: i32,
     ) -> Result<models::NewFoodPost, Box<dyn StdError>> {
         if let Some(food_post) = post {
             // get food post field
@@ -238,11 +404,10 @@ impl models::Post {
                 // make post to insert
                 let new_food_post = NewFoodPost {
                     title: base_post.title,
-                    user_id: base_post.user_id,
+                    user_id: the_user_id,
                     content: base_post.content,
                     post_type: crate::db::models::PostType::FOODPOST,
                     images: NullableIntArray(image_ids),
-                    comments_id: NullableIntArray(vec![]),
 
                     food_place: Some(the_food_place),
                     score: Some(the_score),
@@ -256,34 +421,26 @@ impl models::Post {
         }
     }
 
+    /// `viewer` is the user the post is shown to.
     pub fn to_proto_food_post(
         &self,
         conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        viewer: i32,
     ) -> Result<FoodPost, Box<dyn StdError>> {
         if self.post_type != models::PostType::FOODPOST {
             return Err(Box::new(std::fmt::Error));
         }
-        let update_time = if let Some(update_naive_time) = self.updated_at {
-            Some(update_naive_time.and_utc().timestamp())
-        } else {
-            None
-        };
+        let update_time = self
+            .updated_at
+            .map(|update_naive_time| update_naive_time.and_utc().timestamp());
         // get comments
-        let mut comments = vec![];
-        for comment_id in &self.comments_id.0 {
-            if let Some(comment_id) = comment_id {
-                let comment = query_comment_by_id(conn, *comment_id)?;
-                comments.push(comment.to_proto_comment());
-            }
-        }
+        let comments = query_post_comments_as(conn, self.id, viewer)?;
 
         // get images
         let mut images = vec![];
-        for image_id in &self.images.0 {
-            if let Some(image_id) = image_id {
-                let image = query_image_by_id(*image_id)?;
-                images.push(image);
-            }
+        for image_id in self.images.0.iter().flatten() {
+            let image = query_image_by_id(*image_id)?;
+            images.push(image);
         }
 
         let base_post = crate::codegen::post::Post {
@@ -295,8 +452,8 @@ impl models::Post {
             favorates: self.favorates,
             created_at: self.created_at.and_utc().timestamp(),
             updated_at: update_time,
-            comments: comments,
-            images: images,
+            comments,
+            images,
             post_type: self.post_type.to_proto_type().into(),
         };
 
@@ -319,6 +476,7 @@ impl models::Post {
 
     pub fn from_proto_amusement_post(
         post: Option<AmusementPost>,
+        the_user_id: i32,
     ) -> Result<models::NewAmusementPost, Box<dyn StdError>> {
         if let Some(amusement_post) = post {
             // get amusement post field
@@ -328,7 +486,8 @@ impl models::Post {
                 &amusement_post.game_type(),
             ));
             let the_amuse_place = Some(amusement_post.amuse_place);
-            let the_start_time = Some(NaiveDateTime::from_timestamp(amusement_post.start_time, 0));
+            let the_start_time =
+                DateTime::from_timestamp(amusement_post.start_time, 0).map(|t| t.naive_utc());
             let the_contact = Some(amusement_post.contact);
             if let Some(base_post) = amusement_post.post {
                 // store images
@@ -341,11 +500,10 @@ impl models::Post {
                 // make post to insert
                 let new_amusement_post = NewAmusementPost {
                     title: base_post.title,
-                    user_id: base_post.user_id,
+                    user_id: the_user_id,
                     content: base_post.content,
                     post_type: crate::db::models::PostType::AMUSEMENTPOST,
                     images: NullableIntArray(image_ids),
-                    comments_id: NullableIntArray(vec![]),
 
                     people_all: the_people_all,
                     people_already: the_people_already,
@@ -364,34 +522,26 @@ impl models::Post {
     }
 
     // convert a models::Post to codegen::amusement_post::AmusementPost
+    /// `viewer` is the user the post is shown to.
     pub fn to_proto_amusement_post(
         &self,
         conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        viewer: i32,
     ) -> Result<AmusementPost, Box<dyn StdError>> {
         if self.post_type != models::PostType::AMUSEMENTPOST {
             return Err(Box::new(std::fmt::Error));
         }
-        let update_time = if let Some(update_naive_time) = self.updated_at {
-            Some(update_naive_time.and_utc().timestamp())
-        } else {
-            None
-        };
+        let update_time = self
+            .updated_at
+            .map(|update_naive_time| update_naive_time.and_utc().timestamp());
         // get comments
-        let mut comments = vec![];
-        for comment_id in &self.comments_id.0 {
-            if let Some(comment_id) = comment_id {
-                let comment = query_comment_by_id(conn, *comment_id)?;
-                comments.push(comment.to_proto_comment());
-            }
-        }
+        let comments = query_post_comments_as(conn, self.id, viewer)?;
 
         // get images
         let mut images = vec![];
-        for image_id in &self.images.0 {
-            if let Some(image_id) = image_id {
-                let image = query_image_by_id(*image_id)?;
-                images.push(image);
-            }
+        for image_id in self.images.0.iter().flatten() {
+            let image = query_image_by_id(*image_id)?;
+            images.push(image);
         }
 
         let base_post = crate::codegen::post::Post {
@@ -403,8 +553,8 @@ impl models::Post {
             favorates: self.favorates,
             created_at: self.created_at.and_utc().timestamp(),
             updated_at: update_time,
-            comments: comments,
-            images: images,
+            comments,
+            images,
             post_type: self.post_type.to_proto_type().into(),
         };
 
@@ -448,58 +598,125 @@ impl models::Post {
     }
 }
 
+/// Find the user of an IAAA identity, registering one on its first login.
 pub fn get_iaaa_user_from_db(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-    resp: IAAAValidateResponse,
+    user_info: &IAAAUserInfo,
 ) -> Result<models::User, Box<dyn StdError>> {
-    use crate::dbschema::Users::dsl::*;
-    let dbuser: Option<models::User> = Users
-        .filter(schema::Users::username.eq(&resp.user_info.identity_id))
-        .select(models::User::as_select())
-        .first(conn)
-        .ok();
+    let identity_id = &user_info.identity_id;
+    if let Some(dbuser) = query_user_by_identity(conn, models::LoginProvider::IAAA, identity_id)? {
+        return Ok(dbuser);
+    }
 
-    let dbuser = if let Some(dbuser) = dbuser {
-        dbuser
-    } else {
-        // create a new user
-        let new_user =
-            models::IaaaNewUser::new(resp.user_info.identity_id, Some(resp.user_info.name));
+    // create a new user
+    let new_user = models::IaaaNewUser::new(identity_id.clone(), Some(user_info.name.clone()));
+    let new_user = conn
+        .transaction(|conn| {
+            let new_user: models::User = diesel::insert_into(schema::Users::table)
+                .values(&new_user)
+                .returning(models::User::as_returning())
+                .get_result(conn)?;
+            diesel::insert_into(schema::Identities::table)
+                .values(&models::NewIdentity {
+                    user_id: new_user.id,
+                    provider: models::LoginProvider::IAAA,
+                    subject: identity_id.clone(),
+                })
+                .execute(conn)?;
+            Ok::<_, diesel::result::Error>(new_user)
+        })
+        .map_err(|e| format!("Failed to create user: {e}"))?;
+    Ok(new_user)
+}
+
+/// Find the user of an OIDC identity, registering one on its first login.
+/// The new user is named after its `preferred_username`, unless it is taken.
+pub fn get_oidc_user_from_db(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    claims: &IdTokenClaims,
+) -> Result<models::User, Box<dyn StdError>> {
+    if let Some(dbuser) = query_user_by_identity(conn, models::LoginProvider::OIDC, &claims.sub)? {
+        return Ok(dbuser);
+    }
+
+    let mut the_username = format!("oidc-{}", claims.sub);
+    if let Some(preferred) = claims.preferred_username.as_deref() {
+        if !preferred.is_empty()
+            && query_user_by_username(conn, preferred)?.is_none()
+            && query_user_by_identity(conn, models::LoginProvider::PASSWORD, preferred)?.is_none()
+        {
+            the_username = preferred.to_string();
+        }
+    }
+    let new_user = models::OidcNewUser::new(
+        the_username,
+        claims.email.clone(),
+        claims.name.clone(),
+        claims.email_verified,
+    );
+    let new_user = conn.transaction(|conn| {
         let new_user: models::User = diesel::insert_into(schema::Users::table)
             .values(&new_user)
             .returning(models::User::as_returning())
-            .get_result(conn)
-            .map_err(|_| "Failed to create user")?;
-        new_user
-    };
-    Ok(dbuser)
+            .get_result(conn)?;
+        diesel::insert_into(schema::Identities::table)
+            .values(&models::NewIdentity {
+                user_id: new_user.id,
+                provider: models::LoginProvider::OIDC,
+                subject: claims.sub.clone(),
+            })
+            .execute(conn)?;
+        Ok::<_, diesel::result::Error>(new_user)
+    })?;
+    Ok(new_user)
 }
 
+/// Find the user who logs in with `user_name` and a password.
 pub fn get_password_user_from_db(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
-    user_name: &String,
+    user_name: &str,
 ) -> Result<models::User, Box<dyn StdError>> {
-    use crate::dbschema::Users::dsl::*;
-    let dbuser: models::User = Users
-        .filter(schema::Users::username.eq(&user_name))
-        .select(models::User::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
+    let dbuser = query_user_by_identity(conn, models::LoginProvider::PASSWORD, user_name)?
+        .ok_or("User not found")?;
     Ok(dbuser)
 }
 
+/// Insert a user along with its password identity.
 pub fn insert_password_user_into_db(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     new_user: &models::PasswordNewUser,
 ) -> Result<models::User, Box<dyn StdError>> {
     use crate::dbschema::Users::dsl::*;
-    let new_user = diesel::insert_into(Users)
-        .values(new_user)
-        .returning(models::User::as_returning())
-        .get_result(conn)?;
+    let new_user = conn.transaction(|conn| {
+        let new_user: models::User = diesel::insert_into(Users)
+            .values(new_user)
+            .returning(models::User::as_returning())
+            .get_result(conn)?;
+        diesel::insert_into(schema::Identities::table)
+            .values(&models::NewIdentity {
+                user_id: new_user.id,
+                provider: models::LoginProvider::PASSWORD,
+                subject: new_user.username.clone(),
+            })
+            .execute(conn)?;
+        Ok::<_, diesel::result::Error>(new_user)
+    })?;
     Ok(new_user)
 }
 
+pub fn query_user_by_username(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_username: &str,
+) -> Result<Option<models::User>, Box<dyn StdError>> {
+    use crate::dbschema::Users::dsl::*;
+    let user = Users
+        .filter(username.eq(the_username))
+        .select(models::User::as_select())
+        .first(conn)
+        .optional()?;
+    Ok(user)
+}
+
 pub fn get_user_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_user_id: i32,
@@ -586,6 +803,41 @@ pub fn query_post_by_id(
     Ok(post)
 }
 
+/// Comments of a post, oldest first.
+pub fn query_post_comments(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_post_id: i32,
+) -> Result<Vec<models::Comment>, Box<dyn StdError>> {
+    use crate::dbschema::Comments::dsl::*;
+    let comments = Comments
+        .filter(post_id.eq(the_post_id))
+        .order(id)
+        .select(models::Comment::as_select())
+        .load(conn)?;
+    Ok(comments)
+}
+
+/// Comments of a post as `viewer` sees them, oldest first.
+fn query_post_comments_as(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_post_id: i32,
+    viewer: i32,
+) -> Result<Vec<Comment>, Box<dyn StdError>> {
+    let comments = query_post_comments(conn, the_post_id)?;
+    let liked: HashSet<i32> = schema::CommentLikes::table
+        .inner_join(schema::Comments::table)
+        .filter(schema::CommentLikes::user_id.eq(viewer))
+        .filter(schema::Comments::post_id.eq(the_post_id))
+        .select(schema::CommentLikes::comment_id)
+        .load::<i32>(conn)?
+        .into_iter()
+        .collect();
+    Ok(comments
+        .iter()
+        .map(|comment| comment.to_proto_comment(liked.contains(&comment.id)))
+        .collect())
+}
+
 pub fn query_comment_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     comment_id: i32,
@@ -599,6 +851,15 @@ pub fn query_comment_by_id(
     Ok(comment)
 }
 
+/// Ids of the IAAA users of a department, to filter posts by their authors.
+#[diesel::dsl::auto_type(no_type_alias)]
+fn users_of_dept(the_dept_id: String) -> _ {
+    schema::IaaaProfiles::table
+        .filter(schema::IaaaProfiles::dept_id.eq(the_dept_id))
+        .select(schema::IaaaProfiles::user_id)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn query_and_filter_amusement_post(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_game_type: Option<models::GameType>,
@@ -606,54 +867,35 @@ pub fn query_and_filter_amusement_post(
     the_people_all_upbound: i32,
     the_people_diff_upbound: i32,
     the_time_about: Option<NaiveDateTime>,
+    the_dept_id: Option<String>,
     limit: i32,
 ) -> Result<Vec<models::Post>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    let posts = Posts
+    let mut posts = Posts
         .filter(schema::Posts::post_type.eq(&models::PostType::AMUSEMENTPOST))
         .filter(schema::Posts::people_all.ge(the_people_all_lowbound))
         .filter(schema::Posts::people_all.le(the_people_all_upbound))
         .filter(
             (schema::Posts::people_all - schema::Posts::people_already).le(the_people_diff_upbound),
-        );
-    if the_game_type.is_some() {
-        let posts = posts.filter(schema::Posts::game_type.eq(the_game_type.unwrap())); // safe unwrap
-        if the_time_about.is_some() {
-            let posts = posts
-                .filter(schema::Posts::start_time.le(the_time_about.unwrap() + Duration::hours(2)))
-                .filter(schema::Posts::start_time.ge(the_time_about.unwrap() - Duration::hours(2)))
-                .limit(limit.into())
-                .select(models::Post::as_select())
-                .load(conn)
-                .map_err(|e| e.to_string())?;
-            Ok(posts)
-        } else {
-            let posts = posts
-                .limit(limit.into())
-                .select(models::Post::as_select())
-                .load(conn)
-                .map_err(|e| e.to_string())?;
-            Ok(posts)
-        }
-    } else {
-        if the_time_about.is_some() {
-            let posts = posts
-                .filter(schema::Posts::start_time.le(the_time_about.unwrap() + Duration::hours(2)))
-                .filter(schema::Posts::start_time.ge(the_time_about.unwrap() - Duration::hours(2)))
-                .limit(limit.into())
-                .select(models::Post::as_select())
-                .load(conn)
-                .map_err(|e| e.to_string())?;
-            Ok(posts)
-        } else {
-            let posts = posts
-                .limit(limit.into())
-                .select(models::Post::as_select())
-                .load(conn)
-                .map_err(|e| e.to_string())?;
-            Ok(posts)
-        }
+        )
+        .into_boxed();
+    if let Some(the_game_type) = the_game_type {
+        posts = posts.filter(schema::Posts::game_type.eq(the_game_type));
+    }
+    if let Some(the_time_about) = the_time_about {
+        posts = posts
+            .filter(schema::Posts::start_time.le(the_time_about + Duration::hours(2)))
+            .filter(schema::Posts::start_time.ge(the_time_about - Duration::hours(2)));
+    }
+    if let Some(the_dept_id) = the_dept_id {
+        posts = posts.filter(schema::Posts::user_id.eq_any(users_of_dept(the_dept_id)));
     }
+    let posts = posts
+        .limit(limit.into())
+        .select(models::Post::as_select())
+        .load(conn)
+        .map_err(|e| e.to_string())?;
+    Ok(posts)
 }
 
 pub fn query_and_filter_food_post(
@@ -661,40 +903,39 @@ pub fn query_and_filter_food_post(
     the_food_place: Option<models::Place>,
     the_score_lowbound: i32,
     is_random: bool,
+    the_dept_id: Option<String>,
     limit: i32,
 ) -> Result<Vec<models::Post>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
+    let mut posts = Posts
+        .filter(schema::Posts::post_type.eq(&models::PostType::FOODPOST))
+        .into_boxed();
+    if let Some(the_dept_id) = the_dept_id {
+        posts = posts.filter(schema::Posts::user_id.eq_any(users_of_dept(the_dept_id)));
+    }
     if is_random {
-        let mut the_posts: Vec<models::Post> = Posts
-            .filter(schema::Posts::post_type.eq(&models::PostType::FOODPOST))
+        let mut the_posts: Vec<models::Post> = posts
             .limit(limit.into())
             .select(models::Post::as_select())
             .load(conn)
             .map_err(|e| e.to_string())?;
+        if the_posts.is_empty() {
+            return Ok(the_posts);
+        }
         let the_random_one = the_posts.remove(rand::thread_rng().gen::<usize>() % the_posts.len());
         let posts = vec![the_random_one];
         Ok(posts)
     } else {
-        let posts = Posts
-            .filter(schema::Posts::post_type.eq(&models::PostType::FOODPOST))
-            .filter(schema::Posts::score.ge(the_score_lowbound));
-
-        if the_food_place.is_some() {
-            let posts = posts
-                .filter(schema::Posts::food_place.eq(the_food_place.unwrap())) //safe unwrap
-                .limit(limit.into())
-                .select(models::Post::as_select())
-                .load(conn)
-                .map_err(|e| e.to_string())?;
-            Ok(posts)
-        } else {
-            let posts = posts
-                .limit(limit.into())
-                .select(models::Post::as_select())
-                .load(conn)
-                .map_err(|e| e.to_string())?;
-            Ok(posts)
+        posts = posts.filter(schema::Posts::score.ge(the_score_lowbound));
+        if let Some(the_food_place) = the_food_place {
+            posts = posts.filter(schema::Posts::food_place.eq(the_food_place));
         }
+        let posts = posts
+            .limit(limit.into())
+            .select(models::Post::as_select())
+            .load(conn)
+            .map_err(|e| e.to_string())?;
+        Ok(posts)
     }
 }
 
@@ -702,91 +943,50 @@ pub fn query_and_filter_sell_post(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_goods_type: Option<models::GoodsType>,
     price_upbound: i32,
+    the_dept_id: Option<String>,
     limit: i32,
 ) -> Result<Vec<models::Post>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    let the_post = Posts
+    let mut the_post = Posts
         .filter(schema::Posts::post_type.eq(&models::PostType::SELLPOST))
         .filter(schema::Posts::price.le(price_upbound))
-        .filter(schema::Posts::sold.eq(false));
-    if the_goods_type.is_some() {
-        let the_post = the_post
-            .filter(schema::Posts::goods_type.eq(&the_goods_type.unwrap()))
-            .limit(limit.into())
-            .select(models::Post::as_select())
-            .load(conn)
-            .map_err(|e| e.to_string())?;
-        Ok(the_post)
-    } else {
-        let the_post = the_post
-            .limit(limit.into())
-            .select(models::Post::as_select())
-            .load(conn)
-            .map_err(|e| e.to_string())?;
-
-        Ok(the_post)
+        .filter(schema::Posts::sold.eq(false))
+        .into_boxed();
+    if let Some(the_goods_type) = the_goods_type {
+        the_post = the_post.filter(schema::Posts::goods_type.eq(the_goods_type));
     }
+    if let Some(the_dept_id) = the_dept_id {
+        the_post = the_post.filter(schema::Posts::user_id.eq_any(users_of_dept(the_dept_id)));
+    }
+    let the_post = the_post
+        .limit(limit.into())
+        .select(models::Post::as_select())
+        .load(conn)
+        .map_err(|e| e.to_string())?;
+    Ok(the_post)
 }
 
-pub fn insert_comment_and_update_post(
+pub fn insert_comment(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     new_comment: &models::NewComment,
-) -> Result<(), Box<dyn StdError>> {
+) -> Result<models::Comment, Box<dyn StdError>> {
     use crate::dbschema::Comments::dsl::*;
-    use crate::dbschema::Posts::dsl::*;
-
-    let inserted_comment: models::Comment = diesel::insert_into(Comments)
+    let inserted_comment = diesel::insert_into(Comments)
         .values(new_comment)
+        .returning(models::Comment::as_returning())
         .get_result(conn)?;
-
-    let new_comments_id = {
-        let mut current_comments_id: NullableIntArray = Posts
-            .filter(schema::Posts::id.eq(inserted_comment.post_id))
-            .select(comments_id)
-            .first(conn)?;
-
-        current_comments_id.0.push(Some(inserted_comment.id));
-        current_comments_id
-    };
-
-    diesel::update(Posts.filter(schema::Posts::id.eq(inserted_comment.post_id)))
-        .set(comments_id.eq(new_comments_id))
-        .execute(conn)?;
-
-    Ok(())
+    Ok(inserted_comment)
 }
 
-pub fn delete_comment_and_update_post(
+pub fn delete_comment(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     comment_id: i32,
 ) -> Result<(), Box<dyn StdError>> {
     use crate::dbschema::Comments::dsl::*;
-    use crate::dbschema::Posts::dsl::*;
-
-    // 获取要删除的评论
-    let comment_to_delete: models::Comment = Comments
-        .filter(schema::Comments::id.eq(comment_id))
-        .first(conn)?;
-
-    // 删除评论
-    diesel::delete(Comments.filter(schema::Comments::id.eq(comment_id))).execute(conn)?;
-
-    // 更新Post的comments_id字段
-    let new_comments_id = {
-        let mut current_comments_id: NullableIntArray = Posts
-            .filter(schema::Posts::id.eq(comment_to_delete.post_id))
-            .select(comments_id)
-            .first(conn)?;
-
-        // 从comments_id数组中移除要删除的评论ID
-        current_comments_id.0.retain(|&x| x != Some(comment_id));
-        current_comments_id
-    };
-
-    diesel::update(Posts.filter(schema::Posts::id.eq(comment_to_delete.post_id)))
-        .set(comments_id.eq(new_comments_id))
-        .execute(conn)?;
-
+    let deleted = diesel::delete(Comments.filter(id.eq(comment_id))).execute(conn)?;
+    if deleted == 0 {
+        return Err("Comment not found".into());
+    }
     Ok(())
 }
 
@@ -818,242 +1018,455 @@ pub fn set_sold_for_sell_post_by_id(
     Ok(())
 }
 
-/// add post_id into user's liked_posts
-/// and add post's likes by 1
+/// The state of a like, favorite or participation after toggling it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Toggled {
+    /// false if it was so already, and nothing was done
+    pub changed: bool,
+    /// the counter of the post or comment after toggling
+    pub count: i32,
+}
+
+/// record that the user likes the post
+/// and add post's likes by 1.
+/// `None` if there is no such post
 pub fn like_post_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_user_id: i32,
     the_post_id: i32,
-) -> Result<(), Box<dyn StdError>> {
+) -> Result<Option<Toggled>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    use crate::dbschema::Users::dsl::*;
-    let the_user: models::User = Users
-        .filter(schema::Users::id.eq(the_user_id))
-        .select(models::User::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let mut user_like_ids = the_user.liked_posts.0;
-    if user_like_ids.iter().any(|x| x == &Some(the_post_id)) {
-        // already liked
-        // do not report error to frontend
-        return Ok(());
-    }
-    user_like_ids.push(Some(the_post_id));
-    let user_like_ids = NullableIntArray(user_like_ids);
-
-    let the_post: models::Post = Posts
-        .filter(schema::Posts::id.eq(the_post_id))
-        .select(models::Post::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let likes_before = the_post.likes;
-
-    diesel::update(Posts.filter(schema::Posts::id.eq(the_post_id)))
-        .set(likes.eq(likes_before + 1))
-        .execute(conn)?;
-
-    diesel::update(Users.filter(schema::Users::id.eq(the_user_id)))
-        .set(liked_posts.eq(user_like_ids))
-        .execute(conn)?;
-
-    Ok(())
+    let toggled = conn.transaction(|conn| {
+        let Some(count) = Posts
+            .find(the_post_id)
+            .select(likes)
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let inserted = diesel::insert_into(schema::PostLikes::table)
+            .values(&models::NewPostLike {
+                user_id: the_user_id,
+                post_id: the_post_id,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        if inserted == 0 {
+            // already liked
+            return Ok(Some(Toggled {
+                changed: false,
+                count,
+            }));
+        }
+        let count = diesel::update(Posts.find(the_post_id))
+            .set(likes.eq(likes + 1))
+            .returning(likes)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(Some(Toggled {
+            changed: true,
+            count,
+        }))
+    })?;
+    Ok(toggled)
 }
 
-/// delete post_id from user's liked_posts
-/// and minus post's likes by 1
+/// forget that the user likes the post
+/// and minus post's likes by 1.
+/// `None` if there is no such post
 pub fn unlike_post_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_user_id: i32,
     the_post_id: i32,
-) -> Result<(), Box<dyn StdError>> {
+) -> Result<Option<Toggled>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    use crate::dbschema::Users::dsl::*;
-    let the_user: models::User = Users
-        .filter(schema::Users::id.eq(the_user_id))
-        .select(models::User::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let mut user_like_ids = the_user.liked_posts.0;
-    if !(user_like_ids.iter().any(|x| x == &Some(the_post_id))) {
-        // not liked
-        // do not report error to frontend
-        return Ok(());
-    }
-    user_like_ids.retain(|x| x != &Some(the_post_id));
-    let user_like_ids = NullableIntArray(user_like_ids);
-
-    let the_post: models::Post = Posts
-        .filter(schema::Posts::id.eq(the_post_id))
-        .select(models::Post::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let likes_before = the_post.likes;
-
-    diesel::update(Posts.filter(schema::Posts::id.eq(the_post_id)))
-        .set(likes.eq(likes_before - 1))
-        .execute(conn)?;
-
-    diesel::update(Users.filter(schema::Users::id.eq(the_user_id)))
-        .set(liked_posts.eq(user_like_ids))
+    let toggled = conn.transaction(|conn| {
+        let Some(count) = Posts
+            .find(the_post_id)
+            .select(likes)
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let deleted = diesel::delete(
+            schema::PostLikes::table
+                .filter(schema::PostLikes::user_id.eq(the_user_id))
+                .filter(schema::PostLikes::post_id.eq(the_post_id)),
+        )
         .execute(conn)?;
-
-    Ok(())
+        if deleted == 0 {
+            // not liked
+            return Ok(Some(Toggled {
+                changed: false,
+                count,
+            }));
+        }
+        let count = diesel::update(Posts.find(the_post_id))
+            .set(likes.eq(likes - 1))
+            .returning(likes)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(Some(Toggled {
+            changed: true,
+            count,
+        }))
+    })?;
+    Ok(toggled)
 }
 
+/// record that the user favorites the post
+/// and add post's favorates by 1.
+/// `None` if there is no such post
 pub fn favorate_post_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_user_id: i32,
     the_post_id: i32,
-) -> Result<(), Box<dyn StdError>> {
+) -> Result<Option<Toggled>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    use crate::dbschema::Users::dsl::*;
-    let the_user: models::User = Users
-        .filter(schema::Users::id.eq(the_user_id))
-        .select(models::User::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let mut user_favorate_ids = the_user.favorite_posts.0;
-    if user_favorate_ids.iter().any(|x| x == &Some(the_post_id)) {
-        // already liked
-        // do not report error to frontend
-        return Ok(());
-    }
-    user_favorate_ids.push(Some(the_post_id));
-    let user_favorate_ids = NullableIntArray(user_favorate_ids);
-
-    let the_post: models::Post = Posts
-        .filter(schema::Posts::id.eq(the_post_id))
-        .select(models::Post::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let favorate_before = the_post.favorates;
-
-    diesel::update(Posts.filter(schema::Posts::id.eq(the_post_id)))
-        .set(favorates.eq(favorate_before + 1))
-        .execute(conn)?;
-
-    diesel::update(Users.filter(schema::Users::id.eq(the_user_id)))
-        .set(favorite_posts.eq(user_favorate_ids))
-        .execute(conn)?;
-
-    Ok(())
+    let toggled = conn.transaction(|conn| {
+        let Some(count) = Posts
+            .find(the_post_id)
+            .select(favorates)
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let inserted = diesel::insert_into(schema::PostFavorites::table)
+            .values(&models::NewPostFavorite {
+                user_id: the_user_id,
+                post_id: the_post_id,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        if inserted == 0 {
+            // already favorited
+            return Ok(Some(Toggled {
+                changed: false,
+                count,
+            }));
+        }
+        let count = diesel::update(Posts.find(the_post_id))
+            .set(favorates.eq(favorates + 1))
+            .returning(favorates)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(Some(Toggled {
+            changed: true,
+            count,
+        }))
+    })?;
+    Ok(toggled)
 }
 
+/// forget that the user favorites the post
+/// and minus post's favorates by 1.
+/// `None` if there is no such post
 pub fn unfavorate_post_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_user_id: i32,
     the_post_id: i32,
-) -> Result<(), Box<dyn StdError>> {
+) -> Result<Option<Toggled>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    use crate::dbschema::Users::dsl::*;
-    let the_user: models::User = Users
-        .filter(schema::Users::id.eq(the_user_id))
-        .select(models::User::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let mut user_favorate_ids = the_user.favorite_posts.0;
-    if !(user_favorate_ids.iter().any(|x| x == &Some(the_post_id))) {
-        // already liked
-        // do not report error to frontend
-        return Ok(());
-    }
-    user_favorate_ids.retain(|x| x != &Some(the_post_id));
-    let user_favorate_ids = NullableIntArray(user_favorate_ids);
-
-    let the_post: models::Post = Posts
-        .filter(schema::Posts::id.eq(the_post_id))
-        .select(models::Post::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let favorate_before = the_post.favorates;
-
-    diesel::update(Posts.filter(schema::Posts::id.eq(the_post_id)))
-        .set(favorates.eq(favorate_before - 1))
+    let toggled = conn.transaction(|conn| {
+        let Some(count) = Posts
+            .find(the_post_id)
+            .select(favorates)
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let deleted = diesel::delete(
+            schema::PostFavorites::table
+                .filter(schema::PostFavorites::user_id.eq(the_user_id))
+                .filter(schema::PostFavorites::post_id.eq(the_post_id)),
+        )
         .execute(conn)?;
+        if deleted == 0 {
+            // not favorited
+            return Ok(Some(Toggled {
+                changed: false,
+                count,
+            }));
+        }
+        let count = diesel::update(Posts.find(the_post_id))
+            .set(favorates.eq(favorates - 1))
+            .returning(favorates)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(Some(Toggled {
+            changed: true,
+            count,
+        }))
+    })?;
+    Ok(toggled)
+}
+
+/// record that the user likes the comment
+/// and add comment's likes by 1.
+/// `None` if there is no such comment
+pub fn like_comment_by_id(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    the_comment_id: i32,
+) -> Result<Option<Toggled>, Box<dyn StdError>> {
+    use crate::dbschema::Comments::dsl::*;
+    let toggled = conn.transaction(|conn| {
+        let Some(count) = Comments
+            .find(the_comment_id)
+            .select(likes)
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let inserted = diesel::insert_into(schema::CommentLikes::table)
+            .values(&models::NewCommentLike {
+                user_id: the_user_id,
+                comment_id: the_comment_id,
+            })
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        if inserted == 0 {
+            // already liked
+            return Ok(Some(Toggled {
+                changed: false,
+                count,
+            }));
+        }
+        let count = diesel::update(Comments.find(the_comment_id))
+            .set(likes.eq(likes + 1))
+            .returning(likes)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(Some(Toggled {
+            changed: true,
+            count,
+        }))
+    })?;
+    Ok(toggled)
+}
 
-    diesel::update(Users.filter(schema::Users::id.eq(the_user_id)))
-        .set(favorite_posts.eq(user_favorate_ids))
+/// forget that the user likes the comment
+/// and minus comment's likes by 1.
+/// `None` if there is no such comment
+pub fn unlike_comment_by_id(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    the_comment_id: i32,
+) -> Result<Option<Toggled>, Box<dyn StdError>> {
+    use crate::dbschema::Comments::dsl::*;
+    let toggled = conn.transaction(|conn| {
+        let Some(count) = Comments
+            .find(the_comment_id)
+            .select(likes)
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+        let deleted = diesel::delete(
+            schema::CommentLikes::table
+                .filter(schema::CommentLikes::user_id.eq(the_user_id))
+                .filter(schema::CommentLikes::comment_id.eq(the_comment_id)),
+        )
         .execute(conn)?;
+        if deleted == 0 {
+            // not liked
+            return Ok(Some(Toggled {
+                changed: false,
+                count,
+            }));
+        }
+        let count = diesel::update(Comments.find(the_comment_id))
+            .set(likes.eq(likes - 1))
+            .returning(likes)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(Some(Toggled {
+            changed: true,
+            count,
+        }))
+    })?;
+    Ok(toggled)
+}
 
-    Ok(())
+/// What came of taking part in an amusement post.
+#[derive(Debug, PartialEq, Eq)]
+pub enum TakePart {
+    /// The user takes part, now or already did.
+    Joined(Toggled),
+    /// The event has as many people as it wants.
+    Full,
+    /// No such amusement post.
+    NoSuchEvent,
 }
 
+/// record that the user takes part in the amusement post
+/// and add post's people_already by 1, unless it is full
 pub fn take_part_post_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_user_id: i32,
     the_post_id: i32,
-) -> Result<(), Box<dyn StdError>> {
+) -> Result<TakePart, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    use crate::dbschema::Users::dsl::*;
-    let the_user: models::User = Users
-        .filter(schema::Users::id.eq(the_user_id))
-        .select(models::User::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let mut user_take_part_ids = the_user.take_part_posts.0;
-    if user_take_part_ids.iter().any(|x| x == &Some(the_post_id)) {
-        // already liked
-        // do not report error to frontend
-        return Ok(());
-    }
-    user_take_part_ids.push(Some(the_post_id));
-    let user_take_part_ids = NullableIntArray(user_take_part_ids);
-
-    let the_post: models::Post = Posts
-        .filter(schema::Posts::id.eq(the_post_id))
-        .select(models::Post::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let people_already_before = the_post.people_already.unwrap_or(0);
-
-    diesel::update(Posts.filter(schema::Posts::id.eq(the_post_id)))
-        .set(people_already.eq(people_already_before + 1))
-        .execute(conn)?;
+    let outcome = conn.transaction(|conn| {
+        // lock the post, so that people joining at once do not over-fill it
+        let the_post: Option<(PostType, Option<i32>, Option<i32>)> = Posts
+            .find(the_post_id)
+            .select((post_type, people_all, people_already))
+            .for_update()
+            .first(conn)
+            .optional()?;
+        let Some((PostType::AMUSEMENTPOST, the_people_all, the_people_already)) = the_post else {
+            return Ok(TakePart::NoSuchEvent);
+        };
+        let the_people_already = the_people_already.unwrap_or(0);
 
-    diesel::update(Users.filter(schema::Users::id.eq(the_user_id)))
-        .set(take_part_posts.eq(user_take_part_ids))
-        .execute(conn)?;
+        let joined = diesel::select(diesel::dsl::exists(
+            schema::PostParticipants::table
+                .filter(schema::PostParticipants::user_id.eq(the_user_id))
+                .filter(schema::PostParticipants::post_id.eq(the_post_id)),
+        ))
+        .get_result(conn)?;
+        if joined {
+            // already taken part
+            return Ok(TakePart::Joined(Toggled {
+                changed: false,
+                count: the_people_already,
+            }));
+        }
+        if the_people_already >= the_people_all.unwrap_or(0) {
+            return Ok(TakePart::Full);
+        }
 
-    Ok(())
+        diesel::insert_into(schema::PostParticipants::table)
+            .values(&models::NewPostParticipant {
+                user_id: the_user_id,
+                post_id: the_post_id,
+            })
+            .execute(conn)?;
+        let count: Option<i32> = diesel::update(Posts.find(the_post_id))
+            .set(people_already.eq(people_already + 1))
+            .returning(people_already)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(TakePart::Joined(Toggled {
+            changed: true,
+            count: count.unwrap_or(0),
+        }))
+    })?;
+    Ok(outcome)
 }
 
+/// forget that the user takes part in the amusement post
+/// and minus post's people_already by 1.
+/// `None` if there is no such amusement post
 pub fn no_take_part_post_by_id(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     the_user_id: i32,
     the_post_id: i32,
-) -> Result<(), Box<dyn StdError>> {
+) -> Result<Option<Toggled>, Box<dyn StdError>> {
     use crate::dbschema::Posts::dsl::*;
-    use crate::dbschema::Users::dsl::*;
-    let the_user: models::User = Users
-        .filter(schema::Users::id.eq(the_user_id))
-        .select(models::User::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let mut user_take_part_ids = the_user.take_part_posts.0;
-    if !(user_take_part_ids.iter().any(|x| x == &Some(the_post_id))) {
-        // already liked
-        // do not report error to frontend
-        return Ok(());
-    }
-    user_take_part_ids.retain(|x| x != &Some(the_post_id));
-    let user_take_part_ids = NullableIntArray(user_take_part_ids);
-
-    let the_post: models::Post = Posts
-        .filter(schema::Posts::id.eq(the_post_id))
-        .select(models::Post::as_select())
-        .first(conn)
-        .map_err(|e| e.to_string())?;
-    let people_already_before = the_post.people_already.unwrap_or(1);
+    let toggled = conn.transaction(|conn| {
+        let the_post: Option<(PostType, Option<i32>)> = Posts
+            .find(the_post_id)
+            .select((post_type, people_already))
+            .first(conn)
+            .optional()?;
+        let Some((PostType::AMUSEMENTPOST, the_people_already)) = the_post else {
+            return Ok(None);
+        };
 
-    diesel::update(Posts.filter(schema::Posts::id.eq(the_post_id)))
-        .set(people_already.eq(people_already_before - 1))
+        let deleted = diesel::delete(
+            schema::PostParticipants::table
+                .filter(schema::PostParticipants::user_id.eq(the_user_id))
+                .filter(schema::PostParticipants::post_id.eq(the_post_id)),
+        )
         .execute(conn)?;
+        if deleted == 0 {
+            // not taken part
+            return Ok(Some(Toggled {
+                changed: false,
+                count: the_people_already.unwrap_or(0),
+            }));
+        }
+        let count: Option<i32> = diesel::update(Posts.find(the_post_id))
+            .set(people_already.eq(people_already - 1))
+            .returning(people_already)
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(Some(Toggled {
+            changed: true,
+            count: count.unwrap_or(0),
+        }))
+    })?;
+    Ok(toggled)
+}
 
-    diesel::update(Users.filter(schema::Users::id.eq(the_user_id)))
-        .set(take_part_posts.eq(user_take_part_ids))
-        .execute(conn)?;
+/// Posts a user liked, favorited and took part in, in the order it did.
+#[derive(Debug, Default)]
+pub struct UserPostIds {
+    pub liked: Vec<i32>,
+    pub favorite: Vec<i32>,
+    pub take_part: Vec<i32>,
+}
 
-    Ok(())
+pub fn query_user_post_ids(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<UserPostIds, Box<dyn StdError>> {
+    let liked = schema::PostLikes::table
+        .filter(schema::PostLikes::user_id.eq(the_user_id))
+        .order(schema::PostLikes::id)
+        .select(schema::PostLikes::post_id)
+        .load(conn)?;
+    let favorite = schema::PostFavorites::table
+        .filter(schema::PostFavorites::user_id.eq(the_user_id))
+        .order(schema::PostFavorites::id)
+        .select(schema::PostFavorites::post_id)
+        .load(conn)?;
+    let take_part = schema::PostParticipants::table
+        .filter(schema::PostParticipants::user_id.eq(the_user_id))
+        .order(schema::PostParticipants::id)
+        .select(schema::PostParticipants::post_id)
+        .load(conn)?;
+    Ok(UserPostIds {
+        liked,
+        favorite,
+        take_part,
+    })
+}
+
+/// Posts of `the_post_type` the user favorited, in the order it did.
+pub fn query_favorite_posts(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    the_post_type: PostType,
+    number: i32,
+) -> Result<Vec<models::Post>, Box<dyn StdError>> {
+    let posts = schema::PostFavorites::table
+        .inner_join(schema::Posts::table)
+        .filter(schema::PostFavorites::user_id.eq(the_user_id))
+        .filter(schema::Posts::post_type.eq(&the_post_type))
+        .order(schema::PostFavorites::id)
+        .limit(number.into())
+        .select(models::Post::as_select())
+        .load(conn)?;
+    Ok(posts)
+}
+
+/// Posts of `the_post_type` the user took part in, in the order it did.
+pub fn query_take_part_posts(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    the_post_type: PostType,
+    number: i32,
+) -> Result<Vec<models::Post>, Box<dyn StdError>> {
+    let posts = schema::PostParticipants::table
+        .inner_join(schema::Posts::table)
+        .filter(schema::PostParticipants::user_id.eq(the_user_id))
+        .filter(schema::Posts::post_type.eq(&the_post_type))
+        .order(schema::PostParticipants::id)
+        .limit(number.into())
+        .select(models::Post::as_select())
+        .load(conn)?;
+    Ok(posts)
 }
 
 pub fn query_image_by_id(image_id: i32) -> Result<Vec<u8>, Box<dyn StdError>> {
@@ -1072,7 +1485,7 @@ pub fn query_image_by_id(image_id: i32) -> Result<Vec<u8>, Box<dyn StdError>> {
 }
 
 // static mut IMAGE_ID: i32 = 0;
-pub fn add_image(images: &Vec<u8>) -> Result<i32, Box<dyn StdError>> {
+pub fn add_image(images: &[u8]) -> Result<i32, Box<dyn StdError>> {
     //TODO: write file to local filesystem and return an id.
 
     let new_image_id = uuid::Uuid::new_v4();
@@ -1111,15 +1524,987 @@ pub fn update_user_icon_id(
     Ok(updated_user)
 }
 
-pub fn update_username(
+/// Outcome of [`change_nickname`].
+#[derive(Debug)]
+pub enum NicknameChange {
+    Changed(Box<models::User>),
+    /// Another user goes by the nickname, whatever the case.
+    Taken,
+    /// The last change was too recent, the user may change it again then.
+    TooSoon(NaiveDateTime),
+}
+
+/// Give a user a new nickname, unless it changed it within `cooldown` of
+/// `now`. The old nickname goes to the history of the user.
+pub fn change_nickname(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    new_nickname: &str,
+    cooldown: Duration,
+    now: NaiveDateTime,
+) -> Result<NicknameChange, Box<dyn StdError>> {
+    use crate::dbschema::Users::dsl::*;
+    let result = conn.transaction(|conn| {
+        let (old_nickname, changed_at) = Users
+            .find(the_user_id)
+            .select((nickname, nickname_changed_at))
+            .for_update()
+            .first::<(String, Option<NaiveDateTime>)>(conn)?;
+        if let Some(changed_at) = changed_at {
+            if changed_at + cooldown > now {
+                return Ok(NicknameChange::TooSoon(changed_at + cooldown));
+            }
+        }
+        let taken = diesel::select(diesel::dsl::exists(
+            Users
+                .filter(id.ne(the_user_id))
+                .filter(lower(nickname).eq(lower(new_nickname))),
+        ))
+        .get_result::<bool>(conn)?;
+        if taken {
+            return Ok(NicknameChange::Taken);
+        }
+
+        diesel::insert_into(schema::NicknameHistory::table)
+            .values(&models::NewPastNickname {
+                user_id: the_user_id,
+                nickname: old_nickname,
+            })
+            .execute(conn)?;
+        let updated_user = diesel::update(Users.find(the_user_id))
+            .set((nickname.eq(new_nickname), nickname_changed_at.eq(now)))
+            .returning(models::User::as_returning())
+            .get_result(conn)?;
+        Ok::<_, diesel::result::Error>(NicknameChange::Changed(Box::new(updated_user)))
+    });
+    match result {
+        // another user took it meanwhile
+        Err(diesel::result::Error::DatabaseError(
+            diesel::result::DatabaseErrorKind::UniqueViolation,
+            _,
+        )) => Ok(NicknameChange::Taken),
+        result => Ok(result?),
+    }
+}
+
+/// The nicknames a user went by before, the most recent first.
+pub fn query_nickname_history(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Vec<models::PastNickname>, Box<dyn StdError>> {
+    use crate::dbschema::NicknameHistory::dsl::*;
+    let history = NicknameHistory
+        .filter(user_id.eq(the_user_id))
+        .order((changed_at.desc(), id.desc()))
+        .select(models::PastNickname::as_select())
+        .load(conn)?;
+    Ok(history)
+}
+
+pub fn update_user_role(
     conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
     user_id: i32,
-    new_name: String,
+    new_role: models::UserRole,
 ) -> Result<models::User, Box<dyn StdError>> {
     use crate::dbschema::Users::dsl::*;
     let updated_user: models::User = diesel::update(Users.filter(schema::Users::id.eq(user_id)))
-        .set(username.eq(new_name))
+        .set(role.eq(new_role))
         .returning(models::User::as_returning())
         .get_result(conn)?;
     Ok(updated_user)
 }
+
+pub fn query_users_by_role(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_role: models::UserRole,
+) -> Result<Vec<models::User>, Box<dyn StdError>> {
+    use crate::dbschema::Users::dsl::*;
+    let users = Users
+        .filter(role.eq(the_role))
+        .order(id.asc())
+        .select(models::User::as_select())
+        .load(conn)?;
+    Ok(users)
+}
+
+pub fn insert_session(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    new_session: &models::NewSession,
+) -> Result<models::Session, Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    let session = diesel::insert_into(Sessions)
+        .values(new_session)
+        .returning(models::Session::as_returning())
+        .get_result(conn)?;
+    Ok(session)
+}
+
+pub fn query_session_by_refresh_token(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_refresh_token: &str,
+) -> Result<Option<models::Session>, Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    let session = Sessions
+        .filter(refresh_token.eq(the_refresh_token))
+        .select(models::Session::as_select())
+        .first(conn)
+        .optional()?;
+    Ok(session)
+}
+
+/// Replace the refresh token of a live session and extend its lifetime.
+/// Returns `None` if the session is gone, revoked, or `old_refresh_token`
+/// has already been rotated away.
+pub fn rotate_session_refresh_token(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    session_id: i32,
+    old_refresh_token: &str,
+    new_refresh_token: &str,
+    new_expires_at: NaiveDateTime,
+) -> Result<Option<models::Session>, Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    let session = diesel::update(
+        Sessions
+            .filter(id.eq(session_id))
+            .filter(refresh_token.eq(old_refresh_token))
+            .filter(revoked.eq(false)),
+    )
+    .set((
+        refresh_token.eq(new_refresh_token),
+        expires_at.eq(new_expires_at),
+        last_seen_at.eq(diesel::dsl::now),
+    ))
+    .returning(models::Session::as_returning())
+    .get_result(conn)
+    .optional()?;
+    Ok(session)
+}
+
+pub fn revoke_session(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    session_id: i32,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    diesel::update(Sessions.filter(id.eq(session_id)))
+        .set(revoked.eq(true))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Record that a live session has just been used.
+pub fn touch_session(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    session_id: i32,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    diesel::update(Sessions.filter(id.eq(session_id)).filter(revoked.eq(false)))
+        .set(last_seen_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// A session that does not exist is reported as revoked.
+pub fn is_session_revoked(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    session_id: i32,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    let is_revoked = Sessions
+        .filter(id.eq(session_id))
+        .select(revoked)
+        .first::<bool>(conn)
+        .optional()?;
+    Ok(is_revoked.unwrap_or(true))
+}
+
+/// Ids of the sessions of a user that are not revoked yet.
+pub fn query_active_session_ids(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Vec<i32>, Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    let ids = Sessions
+        .filter(user_id.eq(the_user_id))
+        .filter(revoked.eq(false))
+        .select(id)
+        .load::<i32>(conn)?;
+    Ok(ids)
+}
+
+/// The live sessions of a user, the most recently seen first.
+pub fn query_user_sessions(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Vec<models::Session>, Box<dyn StdError>> {
+    use crate::dbschema::Sessions::dsl::*;
+    let sessions = Sessions
+        .filter(user_id.eq(the_user_id))
+        .filter(revoked.eq(false))
+        .filter(expires_at.gt(diesel::dsl::now))
+        .order((last_seen_at.desc(), id.desc()))
+        .select(models::Session::as_select())
+        .load(conn)?;
+    Ok(sessions)
+}
+
+pub fn update_user_password(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    user_id: i32,
+    hashed_password: String,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::Users::dsl::*;
+    diesel::update(Users.filter(schema::Users::id.eq(user_id)))
+        .set(password.eq(Some(hashed_password)))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Store a new reset token for a user, pending tokens of the user are
+/// invalidated.
+pub fn insert_password_reset_token(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    new_token: &models::NewPasswordResetToken,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::PasswordResetTokens::dsl::*;
+    conn.transaction(|conn| {
+        diesel::update(
+            PasswordResetTokens
+                .filter(user_id.eq(new_token.user_id))
+                .filter(used_at.is_null()),
+        )
+        .set(used_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+        diesel::insert_into(PasswordResetTokens)
+            .values(new_token)
+            .execute(conn)
+    })?;
+    Ok(())
+}
+
+/// Consume a reset token and set the new password of its user in one
+/// transaction. Returns the user id, or `None` if the token is unknown,
+/// expired or already used.
+pub fn reset_password_with_token(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_token_hash: &str,
+    hashed_password: String,
+) -> Result<Option<i32>, Box<dyn StdError>> {
+    use crate::dbschema::PasswordResetTokens::dsl::*;
+    let the_user_id = conn.transaction(|conn| {
+        let token = diesel::update(
+            PasswordResetTokens
+                .filter(token_hash.eq(the_token_hash))
+                .filter(used_at.is_null())
+                .filter(expires_at.gt(diesel::dsl::now)),
+        )
+        .set(used_at.eq(diesel::dsl::now))
+        .returning(models::PasswordResetToken::as_returning())
+        .get_result(conn)
+        .optional()?;
+        let Some(token) = token else {
+            return Ok(None);
+        };
+        diesel::update(schema::Users::table.filter(schema::Users::id.eq(token.user_id)))
+            .set(schema::Users::password.eq(Some(hashed_password)))
+            .execute(conn)?;
+        Ok::<_, diesel::result::Error>(Some(token.user_id))
+    })?;
+    Ok(the_user_id)
+}
+
+/// Store a new email verification token, invalidating the pending ones of
+/// the same user.
+pub fn insert_email_verification_token(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    new_token: &models::NewEmailVerificationToken,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::EmailVerificationTokens::dsl::*;
+    conn.transaction(|conn| {
+        diesel::update(
+            EmailVerificationTokens
+                .filter(user_id.eq(new_token.user_id))
+                .filter(used_at.is_null()),
+        )
+        .set(used_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+        diesel::insert_into(EmailVerificationTokens)
+            .values(new_token)
+            .execute(conn)
+    })?;
+    Ok(())
+}
+
+/// Consume a verification token and mark the email of its user verified in
+/// one transaction. Returns the user id, or `None` if the token is unknown,
+/// expired, already used, or the user has changed email since it was sent.
+pub fn verify_email_with_token(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_token_hash: &str,
+) -> Result<Option<i32>, Box<dyn StdError>> {
+    use crate::dbschema::EmailVerificationTokens::dsl::*;
+    let the_user_id = conn.transaction(|conn| {
+        let token = diesel::update(
+            EmailVerificationTokens
+                .filter(token_hash.eq(the_token_hash))
+                .filter(used_at.is_null())
+                .filter(expires_at.gt(diesel::dsl::now)),
+        )
+        .set(used_at.eq(diesel::dsl::now))
+        .returning(models::EmailVerificationToken::as_returning())
+        .get_result(conn)
+        .optional()?;
+        let Some(token) = token else {
+            return Ok(None);
+        };
+        let updated = diesel::update(
+            schema::Users::table
+                .filter(schema::Users::id.eq(token.user_id))
+                .filter(schema::Users::email.eq(&token.email)),
+        )
+        .set(schema::Users::email_verified.eq(true))
+        .execute(conn)?;
+        Ok::<_, diesel::result::Error>((updated > 0).then_some(token.user_id))
+    })?;
+    Ok(the_user_id)
+}
+
+pub fn insert_login_attempt(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    attempt: &models::NewLoginAttempt,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::LoginAttempts::dsl::*;
+    diesel::insert_into(LoginAttempts)
+        .values(attempt)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Count the failed logins on `the_username` after `since` and after its
+/// last successful login. Returns the count and the time of the last one.
+pub fn query_username_login_failures(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_username: &str,
+    since: NaiveDateTime,
+) -> Result<(i64, Option<NaiveDateTime>), Box<dyn StdError>> {
+    use crate::dbschema::LoginAttempts::dsl::*;
+    use diesel::dsl::{count_star, max};
+    let last_success: Option<NaiveDateTime> = LoginAttempts
+        .filter(username.eq(the_username))
+        .filter(success.eq(true))
+        .select(max(created_at))
+        .first(conn)?;
+    let since = last_success.map_or(since, |last| last.max(since));
+    let failures = LoginAttempts
+        .filter(username.eq(the_username))
+        .filter(success.eq(false))
+        .filter(created_at.gt(since))
+        .select((count_star(), max(created_at)))
+        .first(conn)?;
+    Ok(failures)
+}
+
+/// Count the failed logins from `the_ip_address` after `since`, whatever the
+/// username. Returns the count and the time of the last one.
+pub fn query_ip_login_failures(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_ip_address: &str,
+    since: NaiveDateTime,
+) -> Result<(i64, Option<NaiveDateTime>), Box<dyn StdError>> {
+    use crate::dbschema::LoginAttempts::dsl::*;
+    use diesel::dsl::{count_star, max};
+    let failures = LoginAttempts
+        .filter(ip_address.eq(the_ip_address))
+        .filter(success.eq(false))
+        .filter(created_at.gt(since))
+        .select((count_star(), max(created_at)))
+        .first(conn)?;
+    Ok(failures)
+}
+
+/// Store the IAAA attributes of a user, replacing the previous ones.
+pub fn upsert_iaaa_profile(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    profile: &models::IaaaProfile,
+) -> Result<models::IaaaProfile, Box<dyn StdError>> {
+    use crate::dbschema::IaaaProfiles::dsl::*;
+    let profile = diesel::insert_into(IaaaProfiles)
+        .values(profile)
+        .on_conflict(user_id)
+        .do_update()
+        .set(profile)
+        .returning(models::IaaaProfile::as_returning())
+        .get_result(conn)?;
+    Ok(profile)
+}
+
+pub fn query_iaaa_profile(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Option<models::IaaaProfile>, Box<dyn StdError>> {
+    use crate::dbschema::IaaaProfiles::dsl::*;
+    let profile = IaaaProfiles
+        .filter(user_id.eq(the_user_id))
+        .select(models::IaaaProfile::as_select())
+        .first(conn)
+        .optional()?;
+    Ok(profile)
+}
+
+/// The user who logs in with `the_subject` of `the_provider`.
+pub fn query_user_by_identity(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_provider: models::LoginProvider,
+    the_subject: &str,
+) -> Result<Option<models::User>, Box<dyn StdError>> {
+    use crate::dbschema::Identities::dsl::*;
+    let user = Identities
+        .inner_join(schema::Users::table)
+        .filter(provider.eq(the_provider))
+        .filter(subject.eq(the_subject))
+        .select(models::User::as_select())
+        .first(conn)
+        .optional()?;
+    Ok(user)
+}
+
+pub fn query_identities(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Vec<models::Identity>, Box<dyn StdError>> {
+    use crate::dbschema::Identities::dsl::*;
+    let identities = Identities
+        .filter(user_id.eq(the_user_id))
+        .order(id.asc())
+        .select(models::Identity::as_select())
+        .load(conn)?;
+    Ok(identities)
+}
+
+/// Add an identity to a user. Returns `None` if the user has one of the same
+/// provider already, or another user logs in with this one.
+pub fn insert_identity(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    new_identity: &models::NewIdentity,
+) -> Result<Option<models::Identity>, Box<dyn StdError>> {
+    use crate::dbschema::Identities::dsl::*;
+    let identity = diesel::insert_into(Identities)
+        .values(new_identity)
+        .on_conflict_do_nothing()
+        .returning(models::Identity::as_returning())
+        .get_result(conn)
+        .optional()?;
+    Ok(identity)
+}
+
+/// Let a user login with its username and `hashed_password`. Returns `None`
+/// if the user has a password identity already, or another user logs in
+/// with the username.
+pub fn link_password_identity(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    hashed_password: String,
+) -> Result<Option<models::Identity>, Box<dyn StdError>> {
+    use crate::dbschema::Identities::dsl::*;
+    let identity = conn.transaction(|conn| {
+        let the_username: String = schema::Users::table
+            .find(the_user_id)
+            .select(schema::Users::username)
+            .first(conn)?;
+        let identity = diesel::insert_into(Identities)
+            .values(&models::NewIdentity {
+                user_id: the_user_id,
+                provider: models::LoginProvider::PASSWORD,
+                subject: the_username,
+            })
+            .on_conflict_do_nothing()
+            .returning(models::Identity::as_returning())
+            .get_result(conn)
+            .optional()?;
+        if identity.is_some() {
+            diesel::update(schema::Users::table.find(the_user_id))
+                .set(schema::Users::password.eq(Some(hashed_password)))
+                .execute(conn)?;
+        }
+        Ok::<_, diesel::result::Error>(identity)
+    })?;
+    Ok(identity)
+}
+
+/// Let the user of `profile` login with the IAAA identity `the_identity_id`,
+/// and store its attributes. Returns `None` if the user has an IAAA identity
+/// already, or another user logs in with this one.
+pub fn link_iaaa_identity(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_identity_id: &str,
+    profile: &models::IaaaProfile,
+) -> Result<Option<models::Identity>, Box<dyn StdError>> {
+    use crate::dbschema::Identities::dsl::*;
+    conn.transaction(|conn| {
+        let identity = diesel::insert_into(Identities)
+            .values(&models::NewIdentity {
+                user_id: profile.user_id,
+                provider: models::LoginProvider::IAAA,
+                subject: the_identity_id.to_string(),
+            })
+            .on_conflict_do_nothing()
+            .returning(models::Identity::as_returning())
+            .get_result(conn)
+            .optional()?;
+        if identity.is_some() {
+            upsert_iaaa_profile(conn, profile)?;
+        }
+        Ok::<_, Box<dyn StdError>>(identity)
+    })
+}
+
+/// Remove the `the_provider` identity of a user, with its password or IAAA
+/// attributes. Returns `false`, removing nothing, if the user has no such
+/// identity or no other one.
+pub fn unlink_identity(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    the_provider: models::LoginProvider,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::Identities::dsl::*;
+    let unlinked = conn.transaction(|conn| {
+        // lock the user, concurrent unlinks must leave an identity
+        schema::Users::table
+            .find(the_user_id)
+            .select(schema::Users::id)
+            .for_update()
+            .first::<i32>(conn)?;
+        let count: i64 = Identities
+            .filter(user_id.eq(the_user_id))
+            .count()
+            .get_result(conn)?;
+        if count < 2 {
+            return Ok(false);
+        }
+        let deleted = diesel::delete(
+            Identities
+                .filter(user_id.eq(the_user_id))
+                .filter(provider.eq(the_provider)),
+        )
+        .execute(conn)?;
+        if deleted == 0 {
+            return Ok(false);
+        }
+        match the_provider {
+            models::LoginProvider::PASSWORD => {
+                diesel::update(schema::Users::table.find(the_user_id))
+                    .set(schema::Users::password.eq(None::<String>))
+                    .execute(conn)?;
+                // the second factor was for the password
+                delete_two_factor(conn, the_user_id)?;
+            }
+            models::LoginProvider::IAAA => {
+                diesel::delete(
+                    schema::IaaaProfiles::table
+                        .filter(schema::IaaaProfiles::user_id.eq(the_user_id)),
+                )
+                .execute(conn)?;
+            }
+            models::LoginProvider::OIDC => (),
+        }
+        Ok::<_, diesel::result::Error>(true)
+    })?;
+    Ok(unlinked)
+}
+
+pub fn query_totp_credential(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Option<models::TotpCredential>, Box<dyn StdError>> {
+    use crate::dbschema::TotpCredentials::dsl::*;
+    let credential = TotpCredentials
+        .find(the_user_id)
+        .select(models::TotpCredential::as_select())
+        .first(conn)
+        .optional()?;
+    Ok(credential)
+}
+
+/// Store the secret of a new TOTP enrollment, replacing a pending one.
+/// Returns `false` if the user has a confirmed one already.
+pub fn upsert_pending_totp(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    new_credential: &models::NewTotpCredential,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::TotpCredentials::dsl::*;
+    let stored = conn.transaction(|conn| {
+        let confirmed = TotpCredentials
+            .find(new_credential.user_id)
+            .select(confirmed_at.is_not_null())
+            .for_update()
+            .first::<bool>(conn)
+            .optional()?;
+        if confirmed == Some(true) {
+            return Ok(false);
+        }
+        diesel::insert_into(TotpCredentials)
+            .values(new_credential)
+            .on_conflict(user_id)
+            .do_update()
+            .set((
+                secret.eq(&new_credential.secret),
+                last_used_step.eq(None::<i64>),
+                created_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)?;
+        Ok::<_, diesel::result::Error>(true)
+    })?;
+    Ok(stored)
+}
+
+/// Confirm the pending TOTP enrollment of a user with a code of time step
+/// `step`, replacing the recovery codes of the user. Returns `false` if
+/// there is no pending enrollment.
+pub fn confirm_totp(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    step: i64,
+    code_hashes: Vec<String>,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::TotpCredentials::dsl::*;
+    let confirmed = conn.transaction(|conn| {
+        let updated = diesel::update(
+            TotpCredentials
+                .find(the_user_id)
+                .filter(confirmed_at.is_null()),
+        )
+        .set((confirmed_at.eq(diesel::dsl::now), last_used_step.eq(step)))
+        .execute(conn)?;
+        if updated == 0 {
+            return Ok(false);
+        }
+        diesel::delete(
+            schema::RecoveryCodes::table.filter(schema::RecoveryCodes::user_id.eq(the_user_id)),
+        )
+        .execute(conn)?;
+        let new_codes: Vec<_> = code_hashes
+            .into_iter()
+            .map(|code_hash| models::NewRecoveryCode {
+                user_id: the_user_id,
+                code_hash,
+            })
+            .collect();
+        diesel::insert_into(schema::RecoveryCodes::table)
+            .values(&new_codes)
+            .execute(conn)?;
+        Ok::<_, diesel::result::Error>(true)
+    })?;
+    Ok(confirmed)
+}
+
+/// Use the TOTP code of time step `step` of a user. Returns `false` if the
+/// user has no confirmed TOTP enrollment, or has used a code as recent.
+pub fn use_totp_step(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    step: i64,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::TotpCredentials::dsl::*;
+    let updated = diesel::update(
+        TotpCredentials
+            .find(the_user_id)
+            .filter(confirmed_at.is_not_null())
+            .filter(last_used_step.is_null().or(last_used_step.lt(step))),
+    )
+    .set(last_used_step.eq(step))
+    .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Use a recovery code of a user. Returns `false` if there is no such
+/// unused code.
+pub fn use_recovery_code(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    the_code_hash: &str,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::RecoveryCodes::dsl::*;
+    let updated = diesel::update(
+        RecoveryCodes
+            .filter(user_id.eq(the_user_id))
+            .filter(code_hash.eq(the_code_hash))
+            .filter(used_at.is_null()),
+    )
+    .set(used_at.eq(diesel::dsl::now))
+    .execute(conn)?;
+    Ok(updated > 0)
+}
+
+fn delete_two_factor(conn: &mut PgConnection, the_user_id: i32) -> QueryResult<usize> {
+    diesel::delete(
+        schema::LoginChallenges::table.filter(schema::LoginChallenges::user_id.eq(the_user_id)),
+    )
+    .execute(conn)?;
+    diesel::delete(
+        schema::RecoveryCodes::table.filter(schema::RecoveryCodes::user_id.eq(the_user_id)),
+    )
+    .execute(conn)?;
+    diesel::delete(schema::TotpCredentials::table.find(the_user_id)).execute(conn)
+}
+
+/// Turn off two-factor authentication of a user, along with its recovery
+/// codes and pending logins. Returns `false` if it was not enrolled.
+pub fn delete_totp(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<bool, Box<dyn StdError>> {
+    let deleted = conn.transaction(|conn| delete_two_factor(conn, the_user_id))?;
+    Ok(deleted > 0)
+}
+
+pub fn insert_login_challenge(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    new_challenge: &models::NewLoginChallenge,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::LoginChallenges::dsl::*;
+    diesel::insert_into(LoginChallenges)
+        .values(new_challenge)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// The unused and unexpired login challenge with the token hash, if any.
+pub fn query_login_challenge(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_token_hash: &str,
+) -> Result<Option<models::LoginChallenge>, Box<dyn StdError>> {
+    use crate::dbschema::LoginChallenges::dsl::*;
+    let challenge = LoginChallenges
+        .filter(token_hash.eq(the_token_hash))
+        .filter(used_at.is_null())
+        .filter(expires_at.gt(diesel::dsl::now))
+        .select(models::LoginChallenge::as_select())
+        .first(conn)
+        .optional()?;
+    Ok(challenge)
+}
+
+pub fn fail_login_challenge(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_id: i32,
+) -> Result<(), Box<dyn StdError>> {
+    use crate::dbschema::LoginChallenges::dsl::*;
+    diesel::update(LoginChallenges.find(the_id))
+        .set(failed_attempts.eq(failed_attempts + 1))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Mark a login challenge as used. Returns `false` if it was already.
+pub fn use_login_challenge(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_id: i32,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::LoginChallenges::dsl::*;
+    let updated = diesel::update(LoginChallenges.find(the_id).filter(used_at.is_null()))
+        .set(used_at.eq(diesel::dsl::now))
+        .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// All the posts of a user, of every type.
+pub fn query_user_posts(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Vec<models::Post>, Box<dyn StdError>> {
+    use crate::dbschema::Posts::dsl::*;
+    let posts = Posts
+        .filter(user_id.eq(the_user_id))
+        .order(id.asc())
+        .select(models::Post::as_select())
+        .load(conn)?;
+    Ok(posts)
+}
+
+/// All the comments of a user, on any post.
+pub fn query_user_comments(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Vec<models::Comment>, Box<dyn StdError>> {
+    use crate::dbschema::Comments::dsl::*;
+    let comments = Comments
+        .filter(user_id.eq(the_user_id))
+        .order(id.asc())
+        .select(models::Comment::as_select())
+        .load(conn)?;
+    Ok(comments)
+}
+
+/// Ids of the comments a user liked, in the order it did.
+pub fn query_user_liked_comment_ids(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Vec<i32>, Box<dyn StdError>> {
+    let ids = schema::CommentLikes::table
+        .filter(schema::CommentLikes::user_id.eq(the_user_id))
+        .order(schema::CommentLikes::id)
+        .select(schema::CommentLikes::comment_id)
+        .load(conn)?;
+    Ok(ids)
+}
+
+/// Schedule the deletion of a user at `the_delete_after`, unless it is
+/// scheduled already. Returns when the user is to be deleted.
+pub fn schedule_user_deletion(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    the_delete_after: NaiveDateTime,
+) -> Result<NaiveDateTime, Box<dyn StdError>> {
+    use crate::dbschema::Users::dsl::*;
+    let scheduled = conn.transaction(|conn| {
+        diesel::update(Users.find(the_user_id).filter(delete_after.is_null()))
+            .set(delete_after.eq(the_delete_after))
+            .execute(conn)?;
+        Users
+            .find(the_user_id)
+            .select(delete_after)
+            .first::<Option<NaiveDateTime>>(conn)
+    })?;
+    Ok(scheduled.ok_or("deletion not scheduled")?)
+}
+
+/// Returns `false` if the deletion of the user was not scheduled.
+pub fn cancel_user_deletion(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<bool, Box<dyn StdError>> {
+    use crate::dbschema::Users::dsl::*;
+    let updated = diesel::update(Users.find(the_user_id).filter(delete_after.is_not_null()))
+        .set(delete_after.eq(None::<NaiveDateTime>))
+        .execute(conn)?;
+    Ok(updated > 0)
+}
+
+/// Ids of the users to delete by `now`.
+pub fn query_users_to_delete(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    now: NaiveDateTime,
+) -> Result<Vec<i32>, Box<dyn StdError>> {
+    use crate::dbschema::Users::dsl::*;
+    let ids = Users
+        .filter(delete_after.le(now))
+        .select(id)
+        .load::<i32>(conn)?;
+    Ok(ids)
+}
+
+/// Delete a user for good, if it is still to be deleted by `now`. Its likes,
+/// of posts and comments, favorites and participations are withdrawn, its
+/// comments are deleted, and its posts with whatever refers to them. Returns
+/// the images left in the image store, or `None` if the user was not deleted.
+pub fn purge_user(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+    now: NaiveDateTime,
+) -> Result<Option<Vec<i32>>, Box<dyn StdError>> {
+    let images = conn.transaction(|conn| {
+        // the user may cancel meanwhile
+        let Some(user) = schema::Users::table
+            .find(the_user_id)
+            .filter(schema::Users::delete_after.le(now))
+            .select(models::User::as_select())
+            .for_update()
+            .first(conn)
+            .optional()?
+        else {
+            return Ok(None);
+        };
+
+        // withdraw from the posts of others
+        let liked = schema::PostLikes::table
+            .filter(schema::PostLikes::user_id.eq(the_user_id))
+            .select(schema::PostLikes::post_id);
+        diesel::update(schema::Posts::table.filter(schema::Posts::id.eq_any(liked)))
+            .set(schema::Posts::likes.eq(schema::Posts::likes - 1))
+            .execute(conn)?;
+        let favorite = schema::PostFavorites::table
+            .filter(schema::PostFavorites::user_id.eq(the_user_id))
+            .select(schema::PostFavorites::post_id);
+        diesel::update(schema::Posts::table.filter(schema::Posts::id.eq_any(favorite)))
+            .set(schema::Posts::favorates.eq(schema::Posts::favorates - 1))
+            .execute(conn)?;
+        let taken_part = schema::PostParticipants::table
+            .filter(schema::PostParticipants::user_id.eq(the_user_id))
+            .select(schema::PostParticipants::post_id);
+        diesel::update(schema::Posts::table.filter(schema::Posts::id.eq_any(taken_part)))
+            .set(schema::Posts::people_already.eq(schema::Posts::people_already - 1))
+            .execute(conn)?;
+        let liked_comments = schema::CommentLikes::table
+            .filter(schema::CommentLikes::user_id.eq(the_user_id))
+            .select(schema::CommentLikes::comment_id);
+        diesel::update(schema::Comments::table.filter(schema::Comments::id.eq_any(liked_comments)))
+            .set(schema::Comments::likes.eq(schema::Comments::likes - 1))
+            .execute(conn)?;
+
+        let posts: Vec<NullableIntArray> = schema::Posts::table
+            .filter(schema::Posts::user_id.eq(the_user_id))
+            .select(schema::Posts::images)
+            .load(conn)?;
+
+        diesel::delete(
+            schema::LoginAttempts::table.filter(schema::LoginAttempts::username.eq(&user.username)),
+        )
+        .execute(conn)?;
+        // posts, comments, likes, sessions, identities and the rest cascade
+        diesel::delete(schema::Users::table.find(the_user_id)).execute(conn)?;
+
+        let mut images: Vec<i32> = posts
+            .into_iter()
+            .flat_map(NullableIntArray::into_vec_i32)
+            .collect();
+        // icon 0 is the default one, shared by everyone
+        if user.icon != 0 {
+            images.push(user.icon);
+        }
+        Ok::<_, diesel::result::Error>(Some(images))
+    })?;
+    Ok(images)
+}
+
+/// The profile of a user, or `None` if it never set one.
+pub fn query_user_profile(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    the_user_id: i32,
+) -> Result<Option<models::UserProfile>, Box<dyn StdError>> {
+    use crate::dbschema::UserProfiles::dsl::*;
+    let profile = UserProfiles
+        .find(the_user_id)
+        .select(models::UserProfile::as_select())
+        .first(conn)
+        .optional()?;
+    Ok(profile)
+}
+
+pub fn upsert_user_profile(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    update: &models::ProfileUpdate,
+) -> Result<models::UserProfile, Box<dyn StdError>> {
+    use crate::dbschema::UserProfiles::dsl::*;
+    let profile = diesel::insert_into(UserProfiles)
+        .values(update)
+        .on_conflict(user_id)
+        .do_update()
+        .set((update, updated_at.eq(diesel::dsl::now)))
+        .returning(models::UserProfile::as_returning())
+        .get_result(conn)?;
+    Ok(profile)
+}
+
+pub fn upsert_privacy_settings(
+    conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+    update: &models::PrivacyUpdate,
+) -> Result<models::UserProfile, Box<dyn StdError>> {
+    use crate::dbschema::UserProfiles::dsl::*;
+    let profile = diesel::insert_into(UserProfiles)
+        .values(update)
+        .on_conflict(user_id)
+        .do_update()
+        .set((update, updated_at.eq(diesel::dsl::now)))
+        .returning(models::UserProfile::as_returning())
+        .get_result(conn)?;
+    Ok(profile)
+}
@@ -1,10 +1,12 @@
 use crate::codegen;
-use crate::codegen::amusement_post::AmusementPost;
+use crate::dbschema::sql_types::ContactMethod as ContactMethodSql;
 use crate::dbschema::sql_types::GameType as GameTypeSql;
 use crate::dbschema::sql_types::GoodsType as GoodsTypeSql;
 use crate::dbschema::sql_types::LoginProvider as LoginProviderType;
 use crate::dbschema::sql_types::Place as PlaceTypeSql;
 use crate::dbschema::sql_types::PostType as PostTypeSql;
+use crate::dbschema::sql_types::UserRole as UserRoleSql;
+use crate::dbschema::sql_types::Visibility as VisibilitySql;
 use chrono::NaiveDateTime;
 use deserialize::FromSqlRow;
 use diesel::deserialize::FromSql;
@@ -15,13 +17,31 @@ use diesel::*;
 use sql_types::Integer;
 use std::io::Write;
 
-use super::schema::Posts::game_type;
-
-#[derive(Debug, PartialEq, FromSqlRow, AsExpression, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, FromSqlRow, AsExpression, Eq)]
 #[diesel(sql_type = LoginProviderType)]
+#[allow(clippy::upper_case_acronyms)] // variants mirror the SQL enum labels
 pub enum LoginProvider {
     IAAA,
     PASSWORD,
+    OIDC,
+}
+
+impl LoginProvider {
+    pub fn to_proto_type(self) -> crate::codegen::auth::LoginProvider {
+        use crate::codegen::auth;
+        match self {
+            LoginProvider::IAAA => auth::LoginProvider::Iaaa,
+            LoginProvider::PASSWORD => auth::LoginProvider::Password,
+            LoginProvider::OIDC => auth::LoginProvider::Oidc,
+        }
+    }
+    pub fn from_proto_type(proto_provider: crate::codegen::auth::LoginProvider) -> Self {
+        match proto_provider {
+            crate::codegen::auth::LoginProvider::Iaaa => LoginProvider::IAAA,
+            crate::codegen::auth::LoginProvider::Password => LoginProvider::PASSWORD,
+            crate::codegen::auth::LoginProvider::Oidc => LoginProvider::OIDC,
+        }
+    }
 }
 
 impl ToSql<LoginProviderType, Pg> for LoginProvider {
@@ -32,6 +52,7 @@ impl ToSql<LoginProviderType, Pg> for LoginProvider {
         match *self {
             LoginProvider::IAAA => out.write_all(b"IAAA")?,
             LoginProvider::PASSWORD => out.write_all(b"PASSWORD")?,
+            LoginProvider::OIDC => out.write_all(b"OIDC")?,
         }
         Ok(IsNull::No)
     }
@@ -44,6 +65,182 @@ impl FromSql<LoginProviderType, Pg> for LoginProvider {
         match bytes.as_bytes() {
             b"IAAA" => Ok(LoginProvider::IAAA),
             b"PASSWORD" => Ok(LoginProvider::PASSWORD),
+            b"OIDC" => Ok(LoginProvider::OIDC),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, FromSqlRow, AsExpression, Eq)]
+#[diesel(sql_type = UserRoleSql)]
+#[allow(clippy::upper_case_acronyms)] // variants mirror the SQL enum labels
+pub enum UserRole {
+    USER,
+    MODERATOR,
+    ADMIN,
+}
+
+impl UserRole {
+    pub fn to_proto_type(&self) -> crate::codegen::auth::UserRole {
+        use crate::codegen::auth;
+        match *self {
+            UserRole::USER => auth::UserRole::User,
+            UserRole::MODERATOR => auth::UserRole::Moderator,
+            UserRole::ADMIN => auth::UserRole::Admin,
+        }
+    }
+    pub fn from_proto_type(proto_role: crate::codegen::auth::UserRole) -> Self {
+        match proto_role {
+            crate::codegen::auth::UserRole::User => UserRole::USER,
+            crate::codegen::auth::UserRole::Moderator => UserRole::MODERATOR,
+            crate::codegen::auth::UserRole::Admin => UserRole::ADMIN,
+        }
+    }
+}
+
+impl ToSql<UserRoleSql, Pg> for UserRole {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Pg>,
+    ) -> diesel::serialize::Result {
+        match *self {
+            UserRole::USER => out.write_all(b"USER")?,
+            UserRole::MODERATOR => out.write_all(b"MODERATOR")?,
+            UserRole::ADMIN => out.write_all(b"ADMIN")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<UserRoleSql, Pg> for UserRole {
+    fn from_sql(
+        bytes: <Pg as diesel::backend::Backend>::RawValue<'_>,
+    ) -> diesel::deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"USER" => Ok(UserRole::USER),
+            b"MODERATOR" => Ok(UserRole::MODERATOR),
+            b"ADMIN" => Ok(UserRole::ADMIN),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, FromSqlRow, AsExpression, Eq)]
+#[diesel(sql_type = ContactMethodSql)]
+#[allow(clippy::upper_case_acronyms)] // variants mirror the SQL enum labels
+pub enum ContactMethod {
+    EMAIL,
+    PHONE,
+    WECHAT,
+    QQ,
+    OTHER,
+}
+
+impl ContactMethod {
+    pub fn to_proto_type(self) -> crate::codegen::auth::ContactMethod {
+        use crate::codegen::auth;
+        match self {
+            ContactMethod::EMAIL => auth::ContactMethod::Email,
+            ContactMethod::PHONE => auth::ContactMethod::Phone,
+            ContactMethod::WECHAT => auth::ContactMethod::Wechat,
+            ContactMethod::QQ => auth::ContactMethod::Qq,
+            ContactMethod::OTHER => auth::ContactMethod::Other,
+        }
+    }
+    pub fn from_proto_type(proto_method: crate::codegen::auth::ContactMethod) -> Self {
+        use crate::codegen::auth;
+        match proto_method {
+            auth::ContactMethod::Email => ContactMethod::EMAIL,
+            auth::ContactMethod::Phone => ContactMethod::PHONE,
+            auth::ContactMethod::Wechat => ContactMethod::WECHAT,
+            auth::ContactMethod::Qq => ContactMethod::QQ,
+            auth::ContactMethod::Other => ContactMethod::OTHER,
+        }
+    }
+}
+
+impl ToSql<ContactMethodSql, Pg> for ContactMethod {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Pg>,
+    ) -> diesel::serialize::Result {
+        match *self {
+            ContactMethod::EMAIL => out.write_all(b"EMAIL")?,
+            ContactMethod::PHONE => out.write_all(b"PHONE")?,
+            ContactMethod::WECHAT => out.write_all(b"WECHAT")?,
+            ContactMethod::QQ => out.write_all(b"QQ")?,
+            ContactMethod::OTHER => out.write_all(b"OTHER")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<ContactMethodSql, Pg> for ContactMethod {
+    fn from_sql(
+        bytes: <Pg as diesel::backend::Backend>::RawValue<'_>,
+    ) -> diesel::deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"EMAIL" => Ok(ContactMethod::EMAIL),
+            b"PHONE" => Ok(ContactMethod::PHONE),
+            b"WECHAT" => Ok(ContactMethod::WECHAT),
+            b"QQ" => Ok(ContactMethod::QQ),
+            b"OTHER" => Ok(ContactMethod::OTHER),
+            _ => Err("Unrecognized enum variant".into()),
+        }
+    }
+}
+
+/// Who sees a part of a profile, besides its owner.
+#[derive(Debug, Clone, Copy, PartialEq, FromSqlRow, AsExpression, Eq)]
+#[diesel(sql_type = VisibilitySql)]
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)] // variants mirror the SQL enum labels
+pub enum Visibility {
+    ONLY_ME,
+    SIGNED_IN,
+    EVERYONE,
+}
+
+impl Visibility {
+    pub fn to_proto_type(self) -> crate::codegen::auth::Visibility {
+        use crate::codegen::auth;
+        match self {
+            Visibility::ONLY_ME => auth::Visibility::OnlyMe,
+            Visibility::SIGNED_IN => auth::Visibility::SignedIn,
+            Visibility::EVERYONE => auth::Visibility::Everyone,
+        }
+    }
+    pub fn from_proto_type(proto_visibility: crate::codegen::auth::Visibility) -> Self {
+        use crate::codegen::auth;
+        match proto_visibility {
+            auth::Visibility::OnlyMe => Visibility::ONLY_ME,
+            auth::Visibility::SignedIn => Visibility::SIGNED_IN,
+            auth::Visibility::Everyone => Visibility::EVERYONE,
+        }
+    }
+}
+
+impl ToSql<VisibilitySql, Pg> for Visibility {
+    fn to_sql<'b>(
+        &'b self,
+        out: &mut diesel::serialize::Output<'b, '_, Pg>,
+    ) -> diesel::serialize::Result {
+        match *self {
+            Visibility::ONLY_ME => out.write_all(b"ONLY_ME")?,
+            Visibility::SIGNED_IN => out.write_all(b"SIGNED_IN")?,
+            Visibility::EVERYONE => out.write_all(b"EVERYONE")?,
+        }
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<VisibilitySql, Pg> for Visibility {
+    fn from_sql(
+        bytes: <Pg as diesel::backend::Backend>::RawValue<'_>,
+    ) -> diesel::deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"ONLY_ME" => Ok(Visibility::ONLY_ME),
+            b"SIGNED_IN" => Ok(Visibility::SIGNED_IN),
+            b"EVERYONE" => Ok(Visibility::EVERYONE),
             _ => Err("Unrecognized enum variant".into()),
         }
     }
@@ -62,9 +259,10 @@ pub struct User {
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
     pub icon: i32,
-    pub favorite_posts: NullableIntArray,
-    pub liked_posts: NullableIntArray,
-    pub take_part_posts: NullableIntArray,
+    pub role: UserRole,
+    pub email_verified: bool,
+    pub delete_after: Option<NaiveDateTime>,
+    pub nickname_changed_at: Option<NaiveDateTime>,
 }
 
 #[derive(Debug, Insertable)]
@@ -75,9 +273,6 @@ pub struct IaaaNewUser {
     pub login_provider: LoginProvider,
     pub nickname: Option<String>,
     pub icon: i32,
-    pub favorite_posts: NullableIntArray,
-    pub liked_posts: NullableIntArray,
-    pub take_part_posts: NullableIntArray,
 }
 
 #[derive(Debug, Insertable)]
@@ -89,9 +284,17 @@ pub struct PasswordNewUser {
     pub nickname: String,
     pub password: Option<String>,
     pub icon: i32,
-    pub favorite_posts: NullableIntArray,
-    pub liked_posts: NullableIntArray,
-    pub take_part_posts: NullableIntArray,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::Users)]
+pub struct OidcNewUser {
+    pub username: String,
+    pub email: Option<String>,
+    pub login_provider: LoginProvider,
+    pub nickname: String,
+    pub icon: i32,
+    pub email_verified: bool,
 }
 
 #[derive(Debug, PartialEq, Queryable, Identifiable, Selectable, AsChangeset, Insertable)]
@@ -106,7 +309,6 @@ pub struct Post {
     pub favorates: i32,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
-    pub comments_id: NullableIntArray,
     pub images: NullableIntArray,
     pub post_type: PostType,
     pub contact: Option<String>,
@@ -131,7 +333,6 @@ pub struct NewAmusementPost {
     pub title: String,
     pub user_id: i32,
     pub content: String,
-    pub comments_id: NullableIntArray,
     pub images: NullableIntArray,
     pub post_type: PostType,
     pub contact: Option<String>,
@@ -149,7 +350,6 @@ pub struct NewFoodPost {
     pub title: String,
     pub user_id: i32,
     pub content: String,
-    pub comments_id: NullableIntArray,
     pub images: NullableIntArray,
     pub post_type: PostType,
 
@@ -163,7 +363,6 @@ pub struct NewSellPost {
     pub title: String,
     pub user_id: i32,
     pub content: String,
-    pub comments_id: NullableIntArray,
     pub images: NullableIntArray,
     pub post_type: PostType,
     pub contact: Option<String>,
@@ -194,8 +393,282 @@ pub struct NewComment {
     pub content: String,
 }
 
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::CommentLikes)]
+pub struct NewCommentLike {
+    pub user_id: i32,
+    pub comment_id: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::PostLikes)]
+pub struct NewPostLike {
+    pub user_id: i32,
+    pub post_id: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::PostFavorites)]
+pub struct NewPostFavorite {
+    pub user_id: i32,
+    pub post_id: i32,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::PostParticipants)]
+pub struct NewPostParticipant {
+    pub user_id: i32,
+    pub post_id: i32,
+}
+
+#[derive(Debug, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::Sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Session {
+    pub id: i32,
+    pub user_id: i32,
+    pub refresh_token: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked: bool,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub last_seen_at: NaiveDateTime,
+    pub reported_ip_address: Option<String>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::Sessions)]
+pub struct NewSession {
+    pub user_id: i32,
+    pub refresh_token: String,
+    pub expires_at: NaiveDateTime,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub reported_ip_address: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::PasswordResetTokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PasswordResetToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::PasswordResetTokens)]
+pub struct NewPasswordResetToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::EmailVerificationTokens)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct EmailVerificationToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub email: String,
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::EmailVerificationTokens)]
+pub struct NewEmailVerificationToken {
+    pub user_id: i32,
+    pub email: String,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Queryable, Selectable, Insertable, AsChangeset)]
+#[diesel(table_name = crate::dbschema::IaaaProfiles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct IaaaProfile {
+    pub user_id: i32,
+    pub dept_id: String,
+    pub dept: String,
+    pub campus: String,
+    pub identity_type: String,
+    pub detail_type: String,
+    pub identity_status: String,
+    pub graduated: bool,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::Identities)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct Identity {
+    pub id: i32,
+    pub user_id: i32,
+    pub provider: LoginProvider,
+    pub subject: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::Identities)]
+pub struct NewIdentity {
+    pub user_id: i32,
+    pub provider: LoginProvider,
+    pub subject: String,
+}
+
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = crate::dbschema::LoginAttempts)]
+pub struct NewLoginAttempt {
+    pub username: String,
+    pub ip_address: Option<String>,
+    pub success: bool,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::TotpCredentials)]
+#[diesel(primary_key(user_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TotpCredential {
+    pub user_id: i32,
+    /// Sealed, see [`crate::crypto::encrypt_secret`].
+    pub secret: Vec<u8>,
+    pub confirmed_at: Option<NaiveDateTime>,
+    pub last_used_step: Option<i64>,
+    pub created_at: NaiveDateTime,
+}
+
+impl TotpCredential {
+    pub fn is_confirmed(&self) -> bool {
+        self.confirmed_at.is_some()
+    }
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::TotpCredentials)]
+pub struct NewTotpCredential {
+    pub user_id: i32,
+    pub secret: Vec<u8>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::RecoveryCodes)]
+pub struct NewRecoveryCode {
+    pub user_id: i32,
+    pub code_hash: String,
+}
+
+#[derive(Debug, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::LoginChallenges)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct LoginChallenge {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub failed_attempts: i32,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::LoginChallenges)]
+pub struct NewLoginChallenge {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Debug, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::NicknameHistory)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct PastNickname {
+    pub id: i32,
+    pub user_id: i32,
+    pub nickname: String,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = crate::dbschema::NicknameHistory)]
+pub struct NewPastNickname {
+    pub user_id: i32,
+    pub nickname: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Queryable, Identifiable, Selectable)]
+#[diesel(table_name = crate::dbschema::UserProfiles)]
+#[diesel(primary_key(user_id))]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserProfile {
+    pub user_id: i32,
+    pub bio: String,
+    pub pronouns: Option<String>,
+    pub contact_method: Option<ContactMethod>,
+    pub contact: Option<String>,
+    pub links: Vec<Option<String>>,
+    pub favorites_visibility: Visibility,
+    pub likes_visibility: Visibility,
+    pub participations_visibility: Visibility,
+    pub contact_visibility: Visibility,
+    pub updated_at: NaiveDateTime,
+}
+
+impl UserProfile {
+    /// The profile of a user who never set one, as the column defaults.
+    pub fn empty(user_id: i32) -> Self {
+        UserProfile {
+            user_id,
+            bio: String::new(),
+            pronouns: None,
+            contact_method: None,
+            contact: None,
+            links: vec![],
+            favorites_visibility: Visibility::ONLY_ME,
+            likes_visibility: Visibility::ONLY_ME,
+            participations_visibility: Visibility::SIGNED_IN,
+            contact_visibility: Visibility::SIGNED_IN,
+            updated_at: NaiveDateTime::default(),
+        }
+    }
+}
+
+/// The part of a profile its owner writes about itself.
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = crate::dbschema::UserProfiles)]
+#[diesel(treat_none_as_null = true)]
+pub struct ProfileUpdate {
+    pub user_id: i32,
+    pub bio: String,
+    pub pronouns: Option<String>,
+    pub contact_method: Option<ContactMethod>,
+    pub contact: Option<String>,
+    pub links: Vec<Option<String>>,
+}
+
+#[derive(Debug, Insertable, AsChangeset)]
+#[diesel(table_name = crate::dbschema::UserProfiles)]
+pub struct PrivacyUpdate {
+    pub user_id: i32,
+    pub favorites_visibility: Visibility,
+    pub likes_visibility: Visibility,
+    pub participations_visibility: Visibility,
+    pub contact_visibility: Visibility,
+}
+
 #[derive(Debug, PartialEq, FromSqlRow, AsExpression, Eq)]
 #[diesel(sql_type = PostTypeSql)]
+#[allow(clippy::upper_case_acronyms)] // variants mirror the SQL enum labels
 pub enum PostType {
     FOODPOST,
     SELLPOST,
@@ -323,7 +796,6 @@ impl FromSql<PlaceTypeSql, Pg> for Place {
 
 #[derive(Debug, PartialEq, FromSqlRow, AsExpression, Eq)]
 #[diesel(sql_type = GameTypeSql)]
-
 pub enum GameType {
     WolfKill,
     JvBen,
@@ -399,7 +871,6 @@ impl FromSql<GameTypeSql, Pg> for GameType {
 
 #[derive(Debug, PartialEq, FromSqlRow, AsExpression, Eq)]
 #[diesel(sql_type = GoodsTypeSql)]
-
 pub enum GoodsType {
     Ticket,
     Book,
@@ -494,11 +965,7 @@ impl ToSql<Array<Nullable<Integer>>, Pg> for NullableIntArray {
 }
 
 impl NullableIntArray {
-    pub fn to_vec_i32(self) -> Vec<i32> {
-        self.0
-            .into_iter()
-            .filter(|x| x.is_some())
-            .map(|opt| opt.unwrap())
-            .collect()
+    pub fn into_vec_i32(self) -> Vec<i32> {
+        self.0.into_iter().flatten().collect()
     }
 }
@@ -0,0 +1,75 @@
+//! Settings of the database connection pool.
+use diesel::connection::SimpleConnection;
+use diesel::r2d2::CustomizeConnection;
+use diesel::PgConnection;
+use std::time::Duration;
+
+/// Maximum number of connections, unless `DB_POOL_SIZE` is set.
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+/// How long to wait for a free connection, unless `DB_CONNECTION_TIMEOUT` is set.
+pub const DEFAULT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a statement may run, unless `DB_STATEMENT_TIMEOUT` is set.
+pub const DEFAULT_STATEMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// Maximum number of connections, which is also the number of queries
+    /// running at once.
+    pub max_size: u32,
+    /// How long to wait for a free connection before giving up.
+    pub connection_timeout: Duration,
+    /// Statements running longer are cancelled by the database.
+    /// Zero disables the timeout.
+    pub statement_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            max_size: DEFAULT_POOL_SIZE,
+            connection_timeout: DEFAULT_CONNECTION_TIMEOUT,
+            statement_timeout: DEFAULT_STATEMENT_TIMEOUT,
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Configure from `DB_POOL_SIZE`, `DB_CONNECTION_TIMEOUT` and
+    /// `DB_STATEMENT_TIMEOUT` (in seconds), all optional.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(max_size) = std::env::var("DB_POOL_SIZE") {
+            config.max_size = max_size
+                .parse()
+                .ok()
+                .filter(|&max_size| max_size > 0)
+                .expect("DB_POOL_SIZE must be set to a positive integer");
+        }
+        if let Ok(timeout) = std::env::var("DB_CONNECTION_TIMEOUT") {
+            let timeout = timeout
+                .parse()
+                .ok()
+                .filter(|&timeout| timeout > 0)
+                .expect("DB_CONNECTION_TIMEOUT must be set to a positive integer");
+            config.connection_timeout = Duration::from_secs(timeout);
+        }
+        if let Ok(timeout) = std::env::var("DB_STATEMENT_TIMEOUT") {
+            let timeout = timeout
+                .parse()
+                .expect("DB_STATEMENT_TIMEOUT must be set to a non-negative integer");
+            config.statement_timeout = Duration::from_secs(timeout);
+        }
+        config
+    }
+}
+
+/// Sets `statement_timeout` on every new connection of the pool.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct StatementTimeout(pub Duration);
+
+impl CustomizeConnection<PgConnection, diesel::r2d2::Error> for StatementTimeout {
+    fn on_acquire(&self, conn: &mut PgConnection) -> Result<(), diesel::r2d2::Error> {
+        conn.batch_execute(&format!("SET statement_timeout = {}", self.0.as_millis()))
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
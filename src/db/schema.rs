@@ -1,6 +1,10 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "ContactMethod"))]
+    pub struct ContactMethod;
+
     #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "GameType"))]
     pub struct GameType;
@@ -20,6 +24,23 @@ pub mod sql_types {
     #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "PostType"))]
     pub struct PostType;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "UserRole"))]
+    pub struct UserRole;
+
+    #[derive(diesel::query_builder::QueryId, Clone, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "Visibility"))]
+    pub struct Visibility;
+}
+
+diesel::table! {
+    CommentLikes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        comment_id -> Int4,
+        created_at -> Timestamp,
+    }
 }
 
 diesel::table! {
@@ -34,6 +55,119 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    EmailVerificationTokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 100]
+        email -> Varchar,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    IaaaProfiles (user_id) {
+        user_id -> Int4,
+        dept_id -> Varchar,
+        dept -> Varchar,
+        campus -> Varchar,
+        identity_type -> Varchar,
+        detail_type -> Varchar,
+        identity_status -> Varchar,
+        graduated -> Bool,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::LoginProvider;
+
+    Identities (id) {
+        id -> Int4,
+        user_id -> Int4,
+        provider -> LoginProvider,
+        #[max_length = 255]
+        subject -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    LoginAttempts (id) {
+        id -> Int4,
+        username -> Varchar,
+        ip_address -> Nullable<Varchar>,
+        success -> Bool,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    LoginChallenges (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        failed_attempts -> Int4,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    NicknameHistory (id) {
+        id -> Int4,
+        user_id -> Int4,
+        nickname -> Varchar,
+        changed_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    PasswordResetTokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    PostFavorites (id) {
+        id -> Int4,
+        user_id -> Int4,
+        post_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    PostLikes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        post_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    PostParticipants (id) {
+        id -> Int4,
+        user_id -> Int4,
+        post_id -> Int4,
+        created_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::PostType;
@@ -51,7 +185,6 @@ diesel::table! {
         favorates -> Int4,
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
-        comments_id -> Array<Nullable<Int4>>,
         images -> Array<Nullable<Int4>>,
         post_type -> PostType,
         #[max_length = 255]
@@ -70,9 +203,74 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    RecoveryCodes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        code_hash -> Varchar,
+        created_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    Sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        #[max_length = 64]
+        refresh_token -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked -> Bool,
+        #[max_length = 255]
+        device_name -> Nullable<Varchar>,
+        #[max_length = 64]
+        ip_address -> Nullable<Varchar>,
+        #[max_length = 255]
+        user_agent -> Nullable<Varchar>,
+        last_seen_at -> Timestamp,
+        #[max_length = 64]
+        reported_ip_address -> Nullable<Varchar>,
+    }
+}
+
+diesel::table! {
+    TotpCredentials (user_id) {
+        user_id -> Int4,
+        secret -> Bytea,
+        confirmed_at -> Nullable<Timestamp>,
+        last_used_step -> Nullable<Int8>,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::ContactMethod;
+    use super::sql_types::Visibility;
+
+    UserProfiles (user_id) {
+        user_id -> Int4,
+        bio -> Text,
+        #[max_length = 32]
+        pronouns -> Nullable<Varchar>,
+        contact_method -> Nullable<ContactMethod>,
+        #[max_length = 255]
+        contact -> Nullable<Varchar>,
+        links -> Array<Nullable<Text>>,
+        favorites_visibility -> Visibility,
+        likes_visibility -> Visibility,
+        participations_visibility -> Visibility,
+        contact_visibility -> Visibility,
+        updated_at -> Timestamp,
+    }
+}
+
 diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::LoginProvider;
+    use super::sql_types::UserRole;
 
     Users (id) {
         id -> Int4,
@@ -86,18 +284,52 @@ diesel::table! {
         created_at -> Timestamp,
         updated_at -> Nullable<Timestamp>,
         icon -> Int4,
-        favorite_posts -> Array<Nullable<Int4>>,
-        liked_posts -> Array<Nullable<Int4>>,
-        take_part_posts -> Array<Nullable<Int4>>,
+        role -> UserRole,
+        email_verified -> Bool,
+        delete_after -> Nullable<Timestamp>,
+        nickname_changed_at -> Nullable<Timestamp>,
     }
 }
 
+diesel::joinable!(CommentLikes -> Comments (comment_id));
+diesel::joinable!(CommentLikes -> Users (user_id));
 diesel::joinable!(Comments -> Posts (post_id));
 diesel::joinable!(Comments -> Users (user_id));
+diesel::joinable!(EmailVerificationTokens -> Users (user_id));
+diesel::joinable!(IaaaProfiles -> Users (user_id));
+diesel::joinable!(Identities -> Users (user_id));
+diesel::joinable!(LoginChallenges -> Users (user_id));
+diesel::joinable!(NicknameHistory -> Users (user_id));
+diesel::joinable!(PasswordResetTokens -> Users (user_id));
+diesel::joinable!(PostFavorites -> Posts (post_id));
+diesel::joinable!(PostFavorites -> Users (user_id));
+diesel::joinable!(PostLikes -> Posts (post_id));
+diesel::joinable!(PostLikes -> Users (user_id));
+diesel::joinable!(PostParticipants -> Posts (post_id));
+diesel::joinable!(PostParticipants -> Users (user_id));
 diesel::joinable!(Posts -> Users (user_id));
+diesel::joinable!(RecoveryCodes -> Users (user_id));
+diesel::joinable!(Sessions -> Users (user_id));
+diesel::joinable!(TotpCredentials -> Users (user_id));
+diesel::joinable!(UserProfiles -> Users (user_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    CommentLikes,
     Comments,
+    EmailVerificationTokens,
+    IaaaProfiles,
+    Identities,
+    LoginAttempts,
+    LoginChallenges,
+    NicknameHistory,
+    PasswordResetTokens,
+    PostFavorites,
+    PostLikes,
+    PostParticipants,
     Posts,
+    RecoveryCodes,
+    Sessions,
+    TotpCredentials,
+    UserProfiles,
     Users,
 );
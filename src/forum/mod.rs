@@ -1,17 +1,9 @@
-use chrono::{NaiveDateTime, Utc};
-use diesel::Identifiable;
+use chrono::DateTime;
 use log::{error, trace};
-use models::NewAmusementPost;
-use models::NullableIntArray;
-use models::PostType;
-use schema::Posts::comments_id;
-use schema::Posts::images;
-use schema::Posts::people_all;
-use schema::Posts::sold;
-use tonic::{Request, Response, Status};
+use tonic::{Response, Status};
 
+use crate::auth::profile::is_visible;
 use crate::codegen;
-use crate::codegen::amusement_post;
 use crate::codegen::amusement_post::AmusementPost;
 use crate::codegen::food_post::FoodPost;
 use crate::codegen::forum::forum_server::Forum;
@@ -40,13 +32,13 @@ use crate::codegen::forum::{TakePartAmusePostRequest, TakePartAmusePostResponse}
 use crate::codegen::forum::{UnfavorateRequest, UnfavorateResponse};
 use crate::codegen::forum::{UnlikeCommentRequest, UnlikeCommentResponse};
 use crate::codegen::forum::{UnlikePostRequest, UnlikePostResponse};
-use crate::codegen::post::Post;
 use crate::codegen::sell_post::SellPost;
 use crate::db::*;
 
 use crate::db::models::NewComment;
-use crate::db::schema::Comments::user_id;
 use crate::db::DBClient;
+use crate::middleware::{current_user, ensure_acting_user};
+use crate::permission::{ensure_can_delete, ensure_can_modify, ensure_email_verified};
 
 #[derive(Debug)]
 pub struct ForumService {
@@ -54,353 +46,440 @@ pub struct ForumService {
 }
 
 #[tonic::async_trait]
+#[allow(clippy::result_large_err)]
 impl Forum for ForumService {
     async fn delete_post(
         &self,
         request: tonic::Request<DeletePostRequest>,
     ) -> std::result::Result<tonic::Response<DeletePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("DeletePost got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to delete post")
-        })?;
-
-        // delete the post from Db and get the post
-        let post_id = req.post_id;
-        let the_post = delete_post(conn, post_id).map_err(|e| {
-            error!("Fail to delete post {post_id}: {e}");
-            Status::not_found("No such post")
-        })?;
-
-        // make response
-        let response = DeletePostResponse { success: true };
-
-        // delete images of the post
-        let image_ids = the_post.images.0;
-        for image_id in image_ids {
-            if let Some(image_id) = image_id {
-                let _delete_image_result = delete_image(image_id).map_err(|e| {
-                    error!("Fail to delete image {image_id}: {e}");
-                    // Status::not_found("No such image")
-                });
-                // delete image fail should not be reported to frontend
-            }
-        }
-
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                // only the author or a moderator may delete the post
+                let post_id = req.post_id;
+                let the_post = query_post_by_id(conn, post_id).map_err(|e| {
+                    error!("Fail to get post {post_id}: {e}");
+                    Status::not_found("No such post")
+                })?;
+                ensure_can_delete(&user, &the_post)?;
+
+                // delete the post from Db and get the post
+                let the_post = delete_post(conn, post_id).map_err(|e| {
+                    error!("Fail to delete post {post_id}: {e}");
+                    Status::not_found("No such post")
+                })?;
+
+                // make response
+                let response = DeletePostResponse { success: true };
+
+                // delete images of the post
+                let image_ids = the_post.images.0;
+                for image_id in image_ids.into_iter().flatten() {
+                    let _delete_image_result = delete_image(image_id).map_err(|e| {
+                        error!("Fail to delete image {image_id}: {e}");
+                        // Status::not_found("No such image")
+                    });
+                    // delete image fail should not be reported to frontend
+                }
+
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn list_personal_posts(
         &self,
         request: tonic::Request<ListPersonalPostsRequest>,
     ) -> std::result::Result<tonic::Response<ListPersonalPostsResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("ListPersonalPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to list personal post")
-        })?;
-
-        let the_user_id = req.user_id();
-        let post_type = req.post_type();
-        let request_type = req.r#type(); // own? star? takepart?
-        let number = req.number;
-
-        let user = get_user_by_id(conn, the_user_id).map_err(|e| {
-            error!("Fail to get user from database: {e}");
-            Status::internal("Fail to list personal post")
-        })?;
-
-        let result = match request_type {
-            ListRequestType::Own => {
-                let mut posts = query_post_by_user_id(
-                    conn,
-                    the_user_id,
-                    models::PostType::from_proto_type(post_type),
-                    number,
-                )
-                .map_err(|e| {
-                    error!("Fail to query post of user from database: {e}");
-                    Status::internal("Fail to list personal post")
-                })?;
-                posts.retain(|post| post.post_type.to_proto_type() == post_type);
-                posts
-            }
-            ListRequestType::Takepart => {
-                let posts_result: Vec<Result<models::Post, Box<dyn std::error::Error>>> = user
-                    .take_part_posts
-                    .0
-                    .iter()
-                    .filter(|the_post_id| (**the_post_id).is_some())
-                    .map(|the_post_id| query_post_by_id(conn, the_post_id.unwrap())) // safe unwrap
-                    .collect();
-                let posts: Vec<models::Post> = posts_result
-                    .into_iter()
-                    .filter(|result| (*result).is_ok())
-                    .map(|result| result.unwrap()) // safe unwrap
-                    .filter(|post| post.post_type == PostType::SELLPOST)
-                    .take(number as usize)
-                    .collect();
-                posts
-            }
-            ListRequestType::Star => {
-                let posts_result: Vec<Result<models::Post, Box<dyn std::error::Error>>> = user
-                    .favorite_posts
-                    .0
-                    .iter()
-                    .filter(|the_post_id| (**the_post_id).is_some())
-                    .map(|the_post_id| query_post_by_id(conn, the_post_id.unwrap())) // safe unwrap
-                    .collect();
-                let posts: Vec<models::Post> = posts_result
-                    .into_iter()
-                    .filter(|result| (*result).is_ok())
-                    .map(|result| result.unwrap()) // safe unwrap
-                    .filter(|post| post.post_type.to_proto_type() == post_type)
-                    .take(number as usize)
-                    .collect();
-                posts
-            }
-        };
-
-        let response = match post_type {
-            codegen::post::PostType::Amusementpost => {
-                let posts = result
-                    .into_iter()
-                    .map(|post| post.to_proto_amusement_post(conn))
-                    .map(|result| {
-                        result.map_err(|e| {
-                            error!("Fail to query post of user from database: {e}");
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let the_user_id = req.user_id.unwrap_or(user.user_id);
+                let post_type = req.post_type();
+                let request_type = req.r#type(); // own? star? takepart?
+                let number = req.number;
+
+                // Others see favorites and participations only as the privacy
+                // settings of the user allow.
+                let visibility = |profile: models::UserProfile| match request_type {
+                    ListRequestType::Own => models::Visibility::EVERYONE,
+                    ListRequestType::Star => profile.favorites_visibility,
+                    ListRequestType::Takepart => profile.participations_visibility,
+                };
+                let hidden = the_user_id != user.user_id && {
+                    let profile = query_user_profile(conn, the_user_id)
+                        .map_err(|e| {
+                            error!("Fail to query profile of user {the_user_id}: {e}");
                             Status::internal("Fail to list personal post")
-                        })
-                    })
-                    .collect::<Result<Vec<AmusementPost>, tonic::Status>>()?;
-                ListPersonalPostsResponse {
-                    message: Some(
-                        codegen::forum::list_personal_posts_response::Message::AResponse(
-                            ListAmusementPostsResponse { posts },
-                        ),
-                    ),
-                }
-            }
-            codegen::post::PostType::Sellpost => {
-                let posts = result
-                    .into_iter()
-                    .map(|post| post.to_proto_sell_post(conn))
-                    .map(|result| {
-                        result.map_err(|e| {
+                        })?
+                        .unwrap_or_else(|| models::UserProfile::empty(the_user_id));
+                    !is_visible(visibility(profile), true)
+                };
+
+                let result = match request_type {
+                    _ if hidden => vec![],
+                    ListRequestType::Own => {
+                        let mut posts = query_post_by_user_id(
+                            conn,
+                            the_user_id,
+                            models::PostType::from_proto_type(post_type),
+                            number,
+                        )
+                        .map_err(|e| {
                             error!("Fail to query post of user from database: {e}");
                             Status::internal("Fail to list personal post")
-                        })
-                    })
-                    .collect::<Result<Vec<SellPost>, tonic::Status>>()?;
-                ListPersonalPostsResponse {
-                    message: Some(
-                        codegen::forum::list_personal_posts_response::Message::SResponse(
-                            ListSellPostsResponse { posts },
-                        ),
-                    ),
-                }
-            }
-            codegen::post::PostType::Foodpost => {
-                let posts = result
-                    .into_iter()
-                    .map(|post| post.to_proto_food_post(conn))
-                    .map(|result| {
-                        result.map_err(|e| {
-                            error!("Fail to query post of user from database: {e}");
-                            Status::internal("Fail to list personal post")
-                        })
-                    })
-                    .collect::<Result<Vec<FoodPost>, tonic::Status>>()?;
-                ListPersonalPostsResponse {
-                    message: Some(
-                        codegen::forum::list_personal_posts_response::Message::FResponse(
-                            ListFoodPostsResponse { posts },
-                        ),
-                    ),
-                }
-            }
-        };
-
-        Ok(Response::new(response))
+                        })?;
+                        posts.retain(|post| post.post_type.to_proto_type() == post_type);
+                        posts
+                    }
+                    ListRequestType::Takepart => query_take_part_posts(
+                        conn,
+                        the_user_id,
+                        models::PostType::from_proto_type(post_type),
+                        number,
+                    )
+                    .map_err(|e| {
+                        error!("Fail to query posts user took part in from database: {e}");
+                        Status::internal("Fail to list personal post")
+                    })?,
+                    ListRequestType::Star => query_favorite_posts(
+                        conn,
+                        the_user_id,
+                        models::PostType::from_proto_type(post_type),
+                        number,
+                    )
+                    .map_err(|e| {
+                        error!("Fail to query favorite posts of user from database: {e}");
+                        Status::internal("Fail to list personal post")
+                    })?,
+                };
+
+                let response = match post_type {
+                    codegen::post::PostType::Amusementpost => {
+                        let posts = result
+                            .into_iter()
+                            .map(|post| post.to_proto_amusement_post(conn, user.user_id))
+                            .map(|result| {
+                                result.map_err(|e| {
+                                    error!("Fail to query post of user from database: {e}");
+                                    Status::internal("Fail to list personal post")
+                                })
+                            })
+                            .collect::<Result<Vec<AmusementPost>, tonic::Status>>()?;
+                        ListPersonalPostsResponse {
+                            message: Some(
+                                codegen::forum::list_personal_posts_response::Message::AResponse(
+                                    ListAmusementPostsResponse { posts },
+                                ),
+                            ),
+                        }
+                    }
+                    codegen::post::PostType::Sellpost => {
+                        let posts = result
+                            .into_iter()
+                            .map(|post| post.to_proto_sell_post(conn, user.user_id))
+                            .map(|result| {
+                                result.map_err(|e| {
+                                    error!("Fail to query post of user from database: {e}");
+                                    Status::internal("Fail to list personal post")
+                                })
+                            })
+                            .collect::<Result<Vec<SellPost>, tonic::Status>>()?;
+                        ListPersonalPostsResponse {
+                            message: Some(
+                                codegen::forum::list_personal_posts_response::Message::SResponse(
+                                    ListSellPostsResponse { posts },
+                                ),
+                            ),
+                        }
+                    }
+                    codegen::post::PostType::Foodpost => {
+                        let posts = result
+                            .into_iter()
+                            .map(|post| post.to_proto_food_post(conn, user.user_id))
+                            .map(|result| {
+                                result.map_err(|e| {
+                                    error!("Fail to query post of user from database: {e}");
+                                    Status::internal("Fail to list personal post")
+                                })
+                            })
+                            .collect::<Result<Vec<FoodPost>, tonic::Status>>()?;
+                        ListPersonalPostsResponse {
+                            message: Some(
+                                codegen::forum::list_personal_posts_response::Message::FResponse(
+                                    ListFoodPostsResponse { posts },
+                                ),
+                            ),
+                        }
+                    }
+                };
+
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn comment(
         &self,
         request: tonic::Request<CommentRequest>,
     ) -> std::result::Result<tonic::Response<CommentResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("Comment got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        // make insertable comment
-        let comment = NewComment {
-            post_id: req.post_id,
-            user_id: req.user_id,
-            content: req.content,
-        };
-
-        // insert and update post
-        insert_comment_and_update_post(conn, &comment).map_err(|e| {
-            error!("Fail to insert comment to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        let response = CommentResponse { success: true };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                // make insertable comment
+                let comment = NewComment {
+                    post_id: req.post_id,
+                    user_id: user.user_id,
+                    content: req.content,
+                };
+
+                // insert the comment
+                insert_comment(conn, &comment).map_err(|e| {
+                    error!("Fail to insert comment to database: {e}");
+                    Status::internal("Fail to comment")
+                })?;
+
+                let response = CommentResponse { success: true };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn delete_comment(
         &self,
         request: tonic::Request<DeleteCommentRequest>,
     ) -> std::result::Result<tonic::Response<DeleteCommentResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("DeleteComment got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                // only the author or a moderator may delete the comment
+                let comment_id_to_delete = req.comment_id;
+                let the_comment = query_comment_by_id(conn, comment_id_to_delete).map_err(|e| {
+                    error!("Fail to get comment {comment_id_to_delete}: {e}");
+                    Status::not_found("No such comment")
+                })?;
+                ensure_can_delete(&user, &the_comment)?;
 
-        // delete the comment
-        let comment_id_to_delete = req.comment_id;
-        delete_comment_and_update_post(conn, comment_id_to_delete).map_err(|e| {
-            error!("Fail to delete comment from database: {e}");
-            Status::internal("Fail to delete comment")
-        })?;
+                // delete the comment
+                delete_comment(conn, comment_id_to_delete).map_err(|e| {
+                    error!("Fail to delete comment from database: {e}");
+                    Status::internal("Fail to delete comment")
+                })?;
 
-        let response = DeleteCommentResponse { success: true };
+                let response = DeleteCommentResponse { success: true };
 
-        Ok(Response::new(response))
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn like_post(
         &self,
         request: tonic::Request<LikePostRequest>,
     ) -> std::result::Result<tonic::Response<LikePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("LikePost got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
-        let the_user_id = req.user_id;
+        let the_user_id = user.user_id;
         let the_post_id = req.post_id;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        like_post_by_id(conn, the_user_id, the_post_id).map_err(|e| {
-            error!("Fail to like post from database: {e}");
-            Status::internal("Fail to like post")
-        })?;
-
-        let response = LikePostResponse { success: true };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let toggled = like_post_by_id(conn, the_user_id, the_post_id)
+                    .map_err(|e| {
+                        error!("Fail to like post from database: {e}");
+                        Status::internal("Fail to like post")
+                    })?
+                    .ok_or_else(|| Status::not_found("No such post"))?;
+
+                let response = LikePostResponse {
+                    success: true,
+                    liked: true,
+                    likes: toggled.count,
+                    changed: toggled.changed,
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn unlike_post(
         &self,
         request: tonic::Request<UnlikePostRequest>,
     ) -> std::result::Result<tonic::Response<UnlikePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("UnlikePost got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
-        let the_user_id = req.user_id;
+        let the_user_id = user.user_id;
         let the_post_id = req.post_id;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        unlike_post_by_id(conn, the_user_id, the_post_id).map_err(|e| {
-            error!("Fail to unlike post from database: {e}");
-            Status::internal("Fail to unlike post")
-        })?;
-
-        let response = UnlikePostResponse { success: true };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let toggled = unlike_post_by_id(conn, the_user_id, the_post_id)
+                    .map_err(|e| {
+                        error!("Fail to unlike post from database: {e}");
+                        Status::internal("Fail to unlike post")
+                    })?
+                    .ok_or_else(|| Status::not_found("No such post"))?;
+
+                let response = UnlikePostResponse {
+                    success: true,
+                    liked: false,
+                    likes: toggled.count,
+                    changed: toggled.changed,
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn like_comment(
         &self,
         request: tonic::Request<LikeCommentRequest>,
     ) -> std::result::Result<tonic::Response<LikeCommentResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("LikeComment got request: {req:#?}");
-
-        // need data struct about user, or change of 'Comment' data struct
-        todo!();
+        ensure_acting_user(req.user_id, &user)?;
+
+        let the_comment_id = req.comment_id;
+
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let toggled = like_comment_by_id(conn, user.user_id, the_comment_id)
+                    .map_err(|e| {
+                        error!("Fail to like comment from database: {e}");
+                        Status::internal("Fail to like comment")
+                    })?
+                    .ok_or_else(|| Status::not_found("No such comment"))?;
+
+                let response = LikeCommentResponse {
+                    success: true,
+                    liked: true,
+                    likes: toggled.count,
+                    changed: toggled.changed,
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn unlike_comment(
         &self,
         request: tonic::Request<UnlikeCommentRequest>,
     ) -> std::result::Result<tonic::Response<UnlikeCommentResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("UnlikeComment got request: {req:#?}");
-
-        // need data struct about user, or change of 'Comment' data struct
-        todo!();
+        ensure_acting_user(req.user_id, &user)?;
+
+        let the_comment_id = req.comment_id;
+
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let toggled = unlike_comment_by_id(conn, user.user_id, the_comment_id)
+                    .map_err(|e| {
+                        error!("Fail to unlike comment from database: {e}");
+                        Status::internal("Fail to unlike comment")
+                    })?
+                    .ok_or_else(|| Status::not_found("No such comment"))?;
+
+                let response = UnlikeCommentResponse {
+                    success: true,
+                    liked: false,
+                    likes: toggled.count,
+                    changed: toggled.changed,
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn favorate(
         &self,
         request: tonic::Request<FavorateRequest>,
     ) -> std::result::Result<tonic::Response<FavorateResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("Favorate got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
-        let the_user_id = req.user_id;
+        let the_user_id = user.user_id;
         let the_post_id = req.post_id;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        favorate_post_by_id(conn, the_user_id, the_post_id).map_err(|e| {
-            error!("Fail to favorate post from database: {e}");
-            Status::internal("Fail to favorate post")
-        })?;
-
-        let response = FavorateResponse { success: true };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let toggled = favorate_post_by_id(conn, the_user_id, the_post_id)
+                    .map_err(|e| {
+                        error!("Fail to favorate post from database: {e}");
+                        Status::internal("Fail to favorate post")
+                    })?
+                    .ok_or_else(|| Status::not_found("No such post"))?;
+
+                let response = FavorateResponse {
+                    success: true,
+                    favorated: true,
+                    favorates: toggled.count,
+                    changed: toggled.changed,
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn unfavorate(
         &self,
         request: tonic::Request<UnfavorateRequest>,
     ) -> std::result::Result<tonic::Response<UnfavorateResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("Unfavorate got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
-        let the_user_id = req.user_id;
+        let the_user_id = user.user_id;
         let the_post_id = req.post_id;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        unfavorate_post_by_id(conn, the_user_id, the_post_id).map_err(|e| {
-            error!("Fail to unfavorate post from database: {e}");
-            Status::internal("Fail to unfavorate post")
-        })?;
-
-        let response = UnfavorateResponse { success: true };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let toggled = unfavorate_post_by_id(conn, the_user_id, the_post_id)
+                    .map_err(|e| {
+                        error!("Fail to unfavorate post from database: {e}");
+                        Status::internal("Fail to unfavorate post")
+                    })?
+                    .ok_or_else(|| Status::not_found("No such post"))?;
+
+                let response = UnfavorateResponse {
+                    success: true,
+                    favorated: false,
+                    favorates: toggled.count,
+                    changed: toggled.changed,
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     // about amusement
@@ -409,168 +488,196 @@ impl Forum for ForumService {
         &self,
         request: tonic::Request<CreateAmusementPostRequest>,
     ) -> std::result::Result<tonic::Response<CreatePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("CreateAmusementPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        let new_post = models::Post::from_proto_amusement_post(req.post).map_err(|e| {
-            error!("Fail to convert to amusement post: {e}");
-            Status::internal("Fail to create amusement post")
-        })?;
-
-        let the_post = insert_amusement_post(conn, &new_post).map_err(|e| {
-            error!("Fail to insert amusement post to database: {e}");
-            Status::internal("Fail to create amusement post")
-        })?;
-
-        let response = CreatePostResponse {
-            success: true,
-            post_id: the_post.id,
-            message: "".into(),
-        };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let new_post = models::Post::from_proto_amusement_post(req.post, user.user_id)
+                    .map_err(|e| {
+                        error!("Fail to convert to amusement post: {e}");
+                        Status::internal("Fail to create amusement post")
+                    })?;
+
+                let the_post = insert_amusement_post(conn, &new_post).map_err(|e| {
+                    error!("Fail to insert amusement post to database: {e}");
+                    Status::internal("Fail to create amusement post")
+                })?;
+
+                let response = CreatePostResponse {
+                    success: true,
+                    post_id: the_post.id,
+                    message: "".into(),
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn get_amusement_post(
         &self,
         request: tonic::Request<GetPostRequest>,
     ) -> std::result::Result<tonic::Response<GetAmusementPostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("GetAmusementPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let the_post_id = req.post_id;
 
-        let the_post_id = req.post_id;
+                let the_post = query_post_by_id(conn, the_post_id).map_err(|e| {
+                    error!("Fail to get post from database: {e}");
+                    Status::internal("Fail to get post")
+                })?;
 
-        let the_post = query_post_by_id(conn, the_post_id).map_err(|e| {
-            error!("Fail to get post from database: {e}");
-            Status::internal("Fail to get post")
-        })?;
-
-        if the_post.post_type != models::PostType::AMUSEMENTPOST {
-            error!("Fail to get post from database: Wrong post type");
-            Err(Status::internal("Fail to get post of amusement post"))
-        } else {
-            let the_post = the_post.to_proto_amusement_post(conn).map_err(|e| {
-                error!("Fail to get post from database: {e}");
-                Status::internal("Fail to get post of amusement post")
-            })?;
-            let response = GetAmusementPostResponse {
-                success: true,
-                post: Some(the_post),
-            };
-            Ok(Response::new(response))
-        }
+                if the_post.post_type != models::PostType::AMUSEMENTPOST {
+                    error!("Fail to get post from database: Wrong post type");
+                    Err(Status::internal("Fail to get post of amusement post"))
+                } else {
+                    let the_post = the_post
+                        .to_proto_amusement_post(conn, user.user_id)
+                        .map_err(|e| {
+                            error!("Fail to get post from database: {e}");
+                            Status::internal("Fail to get post of amusement post")
+                        })?;
+                    let response = GetAmusementPostResponse {
+                        success: true,
+                        post: Some(the_post),
+                    };
+                    Ok(Response::new(response))
+                }
+            })
+            .await
     }
 
     async fn list_amusement_posts(
         &self,
         request: tonic::Request<ListAmusementPostsRequest>,
     ) -> std::result::Result<tonic::Response<ListAmusementPostsResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("ListAmusementPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        let post_vec = query_and_filter_amusement_post(
-            conn,
-            if req.game_type.is_some() {
-                Some(models::GameType::from_proto_type(&req.game_type()))
-            //safe unwrap
-            } else {
-                None
-            },
-            req.people_all_lowbound,
-            req.people_all_upbound,
-            req.people_diff_upbound,
-            if req.time_about.is_some() {
-                Some(NaiveDateTime::from_timestamp(req.time_about.unwrap(), 0))
-            //safe unwrap
-            } else {
-                None
-            },
-            req.number,
-        )
-        .map_err(|e| {
-            error!("Fail to query from database: {e}");
-            Status::internal("Fail get amusement posts")
-        })?;
-
-        let mut posts = vec![];
-        for post in post_vec {
-            let post = post.to_proto_amusement_post(conn).map_err(|e| {
-                error!("Fail to convert to amusement post: {e}");
-                Status::internal("Fail get amusement posts")
-            })?;
-            posts.push(post);
-        }
-
-        let response = ListAmusementPostsResponse { posts: posts };
-
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let post_vec = query_and_filter_amusement_post(
+                    conn,
+                    if req.game_type.is_some() {
+                        Some(models::GameType::from_proto_type(&req.game_type()))
+                    //safe unwrap
+                    } else {
+                        None
+                    },
+                    req.people_all_lowbound,
+                    req.people_all_upbound,
+                    req.people_diff_upbound,
+                    if req.time_about.is_some() {
+                        DateTime::from_timestamp(req.time_about(), 0).map(|t| t.naive_utc())
+                    //safe unwrap
+                    } else {
+                        None
+                    },
+                    req.dept_id,
+                    req.number,
+                )
+                .map_err(|e| {
+                    error!("Fail to query from database: {e}");
+                    Status::internal("Fail get amusement posts")
+                })?;
+
+                let mut posts = vec![];
+                for post in post_vec {
+                    let post = post
+                        .to_proto_amusement_post(conn, user.user_id)
+                        .map_err(|e| {
+                            error!("Fail to convert to amusement post: {e}");
+                            Status::internal("Fail get amusement posts")
+                        })?;
+                    posts.push(post);
+                }
+
+                let response = ListAmusementPostsResponse { posts };
+
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn take_part(
         &self,
         request: tonic::Request<TakePartAmusePostRequest>,
     ) -> std::result::Result<tonic::Response<TakePartAmusePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("TakePart got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
-        let the_user_id = req.user_id;
+        let the_user_id = user.user_id;
         let the_post_id = req.post_id;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        take_part_post_by_id(conn, the_user_id, the_post_id).map_err(|e| {
-            error!("Fail to takepart: {e}");
-            Status::internal("Fail to takepart")
-        })?;
-
-        let response = TakePartAmusePostResponse { success: true };
-
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let outcome =
+                    take_part_post_by_id(conn, the_user_id, the_post_id).map_err(|e| {
+                        error!("Fail to takepart: {e}");
+                        Status::internal("Fail to takepart")
+                    })?;
+                let toggled = match outcome {
+                    TakePart::Joined(toggled) => toggled,
+                    TakePart::Full => return Err(Status::failed_precondition("The event is full")),
+                    TakePart::NoSuchEvent => {
+                        return Err(Status::not_found("No such amusement post"))
+                    }
+                };
+
+                let response = TakePartAmusePostResponse {
+                    success: true,
+                    taking_part: true,
+                    people_already: toggled.count,
+                    changed: toggled.changed,
+                };
+
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn no_take_part(
         &self,
         request: tonic::Request<NoTakePartAmusePostRequest>,
     ) -> std::result::Result<tonic::Response<NoTakePartAmusePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("NoTakePart got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
-        let the_user_id = req.user_id;
+        let the_user_id = user.user_id;
         let the_post_id = req.post_id;
 
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        no_take_part_post_by_id(conn, the_user_id, the_post_id).map_err(|e| {
-            error!("Fail to no_takepart: {e}");
-            Status::internal("Fail to no_takepart")
-        })?;
-
-        let response = NoTakePartAmusePostResponse { success: true };
-
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let toggled = no_take_part_post_by_id(conn, the_user_id, the_post_id)
+                    .map_err(|e| {
+                        error!("Fail to no_takepart: {e}");
+                        Status::internal("Fail to no_takepart")
+                    })?
+                    .ok_or_else(|| Status::not_found("No such amusement post"))?;
+
+                let response = NoTakePartAmusePostResponse {
+                    success: true,
+                    taking_part: false,
+                    people_already: toggled.count,
+                    changed: toggled.changed,
+                };
+
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     // about food
@@ -579,110 +686,115 @@ impl Forum for ForumService {
         &self,
         request: tonic::Request<CreateFoodPostRequest>,
     ) -> std::result::Result<tonic::Response<CreatePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("CreateFoodPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to create food post")
-        })?;
-
-        let new_post = models::Post::from_proto_food_post(req.post).map_err(|e| {
-            error!("Fail to convert to food post: {e}");
-            Status::internal("Fail to create food post")
-        })?;
-
-        let the_post = insert_food_post(conn, &new_post).map_err(|e| {
-            error!("Fail to insert food post to database: {e}");
-            Status::internal("Fail to create food post")
-        })?;
-
-        let response = CreatePostResponse {
-            success: true,
-            post_id: the_post.id,
-            message: "".into(),
-        };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let new_post =
+                    models::Post::from_proto_food_post(req.post, user.user_id).map_err(|e| {
+                        error!("Fail to convert to food post: {e}");
+                        Status::internal("Fail to create food post")
+                    })?;
+
+                let the_post = insert_food_post(conn, &new_post).map_err(|e| {
+                    error!("Fail to insert food post to database: {e}");
+                    Status::internal("Fail to create food post")
+                })?;
+
+                let response = CreatePostResponse {
+                    success: true,
+                    post_id: the_post.id,
+                    message: "".into(),
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn get_food_post(
         &self,
         request: tonic::Request<GetPostRequest>,
     ) -> std::result::Result<tonic::Response<GetFoodPostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("GetFoodPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let the_post_id = req.post_id;
 
-        let the_post_id = req.post_id;
+                let the_post = query_post_by_id(conn, the_post_id).map_err(|e| {
+                    error!("Fail to get post from database: {e}");
+                    Status::internal("Fail to get post")
+                })?;
 
-        let the_post = query_post_by_id(conn, the_post_id).map_err(|e| {
-            error!("Fail to get post from database: {e}");
-            Status::internal("Fail to get post")
-        })?;
-
-        if the_post.post_type != models::PostType::FOODPOST {
-            error!("Fail to get post from database: Wrong post type");
-            Err(Status::internal("Fail to get post of food post"))
-        } else {
-            let the_post = the_post.to_proto_food_post(conn).map_err(|e| {
-                error!("Fail to get post from database: {e}");
-                Status::internal("Fail to get post of food post")
-            })?;
-            let response = GetFoodPostResponse {
-                success: true,
-                post: Some(the_post),
-            };
-            Ok(Response::new(response))
-        }
+                if the_post.post_type != models::PostType::FOODPOST {
+                    error!("Fail to get post from database: Wrong post type");
+                    Err(Status::internal("Fail to get post of food post"))
+                } else {
+                    let the_post =
+                        the_post
+                            .to_proto_food_post(conn, user.user_id)
+                            .map_err(|e| {
+                                error!("Fail to get post from database: {e}");
+                                Status::internal("Fail to get post of food post")
+                            })?;
+                    let response = GetFoodPostResponse {
+                        success: true,
+                        post: Some(the_post),
+                    };
+                    Ok(Response::new(response))
+                }
+            })
+            .await
     }
 
     async fn list_food_posts(
         &self,
         request: tonic::Request<ListFoodPostsRequest>,
     ) -> std::result::Result<tonic::Response<ListFoodPostsResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("ListFoodPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        let post_vec = query_and_filter_food_post(
-            conn,
-            if req.food_place.is_some() {
-                Some(models::Place::from_proto_type(&req.food_place()))
-            } else {
-                None
-            },
-            req.score_lowbond,
-            req.random,
-            req.number,
-        )
-        .map_err(|e| {
-            error!("Fail to query from database: {e}");
-            Status::internal("Fail get food posts")
-        })?;
-
-        let mut posts = vec![];
-        for post in post_vec {
-            let post = post.to_proto_food_post(conn).map_err(|e| {
-                error!("Fail to convert to food post: {e}");
-                Status::internal("Fail get food posts")
-            })?;
-            posts.push(post);
-        }
-
-        let response = ListFoodPostsResponse { posts: posts };
-
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let post_vec = query_and_filter_food_post(
+                    conn,
+                    if req.food_place.is_some() {
+                        Some(models::Place::from_proto_type(&req.food_place()))
+                    } else {
+                        None
+                    },
+                    req.score_lowbond,
+                    req.random,
+                    req.dept_id,
+                    req.number,
+                )
+                .map_err(|e| {
+                    error!("Fail to query from database: {e}");
+                    Status::internal("Fail get food posts")
+                })?;
+
+                let mut posts = vec![];
+                for post in post_vec {
+                    let post = post.to_proto_food_post(conn, user.user_id).map_err(|e| {
+                        error!("Fail to convert to food post: {e}");
+                        Status::internal("Fail get food posts")
+                    })?;
+                    posts.push(post);
+                }
+
+                let response = ListFoodPostsResponse { posts };
+
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     // about sell
@@ -691,131 +803,155 @@ impl Forum for ForumService {
         &self,
         request: tonic::Request<CreateSellPostRequest>,
     ) -> std::result::Result<tonic::Response<CreatePostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("CreateSellPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        let new_post = models::Post::from_proto_sell_post(req.post).map_err(|e| {
-            error!("Fail to convert to sell post: {e}");
-            Status::internal("Fail to create sell post")
-        })?;
-
-        let the_post = insert_sell_post(conn, &new_post).map_err(|e| {
-            error!("Fail to insert sell post to database: {e}");
-            Status::internal("Fail to create sell post")
-        })?;
-
-        let response = CreatePostResponse {
-            success: true,
-            post_id: the_post.id,
-            message: "".into(),
-        };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let dbuser = get_user_by_id(conn, user.user_id).map_err(|e| {
+                    error!("Fail to get user {}: {e}", user.user_id);
+                    Status::internal("Fail to create sell post")
+                })?;
+                ensure_email_verified(&dbuser)?;
+
+                let new_post =
+                    models::Post::from_proto_sell_post(req.post, user.user_id).map_err(|e| {
+                        error!("Fail to convert to sell post: {e}");
+                        Status::internal("Fail to create sell post")
+                    })?;
+
+                let the_post = insert_sell_post(conn, &new_post).map_err(|e| {
+                    error!("Fail to insert sell post to database: {e}");
+                    Status::internal("Fail to create sell post")
+                })?;
+
+                let response = CreatePostResponse {
+                    success: true,
+                    post_id: the_post.id,
+                    message: "".into(),
+                };
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn get_sell_post(
         &self,
         request: tonic::Request<GetPostRequest>,
     ) -> std::result::Result<tonic::Response<GetSellPostResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("GetSellPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let the_post_id = req.post_id;
 
-        let the_post_id = req.post_id;
+                let the_post = query_post_by_id(conn, the_post_id).map_err(|e| {
+                    error!("Fail to get post from database: {e}");
+                    Status::internal("Fail to get post")
+                })?;
 
-        let the_post = query_post_by_id(conn, the_post_id).map_err(|e| {
-            error!("Fail to get post from database: {e}");
-            Status::internal("Fail to get post")
-        })?;
-
-        if the_post.post_type != models::PostType::SELLPOST {
-            error!("Fail to get post from database: Wrong post type");
-            Err(Status::internal("Fail to get post of sell post"))
-        } else {
-            let the_post = the_post.to_proto_sell_post(conn).map_err(|e| {
-                error!("Fail to get post from database: {e}");
-                Status::internal("Fail to get post of sell post")
-            })?;
-            let response = GetSellPostResponse {
-                success: true,
-                post: Some(the_post),
-            };
-            Ok(Response::new(response))
-        }
+                if the_post.post_type != models::PostType::SELLPOST {
+                    error!("Fail to get post from database: Wrong post type");
+                    Err(Status::internal("Fail to get post of sell post"))
+                } else {
+                    let the_post =
+                        the_post
+                            .to_proto_sell_post(conn, user.user_id)
+                            .map_err(|e| {
+                                error!("Fail to get post from database: {e}");
+                                Status::internal("Fail to get post of sell post")
+                            })?;
+                    let response = GetSellPostResponse {
+                        success: true,
+                        post: Some(the_post),
+                    };
+                    Ok(Response::new(response))
+                }
+            })
+            .await
     }
 
     async fn list_sell_posts(
         &self,
         request: tonic::Request<ListSellPostsRequest>,
     ) -> std::result::Result<tonic::Response<ListSellPostsResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("ListSellPost got request: {req:#?}");
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-
-        let post_vec = query_and_filter_sell_post(
-            conn,
-            if req.goods_type.is_some() {
-                Some(models::GoodsType::from_proto_type(&req.goods_type()))
-            } else {
-                None
-            },
-            req.price_upbond,
-            req.number,
-        )
-        .map_err(|e| {
-            error!("Fail to query from database: {e}");
-            Status::internal("Fail get food posts")
-        })?;
-
-        let mut posts = vec![];
-        for post in post_vec {
-            let post = post.to_proto_sell_post(conn).map_err(|e| {
-                error!("Fail to convert to food post: {e}");
-                Status::internal("Fail get food posts")
-            })?;
-            posts.push(post);
-        }
-
-        let response = ListSellPostsResponse { posts: posts };
-
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let post_vec = query_and_filter_sell_post(
+                    conn,
+                    if req.goods_type.is_some() {
+                        Some(models::GoodsType::from_proto_type(&req.goods_type()))
+                    } else {
+                        None
+                    },
+                    req.price_upbond,
+                    req.dept_id,
+                    req.number,
+                )
+                .map_err(|e| {
+                    error!("Fail to query from database: {e}");
+                    Status::internal("Fail get food posts")
+                })?;
+
+                let mut posts = vec![];
+                for post in post_vec {
+                    let post = post.to_proto_sell_post(conn, user.user_id).map_err(|e| {
+                        error!("Fail to convert to food post: {e}");
+                        Status::internal("Fail get food posts")
+                    })?;
+                    posts.push(post);
+                }
+
+                let response = ListSellPostsResponse { posts };
+
+                Ok(Response::new(response))
+            })
+            .await
     }
 
     async fn set_sold(
         &self,
         request: tonic::Request<SetSoldRequest>,
     ) -> std::result::Result<tonic::Response<SetSoldResponse>, tonic::Status> {
+        let user = current_user(&request)?;
         let req = request.into_inner();
         trace!("SetSold got request: {req:#?}");
+        ensure_acting_user(req.user_id, &user)?;
 
         // get connection to Db
-        let conn = &mut self.client.get_conn().map_err(|e| {
-            error!("Fail to get connection to database: {e}");
-            Status::internal("Fail to comment")
-        })?;
-        let post_id = req.post_id;
-
-        set_sold_for_sell_post_by_id(conn, post_id).map_err(|e| {
-            error!("Fail to convert to food post: {e}");
-            Status::internal("Fail get food posts")
-        })?;
-
-        let response = SetSoldResponse { success: true };
-        Ok(Response::new(response))
+        self.client
+            .run(move |conn| -> Result<_, Status> {
+                let post_id = req.post_id;
+
+                // only the seller may mark the goods as sold
+                let the_post = query_post_by_id(conn, post_id).map_err(|e| {
+                    error!("Fail to get post {post_id}: {e}");
+                    Status::not_found("No such post")
+                })?;
+                if the_post.post_type != models::PostType::SELLPOST {
+                    error!("Fail to set sold for post {post_id}: Wrong post type");
+                    return Err(Status::invalid_argument("Not a sell post"));
+                }
+                ensure_can_modify(&user, &the_post)?;
+
+                set_sold_for_sell_post_by_id(conn, post_id).map_err(|e| {
+                    error!("Fail to set sold for post {post_id}: {e}");
+                    Status::internal("Fail to set sold")
+                })?;
+
+                let response = SetSoldResponse { success: true };
+                Ok(Response::new(response))
+            })
+            .await
     }
 }
@@ -1,4 +1,5 @@
 pub mod codegen {
+    pub mod admin;
     pub mod amusement_post;
     pub mod auth;
     pub mod food_post;
@@ -7,32 +8,56 @@ pub mod codegen {
     pub mod post;
     pub mod sell_post;
 }
+pub mod admin;
 pub mod auth;
+pub mod clock;
 pub mod crypto;
 pub mod db;
 pub mod forum;
 pub mod hello;
+pub mod mail;
 pub mod middleware;
+pub mod permission;
 #[cfg(test)]
 pub mod tests;
 use db::schema as dbschema;
 use log::info;
+use middleware::keyring::KeyRing;
 use std::env;
 use std::sync::LazyLock;
 
-pub const AUTHORIZATION_KEY: &'static str = "holopku-authorization-bin";
-const JWT_SECRET: LazyLock<String> = LazyLock::new(|| {
-    let jwt_secret = std::env::var("JWT_SECRET").expect("Must set JWT_SECRET");
-    jwt_secret
+pub const AUTHORIZATION_KEY: &str = "holopku-authorization-bin";
+static JWT_KEYRING: LazyLock<KeyRing> = LazyLock::new(|| {
+    KeyRing::from_env().unwrap_or_else(|e| panic!("Fail to load JWT keys: {e}"))
 });
-const JWT_EXPIRE_TIME: LazyLock<usize> = LazyLock::new(|| {
+static JWT_EXPIRE_TIME: LazyLock<usize> = LazyLock::new(|| {
     let jwt_expire_time = std::env::var("JWT_EXPIRE_TIME").expect("Must set JWT_EXPIRE_TIME");
     jwt_expire_time
         .parse::<usize>()
         .expect("JWT_EXPIRE_TIME must be set to a positive integer")
 });
-const JWT_ISSUER: &'static str = "HoloPKU server";
-const AES256KEY: LazyLock<[u8; 32]> = LazyLock::new(|| {
+static REFRESH_TOKEN_EXPIRE_TIME: LazyLock<i64> = LazyLock::new(|| {
+    let expire_time =
+        std::env::var("REFRESH_TOKEN_EXPIRE_TIME").expect("Must set REFRESH_TOKEN_EXPIRE_TIME");
+    expire_time
+        .parse::<i64>()
+        .ok()
+        .filter(|t| *t > 0)
+        .expect("REFRESH_TOKEN_EXPIRE_TIME must be set to a positive integer")
+});
+const JWT_ISSUER: &str = "HoloPKU server";
+const JWT_AUDIENCE: &str = "holopku";
+/// Allowed clock skew (in seconds) when checking `exp` and `nbf`.
+static JWT_LEEWAY: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("JWT_LEEWAY")
+        .map(|leeway| {
+            leeway
+                .parse::<u64>()
+                .expect("JWT_LEEWAY must be set to a non-negative integer")
+        })
+        .unwrap_or(60)
+});
+static AES256KEY: LazyLock<[u8; 32]> = LazyLock::new(|| {
     let key_str = env::var("AES256KEY").expect("Must set AES256KEY");
     let key_bytes = key_str.as_bytes();
     let mut key = [0u8; 32];
@@ -40,7 +65,7 @@ const AES256KEY: LazyLock<[u8; 32]> = LazyLock::new(|| {
     key[..len].copy_from_slice(&key_bytes[..len]);
     key
 });
-const AES256IV: LazyLock<[u8; 16]> = LazyLock::new(|| {
+static AES256IV: LazyLock<[u8; 16]> = LazyLock::new(|| {
     let iv_str = env::var("AES256IV").expect("Must set AES256IV");
     let iv_bytes = iv_str.as_bytes();
     let mut iv = [0u8; 16];
@@ -48,18 +73,32 @@ const AES256IV: LazyLock<[u8; 16]> = LazyLock::new(|| {
     iv[..len].copy_from_slice(&iv_bytes[..len]);
     iv
 });
+// AES-256-GCM key for tokens, AES256KEY and AES256IV only open legacy tokens.
+static TOKEN_KEY: LazyLock<[u8; 32]> = LazyLock::new(|| {
+    let secret = env::var("AES256KEY").expect("Must set AES256KEY");
+    crypto::derive_key(secret.as_bytes())
+});
+// AES-256-GCM key for secrets stored in the database.
+static SECRET_KEY: LazyLock<[u8; 32]> = LazyLock::new(|| {
+    let secret = env::var("AES256KEY").expect("Must set AES256KEY");
+    crypto::derive_secret_key(secret.as_bytes())
+});
 
 /// Check all environment variables to assure integrity.
 pub fn check_envs() {
     // log safe: information stored on server.
-    let _env = JWT_SECRET;
-    info!("JWT_SECRET={:?}", *JWT_SECRET);
-    let _env = JWT_EXPIRE_TIME;
+    info!("JWT_KEYS={:?}", JWT_KEYRING.kids().collect::<Vec<_>>());
+    info!("JWT_CURRENT_KID={:?}", JWT_KEYRING.current_kid());
     info!("JWT_EXPIRE_TIME={:?}", *JWT_EXPIRE_TIME);
-    let _env = JWT_ISSUER;
+    info!("REFRESH_TOKEN_EXPIRE_TIME={:?}", *REFRESH_TOKEN_EXPIRE_TIME);
     info!("JWT_ISSUER={:?}", JWT_ISSUER);
-    let _env = AES256KEY;
-    info!("AES256KEY={:?}", *AES256KEY);
-    let _env = AES256IV;
-    info!("AES256IV={:?}", *AES256IV);
+    info!("JWT_AUDIENCE={:?}", JWT_AUDIENCE);
+    info!("JWT_LEEWAY={:?}", *JWT_LEEWAY);
+    // never log the value: AES256KEY derives the keys of the tokens and of the
+    // TOTP secrets stored in the database.
+    LazyLock::force(&AES256KEY);
+    LazyLock::force(&AES256IV);
+    info!("AES256KEY and AES256IV are set");
+    LazyLock::force(&TOKEN_KEY);
+    LazyLock::force(&SECRET_KEY);
 }
@@ -0,0 +1,182 @@
+//! Keys used to sign and verify JWTs.
+//!
+//! Every key has an id, written to the `kid` header of the tokens it signs.
+//! Several keys can verify tokens at the same time while only the current
+//! one signs, so keys are rotated without invalidating issued tokens:
+//! 1. add the new key to `JWT_KEYS` and restart;
+//! 2. point `JWT_CURRENT_KID` to the new key and restart;
+//! 3. once `JWT_EXPIRE_TIME` has passed, remove the old key.
+//!
+//! `JWT_KEYS` is a `;` separated list of `kid=ALG:value` entries, where
+//! `value` is the secret for `HS256`, and the path to the public key PEM,
+//! followed by `,` and the path to the private key PEM, for `EdDSA` and
+//! `RS256`. The private key is only needed for the current key. Verifying
+//! asymmetric tokens only needs the public key, so other services can check
+//! tokens without being able to issue them.
+//!
+//! If `JWT_KEYS` is not set, `JWT_SECRET` is the only key. Otherwise
+//! `JWT_SECRET` is optional and only verifies tokens without a `kid`, which
+//! were issued before key ids were introduced.
+use std::collections::HashMap;
+use std::env;
+use std::error::Error as StdError;
+
+use jsonwebtoken::errors::Error as JwtError;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header};
+use jsonwebtoken::{TokenData, Validation};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Id of the key built from `JWT_SECRET`.
+pub const LEGACY_KID: &str = "default";
+
+struct JwtKey {
+    algorithm: Algorithm,
+    encoding: Option<EncodingKey>,
+    decoding: DecodingKey,
+}
+
+pub struct KeyRing {
+    current_kid: String,
+    keys: HashMap<String, JwtKey>,
+    /// Verifies tokens without a `kid`.
+    legacy: Option<JwtKey>,
+}
+
+impl KeyRing {
+    /// Load the keyring from `JWT_KEYS`, `JWT_CURRENT_KID` and `JWT_SECRET`.
+    pub fn from_env() -> Result<Self, Box<dyn StdError>> {
+        let secret = env::var("JWT_SECRET").ok();
+        match env::var("JWT_KEYS") {
+            Ok(keys) => {
+                let current_kid = env::var("JWT_CURRENT_KID")
+                    .map_err(|_| "JWT_CURRENT_KID must be set with JWT_KEYS")?;
+                Self::parse(&keys, &current_kid, secret.as_deref())
+            }
+            Err(_) => {
+                let secret = secret.ok_or("Must set JWT_SECRET or JWT_KEYS")?;
+                Ok(Self::from_secret(&secret))
+            }
+        }
+    }
+
+    /// A keyring with `secret` as the only key.
+    pub fn from_secret(secret: &str) -> Self {
+        let mut keys = HashMap::new();
+        keys.insert(LEGACY_KID.to_string(), hmac_key(secret));
+        Self {
+            current_kid: LEGACY_KID.to_string(),
+            keys,
+            legacy: Some(hmac_key(secret)),
+        }
+    }
+
+    /// Parse a `JWT_KEYS` value, see the module documentation.
+    pub fn parse(
+        keys: &str,
+        current_kid: &str,
+        legacy_secret: Option<&str>,
+    ) -> Result<Self, Box<dyn StdError>> {
+        let mut parsed = HashMap::new();
+        for entry in keys.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (kid, spec) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid JWT key entry: {entry}"))?;
+            let (alg, value) = spec
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid JWT key {kid}: missing algorithm"))?;
+            let key = match alg {
+                "HS256" => hmac_key(value),
+                "EdDSA" | "RS256" => pem_key(alg, value)?,
+                _ => return Err(format!("Unsupported algorithm {alg} of JWT key {kid}").into()),
+            };
+            if parsed.insert(kid.to_string(), key).is_some() {
+                return Err(format!("Duplicate JWT key {kid}").into());
+            }
+        }
+
+        let current = parsed
+            .get(current_kid)
+            .ok_or_else(|| format!("JWT_CURRENT_KID {current_kid} is not in JWT_KEYS"))?;
+        if current.encoding.is_none() {
+            return Err(format!("JWT key {current_kid} has no private key to sign").into());
+        }
+
+        Ok(Self {
+            current_kid: current_kid.to_string(),
+            keys: parsed,
+            legacy: legacy_secret.map(hmac_key),
+        })
+    }
+
+    pub fn current_kid(&self) -> &str {
+        &self.current_kid
+    }
+
+    pub fn kids(&self) -> impl Iterator<Item = &str> {
+        self.keys.keys().map(String::as_str)
+    }
+
+    /// Sign `claims` with the current key.
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, JwtError> {
+        let key = &self.keys[&self.current_kid];
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(self.current_kid.clone());
+        // checked to be present in `parse`
+        let encoding = key.encoding.as_ref().expect("current key can sign");
+        encode(&header, claims, encoding)
+    }
+
+    /// Verify `token` with the key named by its `kid`. `validation` is
+    /// adjusted to the algorithm of that key.
+    pub fn verify<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        mut validation: Validation,
+    ) -> Result<TokenData<T>, JwtError> {
+        use jsonwebtoken::errors::ErrorKind;
+        let header = decode_header(token)?;
+        let key = match &header.kid {
+            Some(kid) => self.keys.get(kid),
+            None => self.legacy.as_ref(),
+        }
+        .ok_or(ErrorKind::InvalidKeyFormat)?;
+        validation.algorithms = vec![key.algorithm];
+        decode::<T>(token, &key.decoding, &validation)
+    }
+}
+
+fn hmac_key(secret: &str) -> JwtKey {
+    JwtKey {
+        algorithm: Algorithm::HS256,
+        encoding: Some(EncodingKey::from_secret(secret.as_bytes())),
+        decoding: DecodingKey::from_secret(secret.as_bytes()),
+    }
+}
+
+fn pem_key(alg: &str, paths: &str) -> Result<JwtKey, Box<dyn StdError>> {
+    let (public_path, private_path) = match paths.split_once(',') {
+        Some((public, private)) => (public, Some(private)),
+        None => (paths, None),
+    };
+    let public = std::fs::read(public_path)?;
+    let private = private_path.map(std::fs::read).transpose()?;
+    let key = if alg == "EdDSA" {
+        JwtKey {
+            algorithm: Algorithm::EdDSA,
+            encoding: private
+                .map(|pem| EncodingKey::from_ed_pem(&pem))
+                .transpose()?,
+            decoding: DecodingKey::from_ed_pem(&public)?,
+        }
+    } else {
+        JwtKey {
+            algorithm: Algorithm::RS256,
+            encoding: private
+                .map(|pem| EncodingKey::from_rsa_pem(&pem))
+                .transpose()?,
+            decoding: DecodingKey::from_rsa_pem(&public)?,
+        }
+    };
+    Ok(key)
+}
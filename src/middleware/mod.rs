@@ -1,90 +1,267 @@
-use jsonwebtoken::errors::Error as JwtError;
-use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
-use log::trace;
+pub mod keyring;
+pub mod revocation;
+
+use jsonwebtoken::errors::{Error as JwtError, ErrorKind};
+use jsonwebtoken::Validation;
+use log::{error, trace};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tonic::metadata::MetadataMap;
+use tonic::service::Interceptor;
 use tonic::{Request, Status};
 
-use crate::crypto::{decrypt_aes256, encrypt_aes256};
-use crate::{AUTHORIZATION_KEY, JWT_EXPIRE_TIME, JWT_ISSUER, JWT_SECRET};
+use crate::codegen::auth::UserRole;
+use crate::crypto::{decrypt_token, encrypt_token};
+use crate::{
+    AUTHORIZATION_KEY, JWT_AUDIENCE, JWT_EXPIRE_TIME, JWT_ISSUER, JWT_KEYRING, JWT_LEEWAY,
+};
+use revocation::RevocationStore;
+
+/// Identity of the caller, decoded from the JWT carried in the request.
+/// Handlers must use this instead of any `user_id` sent in the request body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub user_id: i32,
+    /// Session the access token was issued for.
+    pub session_id: i32,
+    /// Role of the caller when the access token was issued.
+    pub role: UserRole,
+}
 
 /// Authentication interceptor to verify JWT in the request.
-/// This function will get called on each inbound request, if a `Status`
-/// is returned, it will cancel the request and return that status to the
-/// client.
+/// It will get called on each inbound request, if a `Status` is returned,
+/// it will cancel the request and return that status to the client.
+/// On success the caller is attached to the request as an
+/// [`AuthenticatedUser`] extension.
+#[derive(Clone)]
+pub struct AuthInterceptor {
+    revocations: Arc<dyn RevocationStore>,
+    required: bool,
+}
+
+impl AuthInterceptor {
+    /// Reject every request without a valid token.
+    pub fn required(revocations: Arc<dyn RevocationStore>) -> Self {
+        Self {
+            revocations,
+            required: true,
+        }
+    }
+
+    /// Let requests without a valid token through unauthenticated, for
+    /// services mixing public and private methods. Private methods still
+    /// fail in [`current_user`].
+    pub fn optional(revocations: Arc<dyn RevocationStore>) -> Self {
+        Self {
+            revocations,
+            required: false,
+        }
+    }
+}
+
+impl Interceptor for AuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        // not the metadata, which has the token
+        trace!("Auth intercepting request from {:?}", request.remote_addr());
+
+        match authenticate(request.metadata(), self.revocations.as_ref()) {
+            Ok(user) => {
+                request.extensions_mut().insert(user);
+            }
+            Err(status) if self.required => return Err(status),
+            Err(status) => trace!("Unauthenticated request: {}", status.message()),
+        }
+        Ok(request)
+    }
+}
+
+/// Get the caller of a request that went through [`AuthInterceptor`].
+#[allow(clippy::result_large_err)]
+pub fn current_user<T>(request: &Request<T>) -> Result<AuthenticatedUser, Status> {
+    request
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .copied()
+        .ok_or_else(|| Status::unauthenticated("Missing authorization header"))
+}
 
-pub fn auth_interceptor(request: Request<()>) -> Result<Request<()>, Status> {
-    trace!("Auth intercepting request: {:?}", request);
+/// Reject a request whose body names another user than the caller.
+/// A zero `user_id` is treated as omitted.
+#[allow(clippy::result_large_err)]
+pub fn ensure_acting_user(user_id: i32, user: &AuthenticatedUser) -> Result<(), Status> {
+    if user_id != 0 && user_id != user.user_id {
+        trace!(
+            "User {} tried to act on behalf of user {user_id}",
+            user.user_id
+        );
+        return Err(Status::permission_denied(
+            "Cannot act on behalf of other users",
+        ));
+    }
+    Ok(())
+}
 
-    let token = match request.metadata().get(AUTHORIZATION_KEY) {
-        Some(token) => token.to_str().unwrap_or(""),
+/// Verify the token in `metadata` and decode the caller from its claims.
+/// Tokens of revoked sessions are rejected.
+#[allow(clippy::result_large_err)]
+pub fn authenticate(
+    metadata: &MetadataMap,
+    revocations: &dyn RevocationStore,
+) -> Result<AuthenticatedUser, Status> {
+    let token = match metadata.get_bin(AUTHORIZATION_KEY) {
+        Some(token) => token
+            .to_bytes()
+            .map_err(|_| Status::unauthenticated("Malformed authorization header"))?,
         None => return Err(Status::unauthenticated("Missing authorization header")),
     };
 
     // decode JWT
-    let token = decrypt_aes256(token.as_bytes())
-        .map_err(|e| Status::unauthenticated(format!("Fail to decode token: {e}")))?;
-    // [`std::mem::transmute`] no panic
-    // TODO: change `jwt` crate [`jsonwebtoken::decoding::decode`] API to `decode_bytes` or something
-    // when this API is available.
-    let token = unsafe { std::str::from_utf8_unchecked(&token) };
-
-    // verify JWT
-    if let Ok(claims) = validate_jwt(token) {
-        // validate time
-        let now = chrono::Utc::now().timestamp();
-        let expire_at = claims.iat + claims.exp;
-        if now >= (expire_at as i64) {
-            trace!("Token {token} expired");
-            return Err(Status::unauthenticated("Token expired"));
+    // the same answer for every failure, which must not tell a bad padding of
+    // a legacy token from a bad ciphertext
+    let token = decrypt_token(&token).map_err(|e| {
+        trace!("Fail to decrypt token: {e}");
+        Status::unauthenticated("Fail to decode token")
+    })?;
+    let token =
+        std::str::from_utf8(&token).map_err(|_| Status::unauthenticated("Fail to decode token"))?;
+
+    // verify JWT, including its expiry
+    let claims = validate_jwt(token).map_err(|e| {
+        trace!("Invalid token: {e}");
+        match e.kind() {
+            ErrorKind::ExpiredSignature => Status::unauthenticated("Token expired"),
+            _ => Status::unauthenticated("Invalid token"),
         }
-        // communicate with Redis to check whether it's valid or not
-        // todo!("communicate with Redis and Database to check whether the user is valid or not");
-        Ok(request)
-    } else {
-        Err(Status::unauthenticated("Invalid token"))
+    })?;
+
+    let user_id = claims
+        .sub
+        .parse::<i32>()
+        .map_err(|_| Status::unauthenticated("Invalid token"))?;
+
+    let session_id = claims.sid;
+    let revoked = revocations.is_revoked(session_id).map_err(|e| {
+        error!("Fail to check revocation of session {session_id}: {e}");
+        Status::internal("Fail to authorize")
+    })?;
+    if revoked {
+        trace!("Session {session_id} revoked");
+        return Err(Status::unauthenticated("Session revoked"));
+    }
+    // only shown by `ListSessions`, not worth failing the request
+    if let Err(e) = revocations.seen(session_id) {
+        error!("Fail to record activity of session {session_id}: {e}");
     }
+
+    Ok(AuthenticatedUser {
+        user_id,
+        session_id,
+        role: UserRole::try_from(claims.role).unwrap_or(UserRole::User),
+    })
 }
 
+/// Claims of an access token.
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
-    aud: String, // Optional. Audience
-    exp: usize, // Required (validate_exp defaults to true in validation). Expiration time (as UTC timestamp)
-    iat: usize, // Optional. Issued at (as UTC timestamp)
-    iss: String, // Optional. Issuer
-    // nbf: usize,          // Optional. Not Before (as UTC timestamp)
-    sub: String, // Optional. Subject (whom token refers to)
+    iss: String, // Issuer, always `JWT_ISSUER`
+    sub: String, // Subject, the user id
+    aud: String, // Audience, always `JWT_AUDIENCE`
+    exp: i64,    // Expiration time (as UTC timestamp)
+    nbf: i64,    // Not before (as UTC timestamp)
+    iat: i64,    // Issued at (as UTC timestamp)
+    jti: String, // Unique id of the token
+    sid: i32,    // Session the token was issued for
+    role: i32,   // Role of the user, picked up again on each refresh
 }
 
-pub fn issue_token(user_id: &String, email: &String) -> Result<Vec<u8>, JwtError> {
-    let token = issue_token_inner(user_id, email)?;
-    trace!("Token: {token}");
+/// Claims of access tokens issued with sessions but before the standard
+/// claims, where `aud` is the user id, `sub` the email, `exp` a duration
+/// relative to `iat` and `sid` the session. Older tokens carry no `sid`, so
+/// they cannot be revoked and are rejected, their holders have to log in.
+// TODO: remove in the next release, once all legacy tokens have expired.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyClaims {
+    aud: String,
+    exp: i64,
+    iat: i64,
+    iss: String,
+    sub: String,
+    sid: i32,
+    #[serde(default)]
+    role: i32,
+}
+
+impl LegacyClaims {
+    fn into_claims(self) -> Claims {
+        Claims {
+            iss: self.iss,
+            sub: self.aud,
+            aud: JWT_AUDIENCE.into(),
+            exp: self.iat + self.exp,
+            nbf: self.iat,
+            iat: self.iat,
+            jti: String::new(),
+            sid: self.sid,
+            role: self.role,
+        }
+    }
+}
+
+pub fn issue_token(user_id: i32, session_id: i32, role: UserRole) -> Result<Vec<u8>, JwtError> {
+    let token = issue_token_inner(user_id, session_id, role)?;
+    trace!("Token issued for session {session_id} of user {user_id}");
     // encrypt the token
-    let encrypt_token = encrypt_aes256(token.as_bytes());
-    trace!("Encrypted token: {token}");
+    let encrypt_token = encrypt_token(token.as_bytes());
     Ok(encrypt_token)
 }
 
-fn issue_token_inner(user_id: &String, email: &String) -> Result<String, JwtError> {
+fn issue_token_inner(user_id: i32, session_id: i32, role: UserRole) -> Result<String, JwtError> {
+    let now = chrono::Utc::now().timestamp();
     let claims = Claims {
         iss: JWT_ISSUER.into(),
-        exp: *JWT_EXPIRE_TIME,
-        iat: chrono::Utc::now().timestamp() as usize,
-        aud: user_id.clone(),
-        sub: email.clone(),
+        sub: user_id.to_string(),
+        aud: JWT_AUDIENCE.into(),
+        exp: now + *JWT_EXPIRE_TIME as i64,
+        nbf: now,
+        iat: now,
+        jti: uuid::Uuid::new_v4().to_string(),
+        sid: session_id,
+        role: role as i32,
     };
     trace!("Claims: {claims:#?}");
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(JWT_SECRET.as_ref()),
-    )
+    JWT_KEYRING.sign(&claims)
 }
 
 fn validate_jwt(token: &str) -> Result<Claims, JwtError> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(JWT_SECRET.as_ref()),
-        &Validation::new(Algorithm::HS256),
-    )
-    .map(|data| data.claims)
+    // the algorithm is set by the keyring from the key named by `kid`
+    let mut validation = Validation::default();
+    validation.leeway = *JWT_LEEWAY;
+    validation.validate_nbf = true;
+    validation.set_audience(&[JWT_AUDIENCE]);
+    validation.set_issuer(&[JWT_ISSUER]);
+    validation.set_required_spec_claims(&["exp", "nbf", "aud", "iss", "sub"]);
+    match JWT_KEYRING.verify::<Claims>(token, validation) {
+        Ok(data) => Ok(data.claims),
+        Err(e) => validate_legacy_jwt(token).map_err(|_| e),
+    }
+}
+
+fn validate_legacy_jwt(token: &str) -> Result<Claims, JwtError> {
+    let mut validation = Validation::default();
+    validation.validate_aud = false;
+    // `exp` is relative, checked below once converted
+    validation.validate_exp = false;
+    validation.set_required_spec_claims(&["exp", "iss"]);
+    validation.set_issuer(&[JWT_ISSUER]);
+    let claims = JWT_KEYRING
+        .verify::<LegacyClaims>(token, validation)?
+        .claims
+        .into_claims();
+    let now = chrono::Utc::now().timestamp();
+    if claims.exp + (*JWT_LEEWAY as i64) <= now {
+        return Err(ErrorKind::ExpiredSignature.into());
+    }
+    trace!("Accepted legacy token of user {}", claims.sub);
+    Ok(claims)
 }
@@ -0,0 +1,140 @@
+//! Server side record of revoked sessions.
+//! Access tokens are stateless, the interceptor asks a [`RevocationStore`]
+//! whether the session a token belongs to has been ended.
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::PgConnection;
+
+use crate::db::{is_session_revoked, revoke_session, touch_session, DBClient};
+
+pub trait RevocationStore: Send + Sync + 'static {
+    /// Whether the session has been revoked.
+    fn is_revoked(&self, session_id: i32) -> Result<bool, Box<dyn StdError>>;
+    /// Revoke the session, tokens issued for it are no longer accepted.
+    fn revoke(&self, session_id: i32) -> Result<(), Box<dyn StdError>>;
+    /// A token of the live session has just been accepted. Stores may keep
+    /// it as the last activity of the session. Nothing by default.
+    fn seen(&self, _session_id: i32) -> Result<(), Box<dyn StdError>> {
+        Ok(())
+    }
+
+    /// [`RevocationStore::is_revoked`] for callers that hold a connection
+    /// already, which a store in the database uses instead of taking another
+    /// one from the pool.
+    fn is_revoked_with(
+        &self,
+        _conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        session_id: i32,
+    ) -> Result<bool, Box<dyn StdError>> {
+        self.is_revoked(session_id)
+    }
+
+    /// [`RevocationStore::revoke`] on a connection the caller holds.
+    fn revoke_with(
+        &self,
+        _conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        session_id: i32,
+    ) -> Result<(), Box<dyn StdError>> {
+        self.revoke(session_id)
+    }
+}
+
+/// How often the activity of a session is written to the database.
+const SEEN_INTERVAL: Duration = Duration::from_secs(60);
+/// Past this many sessions, the ones not seen lately are forgotten.
+const MAX_SEEN_SESSIONS: usize = 4096;
+
+/// Revocation store backed by the `Sessions` table, which also keeps the
+/// `last_seen_at` of the sessions, at most once per [`SEEN_INTERVAL`].
+#[derive(Debug, Clone)]
+pub struct DBRevocationStore {
+    client: DBClient,
+    /// When the activity of each session was last written.
+    seen: Arc<Mutex<HashMap<i32, Instant>>>,
+}
+
+impl DBRevocationStore {
+    pub fn new(client: DBClient) -> Self {
+        Self {
+            client,
+            seen: Default::default(),
+        }
+    }
+}
+
+impl RevocationStore for DBRevocationStore {
+    fn is_revoked(&self, session_id: i32) -> Result<bool, Box<dyn StdError>> {
+        self.client
+            .run_blocking(|conn| self.is_revoked_with(conn, session_id))
+    }
+
+    fn revoke(&self, session_id: i32) -> Result<(), Box<dyn StdError>> {
+        self.client
+            .run_blocking(|conn| self.revoke_with(conn, session_id))
+    }
+
+    fn seen(&self, session_id: i32) -> Result<(), Box<dyn StdError>> {
+        let now = Instant::now();
+        {
+            let mut seen = self.seen.lock().map_err(|e| e.to_string())?;
+            if seen
+                .get(&session_id)
+                .is_some_and(|&at| now - at < SEEN_INTERVAL)
+            {
+                return Ok(());
+            }
+            if seen.len() >= MAX_SEEN_SESSIONS {
+                seen.retain(|_, &mut at| now - at < SEEN_INTERVAL);
+            }
+            seen.insert(session_id, now);
+        }
+        self.client
+            .run_blocking(|conn| touch_session(conn, session_id))
+    }
+
+    fn is_revoked_with(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        session_id: i32,
+    ) -> Result<bool, Box<dyn StdError>> {
+        is_session_revoked(conn, session_id)
+    }
+
+    fn revoke_with(
+        &self,
+        conn: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        session_id: i32,
+    ) -> Result<(), Box<dyn StdError>> {
+        revoke_session(conn, session_id)
+    }
+}
+
+/// In-memory revocation store, for tests and single-process deployments.
+/// Revocations are lost on restart.
+#[derive(Debug, Default)]
+pub struct MemoryRevocationStore {
+    revoked: Mutex<HashSet<i32>>,
+}
+
+impl MemoryRevocationStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RevocationStore for MemoryRevocationStore {
+    fn is_revoked(&self, session_id: i32) -> Result<bool, Box<dyn StdError>> {
+        let revoked = self.revoked.lock().map_err(|e| e.to_string())?;
+        Ok(revoked.contains(&session_id))
+    }
+
+    fn revoke(&self, session_id: i32) -> Result<(), Box<dyn StdError>> {
+        let mut revoked = self.revoked.lock().map_err(|e| e.to_string())?;
+        revoked.insert(session_id);
+        Ok(())
+    }
+}
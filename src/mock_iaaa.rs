@@ -0,0 +1,24 @@
+//! Mock of the IAAA validation endpoint, see `holopku::auth::iaaa::mock`.
+//! Point `IAAA_ENDPOINT` of the server to it to log in through IAAA without
+//! the real PKU service.
+use holopku::auth::iaaa::mock::MockIaaa;
+use std::env;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenvy::dotenv().ok();
+    log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
+
+    let iaaa_id = env::var("IAAA_ID").expect("Must set IAAA_ID");
+    let iaaa_key = env::var("IAAA_KEY").expect("Must set IAAA_KEY");
+    let addr = env::var("MOCK_IAAA_ADDR").unwrap_or_else(|_| "127.0.0.1:8081".into());
+
+    let listener = TcpListener::bind(&addr).await?;
+    println!("Mock IAAA listening on http://{addr}");
+    Arc::new(MockIaaa::new(iaaa_id, iaaa_key))
+        .serve(listener)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,25 @@
+//! Mock of an OpenID Connect issuer, see `holopku::auth::oidc::mock`. Point
+//! `OIDC_ISSUER` of the server to it to log in through OIDC without a real
+//! issuer.
+use holopku::auth::oidc::mock::MockIssuer;
+use std::env;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    dotenvy::dotenv().ok();
+    log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
+
+    let client_id = env::var("OIDC_CLIENT_ID").expect("Must set OIDC_CLIENT_ID");
+    let client_secret = env::var("OIDC_CLIENT_SECRET").ok();
+    let addr = env::var("MOCK_OIDC_ADDR").unwrap_or_else(|_| "127.0.0.1:8082".into());
+    let issuer = format!("http://{addr}");
+
+    let listener = TcpListener::bind(&addr).await?;
+    println!("Mock OIDC issuer listening on {issuer}");
+    Arc::new(MockIssuer::new(issuer, client_id, client_secret))
+        .serve(listener)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,87 @@
+//! HoloPKU permission checks on forum content and privileged operations.
+
+use log::trace;
+use tonic::Status;
+
+use crate::codegen::auth::UserRole;
+use crate::db::models;
+use crate::middleware::AuthenticatedUser;
+
+/// Content that belongs to a single user.
+pub trait Owned {
+    fn owner_id(&self) -> i32;
+}
+
+impl Owned for models::Post {
+    fn owner_id(&self) -> i32 {
+        self.user_id
+    }
+}
+
+impl Owned for models::Comment {
+    fn owner_id(&self) -> i32 {
+        self.user_id
+    }
+}
+
+/// Check that `user` has at least the rights of `role`.
+/// Roles are ordered: user < moderator < admin.
+#[allow(clippy::result_large_err)]
+pub fn ensure_role(user: &AuthenticatedUser, role: UserRole) -> Result<(), Status> {
+    if user.role >= role {
+        return Ok(());
+    }
+    trace!(
+        "User {} with role {:?} denied, requires {role:?}",
+        user.user_id,
+        user.role
+    );
+    Err(Status::permission_denied(format!(
+        "Requires role {}",
+        role.as_str_name()
+    )))
+}
+
+/// Check that `user` may edit or mark `resource`.
+/// Only the author of the content is allowed to.
+#[allow(clippy::result_large_err)]
+pub fn ensure_can_modify<T: Owned>(user: &AuthenticatedUser, resource: &T) -> Result<(), Status> {
+    if resource.owner_id() == user.user_id {
+        return Ok(());
+    }
+    trace!(
+        "User {} denied to modify content of user {}",
+        user.user_id,
+        resource.owner_id()
+    );
+    Err(Status::permission_denied(
+        "Only the author can modify this content",
+    ))
+}
+
+/// Check that `user` may delete `resource`.
+/// The author of the content and moderators are allowed to.
+#[allow(clippy::result_large_err)]
+pub fn ensure_can_delete<T: Owned>(user: &AuthenticatedUser, resource: &T) -> Result<(), Status> {
+    if user.role >= UserRole::Moderator {
+        trace!(
+            "User {} deletes content of user {} as {:?}",
+            user.user_id,
+            resource.owner_id(),
+            user.role
+        );
+        return Ok(());
+    }
+    ensure_can_modify(user, resource)
+}
+
+/// Check that `dbuser` has verified their email. IAAA accounts are verified
+/// by the university.
+#[allow(clippy::result_large_err)]
+pub fn ensure_email_verified(dbuser: &models::User) -> Result<(), Status> {
+    if dbuser.is_email_verified() {
+        return Ok(());
+    }
+    trace!("User {} denied, email not verified", dbuser.id);
+    Err(Status::permission_denied("Verify your email first"))
+}
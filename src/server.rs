@@ -1,14 +1,26 @@
+use holopku::admin::AdminService;
+use holopku::auth::account::run_account_purger;
+use holopku::auth::hasher::Argon2Hasher;
+use holopku::auth::iaaa::IaaaValidator;
+use holopku::auth::oidc::{OidcClient, OidcConfig};
+use holopku::auth::throttle::{Backoff, DBLoginAttemptStore, LoginThrottle};
+use holopku::clock::SystemClock;
+use holopku::codegen::admin::admin_server::AdminServer;
 use holopku::codegen::auth::auth_server::AuthServer;
 use holopku::codegen::forum::forum_server::ForumServer;
 use holopku::codegen::hello::hello_server::HelloServer;
+use holopku::db::pool::PoolConfig;
 use holopku::db::DBClient;
 use holopku::forum::ForumService;
 use holopku::hello::HelloService;
-use holopku::middleware::auth_interceptor;
+use holopku::mail;
+use holopku::middleware::revocation::{DBRevocationStore, RevocationStore};
+use holopku::middleware::AuthInterceptor;
 use holopku::{auth::AuthService, check_envs};
 use log::trace;
 use std::env;
-use tonic::transport::{Identity, Server, ServerTlsConfig};
+use std::sync::Arc;
+use tonic::transport::Server;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -17,8 +29,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
 
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    let iaaa_id = env::var("IAAA_ID").expect("Must set IAAA_ID");
-    let iaaa_key = env::var("IAAA_KEY").expect("Must set IAAA_KEY");
     let addr = env::var("LISTEN_ADDR").expect("Must set LISTEN_ADDR");
     // let jwt_secret = env::var("JWT_SECRET").expect("Must set JWT_SECRET");
     // let cert_path = env::var("SSL_CRT_FILE").expect("Must set SSL_CRT_FILE");
@@ -31,24 +41,46 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // let tls_config = ServerTlsConfig::new().identity(identity);
 
     // establish database connection
-    let client = DBClient::connect(&database_url)?;
+    let client = DBClient::connect(&database_url, PoolConfig::from_env())?;
     let addr = addr.parse().unwrap();
     trace!("Auth server listening on: {}", addr);
 
     let hello_srv = HelloService {};
     let hello_srv = HelloServer::new(hello_srv);
 
+    // accounts are deleted once their grace period is over
+    tokio::spawn(run_account_purger(client.clone(), Arc::new(SystemClock)));
+
+    let revocations: Arc<dyn RevocationStore> = Arc::new(DBRevocationStore::new(client.clone()));
+
     let auth_srv = AuthService {
         client: client.clone(),
-        iaaa_id,
-        iaaa_key,
+        iaaa: Arc::new(IaaaValidator::from_env()),
+        revocations: revocations.clone(),
+        mailer: mail::from_env(),
+        hasher: Arc::new(Argon2Hasher::default()),
+        throttle: LoginThrottle::new(
+            Arc::new(DBLoginAttemptStore::new(client.clone())),
+            Arc::new(SystemClock),
+        )
+        .with_backoff(Backoff::PER_USERNAME, Backoff::per_ip_from_env()),
+        oidc: OidcConfig::from_env().map(|config| Arc::new(OidcClient::new(config))),
     };
-    let auth_srv = AuthServer::new(auth_srv);
+    // Login, Register, RefreshToken and the password reset are open to everyone
+    let auth_srv =
+        AuthServer::with_interceptor(auth_srv, AuthInterceptor::optional(revocations.clone()));
 
     let forum_srv = ForumService {
         client: client.clone(),
     };
-    let forum_srv = ForumServer::with_interceptor(forum_srv, auth_interceptor);
+    let forum_srv =
+        ForumServer::with_interceptor(forum_srv, AuthInterceptor::required(revocations.clone()));
+
+    let admin_srv = AdminService {
+        client: client.clone(),
+    };
+    let admin_srv =
+        AdminServer::with_interceptor(admin_srv, AuthInterceptor::required(revocations));
 
     Server::builder()
         // .tls_config(tls_config)?
@@ -57,6 +89,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .add_service(tonic_web::enable(hello_srv))
         .add_service(tonic_web::enable(auth_srv))
         .add_service(tonic_web::enable(forum_srv))
+        .add_service(tonic_web::enable(admin_srv))
         .serve(addr)
         .await?;
 
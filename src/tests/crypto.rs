@@ -0,0 +1,86 @@
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockEncryptMut, KeyIvInit};
+use aes::Aes256;
+
+use crate::crypto::{derive_key, derive_secret_key, open, open_legacy, seal, CryptoError};
+
+const SECRET: &[u8] = b"1234123412341234";
+
+/// Encrypt like tokens issued before the switch to AES-256-GCM.
+fn seal_legacy(key: &[u8; 32], iv: &[u8; 16], plain_text: &[u8]) -> Vec<u8> {
+    cbc::Encryptor::<Aes256>::new(key.into(), iv.into()).encrypt_padded_vec_mut::<Pkcs7>(plain_text)
+}
+
+#[test]
+fn seal_and_open() {
+    let key = derive_key(SECRET);
+    let sealed = seal(&key, b"a token");
+    assert_eq!(open(&key, &sealed).unwrap(), b"a token");
+}
+
+#[test]
+fn seal_is_randomized() {
+    let key = derive_key(SECRET);
+    assert_ne!(seal(&key, b"a token"), seal(&key, b"a token"));
+}
+
+#[test]
+fn derive_key_uses_whole_secret() {
+    // the legacy key only used the first 32 bytes of the secret
+    let secret = [b'a'; 40];
+    let mut other = secret;
+    other[39] = b'b';
+    assert_ne!(derive_key(&secret), derive_key(&other));
+}
+
+#[test]
+fn secrets_and_tokens_use_different_keys() {
+    let sealed = seal(&derive_secret_key(SECRET), b"a secret");
+    assert_ne!(derive_key(SECRET), derive_secret_key(SECRET));
+    assert!(open(&derive_key(SECRET), &sealed).is_err());
+}
+
+#[test]
+fn open_rejects_tampering() {
+    let key = derive_key(SECRET);
+    let sealed = seal(&key, b"a token");
+    // flip a bit in the nonce, the ciphertext and the tag
+    for i in [1, 13, sealed.len() - 1] {
+        let mut tampered = sealed.clone();
+        tampered[i] ^= 0x01;
+        assert!(matches!(
+            open(&key, &tampered),
+            Err(CryptoError::Unauthenticated)
+        ));
+    }
+    // truncate the tag
+    assert!(open(&key, &sealed[..sealed.len() - 1]).is_err());
+}
+
+#[test]
+fn open_rejects_wrong_key() {
+    let sealed = seal(&derive_key(SECRET), b"a token");
+    assert!(open(&derive_key(b"another secret"), &sealed).is_err());
+}
+
+#[test]
+fn open_rejects_malformed() {
+    let key = derive_key(SECRET);
+    assert!(matches!(open(&key, &[]), Err(CryptoError::Malformed)));
+    assert!(matches!(
+        open(&key, &[1, 2, 3]),
+        Err(CryptoError::Malformed)
+    ));
+    let mut sealed = seal(&key, b"a token");
+    sealed[0] = 2;
+    assert!(matches!(open(&key, &sealed), Err(CryptoError::Malformed)));
+}
+
+#[test]
+fn open_legacy_token() {
+    let mut key = [0u8; 32];
+    key[..SECRET.len()].copy_from_slice(SECRET);
+    let iv = *b"5678567856785678";
+    let sealed = seal_legacy(&key, &iv, b"a token");
+    assert_eq!(open_legacy(&key, &iv, &sealed).unwrap(), b"a token");
+}
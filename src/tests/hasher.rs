@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use argon2::Params;
+
+use crate::auth::hasher::{hash_password, verify_password};
+use crate::auth::hasher::{Argon2Hasher, PasswordHasher, PasswordMatch};
+
+/// Cheap parameters, the defaults are slow without optimizations.
+fn cheap_hasher() -> Argon2Hasher {
+    Argon2Hasher::new(Params::new(1024, 1, 1, None).unwrap())
+}
+
+#[test]
+fn argon2id_roundtrip() {
+    let hasher = cheap_hasher();
+    let hash = hasher.hash("mypassword").unwrap();
+    assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+    assert_ne!(hash, hasher.hash("mypassword").unwrap(), "salted");
+    assert_eq!(hasher.verify("mypassword", &hash), PasswordMatch::Match);
+    assert_eq!(
+        hasher.verify("wrongpassword", &hash),
+        PasswordMatch::Mismatch
+    );
+}
+
+#[test]
+fn changed_params_are_outdated() {
+    let hash = cheap_hasher().hash("mypassword").unwrap();
+    let stronger = Argon2Hasher::new(Params::new(2048, 1, 1, None).unwrap());
+    assert_eq!(
+        stronger.verify("mypassword", &hash),
+        PasswordMatch::Outdated
+    );
+    assert_eq!(
+        stronger.verify("wrongpassword", &hash),
+        PasswordMatch::Mismatch
+    );
+}
+
+#[test]
+fn bcrypt_is_outdated() {
+    let hash = bcrypt::hash("mypassword", 4).unwrap();
+    let hasher = cheap_hasher();
+    assert_eq!(hasher.verify("mypassword", &hash), PasswordMatch::Outdated);
+    assert_eq!(
+        hasher.verify("wrongpassword", &hash),
+        PasswordMatch::Mismatch
+    );
+}
+
+#[test]
+fn malformed_hash_mismatches() {
+    let hasher = cheap_hasher();
+    assert_eq!(hasher.verify("mypassword", ""), PasswordMatch::Mismatch);
+    assert_eq!(
+        hasher.verify("mypassword", "mypassword"),
+        PasswordMatch::Mismatch
+    );
+    assert_eq!(
+        hasher.verify("mypassword", "$argon2id$"),
+        PasswordMatch::Mismatch
+    );
+}
+
+#[tokio::test]
+async fn hash_off_runtime() {
+    let hasher: Arc<dyn PasswordHasher> = Arc::new(cheap_hasher());
+    let hash = hash_password(&hasher, "mypassword".into()).await.unwrap();
+    let matched = verify_password(&hasher, "mypassword".into(), Some(hash)).await;
+    assert_eq!(matched.unwrap(), PasswordMatch::Match);
+    // no such user
+    let matched = verify_password(&hasher, "mypassword".into(), None).await;
+    assert_eq!(matched.unwrap(), PasswordMatch::Mismatch);
+}
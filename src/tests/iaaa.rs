@@ -0,0 +1,87 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use crate::auth::iaaa::mock::{MockIaaa, MOCK_IDENTITY_ID};
+use crate::auth::iaaa::{IaaaValidator, IdentityValidator};
+
+/// Start a mock IAAA on a free port, returns it with its endpoint.
+async fn start_mock() -> (Arc<MockIaaa>, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}/validate.do", listener.local_addr().unwrap());
+    let mock = Arc::new(MockIaaa::new("app", "key").with_delay(Duration::from_secs(2)));
+    tokio::spawn(mock.clone().serve(listener));
+    (mock, endpoint)
+}
+
+fn validator(endpoint: &str) -> IaaaValidator {
+    IaaaValidator::new(endpoint, "app".into(), "key".into())
+        .with_timeout(Duration::from_millis(500))
+        .with_retries(0)
+}
+
+#[tokio::test]
+async fn validate_success() {
+    let (_, endpoint) = start_mock().await;
+    let validator = validator(&endpoint);
+
+    let resp = validator.validate("10.0.0.1", "2200012345").await.unwrap();
+    assert!(resp.is_success());
+    assert_eq!(resp.user_info.identity_id, "2200012345");
+
+    let resp = validator.validate("10.0.0.1", "a&b=c d").await.unwrap();
+    assert!(resp.is_success());
+    assert_eq!(resp.user_info.identity_id, MOCK_IDENTITY_ID);
+}
+
+#[tokio::test]
+async fn validate_failure() {
+    let (_, endpoint) = start_mock().await;
+    let resp = validator(&endpoint).validate("10.0.0.1", "fail").await;
+    assert!(!resp.unwrap().is_success());
+
+    // signed with another key
+    let validator = IaaaValidator::new(&endpoint, "app".into(), "other".into());
+    let resp = validator.validate("10.0.0.1", "2200012345").await;
+    assert!(!resp.unwrap().is_success());
+}
+
+#[tokio::test]
+async fn validate_malformed() {
+    let (mock, endpoint) = start_mock().await;
+    let validator = validator(&endpoint).with_retries(2);
+    assert!(validator.validate("10.0.0.1", "malformed").await.is_err());
+    // not worth retrying
+    assert_eq!(mock.requests(), 1);
+}
+
+#[tokio::test]
+async fn validate_retries_server_errors() {
+    let (mock, endpoint) = start_mock().await;
+    let validator = validator(&endpoint).with_retries(2);
+    assert!(validator.validate("10.0.0.1", "error").await.is_err());
+    assert_eq!(mock.requests(), 3);
+}
+
+#[tokio::test]
+async fn validate_timeout() {
+    let (mock, endpoint) = start_mock().await;
+    let validator = validator(&endpoint).with_retries(1);
+    assert!(validator.validate("10.0.0.1", "slow").await.is_err());
+    assert_eq!(mock.requests(), 2);
+
+    let validator = validator.with_timeout(Duration::from_secs(5));
+    assert!(validator.validate("10.0.0.1", "slow").await.is_ok());
+}
+
+#[tokio::test]
+async fn validate_unreachable() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}/validate.do", listener.local_addr().unwrap());
+    drop(listener);
+    assert!(validator(&endpoint)
+        .validate("10.0.0.1", "2200012345")
+        .await
+        .is_err());
+}
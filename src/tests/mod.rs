@@ -1,17 +1,41 @@
+mod crypto;
+mod hasher;
+mod iaaa;
+mod keyring;
+mod nickname;
+mod oidc;
+mod profile;
+mod throttle;
+mod totp;
+
+use crate::auth::account::{purge_deleted_accounts, DELETION_GRACE_PERIOD};
+use crate::auth::totp::{base32_decode, time_step, totp_code};
+use crate::codegen::admin::admin_client::AdminClient;
+use crate::codegen::admin::{ListUsersByRoleRequest, ResetTotpRequest, SetUserRoleRequest};
 use crate::codegen::auth::auth_client::AuthClient;
-use crate::codegen::auth::{GetUserRequest, LoginProvider, LoginRequest, RegisterRequest};
+use crate::codegen::auth::ListNicknameHistoryRequest;
+use crate::codegen::auth::{BeginTotpEnrollmentRequest, ConfirmTotpRequest, SecondFactor};
+use crate::codegen::auth::{CancelAccountDeletionRequest, DeleteAccountRequest};
+use crate::codegen::auth::{ChangeNicknameRequest, ChangeUsernameRequest};
+use crate::codegen::auth::{ChangePasswordRequest, RequestPasswordResetRequest};
+use crate::codegen::auth::{ExportMyDataRequest, GetUserRequest};
+use crate::codegen::auth::{GetOidcConfigRequest, OidcAuthorization};
+use crate::codegen::auth::{LinkIdentityRequest, UnlinkIdentityRequest};
+use crate::codegen::auth::{ListSessionsRequest, RevokeSessionRequest};
+use crate::codegen::auth::{LoginProvider, LoginRequest, RegisterRequest};
+use crate::codegen::auth::{LogoutRequest, RefreshTokenRequest, ResetPasswordRequest, UserRole};
+use crate::codegen::auth::{ResendVerificationRequest, VerifyEmailRequest};
 use crate::codegen::food_post::FoodPost;
 use crate::codegen::forum::forum_client::ForumClient;
+use crate::codegen::forum::ListSellPostsRequest;
 use crate::codegen::post::Post;
+use crate::codegen::sell_post::SellPost;
+use crate::db::DBClient;
 use tokio::runtime::Runtime;
 
 use crate::AUTHORIZATION_KEY;
-use hyper::client::conn::http1;
-use hyper::header::HeaderValue;
-use hyper_util::rt::TokioExecutor;
-use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::metadata::MetadataValue;
 use tonic::{IntoRequest, Request};
-use tonic_web::GrpcWebClientLayer;
 
 /// 前提：数据库中没有名为test_user_ne的用户
 #[test]
@@ -28,6 +52,9 @@ fn login_without_register() -> Result<(), Box<dyn std::error::Error>> {
         username: "test_user_ne".into(),
         password: "mypassword".into(),
         ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
     }));
     let response = rt.block_on(response);
     println!("RESPONSE = {:?}", response);
@@ -36,20 +63,20 @@ fn login_without_register() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-/// 前提：数据库中没有名为test_user_note的用户
 #[test]
 fn register() -> Result<(), Box<dyn std::error::Error>> {
     let rt = Runtime::new().expect("Failed to create runtime");
     //log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
 
     let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let username = format!("test_user_note_{}", chrono::Utc::now().timestamp_micros());
 
     println!("Try password registration");
     let response = auth_client.register(Request::new(RegisterRequest {
         auth_provider: LoginProvider::Password.into(),
-        username: "test_user_note".into(),
+        username: username.clone(),
         password: "mypassword".into(),
-        email: "lol@example.com".into(),
+        email: format!("{username}@example.com"),
     }));
     let response = rt.block_on(response);
     println!("RESPONSE = {:?}", response);
@@ -74,6 +101,9 @@ fn login() -> Result<(), Box<dyn std::error::Error>> {
         username: "test_user".into(),
         password: "mypassword".into(),
         ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
     }));
     let response = rt.block_on(response);
     println!("RESPONSE = {:?}", response);
@@ -105,6 +135,23 @@ fn register_again() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+#[test]
+fn register_short_password() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let username = format!("test_short_{}", chrono::Utc::now().timestamp_micros());
+
+    println!("Try password registration with a short password, should fail");
+    let response = rt.block_on(auth_client.register(Request::new(RegisterRequest {
+        auth_provider: LoginProvider::Password.into(),
+        username,
+        password: "short".into(),
+        email: "short@example.com".into(),
+    })));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    Ok(())
+}
+
 /// 前提：数据库中有名字为test_user的用户
 #[test]
 fn send_post_and_delete() -> Result<(), Box<dyn std::error::Error>> {
@@ -120,6 +167,9 @@ fn send_post_and_delete() -> Result<(), Box<dyn std::error::Error>> {
         username: "test_user".into(),
         password: "mypassword".into(),
         ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
     }));
     let response = rt.block_on(response);
     println!("RESPONSE = {:?}", response);
@@ -240,7 +290,7 @@ fn send_post_and_delete() -> Result<(), Box<dyn std::error::Error>> {
     let response = forum_client.delete_post({
         let mut delete_post = crate::codegen::forum::DeletePostRequest {
             post_id: the_new_post_id,
-            user_id: user_id,
+            user_id,
         }
         .into_request();
         let metadata = delete_post.metadata_mut();
@@ -255,8 +305,7 @@ fn send_post_and_delete() -> Result<(), Box<dyn std::error::Error>> {
     // get user
     println!("Try GetUser request");
     let response = auth_client.get_user({
-        let mut delete_post =
-            crate::codegen::auth::GetUserRequest { user_id: user_id }.into_request();
+        let mut delete_post = crate::codegen::auth::GetUserRequest { user_id }.into_request();
         let metadata = delete_post.metadata_mut();
 
         metadata.append_bin(AUTHORIZATION_KEY, MetadataValue::from_bytes(&token));
@@ -268,3 +317,2015 @@ fn send_post_and_delete() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+/// 前提：数据库中有名字为test_user的用户
+#[test]
+fn act_on_behalf_of_others() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let response = auth_client.login(Request::new(LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: "test_user".into(),
+        password: "mypassword".into(),
+        ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    }));
+    let response = rt.block_on(response)?.into_inner();
+    let token = response.token;
+    let user_id = response.user.unwrap().id;
+
+    println!("Try LikePost request without token, should fail");
+    let response = forum_client.like_post(crate::codegen::forum::LikePostRequest {
+        user_id,
+        post_id: 1,
+    });
+    let response = rt.block_on(response);
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try LikePost request on behalf of another user, should fail");
+    let response = forum_client.like_post({
+        let mut like_post = crate::codegen::forum::LikePostRequest {
+            user_id: user_id + 1,
+            post_id: 1,
+        }
+        .into_request();
+        let metadata = like_post.metadata_mut();
+        metadata.append_bin(AUTHORIZATION_KEY, MetadataValue::from_bytes(&token));
+        like_post
+    });
+    let response = rt.block_on(response);
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+    Ok(())
+}
+
+/// Attach `token` to a request.
+fn with_token<T>(message: T, token: &[u8]) -> Request<T> {
+    let mut request = message.into_request();
+    let metadata = request.metadata_mut();
+    metadata.append_bin(AUTHORIZATION_KEY, MetadataValue::from_bytes(token));
+    request
+}
+
+/// Log in as `username`, registering it first if needed.
+/// Returns the token and the user id.
+fn login_as(rt: &Runtime, username: &str) -> Result<(Vec<u8>, i32), Box<dyn std::error::Error>> {
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    // registering an existing user fails, which is fine here
+    let _ = rt.block_on(auth_client.register(Request::new(RegisterRequest {
+        auth_provider: LoginProvider::Password.into(),
+        username: username.into(),
+        password: "mypassword".into(),
+        email: format!("{username}@example.com"),
+    })));
+    let response = rt.block_on(auth_client.login(Request::new(LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: username.into(),
+        password: "mypassword".into(),
+        ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    })))?;
+    let response = response.into_inner();
+    let user = response.user.unwrap();
+    if !user.email_verified {
+        // tests sharing a user race on the token, one of them wins
+        let request = with_token(ResendVerificationRequest {}, &response.token);
+        let _ = rt.block_on(auth_client.resend_verification(request));
+        if let Ok(verification_token) = last_mailed_token(&user.email.unwrap_or_default()) {
+            let _ = rt.block_on(
+                auth_client.verify_email(Request::new(VerifyEmailRequest { verification_token })),
+            );
+        }
+    }
+    Ok((response.token, user.id))
+}
+
+/// Create a sell post as the owner of `token`, returns the post id.
+fn create_sell_post(rt: &Runtime, token: &[u8]) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let request = crate::codegen::forum::CreateSellPostRequest {
+        post: Some(SellPost {
+            post: Some(Post {
+                id: 0,
+                title: "sell post--test".into(),
+                user_id: 0,
+                content: "this is a sell post to test".into(),
+                likes: 0,
+                favorates: 0,
+                created_at: 0,
+                updated_at: None,
+                comments: vec![],
+                images: vec![],
+                post_type: crate::codegen::post::PostType::Sellpost.into(),
+            }),
+            contact: Some("lol@example.com".into()),
+            price: 10,
+            goods_type: crate::codegen::sell_post::GoodsType::Book.into(),
+            sold: false,
+        }),
+    };
+    let response = rt.block_on(forum_client.create_sell_post(with_token(request, token)))?;
+    Ok(response.into_inner().post_id)
+}
+
+/// Create an amusement post wanting `people_all` people, its organizer
+/// among them, as the owner of `token`, returns the post id.
+fn create_amusement_post(
+    rt: &Runtime,
+    token: &[u8],
+    people_all: i32,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    use crate::codegen::amusement_post::{AmusementPost, GameType};
+
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let request = crate::codegen::forum::CreateAmusementPostRequest {
+        post: Some(AmusementPost {
+            post: Some(Post {
+                id: 0,
+                title: "amusement post--test".into(),
+                user_id: 0,
+                content: "this is an amusement post to test".into(),
+                likes: 0,
+                favorates: 0,
+                created_at: 0,
+                updated_at: None,
+                comments: vec![],
+                images: vec![],
+                post_type: crate::codegen::post::PostType::Amusementpost.into(),
+            }),
+            people_all,
+            people_already: 1,
+            game_type: GameType::BoardGame.into(),
+            start_time: chrono::Utc::now().timestamp() + 3600,
+            amuse_place: "理教".into(),
+            contact: "lol@example.com".into(),
+        }),
+    };
+    let response = rt.block_on(forum_client.create_amusement_post(with_token(request, token)))?;
+    Ok(response.into_inner().post_id)
+}
+
+/// 前提：无
+#[test]
+fn delete_others_post() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let (token, _) = login_as(&rt, "test_author")?;
+    let (other_token, _) = login_as(&rt, "test_other")?;
+    let post_id = create_sell_post(&rt, &token)?;
+
+    println!("Try DeletePost request on other's post, should fail");
+    let request = crate::codegen::forum::DeletePostRequest {
+        user_id: 0,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.delete_post(with_token(request, &other_token)));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+    println!("Try DeletePost request on own post");
+    let response = rt.block_on(forum_client.delete_post(with_token(request, &token)));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+
+    Ok(())
+}
+
+/// 前提：无
+#[test]
+fn delete_others_comment() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let (token, _) = login_as(&rt, "test_author")?;
+    let (other_token, _) = login_as(&rt, "test_other")?;
+    let post_id = create_sell_post(&rt, &token)?;
+
+    let request = crate::codegen::forum::CommentRequest {
+        user_id: 0,
+        post_id,
+        content: "a comment to test".into(),
+    };
+    rt.block_on(forum_client.comment(with_token(request, &token)))?;
+    let request = crate::codegen::forum::GetPostRequest { post_id };
+    let response = rt.block_on(forum_client.get_sell_post(with_token(request, &token)))?;
+    let comment_id = response.into_inner().post.unwrap().post.unwrap().comments[0].id;
+
+    println!("Try DeleteComment request on other's comment, should fail");
+    let request = crate::codegen::forum::DeleteCommentRequest {
+        user_id: 0,
+        post_id,
+        comment_id,
+    };
+    let response = rt.block_on(forum_client.delete_comment(with_token(request, &other_token)));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+    println!("Try DeleteComment request on own comment");
+    let response = rt.block_on(forum_client.delete_comment(with_token(request, &token)));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+
+    Ok(())
+}
+
+/// 前提：无
+#[test]
+fn set_others_sold() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let (token, _) = login_as(&rt, "test_author")?;
+    let (other_token, _) = login_as(&rt, "test_other")?;
+    let post_id = create_sell_post(&rt, &token)?;
+
+    println!("Try SetSold request on other's post, should fail");
+    let request = crate::codegen::forum::SetSoldRequest {
+        user_id: 0,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.set_sold(with_token(request, &other_token)));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+    println!("Try SetSold request on own post");
+    let response = rt.block_on(forum_client.set_sold(with_token(request, &token)));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+
+    Ok(())
+}
+
+/// 前提：无
+#[test]
+fn refresh_and_logout() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    login_as(&rt, "test_session")?;
+    let response = rt.block_on(auth_client.login(Request::new(LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: "test_session".into(),
+        password: "mypassword".into(),
+        ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    })))?;
+    let refresh_token = response.into_inner().refresh_token;
+
+    println!("Try RefreshToken request");
+    let request = RefreshTokenRequest {
+        refresh_token: refresh_token.clone(),
+    };
+    let response = rt
+        .block_on(auth_client.refresh_token(request))?
+        .into_inner();
+    println!("RESPONSE = {:?}", response);
+    let token = response.token;
+    let new_refresh_token = response.refresh_token;
+    create_sell_post(&rt, &token)?;
+
+    println!("Try RefreshToken request with a rotated refresh token, should fail");
+    let request = RefreshTokenRequest { refresh_token };
+    let response = rt.block_on(auth_client.refresh_token(request));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try Logout request");
+    let request = LogoutRequest {
+        refresh_token: None,
+    };
+    let response = rt.block_on(auth_client.logout(with_token(request, &token)));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+
+    println!("Try CreateSellPost request after logout, should fail");
+    let response = create_sell_post(&rt, &token);
+    assert!(response.is_err());
+
+    println!("Try RefreshToken request after logout, should fail");
+    let request = RefreshTokenRequest {
+        refresh_token: new_refresh_token,
+    };
+    let response = rt.block_on(auth_client.refresh_token(request));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    Ok(())
+}
+
+/// 前提：数据库中有名字为test_admin的管理员用户
+#[test]
+fn moderator_delete_others_post() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let mut admin_client = rt.block_on(AdminClient::connect("http://[::1]:8080"))?;
+    let (admin_token, _) = login_as(&rt, "test_admin")?;
+    let (token, _) = login_as(&rt, "test_author")?;
+    let (moderator_token, moderator_id) = login_as(&rt, "test_moderator")?;
+
+    println!("Try SetUserRole request as a user, should fail");
+    let request = SetUserRoleRequest {
+        user_id: moderator_id,
+        role: UserRole::Admin.into(),
+    };
+    let response = rt.block_on(admin_client.set_user_role(with_token(request, &moderator_token)));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+    println!("Try SetUserRole request as an admin");
+    let request = SetUserRoleRequest {
+        user_id: moderator_id,
+        role: UserRole::Moderator.into(),
+    };
+    let response = rt.block_on(admin_client.set_user_role(with_token(request, &admin_token)))?;
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(
+        response.into_inner().user.unwrap().role(),
+        UserRole::Moderator
+    );
+
+    let request = ListUsersByRoleRequest {
+        role: UserRole::Moderator.into(),
+    };
+    let response =
+        rt.block_on(admin_client.list_users_by_role(with_token(request, &admin_token)))?;
+    assert!(response
+        .into_inner()
+        .users
+        .iter()
+        .any(|user| user.id == moderator_id));
+
+    println!("Try DeletePost request on other's post as a moderator");
+    // the role is picked up by a new token
+    let (moderator_token, _) = login_as(&rt, "test_moderator")?;
+    let post_id = create_sell_post(&rt, &token)?;
+    let request = crate::codegen::forum::DeletePostRequest {
+        user_id: 0,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.delete_post(with_token(request, &moderator_token)));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+
+    println!("Try SetSold request on other's post as a moderator, should fail");
+    let post_id = create_sell_post(&rt, &token)?;
+    let request = crate::codegen::forum::SetSoldRequest {
+        user_id: 0,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.set_sold(with_token(request, &moderator_token)));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+
+    Ok(())
+}
+
+/// Sign `claims` and encrypt them like an access token.
+fn forge_token(claims: &serde_json::Value) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let token = crate::JWT_KEYRING.sign(claims)?;
+    Ok(crate::crypto::encrypt_token(token.as_bytes()))
+}
+
+/// 前提：无
+#[test]
+fn token_claims() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let (token, user_id) = login_as(&rt, "test_claims")?;
+
+    // read the claims of a real token to reuse its session
+    let jwt = crate::crypto::decrypt_token(&token)?;
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_aud = false;
+    let claims = jsonwebtoken::decode::<serde_json::Value>(
+        std::str::from_utf8(&jwt)?,
+        &jsonwebtoken::DecodingKey::from_secret(&[]),
+        &validation,
+    )?
+    .claims;
+    println!("CLAIMS = {:?}", claims);
+    assert_eq!(claims["sub"], user_id.to_string());
+    assert_eq!(claims["aud"], crate::JWT_AUDIENCE);
+    assert!(claims["exp"].as_i64() > claims["iat"].as_i64());
+    let sid = claims["sid"].clone();
+    let now = chrono::Utc::now().timestamp();
+
+    println!("Try CreateSellPost request with a token expired within leeway");
+    let mut claims = claims.clone();
+    claims["exp"] = (now - 10).into();
+    assert!(create_sell_post(&rt, &forge_token(&claims)?).is_ok());
+
+    println!("Try CreateSellPost request with an expired token, should fail");
+    claims["exp"] = (now - 3600).into();
+    assert!(create_sell_post(&rt, &forge_token(&claims)?).is_err());
+
+    println!("Try CreateSellPost request with a token for another audience, should fail");
+    claims["exp"] = (now + 3600).into();
+    claims["aud"] = "another service".into();
+    assert!(create_sell_post(&rt, &forge_token(&claims)?).is_err());
+
+    println!("Try CreateSellPost request with a token not valid yet, should fail");
+    claims["aud"] = crate::JWT_AUDIENCE.into();
+    claims["nbf"] = (now + 3600).into();
+    assert!(create_sell_post(&rt, &forge_token(&claims)?).is_err());
+
+    println!("Try CreateSellPost request with a legacy token");
+    let mut legacy = serde_json::json!({
+        "aud": user_id.to_string(),
+        "exp": 3600,
+        "iat": now,
+        "iss": crate::JWT_ISSUER,
+        "sub": "lol@example.com",
+        "sid": sid,
+    });
+    assert!(create_sell_post(&rt, &forge_token(&legacy)?).is_ok());
+
+    println!("Try CreateSellPost request with an expired legacy token, should fail");
+    legacy["iat"] = (now - 7200).into();
+    assert!(create_sell_post(&rt, &forge_token(&legacy)?).is_err());
+
+    println!("Try CreateSellPost request with a legacy token without session, should fail");
+    legacy["iat"] = now.into();
+    legacy.as_object_mut().unwrap().remove("sid");
+    assert!(create_sell_post(&rt, &forge_token(&legacy)?).is_err());
+
+    Ok(())
+}
+
+/// Log in as `username` with `password`, returns the token.
+fn login_with_password(
+    rt: &Runtime,
+    username: &str,
+    password: &str,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let response = rt.block_on(auth_client.login(Request::new(LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: username.into(),
+        password: password.into(),
+        ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    })))?;
+    Ok(response.into_inner().token)
+}
+
+/// Read the token in the last mail sent to `to` by the file mailer.
+fn last_mailed_token(to: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let dir = std::env::var("MAIL_DIR")?;
+    let suffix = format!("-{to}.eml");
+    let last_mail = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.to_string_lossy().ends_with(&suffix))
+        .max()
+        .ok_or("no mail")?;
+    let content = std::fs::read_to_string(last_mail)?;
+    let token = content
+        .lines()
+        .find(|line| line.len() == 64 && line.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or("no token in mail")?;
+    Ok(token.to_string())
+}
+
+/// 前提：服务器设置了MAIL_DIR
+#[test]
+fn change_and_reset_password() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let username = format!("test_password_{}", chrono::Utc::now().timestamp_micros());
+    let (token, _) = login_as(&rt, &username)?;
+    let other_token = login_with_password(&rt, &username, "mypassword")?;
+
+    println!("Try ChangePassword request with a wrong password, should fail");
+    let request = ChangePasswordRequest {
+        old_password: "wrongpassword".into(),
+        new_password: "mynewpassword".into(),
+    };
+    let response = rt.block_on(auth_client.change_password(with_token(request, &token)));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try ChangePassword request");
+    let request = ChangePasswordRequest {
+        old_password: "mypassword".into(),
+        new_password: "mynewpassword".into(),
+    };
+    let response = rt.block_on(auth_client.change_password(with_token(request, &token)));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+    assert!(login_with_password(&rt, &username, "mypassword").is_err());
+    assert!(create_sell_post(&rt, &other_token).is_err());
+    assert!(create_sell_post(&rt, &token).is_ok());
+
+    println!("Try RequestPasswordReset request for an unknown user");
+    let request = RequestPasswordResetRequest {
+        username: "test_nobody".into(),
+    };
+    let response = rt.block_on(auth_client.request_password_reset(request));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+
+    println!("Try RequestPasswordReset request");
+    let request = RequestPasswordResetRequest {
+        username: username.clone(),
+    };
+    let response = rt.block_on(auth_client.request_password_reset(request));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+    let reset_token = last_mailed_token(&format!("{username}@example.com"))?;
+
+    println!("Try ResetPassword request");
+    let request = ResetPasswordRequest {
+        reset_token: reset_token.clone(),
+        new_password: "mypassword".into(),
+    };
+    let response = rt.block_on(auth_client.reset_password(request.clone()));
+    println!("RESPONSE = {:?}", response);
+    assert!(response.is_ok());
+    assert!(create_sell_post(&rt, &token).is_err());
+    login_with_password(&rt, &username, "mypassword")?;
+
+    println!("Try ResetPassword request with a used token, should fail");
+    let response = rt.block_on(auth_client.reset_password(request));
+    println!("RESPONSE = {:?}", response);
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    Ok(())
+}
+
+/// 前提：服务器设置了MAIL_DIR
+#[test]
+fn verify_email() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let username = format!("test_unverified_{}", chrono::Utc::now().timestamp_micros());
+    rt.block_on(auth_client.register(Request::new(RegisterRequest {
+        auth_provider: LoginProvider::Password.into(),
+        username: username.clone(),
+        password: "mypassword".into(),
+        email: format!("{username}@example.com"),
+    })))?;
+    let token = login_with_password(&rt, &username, "mypassword")?;
+
+    println!("Try CreateSellPost request before verifying email, should fail");
+    let response = create_sell_post(&rt, &token);
+    assert!(response.is_err());
+
+    println!("Try VerifyEmail request with a wrong token, should fail");
+    let response = rt.block_on(auth_client.verify_email(Request::new(VerifyEmailRequest {
+        verification_token: "0".repeat(64),
+    })));
+    assert!(response.is_err());
+
+    println!("Try VerifyEmail request with the mailed token, should succeed");
+    let request = with_token(ResendVerificationRequest {}, &token);
+    rt.block_on(auth_client.resend_verification(request))?;
+    let verification_token = last_mailed_token(&format!("{username}@example.com"))?;
+    rt.block_on(auth_client.verify_email(Request::new(VerifyEmailRequest {
+        verification_token: verification_token.clone(),
+    })))?;
+    assert!(create_sell_post(&rt, &token).is_ok());
+
+    println!("Try reusing the token and resending once verified, should fail");
+    let response = rt.block_on(
+        auth_client.verify_email(Request::new(VerifyEmailRequest { verification_token })),
+    );
+    assert!(response.is_err());
+    let request = with_token(ResendVerificationRequest {}, &token);
+    let response = rt.block_on(auth_client.resend_verification(request));
+    assert_eq!(
+        response.unwrap_err().code(),
+        tonic::Code::FailedPrecondition
+    );
+    Ok(())
+}
+
+/// 前提：服务器的LOGIN_IP_FREE_ATTEMPTS足够大，测试的登录都来自同一地址
+#[test]
+fn login_lockout() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let username = format!("test_lockout_{}", chrono::Utc::now().timestamp_micros());
+    login_as(&rt, &username)?;
+
+    println!("Try login as an unknown user and with a wrong password, should fail alike");
+    let unknown = login_with_password(&rt, &format!("{username}_ne"), "mypassword").unwrap_err();
+    let wrong = login_with_password(&rt, &username, "wrongpassword").unwrap_err();
+    let (unknown, wrong) = (
+        unknown.downcast::<tonic::Status>().unwrap(),
+        wrong.downcast::<tonic::Status>().unwrap(),
+    );
+    assert_eq!(unknown.code(), tonic::Code::Unauthenticated);
+    assert_eq!(unknown.code(), wrong.code());
+    assert_eq!(unknown.message(), wrong.message());
+
+    println!("Try login with the right password after 5 failures, should be locked out");
+    for _ in 0..4 {
+        assert!(login_with_password(&rt, &username, "wrongpassword").is_err());
+    }
+    let locked = login_with_password(&rt, &username, "mypassword").unwrap_err();
+    let locked = locked.downcast::<tonic::Status>().unwrap();
+    assert_eq!(locked.code(), tonic::Code::ResourceExhausted);
+    Ok(())
+}
+
+/// 前提：服务器的IAAA_ENDPOINT指向mock-iaaa
+#[test]
+fn iaaa_login() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let iaaa_login = |token: &str| LoginRequest {
+        auth_provider: LoginProvider::Iaaa.into(),
+        iaaa_token: token.into(),
+        username: "".into(),
+        password: "".into(),
+        ip_address: Some("10.0.0.1".into()),
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    };
+
+    println!("Try IAAA login with a valid token, should register and login");
+    let response = rt.block_on(auth_client.login(Request::new(iaaa_login("2200077777"))))?;
+    let response = response.into_inner();
+    let user = response.user.unwrap();
+    assert_eq!(user.username, "2200077777");
+    assert_eq!(user.login_provider, LoginProvider::Iaaa as i32);
+    let profile = user.iaaa_profile.unwrap();
+    assert_eq!(profile.dept_id, "00048");
+    assert!(!profile.graduated);
+
+    println!("Try ListSellPosts request filtered by department");
+    let token = response.token;
+    let post_id = create_sell_post(&rt, &token)?;
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let list = |dept_id: &str| ListSellPostsRequest {
+        goods_type: None,
+        price_upbond: 1000,
+        number: 100,
+        dept_id: Some(dept_id.into()),
+    };
+    let response = rt.block_on(forum_client.list_sell_posts(with_token(list("00048"), &token)))?;
+    let posts = response.into_inner().posts;
+    assert!(posts.iter().any(|p| p.post.as_ref().unwrap().id == post_id));
+    let response = rt.block_on(forum_client.list_sell_posts(with_token(list("99999"), &token)))?;
+    assert!(response.into_inner().posts.is_empty());
+
+    println!("Try IAAA login of a graduate, should be marked graduated");
+    let response = rt.block_on(auth_client.login(Request::new(iaaa_login("graduated"))))?;
+    let user = response.into_inner().user.unwrap();
+    assert!(user.iaaa_profile.unwrap().graduated);
+
+    println!("Try IAAA login with a rejected token, should fail");
+    let response = rt.block_on(auth_client.login(Request::new(iaaa_login("fail"))));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try IAAA login while IAAA misbehaves, should fail");
+    let response = rt.block_on(auth_client.login(Request::new(iaaa_login("malformed"))));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unavailable);
+    Ok(())
+}
+
+/// 前提：服务器的IAAA_ENDPOINT指向mock-iaaa
+#[test]
+fn link_identity() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let iaaa_login = |token: &str| LoginRequest {
+        auth_provider: LoginProvider::Iaaa.into(),
+        iaaa_token: token.into(),
+        username: "".into(),
+        password: "".into(),
+        ip_address: Some("10.0.0.1".into()),
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    };
+    let link_iaaa = |token: &str| LinkIdentityRequest {
+        provider: LoginProvider::Iaaa.into(),
+        iaaa_token: token.into(),
+        ip_address: Some("10.0.0.1".into()),
+        oidc: None,
+        password: "".into(),
+    };
+    let link_password = LinkIdentityRequest {
+        provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        ip_address: None,
+        oidc: None,
+        password: "mypassword".into(),
+    };
+    let unlink = |provider: LoginProvider| UnlinkIdentityRequest {
+        provider: provider.into(),
+    };
+
+    let response = rt.block_on(auth_client.login(Request::new(iaaa_login("2200066666"))))?;
+    let response = response.into_inner();
+    let (iaaa_token, iaaa_id) = (response.token, response.user.unwrap().id);
+
+    println!("Try UnlinkIdentity request on the only identity, should fail");
+    let request = with_token(unlink(LoginProvider::Iaaa), &iaaa_token);
+    let response = rt.block_on(auth_client.unlink_identity(request));
+    assert_eq!(
+        response.unwrap_err().code(),
+        tonic::Code::FailedPrecondition
+    );
+
+    println!("Try LinkIdentity request of a password, then login with it");
+    let request = with_token(link_password.clone(), &iaaa_token);
+    let response = rt.block_on(auth_client.link_identity(request))?;
+    assert_eq!(response.into_inner().identities.len(), 2);
+    let response = rt.block_on(auth_client.login(Request::new(LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: "2200066666".into(),
+        password: "mypassword".into(),
+        ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    })))?;
+    assert_eq!(response.into_inner().user.unwrap().id, iaaa_id);
+
+    println!("Try LinkIdentity request of an IAAA identity of another account, should fail");
+    let (token, user_id) = login_as(&rt, "test_linker")?;
+    let request = with_token(link_iaaa("2200066666"), &token);
+    let response = rt.block_on(auth_client.link_identity(request));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+    println!("Try LinkIdentity request of a new IAAA identity, then login with it");
+    let request = with_token(link_iaaa("2200055555"), &token);
+    let response = rt.block_on(auth_client.link_identity(request))?;
+    assert_eq!(response.into_inner().identities.len(), 2);
+    let response = rt.block_on(auth_client.login(Request::new(iaaa_login("2200055555"))))?;
+    let user = response.into_inner().user.unwrap();
+    assert_eq!(user.id, user_id);
+    assert!(user.iaaa_profile.is_some());
+
+    println!("Try UnlinkIdentity requests, should not login that way anymore");
+    let request = with_token(unlink(LoginProvider::Iaaa), &token);
+    rt.block_on(auth_client.unlink_identity(request))?;
+    let request = with_token(unlink(LoginProvider::Password), &iaaa_token);
+    let response = rt.block_on(auth_client.unlink_identity(request))?;
+    assert_eq!(response.into_inner().identities.len(), 1);
+    let response = rt.block_on(auth_client.login(Request::new(LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: "2200066666".into(),
+        password: "mypassword".into(),
+        ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    })));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+    Ok(())
+}
+
+/// 前提：服务器的OIDC_ISSUER指向mock-oidc
+#[test]
+fn oidc_login() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let response = rt.block_on(auth_client.get_oidc_config(GetOidcConfigRequest {}))?;
+    let config = response.into_inner();
+    let verifier = "a-code-verifier-long-enough-for-pkce-0123456789";
+    let authorize = |subject: &str, nonce: Option<&str>| {
+        let code = rt.block_on(oidc::authorize(
+            &config.authorization_endpoint,
+            &config.client_id,
+            subject,
+            verifier,
+            nonce,
+        ));
+        OidcAuthorization {
+            code,
+            code_verifier: verifier.into(),
+            redirect_uri: "http://localhost/callback".into(),
+            nonce: nonce.map(String::from),
+        }
+    };
+    let oidc_login = |authorization: OidcAuthorization| LoginRequest {
+        auth_provider: LoginProvider::Oidc.into(),
+        iaaa_token: "".into(),
+        username: "".into(),
+        password: "".into(),
+        ip_address: None,
+        oidc: Some(authorization),
+        second_factor: None,
+        device_name: None,
+    };
+
+    println!("Try OIDC login, should register and login");
+    let authorization = authorize("oidc_tester", Some("nonce-0"));
+    let response = rt.block_on(auth_client.login(oidc_login(authorization.clone())))?;
+    let user = response.into_inner().user.unwrap();
+    assert_eq!(user.username, "oidc_tester");
+    assert_eq!(user.login_provider, LoginProvider::Oidc as i32);
+    assert!(user.email_verified);
+
+    println!("Try OIDC login with a used code, should fail");
+    let response = rt.block_on(auth_client.login(oidc_login(authorization)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try OIDC login with a wrong code verifier, should fail");
+    let mut authorization = authorize("oidc_tester", None);
+    authorization.code_verifier = "another-code-verifier".into();
+    let response = rt.block_on(auth_client.login(oidc_login(authorization)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try LinkIdentity request of an OIDC identity, then login with it");
+    let (token, user_id) = login_as(&rt, "test_oidc_linker")?;
+    let request = LinkIdentityRequest {
+        provider: LoginProvider::Oidc.into(),
+        iaaa_token: "".into(),
+        ip_address: None,
+        password: "".into(),
+        oidc: Some(authorize("oidc_linked", None)),
+    };
+    rt.block_on(auth_client.link_identity(with_token(request, &token)))?;
+    let authorization = authorize("oidc_linked", None);
+    let response = rt.block_on(auth_client.login(oidc_login(authorization)))?;
+    assert_eq!(response.into_inner().user.unwrap().id, user_id);
+    let request = UnlinkIdentityRequest {
+        provider: LoginProvider::Oidc.into(),
+    };
+    rt.block_on(auth_client.unlink_identity(with_token(request, &token)))?;
+    Ok(())
+}
+
+/// 前提：服务器的OIDC_ISSUER指向mock-oidc
+#[test]
+fn oidc_login_with_two_factor() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let response = rt.block_on(auth_client.get_oidc_config(GetOidcConfigRequest {}))?;
+    let config = response.into_inner();
+    let suffix = chrono::Utc::now().timestamp_micros();
+    let subject = format!("oidc_totp_{suffix}");
+    let verifier = "a-code-verifier-long-enough-for-pkce-0123456789";
+    let authorize = || {
+        let code = rt.block_on(oidc::authorize(
+            &config.authorization_endpoint,
+            &config.client_id,
+            &subject,
+            verifier,
+            None,
+        ));
+        OidcAuthorization {
+            code,
+            code_verifier: verifier.into(),
+            redirect_uri: "http://localhost/callback".into(),
+            nonce: None,
+        }
+    };
+    let (token, user_id) = login_as(&rt, &format!("test_oidc_totp_{suffix}"))?;
+    let request = LinkIdentityRequest {
+        provider: LoginProvider::Oidc.into(),
+        iaaa_token: "".into(),
+        ip_address: None,
+        password: "".into(),
+        oidc: Some(authorize()),
+    };
+    rt.block_on(auth_client.link_identity(with_token(request, &token)))?;
+    let request = with_token(BeginTotpEnrollmentRequest {}, &token);
+    let response = rt.block_on(auth_client.begin_totp_enrollment(request))?;
+    let secret = base32_decode(&response.into_inner().secret).unwrap();
+    let step = || time_step(chrono::Utc::now().timestamp());
+    let request = ConfirmTotpRequest {
+        code: totp_code(&secret, step()),
+    };
+    rt.block_on(auth_client.confirm_totp(with_token(request, &token)))?;
+
+    println!("Try OIDC login of an account with two-factor authentication, should get a challenge");
+    let oidc_login = LoginRequest {
+        auth_provider: LoginProvider::Oidc.into(),
+        iaaa_token: "".into(),
+        username: "".into(),
+        password: "".into(),
+        ip_address: None,
+        oidc: Some(authorize()),
+        second_factor: None,
+        device_name: None,
+    };
+    let response = rt.block_on(auth_client.login(Request::new(oidc_login.clone())))?;
+    let response = response.into_inner();
+    assert!(!response.success);
+    assert!(response.token.is_empty());
+    assert!(response.refresh_token.is_empty());
+    let challenge = response.two_factor_challenge.unwrap();
+
+    println!("Try the second factor, should login");
+    let request = LoginRequest {
+        second_factor: Some(SecondFactor {
+            challenge,
+            code: totp_code(&secret, step() + 1),
+        }),
+        ..oidc_login
+    };
+    let response = rt.block_on(auth_client.login(Request::new(request)))?;
+    let response = response.into_inner();
+    assert!(response.success);
+    assert_eq!(response.user.unwrap().id, user_id);
+    Ok(())
+}
+
+/// 前提：数据库中有名字为test_admin的管理员用户
+#[test]
+fn two_factor_login() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let mut admin_client = rt.block_on(AdminClient::connect("http://[::1]:8080"))?;
+    let (token, user_id) = login_as(&rt, "test_totp")?;
+    let password_login = LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: "test_totp".into(),
+        password: "mypassword".into(),
+        ip_address: None,
+        oidc: None,
+        second_factor: None,
+        device_name: None,
+    };
+    let second_factor = |challenge: &str, code: &str| LoginRequest {
+        second_factor: Some(SecondFactor {
+            challenge: challenge.into(),
+            code: code.into(),
+        }),
+        ..password_login.clone()
+    };
+    let step = || time_step(chrono::Utc::now().timestamp());
+
+    println!("Try BeginTotpEnrollment and ConfirmTotp requests");
+    let request = with_token(BeginTotpEnrollmentRequest {}, &token);
+    let response = rt.block_on(auth_client.begin_totp_enrollment(request))?;
+    let response = response.into_inner();
+    assert!(response
+        .otpauth_uri
+        .starts_with("otpauth://totp/HoloPKU:test_totp?"));
+    let secret = base32_decode(&response.secret).unwrap();
+    let request = ConfirmTotpRequest {
+        code: "abcdef".into(),
+    };
+    let response = rt.block_on(auth_client.confirm_totp(with_token(request, &token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+    let code = totp_code(&secret, step());
+    let request = ConfirmTotpRequest { code: code.clone() };
+    let response = rt.block_on(auth_client.confirm_totp(with_token(request, &token)))?;
+    let recovery_codes = response.into_inner().recovery_codes;
+    assert_eq!(recovery_codes.len(), 10);
+    let request = with_token(BeginTotpEnrollmentRequest {}, &token);
+    let response = rt.block_on(auth_client.begin_totp_enrollment(request));
+    assert_eq!(
+        response.unwrap_err().code(),
+        tonic::Code::FailedPrecondition
+    );
+
+    println!("Try login with the password, should get a challenge");
+    let response = rt.block_on(auth_client.login(Request::new(password_login.clone())))?;
+    let response = response.into_inner();
+    assert!(!response.success);
+    assert!(response.token.is_empty());
+    let challenge = response.two_factor_challenge.unwrap();
+
+    println!("Try the second factor with a used code, should fail");
+    let response = rt.block_on(auth_client.login(second_factor(&challenge, &code)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try the second factor with the next code, should login");
+    let code = totp_code(&secret, step() + 1);
+    let response = rt.block_on(auth_client.login(second_factor(&challenge, &code)))?;
+    let response = response.into_inner();
+    assert!(response.success);
+    assert_eq!(response.user.unwrap().id, user_id);
+    let response = rt.block_on(auth_client.login(second_factor(&challenge, &code)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try the second factor with a recovery code, only once");
+    let response = rt.block_on(auth_client.login(Request::new(password_login.clone())))?;
+    let challenge = response.into_inner().two_factor_challenge.unwrap();
+    let code = recovery_codes[0].to_uppercase();
+    rt.block_on(auth_client.login(second_factor(&challenge, &code)))?;
+    let response = rt.block_on(auth_client.login(Request::new(password_login.clone())))?;
+    let challenge = response.into_inner().two_factor_challenge.unwrap();
+    let response = rt.block_on(auth_client.login(second_factor(&challenge, &code)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+
+    println!("Try ResetTotp request as the user, then as an admin");
+    let request = ResetTotpRequest { user_id };
+    let response = rt.block_on(admin_client.reset_totp(with_token(request, &token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::PermissionDenied);
+    let (admin_token, _) = login_as(&rt, "test_admin")?;
+    rt.block_on(admin_client.reset_totp(with_token(request, &admin_token)))?;
+    let response = rt.block_on(auth_client.login(Request::new(password_login)))?;
+    assert!(response.into_inner().success);
+    let response = rt.block_on(admin_client.reset_totp(with_token(request, &admin_token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+    Ok(())
+}
+
+#[test]
+fn list_and_revoke_sessions() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    login_as(&rt, "test_sessions")?;
+    let (other_token, _) = login_as(&rt, "test_sessions_other")?;
+    let login_on = |device_name: &str, ip_address: Option<&str>| LoginRequest {
+        auth_provider: LoginProvider::Password.into(),
+        iaaa_token: "".into(),
+        username: "test_sessions".into(),
+        password: "mypassword".into(),
+        ip_address: ip_address.map(String::from),
+        oidc: None,
+        second_factor: None,
+        device_name: Some(device_name.into()),
+    };
+
+    println!("Try login on two devices, then ListSessions request");
+    let response = rt.block_on(auth_client.login(login_on("Lab computer", Some("10.0.0.2"))))?;
+    let lab_token = response.into_inner().token;
+    let response = rt.block_on(auth_client.login(login_on("Phone", None)))?;
+    let phone_token = response.into_inner().token;
+    let request = with_token(ListSessionsRequest {}, &phone_token);
+    let sessions = rt.block_on(auth_client.list_sessions(request))?;
+    let sessions = sessions.into_inner().sessions;
+    let device = |name: &str| {
+        sessions
+            .iter()
+            .find(|s| s.device_name.as_deref() == Some(name))
+            .cloned()
+            .unwrap()
+    };
+    let (lab, phone) = (device("Lab computer"), device("Phone"));
+    // the address the device claims is kept apart from the real one
+    assert_eq!(lab.reported_ip_address.as_deref(), Some("10.0.0.2"));
+    assert_ne!(lab.ip_address.as_deref(), Some("10.0.0.2"));
+    assert!(lab.ip_address.is_some() && phone.ip_address.is_some());
+    assert!(phone.reported_ip_address.is_none());
+    assert!(phone.user_agent.unwrap().contains("tonic"));
+    assert!(phone.current && !lab.current);
+
+    println!("Try RevokeSession request of another user, should fail");
+    let request = RevokeSessionRequest { session_id: lab.id };
+    let response = rt.block_on(auth_client.revoke_session(with_token(request, &other_token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+
+    println!("Try RevokeSession request from the phone, the lab computer is logged out");
+    rt.block_on(auth_client.revoke_session(with_token(request, &phone_token)))?;
+    let list_request = with_token(ListSessionsRequest {}, &lab_token);
+    let response = rt.block_on(auth_client.list_sessions(list_request));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+    let list_request = with_token(ListSessionsRequest {}, &phone_token);
+    let sessions = rt.block_on(auth_client.list_sessions(list_request))?;
+    assert!(sessions
+        .into_inner()
+        .sessions
+        .iter()
+        .all(|s| s.id != lab.id));
+    let response = rt.block_on(auth_client.revoke_session(with_token(request, &phone_token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::NotFound);
+    Ok(())
+}
+
+#[test]
+fn undecodable_tokens() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+
+    println!("Try LikePost request with undecodable tokens, should fail alike");
+    // a bad padding and a bad length of legacy tokens, a bad tag of sealed ones
+    for token in [vec![2u8; 32], vec![2u8; 31], vec![1u8; 48]] {
+        let request = crate::codegen::forum::LikePostRequest {
+            user_id: 0,
+            post_id: 1,
+        };
+        let response = rt.block_on(forum_client.like_post(with_token(request, &token)));
+        println!("RESPONSE = {:?}", response);
+        let status = response.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        assert_eq!(status.message(), "Fail to decode token");
+    }
+    Ok(())
+}
+
+/// 前提：服务器与测试使用同一个数据库（DATABASE_URL）
+#[test]
+fn session_last_seen() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::dbschema::Sessions::dsl::*;
+    use diesel::prelude::*;
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let username = format!("test_last_seen_{}", chrono::Utc::now().timestamp_micros());
+    // the token of `login_as` is already used, log in again for a session never seen
+    let (_, the_user_id) = login_as(&rt, &username)?;
+    let (token, _) = login_as(&rt, &username)?;
+    let client = DBClient::connect(&std::env::var("DATABASE_URL")?, Default::default())?;
+    let an_hour_ago = chrono::Utc::now().naive_utc() - chrono::Duration::hours(1);
+    diesel::update(Sessions.filter(user_id.eq(the_user_id)))
+        .set(last_seen_at.eq(an_hour_ago))
+        .execute(&mut client.get_conn()?)?;
+
+    println!("Try ListSessions request, the session was just seen");
+    let request = with_token(ListSessionsRequest {}, &token);
+    let sessions = rt.block_on(auth_client.list_sessions(request))?;
+    let sessions = sessions.into_inner().sessions;
+    let session = sessions
+        .into_iter()
+        .find(|session| session.current)
+        .unwrap();
+    assert!(session.last_seen_at > an_hour_ago.and_utc().timestamp() + 60);
+    Ok(())
+}
+
+/// 前提：服务器与测试使用同一个数据库（DATABASE_URL）
+#[test]
+fn export_and_delete_account() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::forum::LikePostRequest;
+    use crate::codegen::forum::{CommentRequest, FavorateRequest, GetPostRequest};
+    use std::io::Read;
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let username = format!("test_delete_{}", chrono::Utc::now().timestamp_micros());
+    let (token, user_id) = login_as(&rt, &username)?;
+    let (other_token, other_id) = login_as(&rt, "test_delete_other")?;
+
+    // a post of the user, liked by the other one, and the other way round
+    let post_id = create_sell_post(&rt, &token)?;
+    let other_post_id = create_sell_post(&rt, &other_token)?;
+    let like = |user_id, post_id| LikePostRequest { user_id, post_id };
+    rt.block_on(forum_client.like_post(with_token(like(other_id, post_id), &other_token)))?;
+    let favorate = FavorateRequest {
+        user_id: other_id,
+        post_id,
+    };
+    rt.block_on(forum_client.favorate(with_token(favorate, &other_token)))?;
+    rt.block_on(forum_client.like_post(with_token(like(user_id, other_post_id), &token)))?;
+    let comment = CommentRequest {
+        user_id,
+        post_id: other_post_id,
+        content: "to be deleted".into(),
+    };
+    rt.block_on(forum_client.comment(with_token(comment, &token)))?;
+
+    println!("Try ExportMyData request");
+    let request = with_token(ExportMyDataRequest {}, &token);
+    let mut stream = rt
+        .block_on(auth_client.export_my_data(request))?
+        .into_inner();
+    let (mut archive, mut chunks) = (vec![], 0);
+    while let Some(response) = rt.block_on(stream.message())? {
+        archive.extend(response.chunk);
+        chunks += 1;
+    }
+    // sent file by file, not at once
+    assert!(chunks > 1);
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(archive))?;
+    let mut data = String::new();
+    archive.by_name("data.json")?.read_to_string(&mut data)?;
+    let data: serde_json::Value = serde_json::from_str(&data)?;
+    assert_eq!(data["user"]["username"], username);
+    assert_eq!(data["posts"][0]["id"], post_id);
+    assert_eq!(data["comments"][0]["post_id"], other_post_id);
+    assert_eq!(data["liked_posts"], serde_json::json!([other_post_id]));
+    assert!(archive
+        .by_name(data["user"]["icon"].as_str().unwrap())
+        .is_ok());
+
+    println!("Try DeleteAccount request, then cancel it");
+    let request = with_token(DeleteAccountRequest {}, &token);
+    let delete_after = rt.block_on(auth_client.delete_account(request))?;
+    let delete_after = delete_after.into_inner().delete_after;
+    let grace_period = delete_after - chrono::Utc::now().timestamp();
+    assert!((13 * 24 * 3600..=14 * 24 * 3600).contains(&grace_period));
+    let request = with_token(DeleteAccountRequest {}, &token);
+    let response = rt.block_on(auth_client.delete_account(request))?;
+    assert_eq!(response.into_inner().delete_after, delete_after);
+    let request = with_token(CancelAccountDeletionRequest {}, &token);
+    rt.block_on(auth_client.cancel_account_deletion(request))?;
+    let request = with_token(CancelAccountDeletionRequest {}, &token);
+    let response = rt.block_on(auth_client.cancel_account_deletion(request));
+    assert_eq!(
+        response.unwrap_err().code(),
+        tonic::Code::FailedPrecondition
+    );
+
+    println!("Try DeleteAccount request, then let the grace period pass");
+    let request = with_token(DeleteAccountRequest {}, &token);
+    rt.block_on(auth_client.delete_account(request))?;
+    let client = DBClient::connect(&std::env::var("DATABASE_URL")?, Default::default())?;
+    let after_grace_period = chrono::Utc::now().naive_utc() + DELETION_GRACE_PERIOD;
+    assert!(purge_deleted_accounts(&client, after_grace_period)? >= 1);
+
+    let request = with_token(ListSessionsRequest {}, &token);
+    let response = rt.block_on(auth_client.list_sessions(request));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unauthenticated);
+    let request = with_token(GetPostRequest { post_id }, &other_token);
+    assert!(rt.block_on(forum_client.get_sell_post(request)).is_err());
+    let request = GetPostRequest {
+        post_id: other_post_id,
+    };
+    let response = rt.block_on(forum_client.get_sell_post(with_token(request, &other_token)))?;
+    let other_post = response.into_inner().post.unwrap().post.unwrap();
+    assert_eq!(other_post.likes, 0);
+    assert!(other_post.comments.is_empty());
+    let request = GetUserRequest { user_id: other_id };
+    let other = rt
+        .block_on(auth_client.get_user(request))?
+        .into_inner()
+        .user
+        .unwrap();
+    assert!(!other.liked_posts.contains(&post_id));
+    assert!(!other.favorite_posts.contains(&post_id));
+    Ok(())
+}
+
+#[test]
+fn change_nickname() -> Result<(), Box<dyn std::error::Error>> {
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    // nicknames can only be changed once in a while, start afresh every run
+    let suffix = chrono::Utc::now().timestamp_micros() % 1_000_000_000_000;
+    let (token, user_id) = login_as(&rt, &format!("test_nickname_{suffix}"))?;
+    let (other_token, _) = login_as(&rt, &format!("test_nickname_other_{suffix}"))?;
+    let nickname = format!("nick{suffix}");
+
+    println!("Try ChangeUsername request, usernames are immutable");
+    let request = ChangeUsernameRequest {
+        user_id,
+        new_name: "test_nickname_renamed".into(),
+    };
+    #[allow(deprecated)]
+    let response = rt.block_on(auth_client.change_username(with_token(request, &token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::Unimplemented);
+
+    println!("Try ChangeNickname request with invalid nicknames, should fail");
+    for invalid in ["x", "bad\nnickname", "no  double spaces"] {
+        let request = ChangeNicknameRequest {
+            nickname: invalid.into(),
+        };
+        let response = rt.block_on(auth_client.change_nickname(with_token(request, &token)));
+        assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    println!("Try ChangeNickname request");
+    let request = ChangeNicknameRequest {
+        nickname: format!(" {nickname} "),
+    };
+    let response = rt.block_on(auth_client.change_nickname(with_token(request, &token)))?;
+    let user = response.into_inner().user.unwrap();
+    assert_eq!(user.nickname, nickname);
+    assert_eq!(user.username, format!("test_nickname_{suffix}"));
+
+    println!("Try ChangeNickname request again at once, should fail");
+    let request = ChangeNicknameRequest {
+        nickname: format!("{nickname}x"),
+    };
+    let response = rt.block_on(auth_client.change_nickname(with_token(request, &token)));
+    assert_eq!(
+        response.unwrap_err().code(),
+        tonic::Code::FailedPrecondition
+    );
+
+    println!("Try ChangeNickname request with a taken nickname, should fail");
+    let request = ChangeNicknameRequest {
+        nickname: nickname.to_uppercase(),
+    };
+    let response = rt.block_on(auth_client.change_nickname(with_token(request, &other_token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::AlreadyExists);
+
+    println!("Try ListNicknameHistory request");
+    let request = with_token(ListNicknameHistoryRequest {}, &token);
+    let response = rt.block_on(auth_client.list_nickname_history(request))?;
+    let nicknames = response.into_inner().nicknames;
+    assert_eq!(nicknames.len(), 1);
+    // password users start without one
+    assert_eq!(nicknames[0].nickname, "");
+    Ok(())
+}
+
+#[test]
+fn profile_and_privacy() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::auth::{ContactMethod, PrivacySettings, Profile, Visibility};
+    use crate::codegen::auth::{UpdatePrivacySettingsRequest, UpdateProfileRequest};
+    use crate::codegen::forum::LikePostRequest;
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let username = format!("test_profile_{}", chrono::Utc::now().timestamp_micros());
+    let (token, user_id) = login_as(&rt, &username)?;
+    let (other_token, _) = login_as(&rt, "test_profile_other")?;
+    let post_id = create_sell_post(&rt, &other_token)?;
+    let like = LikePostRequest { user_id, post_id };
+    rt.block_on(forum_client.like_post(with_token(like, &token)))?;
+
+    println!("Try UpdateProfile request with an invalid link, should fail");
+    let profile = Profile {
+        bio: "爱吃学五的麻辣香锅".into(),
+        pronouns: Some("they/them".into()),
+        contact_method: Some(ContactMethod::Wechat.into()),
+        contact: Some("jack_ma".into()),
+        links: vec!["javascript:alert(1)".into()],
+    };
+    let request = UpdateProfileRequest {
+        profile: Some(profile.clone()),
+    };
+    let response = rt.block_on(auth_client.update_profile(with_token(request, &token)));
+    assert_eq!(response.unwrap_err().code(), tonic::Code::InvalidArgument);
+
+    println!("Try UpdateProfile request");
+    let profile = Profile {
+        links: vec!["https://github.com/jack".into()],
+        ..profile
+    };
+    let request = UpdateProfileRequest {
+        profile: Some(profile.clone()),
+    };
+    let response = rt.block_on(auth_client.update_profile(with_token(request, &token)))?;
+    let user = response.into_inner().user.unwrap();
+    assert_eq!(user.profile, Some(profile.clone()));
+    // nothing is shared beyond the defaults yet
+    let privacy = user.privacy.unwrap();
+    assert_eq!(privacy.likes(), Visibility::OnlyMe);
+    assert_eq!(privacy.contact(), Visibility::SignedIn);
+
+    println!("Try UpdatePrivacySettings request");
+    let privacy = PrivacySettings {
+        favorites: Visibility::OnlyMe.into(),
+        likes: Visibility::SignedIn.into(),
+        participations: Visibility::OnlyMe.into(),
+        contact: Visibility::Everyone.into(),
+    };
+    let request = UpdatePrivacySettingsRequest {
+        privacy: Some(privacy),
+    };
+    let response = rt.block_on(auth_client.update_privacy_settings(with_token(request, &token)))?;
+    assert_eq!(response.into_inner().privacy, Some(privacy));
+
+    println!("Try GetUser request as the owner, sees everything");
+    let request = with_token(GetUserRequest { user_id }, &token);
+    let user = rt.block_on(auth_client.get_user(request))?.into_inner();
+    let user = user.user.unwrap();
+    assert!(user.email.is_some());
+    assert_eq!(user.liked_posts, vec![post_id]);
+    assert_eq!(user.profile, Some(profile.clone()));
+    assert_eq!(user.privacy, Some(privacy));
+
+    println!("Try GetUser request as another user, sees the likes and the contact");
+    let request = with_token(GetUserRequest { user_id }, &other_token);
+    let user = rt.block_on(auth_client.get_user(request))?.into_inner();
+    let user = user.user.unwrap();
+    assert_eq!(user.email, None);
+    assert_eq!(user.privacy, None);
+    assert_eq!(user.liked_posts, vec![post_id]);
+    assert_eq!(user.profile, Some(profile.clone()));
+
+    println!("Try GetUser request anonymously, sees the contact only");
+    let request = Request::new(GetUserRequest { user_id });
+    let user = rt.block_on(auth_client.get_user(request))?.into_inner();
+    let user = user.user.unwrap();
+    assert_eq!(user.email, None);
+    assert!(user.liked_posts.is_empty());
+    assert_eq!(user.profile, Some(profile.clone()));
+
+    println!("Hide the contact from others, only the owner keeps seeing it");
+    let request = UpdatePrivacySettingsRequest {
+        privacy: Some(PrivacySettings {
+            contact: Visibility::OnlyMe.into(),
+            ..privacy
+        }),
+    };
+    rt.block_on(auth_client.update_privacy_settings(with_token(request, &token)))?;
+    let request = with_token(GetUserRequest { user_id }, &other_token);
+    let user = rt.block_on(auth_client.get_user(request))?.into_inner();
+    let shown = user.user.unwrap().profile.unwrap();
+    assert_eq!(shown.bio, profile.bio);
+    assert_eq!(shown.contact, None);
+    assert_eq!(shown.contact_method, None);
+    let request = with_token(GetUserRequest { user_id }, &token);
+    let user = rt.block_on(auth_client.get_user(request))?.into_inner();
+    assert_eq!(user.user.unwrap().profile, Some(profile));
+    Ok(())
+}
+
+#[test]
+fn list_others_favorites() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::auth::{PrivacySettings, UpdatePrivacySettingsRequest, Visibility};
+    use crate::codegen::forum::list_personal_posts_response::Message;
+    use crate::codegen::forum::{FavorateRequest, ListPersonalPostsRequest, ListRequestType};
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let suffix = chrono::Utc::now().timestamp_micros();
+    let (token, user_id) = login_as(&rt, &format!("test_favorites_{suffix}"))?;
+    let (other_token, _) = login_as(&rt, &format!("test_favorites_other_{suffix}"))?;
+    let post_id = create_sell_post(&rt, &other_token)?;
+    let request = FavorateRequest { user_id, post_id };
+    rt.block_on(forum_client.favorate(with_token(request, &token)))?;
+    let mut list_favorites = |token: &[u8]| {
+        let request = ListPersonalPostsRequest {
+            post_type: crate::codegen::post::PostType::Sellpost.into(),
+            user_id: Some(user_id),
+            r#type: ListRequestType::Star.into(),
+            number: 10,
+        };
+        let response = rt.block_on(forum_client.list_personal_posts(with_token(request, token)))?;
+        let Some(Message::SResponse(favorites)) = response.into_inner().message else {
+            panic!("Expected sell posts");
+        };
+        let favorites: Vec<i32> = favorites
+            .posts
+            .iter()
+            .map(|post| post.post.as_ref().unwrap().id)
+            .collect();
+        Ok::<_, Box<dyn std::error::Error>>(favorites)
+    };
+
+    println!("Try ListPersonalPosts request for the favorites of another user, sees nothing");
+    assert!(list_favorites(&other_token)?.is_empty());
+    assert_eq!(list_favorites(&token)?, vec![post_id]);
+
+    println!("Share the favorites with signed in users, now they are listed");
+    let request = UpdatePrivacySettingsRequest {
+        privacy: Some(PrivacySettings {
+            favorites: Visibility::SignedIn.into(),
+            likes: Visibility::OnlyMe.into(),
+            participations: Visibility::SignedIn.into(),
+            contact: Visibility::SignedIn.into(),
+        }),
+    };
+    rt.block_on(auth_client.update_privacy_settings(with_token(request, &token)))?;
+    assert_eq!(list_favorites(&other_token)?, vec![post_id]);
+    Ok(())
+}
+
+#[test]
+fn likes_favorites_and_comments() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::forum::list_personal_posts_response::Message;
+    use crate::codegen::forum::{CommentRequest, DeletePostRequest, FavorateRequest};
+    use crate::codegen::forum::{GetPostRequest, LikePostRequest, UnlikePostRequest};
+    use crate::codegen::forum::{ListPersonalPostsRequest, ListRequestType};
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut auth_client = rt.block_on(AuthClient::connect("http://[::1]:8080"))?;
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let suffix = chrono::Utc::now().timestamp_micros();
+    let (token, _) = login_as(&rt, &format!("test_likes_{suffix}"))?;
+    let (fan_token, fan_id) = login_as(&rt, &format!("test_likes_fan_{suffix}"))?;
+    let post_id = create_sell_post(&rt, &token)?;
+    let other_post_id = create_sell_post(&rt, &token)?;
+    let get_post = |forum_client: &mut ForumClient<_>, post_id| {
+        let request = with_token(GetPostRequest { post_id }, &fan_token);
+        let response = rt.block_on(forum_client.get_sell_post(request))?;
+        Ok::<_, Box<dyn std::error::Error>>(response.into_inner().post.unwrap().post.unwrap())
+    };
+
+    println!("Try LikePost request twice, counts once");
+    for the_post_id in [post_id, post_id, other_post_id] {
+        let request = LikePostRequest {
+            user_id: fan_id,
+            post_id: the_post_id,
+        };
+        rt.block_on(forum_client.like_post(with_token(request, &fan_token)))?;
+    }
+    assert_eq!(get_post(&mut forum_client, post_id)?.likes, 1);
+    let request = with_token(GetUserRequest { user_id: fan_id }, &fan_token);
+    let fan = rt.block_on(auth_client.get_user(request))?.into_inner();
+    assert_eq!(fan.user.unwrap().liked_posts, vec![post_id, other_post_id]);
+
+    println!("Try UnlikePost request");
+    let request = UnlikePostRequest {
+        user_id: fan_id,
+        post_id,
+    };
+    rt.block_on(forum_client.unlike_post(with_token(request, &fan_token)))?;
+    assert_eq!(get_post(&mut forum_client, post_id)?.likes, 0);
+
+    println!("Try Favorate request, the post is listed among the favorites");
+    for the_post_id in [other_post_id, post_id] {
+        let request = FavorateRequest {
+            user_id: fan_id,
+            post_id: the_post_id,
+        };
+        rt.block_on(forum_client.favorate(with_token(request, &fan_token)))?;
+    }
+    let request = ListPersonalPostsRequest {
+        post_type: crate::codegen::post::PostType::Sellpost.into(),
+        user_id: None,
+        r#type: ListRequestType::Star.into(),
+        number: 10,
+    };
+    let response =
+        rt.block_on(forum_client.list_personal_posts(with_token(request, &fan_token)))?;
+    let Some(Message::SResponse(favorites)) = response.into_inner().message else {
+        panic!("Expected sell posts");
+    };
+    let favorites: Vec<i32> = favorites
+        .posts
+        .iter()
+        .map(|post| post.post.as_ref().unwrap().id)
+        .collect();
+    assert_eq!(favorites, vec![other_post_id, post_id]);
+
+    println!("Try Comment request, comments are listed in order");
+    for content in ["first", "second"] {
+        let request = CommentRequest {
+            user_id: fan_id,
+            post_id,
+            content: content.into(),
+        };
+        rt.block_on(forum_client.comment(with_token(request, &fan_token)))?;
+    }
+    let comments: Vec<String> = get_post(&mut forum_client, post_id)?
+        .comments
+        .into_iter()
+        .map(|comment| comment.content)
+        .collect();
+    assert_eq!(comments, vec!["first", "second"]);
+
+    println!("Delete the post, it leaves the favorites of the fan");
+    let request = DeletePostRequest {
+        user_id: 0,
+        post_id,
+    };
+    rt.block_on(forum_client.delete_post(with_token(request, &token)))?;
+    let request = with_token(GetUserRequest { user_id: fan_id }, &fan_token);
+    let fan = rt.block_on(auth_client.get_user(request))?.into_inner();
+    let fan = fan.user.unwrap();
+    assert_eq!(fan.favorite_posts, vec![other_post_id]);
+    assert_eq!(fan.liked_posts, vec![other_post_id]);
+    Ok(())
+}
+
+#[test]
+fn concurrent_likes_and_participants() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::forum::{GetPostRequest, LikePostRequest, UnlikePostRequest};
+    use crate::codegen::forum::{NoTakePartAmusePostRequest, TakePartAmusePostRequest};
+    use std::collections::HashSet;
+
+    const FANS: usize = 12;
+    const TRIES: usize = 4;
+    const PEOPLE_ALL: i32 = 5;
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let suffix = chrono::Utc::now().timestamp_micros();
+    let (token, _) = login_as(&rt, &format!("test_rush_{suffix}"))?;
+    let post_id = create_sell_post(&rt, &token)?;
+    let event_id = create_amusement_post(&rt, &token, PEOPLE_ALL)?;
+    let fans = (0..FANS)
+        .map(|i| login_as(&rt, &format!("test_rush_fan{i}_{suffix}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    let counts = |forum_client: &mut ForumClient<_>| {
+        let request = with_token(GetPostRequest { post_id }, &token);
+        let post = rt
+            .block_on(forum_client.get_sell_post(request))?
+            .into_inner();
+        let request = with_token(GetPostRequest { post_id: event_id }, &token);
+        let event = rt.block_on(forum_client.get_amusement_post(request))?;
+        let likes = post.post.unwrap().post.unwrap().likes;
+        let people_already = event.into_inner().post.unwrap().people_already;
+        Ok::<_, Box<dyn std::error::Error>>((likes, people_already))
+    };
+
+    println!("Every fan likes the post and takes part in the event, {TRIES} times at once");
+    let joined = rt.block_on(async {
+        let mut tasks = tokio::task::JoinSet::new();
+        for (fan_token, fan_id) in &fans {
+            for _ in 0..TRIES {
+                let mut forum_client = forum_client.clone();
+                let (fan_token, fan_id) = (fan_token.clone(), *fan_id);
+                tasks.spawn(async move {
+                    let like = LikePostRequest {
+                        user_id: fan_id,
+                        post_id,
+                    };
+                    forum_client
+                        .like_post(with_token(like, &fan_token))
+                        .await
+                        .unwrap();
+                    let take_part = TakePartAmusePostRequest {
+                        user_id: fan_id,
+                        post_id: event_id,
+                    };
+                    match forum_client
+                        .take_part(with_token(take_part, &fan_token))
+                        .await
+                    {
+                        Ok(_) => Some(fan_id),
+                        Err(status) => {
+                            assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+                            None
+                        }
+                    }
+                });
+            }
+        }
+        let mut joined = HashSet::new();
+        while let Some(fan_id) = tasks.join_next().await {
+            joined.extend(fan_id.unwrap());
+        }
+        joined
+    });
+    // the organizer holds a place already
+    assert_eq!(joined.len(), PEOPLE_ALL as usize - 1);
+    assert_eq!(
+        counts(&mut forum_client.clone())?,
+        (FANS as i32, PEOPLE_ALL)
+    );
+
+    println!("Every fan takes it back, {TRIES} times at once");
+    rt.block_on(async {
+        let mut tasks = tokio::task::JoinSet::new();
+        for (fan_token, fan_id) in &fans {
+            for _ in 0..TRIES {
+                let mut forum_client = forum_client.clone();
+                let (fan_token, fan_id) = (fan_token.clone(), *fan_id);
+                tasks.spawn(async move {
+                    let unlike = UnlikePostRequest {
+                        user_id: fan_id,
+                        post_id,
+                    };
+                    forum_client
+                        .unlike_post(with_token(unlike, &fan_token))
+                        .await
+                        .unwrap();
+                    let no_take_part = NoTakePartAmusePostRequest {
+                        user_id: fan_id,
+                        post_id: event_id,
+                    };
+                    forum_client
+                        .no_take_part(with_token(no_take_part, &fan_token))
+                        .await
+                        .unwrap();
+                });
+            }
+        }
+        while let Some(result) = tasks.join_next().await {
+            result.unwrap();
+        }
+    });
+    assert_eq!(counts(&mut forum_client.clone())?, (0, 1));
+    Ok(())
+}
+
+#[test]
+fn like_comment() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::forum::{CommentRequest, GetPostRequest};
+    use crate::codegen::forum::{LikeCommentRequest, UnlikeCommentRequest};
+    use crate::codegen::post::Comment;
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let suffix = chrono::Utc::now().timestamp_micros();
+    let (token, user_id) = login_as(&rt, &format!("test_comment_likes_{suffix}"))?;
+    let (fan_token, fan_id) = login_as(&rt, &format!("test_comment_likes_fan_{suffix}"))?;
+    let post_id = create_sell_post(&rt, &token)?;
+    let request = CommentRequest {
+        user_id,
+        post_id,
+        content: "like me".into(),
+    };
+    rt.block_on(forum_client.comment(with_token(request, &token)))?;
+    let get_comment = |forum_client: &mut ForumClient<_>, token: &[u8]| {
+        let request = with_token(GetPostRequest { post_id }, token);
+        let response = rt.block_on(forum_client.get_sell_post(request))?;
+        let post = response.into_inner().post.unwrap().post.unwrap();
+        Ok::<Comment, Box<dyn std::error::Error>>(post.comments[0].clone())
+    };
+    let comment_id = get_comment(&mut forum_client, &token)?.id;
+
+    println!("Try LikeComment request twice, counts once");
+    for changed in [true, false] {
+        let request = LikeCommentRequest {
+            user_id: fan_id,
+            comment_id,
+        };
+        let response = rt.block_on(forum_client.like_comment(with_token(request, &fan_token)))?;
+        let response = response.into_inner();
+        assert_eq!((response.liked, response.likes), (true, 1));
+        assert_eq!(response.changed, changed);
+    }
+    let comment = get_comment(&mut forum_client, &fan_token)?;
+    assert_eq!((comment.likes, comment.liked), (1, true));
+    let comment = get_comment(&mut forum_client, &token)?;
+    assert_eq!((comment.likes, comment.liked), (1, false));
+
+    println!("Try UnlikeComment request twice, counts once");
+    for changed in [true, false] {
+        let request = UnlikeCommentRequest {
+            user_id: fan_id,
+            comment_id,
+        };
+        let response = rt.block_on(forum_client.unlike_comment(with_token(request, &fan_token)))?;
+        let response = response.into_inner();
+        assert_eq!((response.liked, response.likes), (false, 0));
+        assert_eq!(response.changed, changed);
+    }
+    let comment = get_comment(&mut forum_client, &fan_token)?;
+    assert_eq!((comment.likes, comment.liked), (0, false));
+
+    println!("Try LikeComment request on a missing comment");
+    let request = LikeCommentRequest {
+        user_id: fan_id,
+        comment_id: i32::MAX,
+    };
+    let status = rt
+        .block_on(forum_client.like_comment(with_token(request, &fan_token)))
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    Ok(())
+}
+
+#[test]
+fn toggle_responses() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::forum::{FavorateRequest, UnfavorateRequest};
+    use crate::codegen::forum::{LikePostRequest, UnlikePostRequest};
+    use crate::codegen::forum::{NoTakePartAmusePostRequest, TakePartAmusePostRequest};
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let mut forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let suffix = chrono::Utc::now().timestamp_micros();
+    let (token, _) = login_as(&rt, &format!("test_toggles_{suffix}"))?;
+    let (fan_token, fan_id) = login_as(&rt, &format!("test_toggles_fan_{suffix}"))?;
+    let post_id = create_sell_post(&rt, &token)?;
+    let event_id = create_amusement_post(&rt, &token, 5)?;
+
+    println!("Try LikePost and UnlikePost requests twice, the second is a no-op");
+    let request = LikePostRequest {
+        user_id: fan_id,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.like_post(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.liked, response.likes, response.changed),
+        (true, 1, true)
+    );
+    let response = rt.block_on(forum_client.like_post(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.liked, response.likes, response.changed),
+        (true, 1, false)
+    );
+    let request = UnlikePostRequest {
+        user_id: fan_id,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.unlike_post(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.liked, response.likes, response.changed),
+        (false, 0, true)
+    );
+    let response = rt.block_on(forum_client.unlike_post(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.liked, response.likes, response.changed),
+        (false, 0, false)
+    );
+
+    println!("Try Favorate and Unfavorate requests twice, the second is a no-op");
+    let request = FavorateRequest {
+        user_id: fan_id,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.favorate(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.favorated, response.favorates, response.changed),
+        (true, 1, true)
+    );
+    let response = rt.block_on(forum_client.favorate(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.favorated, response.favorates, response.changed),
+        (true, 1, false)
+    );
+    let request = UnfavorateRequest {
+        user_id: fan_id,
+        post_id,
+    };
+    let response = rt.block_on(forum_client.unfavorate(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.favorated, response.favorates, response.changed),
+        (false, 0, true)
+    );
+    let response = rt.block_on(forum_client.unfavorate(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    assert_eq!(
+        (response.favorated, response.favorates, response.changed),
+        (false, 0, false)
+    );
+
+    println!("Try TakePart and NoTakePart requests twice, the second is a no-op");
+    let request = TakePartAmusePostRequest {
+        user_id: fan_id,
+        post_id: event_id,
+    };
+    let response = rt.block_on(forum_client.take_part(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    let state = (
+        response.taking_part,
+        response.people_already,
+        response.changed,
+    );
+    // the organizer holds a place already
+    assert_eq!(state, (true, 2, true));
+    let response = rt.block_on(forum_client.take_part(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    let state = (
+        response.taking_part,
+        response.people_already,
+        response.changed,
+    );
+    assert_eq!(state, (true, 2, false));
+    let request = NoTakePartAmusePostRequest {
+        user_id: fan_id,
+        post_id: event_id,
+    };
+    let response = rt.block_on(forum_client.no_take_part(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    let state = (
+        response.taking_part,
+        response.people_already,
+        response.changed,
+    );
+    assert_eq!(state, (false, 1, true));
+    let response = rt.block_on(forum_client.no_take_part(with_token(request, &fan_token)))?;
+    let response = response.into_inner();
+    let state = (
+        response.taking_part,
+        response.people_already,
+        response.changed,
+    );
+    assert_eq!(state, (false, 1, false));
+
+    println!("Try toggles on a missing post");
+    let request = LikePostRequest {
+        user_id: fan_id,
+        post_id: i32::MAX,
+    };
+    let status = rt
+        .block_on(forum_client.like_post(with_token(request, &fan_token)))
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    // a sell post is no event
+    let request = NoTakePartAmusePostRequest {
+        user_id: fan_id,
+        post_id,
+    };
+    let status = rt
+        .block_on(forum_client.no_take_part(with_token(request, &fan_token)))
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+    Ok(())
+}
+
+/// 前提：服务器以默认的连接池大小运行
+#[test]
+fn list_under_load() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::codegen::hello::hello_client::HelloClient;
+    use crate::codegen::hello::HelloRequest;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    const CLIENTS: usize = 64;
+    const REQUESTS: usize = 8;
+
+    let rt = Runtime::new().expect("Failed to create runtime");
+    let forum_client = rt.block_on(ForumClient::connect("http://[::1]:8080"))?;
+    let hello_client = rt.block_on(HelloClient::connect("http://[::1]:8080"))?;
+    let suffix = chrono::Utc::now().timestamp_micros();
+    let (token, _) = login_as(&rt, &format!("test_load_{suffix}"))?;
+    for _ in 0..4 {
+        create_sell_post(&rt, &token)?;
+    }
+    let percentile = |latencies: &mut Vec<Duration>, p: usize| {
+        latencies.sort();
+        latencies[(latencies.len() - 1) * p / 100]
+    };
+
+    println!("Try {CLIENTS} clients listing sell posts at once, while saying hello");
+    let (mut lists, mut hellos) = rt.block_on(async {
+        let done = Arc::new(AtomicBool::new(false));
+        let mut hello_client = hello_client.clone();
+        let hello_done = done.clone();
+        // hello needs no database, it answers as fast as the runtime lets it
+        let hellos = tokio::spawn(async move {
+            let mut latencies = vec![];
+            while !hello_done.load(Ordering::Relaxed) {
+                let start = Instant::now();
+                hello_client
+                    .say_hello(HelloRequest {
+                        name: "load".into(),
+                    })
+                    .await
+                    .unwrap();
+                latencies.push(start.elapsed());
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            latencies
+        });
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..CLIENTS {
+            let mut forum_client = forum_client.clone();
+            let token = token.clone();
+            tasks.spawn(async move {
+                let mut latencies = vec![];
+                for _ in 0..REQUESTS {
+                    let request = ListSellPostsRequest {
+                        goods_type: None,
+                        price_upbond: i32::MAX,
+                        number: 20,
+                        dept_id: None,
+                    };
+                    let start = Instant::now();
+                    forum_client
+                        .list_sell_posts(with_token(request, &token))
+                        .await
+                        .unwrap();
+                    latencies.push(start.elapsed());
+                }
+                latencies
+            });
+        }
+        let mut lists = vec![];
+        while let Some(latencies) = tasks.join_next().await {
+            lists.extend(latencies.unwrap());
+        }
+        done.store(true, Ordering::Relaxed);
+        (lists, hellos.await.unwrap())
+    });
+    let (list_p50, list_p99) = (percentile(&mut lists, 50), percentile(&mut lists, 99));
+    let (hello_p50, hello_p99) = (percentile(&mut hellos, 50), percentile(&mut hellos, 99));
+    println!("ListSellPosts: p50 {list_p50:?}, p99 {list_p99:?}");
+    println!("SayHello: p50 {hello_p50:?}, p99 {hello_p99:?}");
+    // Queries wait for a connection on blocking threads, so requests that do
+    // not touch the database stay responsive.
+    assert!(hello_p99 < Duration::from_secs(1));
+    assert!(list_p99 < Duration::from_secs(5));
+    Ok(())
+}
+
+/// 前提：服务器与测试使用同一个数据库（DATABASE_URL）
+#[test]
+#[allow(clippy::result_large_err)]
+fn stores_on_held_connection() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::auth::throttle::{DBLoginAttemptStore, LoginThrottle};
+    use crate::clock::SystemClock;
+    use crate::db::pool::PoolConfig;
+    use crate::middleware::revocation::{DBRevocationStore, RevocationStore};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // a single connection, which the stores must not ask for twice
+    let config = PoolConfig {
+        max_size: 1,
+        connection_timeout: Duration::from_secs(2),
+        ..Default::default()
+    };
+    let client = DBClient::connect(&std::env::var("DATABASE_URL")?, config)?;
+    let revocations = Arc::new(DBRevocationStore::new(client.clone()));
+    let throttle = LoginThrottle::new(
+        Arc::new(DBLoginAttemptStore::new(client.clone())),
+        Arc::new(SystemClock),
+    );
+    let username = format!("test_held_ne_{}", chrono::Utc::now().timestamp_micros());
+
+    // `#[tokio::test]` runs on a current-thread runtime too
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let store = revocations.clone();
+    rt.block_on(client.run(move |conn| -> Result<_, tonic::Status> {
+        // there is no such session, which counts as revoked
+        let revoked = store
+            .is_revoked_with(conn, 0)
+            .map_err(|e| tonic::Status::internal(e.to_string()))?;
+        assert!(revoked);
+        throttle.check_with(conn, &username, Some("10.0.0.1"))?;
+        throttle.record_with(conn, &username, Some("10.0.0.1"), false)
+    }))?;
+    // where `block_in_place` would panic
+    assert!(rt.block_on(async { revocations.is_revoked(0) })?);
+    Ok(())
+}
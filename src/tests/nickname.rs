@@ -0,0 +1,32 @@
+use tonic::Code;
+
+use crate::auth::nickname::validate_nickname;
+
+#[test]
+fn valid_nicknames() {
+    assert_eq!(validate_nickname("燕园吃货").unwrap(), "燕园吃货");
+    assert_eq!(validate_nickname("  jack_ma-1.0 ").unwrap(), "jack_ma-1.0");
+    assert_eq!(validate_nickname("Jack Ma").unwrap(), "Jack Ma");
+    assert_eq!(validate_nickname("ab").unwrap(), "ab");
+    // characters are counted, not bytes
+    assert!(validate_nickname(&"北".repeat(20)).is_ok());
+}
+
+#[test]
+fn invalid_nicknames() {
+    let invalid = |nickname: &str| {
+        let e = validate_nickname(nickname).unwrap_err();
+        e.code() == Code::InvalidArgument
+    };
+    assert!(invalid(""));
+    assert!(invalid("   "));
+    assert!(invalid("a"));
+    assert!(invalid(&"a".repeat(21)));
+    assert!(invalid(&"北".repeat(21)));
+    assert!(invalid("Jack  Ma"));
+    assert!(invalid("Jack\tMa"));
+    assert!(invalid("line\nbreak"));
+    assert!(invalid("<script>"));
+    assert!(invalid("admin@pku"));
+    assert!(invalid("zero\u{200b}width"));
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use crate::auth::oidc::mock::{MockIssuer, MOCK_SUBJECT};
+use crate::auth::oidc::{code_challenge, OidcClient, OidcConfig, OidcError};
+
+const REDIRECT_URI: &str = "http://localhost/callback";
+const VERIFIER: &str = "a-code-verifier-long-enough-for-pkce-0123456789";
+
+/// Start a mock issuer on a free port, returns it with its issuer URL.
+async fn start_mock(client_secret: Option<&str>) -> (Arc<MockIssuer>, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    let mock = Arc::new(MockIssuer::new(
+        &issuer,
+        "holopku",
+        client_secret.map(String::from),
+    ));
+    tokio::spawn(mock.clone().serve(listener));
+    (mock, issuer)
+}
+
+fn client(issuer: &str, client_secret: Option<&str>) -> OidcClient {
+    OidcClient::new(OidcConfig {
+        issuer: issuer.into(),
+        client_id: "holopku".into(),
+        client_secret: client_secret.map(String::from),
+    })
+    .with_timeout(Duration::from_millis(500))
+}
+
+/// Make the authorization request the way a client would, returns the code.
+pub(super) async fn authorize(
+    authorization_endpoint: &str,
+    client_id: &str,
+    subject: &str,
+    code_verifier: &str,
+    nonce: Option<&str>,
+) -> String {
+    let mut query = vec![
+        ("response_type", "code"),
+        ("client_id", client_id),
+        ("redirect_uri", REDIRECT_URI),
+        ("code_challenge_method", "S256"),
+        ("state", "some-state"),
+        ("login_hint", subject),
+    ];
+    let challenge = code_challenge(code_verifier);
+    query.push(("code_challenge", &challenge));
+    if let Some(nonce) = nonce {
+        query.push(("nonce", nonce));
+    }
+    let http = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = http
+        .get(authorization_endpoint)
+        .query(&query)
+        .send()
+        .await
+        .unwrap();
+    let location = response.headers()[reqwest::header::LOCATION]
+        .to_str()
+        .unwrap();
+    let location = url::Url::parse(location).unwrap();
+    assert!(location.as_str().starts_with(REDIRECT_URI));
+    let code = location.query_pairs().find(|(name, _)| name == "code");
+    code.unwrap().1.into_owned()
+}
+
+#[tokio::test]
+async fn exchange_success() {
+    let (_, issuer) = start_mock(Some("secret")).await;
+    let oidc = client(&issuer, Some("secret"));
+    let endpoint = &oidc.discovery().await.unwrap().authorization_endpoint;
+
+    let code = authorize(endpoint, "holopku", "alice", VERIFIER, Some("n-0")).await;
+    let claims = oidc
+        .exchange(&code, VERIFIER, REDIRECT_URI, Some("n-0"))
+        .await
+        .unwrap();
+    assert_eq!(claims.iss, issuer);
+    assert_eq!(claims.sub, "alice");
+    assert_eq!(claims.email.as_deref(), Some("alice@example.edu"));
+    assert!(claims.email_verified);
+
+    let code = authorize(endpoint, "holopku", "", VERIFIER, None).await;
+    let claims = oidc
+        .exchange(&code, VERIFIER, REDIRECT_URI, None)
+        .await
+        .unwrap();
+    assert_eq!(claims.sub, MOCK_SUBJECT);
+}
+
+#[tokio::test]
+async fn exchange_rejects_bad_requests() {
+    let (_, issuer) = start_mock(Some("secret")).await;
+    let oidc = client(&issuer, Some("secret"));
+    let endpoint = &oidc.discovery().await.unwrap().authorization_endpoint;
+    let rejected = |result: Result<_, OidcError>| matches!(result, Err(OidcError::Rejected(_)));
+
+    // wrong verifier
+    let code = authorize(endpoint, "holopku", "alice", VERIFIER, None).await;
+    let result = oidc.exchange(&code, "another-verifier", REDIRECT_URI, None);
+    assert!(rejected(result.await));
+
+    // wrong redirect URI
+    let code = authorize(endpoint, "holopku", "alice", VERIFIER, None).await;
+    let result = oidc.exchange(&code, VERIFIER, "http://localhost/other", None);
+    assert!(rejected(result.await));
+
+    // reused code
+    let code = authorize(endpoint, "holopku", "alice", VERIFIER, None).await;
+    oidc.exchange(&code, VERIFIER, REDIRECT_URI, None)
+        .await
+        .unwrap();
+    let result = oidc.exchange(&code, VERIFIER, REDIRECT_URI, None);
+    assert!(rejected(result.await));
+
+    // wrong nonce
+    let code = authorize(endpoint, "holopku", "alice", VERIFIER, Some("n-1")).await;
+    let result = oidc.exchange(&code, VERIFIER, REDIRECT_URI, Some("n-2"));
+    assert!(rejected(result.await));
+
+    // wrong client secret, not the fault of the user
+    let oidc = client(&issuer, Some("other"));
+    let code = authorize(endpoint, "holopku", "alice", VERIFIER, None).await;
+    let result = oidc.exchange(&code, VERIFIER, REDIRECT_URI, None).await;
+    assert!(matches!(result, Err(OidcError::Unavailable(_))));
+}
+
+#[tokio::test]
+async fn verify_id_token() {
+    let (mock, issuer) = start_mock(None).await;
+    let oidc = client(&issuer, None);
+    let rejected = |result: Result<_, OidcError>| matches!(result, Err(OidcError::Rejected(_)));
+
+    let id_token = mock.sign(&mock.claims("alice", Some("n-0")));
+    let claims = oidc.verify_id_token(&id_token, Some("n-0")).await.unwrap();
+    assert_eq!(claims.sub, "alice");
+
+    // tampered payload
+    let mut parts: Vec<String> = id_token.split('.').map(String::from).collect();
+    let forged = mock.sign(&mock.claims("mallory", Some("n-0")));
+    parts[1] = forged.split('.').nth(1).unwrap().into();
+    assert!(rejected(oidc.verify_id_token(&parts.join("."), None).await));
+
+    // issued to another client
+    let mut claims = mock.claims("alice", None);
+    claims["aud"] = "another-client".into();
+    assert!(rejected(
+        oidc.verify_id_token(&mock.sign(&claims), None).await
+    ));
+
+    // issued by another issuer
+    let mut claims = mock.claims("alice", None);
+    claims["iss"] = "http://evil.example.com".into();
+    assert!(rejected(
+        oidc.verify_id_token(&mock.sign(&claims), None).await
+    ));
+
+    // expired
+    let mut claims = mock.claims("alice", None);
+    claims["exp"] = (chrono::Utc::now().timestamp() - 3600).into();
+    assert!(rejected(
+        oidc.verify_id_token(&mock.sign(&claims), None).await
+    ));
+}
+
+#[tokio::test]
+async fn issuer_unavailable() {
+    // nothing listens there
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let issuer = format!("http://{}", listener.local_addr().unwrap());
+    drop(listener);
+    let oidc = client(&issuer, None);
+    assert!(matches!(
+        oidc.discovery().await,
+        Err(OidcError::Unavailable(_))
+    ));
+
+    // the discovery document must be of the configured issuer
+    let (_, issuer) = start_mock(None).await;
+    let oidc = client(&format!("{issuer}/"), None);
+    assert!(matches!(
+        oidc.discovery().await,
+        Err(OidcError::Unavailable(_))
+    ));
+}
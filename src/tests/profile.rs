@@ -0,0 +1,94 @@
+use tonic::Code;
+
+use crate::auth::profile::{is_visible, validate_profile};
+use crate::codegen::auth::{ContactMethod, Profile};
+use crate::db::models::Visibility;
+
+fn sample_profile() -> Profile {
+    Profile {
+        bio: " 爱吃学五的麻辣香锅 ".into(),
+        pronouns: Some("she/her".into()),
+        contact_method: Some(ContactMethod::Wechat.into()),
+        contact: Some(" jack_ma ".into()),
+        links: vec!["https://github.com/jack".into(), "  ".into()],
+    }
+}
+
+#[test]
+fn valid_profile() {
+    let profile = validate_profile(sample_profile()).unwrap();
+    assert_eq!(profile.bio, "爱吃学五的麻辣香锅");
+    assert_eq!(profile.contact.as_deref(), Some("jack_ma"));
+    assert_eq!(profile.links, vec!["https://github.com/jack"]);
+
+    // empty optional fields are dropped
+    let profile = validate_profile(Profile {
+        pronouns: Some(" ".into()),
+        contact_method: None,
+        contact: None,
+        ..sample_profile()
+    })
+    .unwrap();
+    assert_eq!(profile.pronouns, None);
+    assert!(validate_profile(Profile::default()).is_ok());
+    // characters are counted, not bytes
+    assert!(validate_profile(Profile {
+        bio: "北".repeat(500),
+        ..sample_profile()
+    })
+    .is_ok());
+}
+
+#[test]
+fn invalid_profiles() {
+    let invalid = |profile: Profile| {
+        let e = validate_profile(profile).unwrap_err();
+        e.code() == Code::InvalidArgument
+    };
+    assert!(invalid(Profile {
+        bio: "北".repeat(501),
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        pronouns: Some("a".repeat(33)),
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        contact: None,
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        contact_method: None,
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        contact_method: Some(42),
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        links: vec!["https://pku.edu.cn".into(); 6],
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        links: vec!["javascript:alert(1)".into()],
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        links: vec!["github.com/jack".into()],
+        ..sample_profile()
+    }));
+    assert!(invalid(Profile {
+        links: vec![format!("https://pku.edu.cn/{}", "a".repeat(255))],
+        ..sample_profile()
+    }));
+}
+
+#[test]
+fn visibility() {
+    assert!(is_visible(Visibility::EVERYONE, false));
+    assert!(is_visible(Visibility::EVERYONE, true));
+    assert!(!is_visible(Visibility::SIGNED_IN, false));
+    assert!(is_visible(Visibility::SIGNED_IN, true));
+    assert!(!is_visible(Visibility::ONLY_ME, false));
+    assert!(!is_visible(Visibility::ONLY_ME, true));
+}
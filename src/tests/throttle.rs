@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use chrono::{Duration, NaiveDate};
+use tonic::Code;
+
+use crate::auth::throttle::{Backoff, Failures, LoginThrottle, MemoryLoginAttemptStore};
+use crate::clock::ManualClock;
+
+fn throttle() -> (LoginThrottle, Arc<ManualClock>) {
+    let start = NaiveDate::from_ymd_opt(2026, 10, 18)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let clock = Arc::new(ManualClock::new(start));
+    let throttle = LoginThrottle::new(Arc::new(MemoryLoginAttemptStore::new()), clock.clone())
+        .with_backoff(
+            Backoff {
+                free_attempts: 3,
+                ..Backoff::PER_USERNAME
+            },
+            Backoff {
+                free_attempts: 5,
+                ..Backoff::PER_IP
+            },
+        );
+    (throttle, clock)
+}
+
+fn fail(throttle: &LoginThrottle, username: &str, ip: &str, times: usize) {
+    for _ in 0..times {
+        throttle.check(username, Some(ip)).unwrap();
+        throttle.record(username, Some(ip), false).unwrap();
+    }
+}
+
+#[test]
+fn backoff_doubles_until_cap() {
+    let backoff = Backoff::PER_USERNAME;
+    let last = NaiveDate::from_ymd_opt(2026, 10, 18)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let locked_until = |count| {
+        backoff.locked_until(&Failures {
+            count,
+            last: Some(last),
+        })
+    };
+    assert_eq!(locked_until(4), None);
+    assert_eq!(locked_until(5), Some(last + Duration::seconds(30)));
+    assert_eq!(locked_until(6), Some(last + Duration::seconds(60)));
+    assert_eq!(locked_until(7), Some(last + Duration::seconds(120)));
+    assert_eq!(locked_until(1000), Some(last + Duration::hours(1)));
+    assert_eq!(backoff.locked_until(&Failures::default()), None);
+}
+
+#[test]
+fn lockout_expires() {
+    let (throttle, clock) = throttle();
+    fail(&throttle, "alice", "10.0.0.1", 3);
+
+    let status = throttle.check("alice", Some("10.0.0.2")).unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    assert!(status.message().contains("30 seconds"));
+    // other users are not affected
+    throttle.check("bob", Some("10.0.0.2")).unwrap();
+
+    clock.advance(Duration::seconds(29));
+    assert!(throttle.check("alice", None).is_err());
+    clock.advance(Duration::seconds(1));
+    throttle.check("alice", None).unwrap();
+
+    // one more failure doubles the wait
+    throttle.record("alice", None, false).unwrap();
+    clock.advance(Duration::seconds(59));
+    assert!(throttle.check("alice", None).is_err());
+    clock.advance(Duration::seconds(1));
+    throttle.check("alice", None).unwrap();
+}
+
+#[test]
+fn success_resets_username_failures() {
+    let (throttle, _) = throttle();
+    fail(&throttle, "alice", "10.0.0.1", 2);
+    throttle.record("alice", Some("10.0.0.1"), true).unwrap();
+    fail(&throttle, "alice", "10.0.0.1", 2);
+    throttle.check("alice", Some("10.0.0.1")).unwrap();
+}
+
+#[test]
+fn failures_outside_window_are_forgotten() {
+    let (throttle, clock) = throttle();
+    fail(&throttle, "alice", "10.0.0.1", 3);
+    clock.advance(Backoff::PER_USERNAME.window);
+    fail(&throttle, "alice", "10.0.0.1", 2);
+    throttle.check("alice", Some("10.0.0.1")).unwrap();
+}
+
+#[test]
+fn ip_failures_span_usernames() {
+    let (throttle, _) = throttle();
+    fail(&throttle, "alice", "10.0.0.1", 2);
+    fail(&throttle, "bob", "10.0.0.1", 2);
+    // a success on one account does not clear the address
+    throttle.record("carol", Some("10.0.0.1"), true).unwrap();
+    fail(&throttle, "dave", "10.0.0.1", 1);
+
+    let status = throttle.check("erin", Some("10.0.0.1")).unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+    throttle.check("erin", Some("10.0.0.2")).unwrap();
+}
@@ -0,0 +1,95 @@
+use crate::auth::totp::{base32_decode, base32_encode, hash_recovery_code, hotp, otpauth_uri};
+use crate::auth::totp::{generate_recovery_codes, time_step, totp_code, verify_code};
+
+/// The secret of the test vectors of RFC 4226 and RFC 6238.
+const SECRET: &[u8] = b"12345678901234567890";
+
+#[test]
+fn hotp_test_vectors() {
+    let codes = [755224, 287082, 359152, 969429, 338314, 254676];
+    for (counter, code) in codes.into_iter().enumerate() {
+        assert_eq!(hotp(SECRET, counter as u64), code);
+    }
+}
+
+#[test]
+fn totp_test_vectors() {
+    // the last 6 of the 8 digits in RFC 6238
+    let codes = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+    ];
+    for (timestamp, code) in codes {
+        assert_eq!(totp_code(SECRET, time_step(timestamp)), code);
+    }
+}
+
+#[test]
+fn verify_code_accepts_drift() {
+    let step = time_step(1234567890);
+    for drift in -1..=1 {
+        let code = totp_code(SECRET, step + drift);
+        assert_eq!(verify_code(SECRET, &code, step), Some(step + drift));
+    }
+    assert_eq!(
+        verify_code(SECRET, &totp_code(SECRET, step + 2), step),
+        None
+    );
+    assert_eq!(
+        verify_code(SECRET, &totp_code(SECRET, step - 2), step),
+        None
+    );
+    assert_eq!(verify_code(SECRET, "12345", step), None);
+    assert_eq!(verify_code(SECRET, "12345a", step), None);
+}
+
+#[test]
+fn base32_round_trip() {
+    // RFC 4648 test vectors, without padding
+    let vectors = [
+        ("", ""),
+        ("f", "MY"),
+        ("fo", "MZXQ"),
+        ("foo", "MZXW6"),
+        ("foob", "MZXW6YQ"),
+        ("fooba", "MZXW6YTB"),
+        ("foobar", "MZXW6YTBOI"),
+    ];
+    for (plain, encoded) in vectors {
+        assert_eq!(base32_encode(plain.as_bytes()), encoded);
+        assert_eq!(base32_decode(encoded).unwrap(), plain.as_bytes());
+    }
+    assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+    assert_eq!(base32_decode("MZXW1"), None);
+}
+
+#[test]
+fn otpauth_uri_format() {
+    let uri = url::Url::parse(&otpauth_uri("some user", SECRET)).unwrap();
+    assert_eq!(uri.scheme(), "otpauth");
+    assert_eq!(uri.host_str(), Some("totp"));
+    assert_eq!(uri.path(), "/HoloPKU:some%20user");
+    let secret = uri.query_pairs().find(|(name, _)| name == "secret");
+    let secret = base32_decode(&secret.unwrap().1).unwrap();
+    assert_eq!(secret, SECRET);
+}
+
+#[test]
+fn recovery_codes() {
+    let codes = generate_recovery_codes();
+    assert_eq!(codes.len(), 10);
+    let mut unique = codes.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), codes.len());
+
+    // typed back without dashes or in upper case, the hash is the same
+    let code = &codes[0];
+    let hash = hash_recovery_code(code);
+    assert_eq!(hash_recovery_code(&code.replace('-', "")), hash);
+    assert_eq!(hash_recovery_code(&code.to_uppercase()), hash);
+    assert_ne!(hash_recovery_code(&codes[1]), hash);
+}